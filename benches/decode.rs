@@ -0,0 +1,36 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use ggst_api::{decode_replay_response, DecodeMode};
+
+// Responses captured from the replay endpoint, the same ones the unit tests decode
+const FIXTURES: [(&str, &[u8]); 4] = [
+    (
+        "replay_response_1",
+        include_bytes!("../test_data/replay_response_1.msgpack"),
+    ),
+    (
+        "replay_response_2",
+        include_bytes!("../test_data/replay_response_2.msgpack"),
+    ),
+    (
+        "replay_response_3",
+        include_bytes!("../test_data/replay_response_3.msgpack"),
+    ),
+    (
+        "replay_response_4",
+        include_bytes!("../test_data/replay_response_4.msgpack"),
+    ),
+];
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_replay_response");
+    for (name, bytes) in FIXTURES {
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| decode_replay_response(black_box(bytes), DecodeMode::Lenient).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
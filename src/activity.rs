@@ -0,0 +1,341 @@
+//! Match counts in fixed-width time buckets, e.g. for matches per hour or per day charts.
+
+use crate::{error::*, Character, Floor, Match};
+use chrono::{DateTime, Datelike, FixedOffset, Timelike, Utc};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Most buckets bucket returns, ten years of hourly buckets. A single match with a corrupted
+/// timestamp far from the others would otherwise fill the memory with empty buckets.
+pub const MAX_BUCKETS: u64 = 100_000;
+
+/// Matches with a timestamp in [start, start + width). The floor and character counts are
+/// serialized as lists of [key, count] pairs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Bucket {
+    pub start: DateTime<Utc>,
+    pub total: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::pairs"))]
+    pub floors: BTreeMap<Floor, u64>,
+    /// Characters are counted once per player, so a mirror match counts twice
+    #[cfg_attr(feature = "serde", serde(with = "crate::pairs"))]
+    pub characters: BTreeMap<Character, u64>,
+}
+
+impl Bucket {
+    fn new(start: DateTime<Utc>) -> Self {
+        Bucket {
+            start,
+            total: 0,
+            floors: BTreeMap::new(),
+            characters: BTreeMap::new(),
+        }
+    }
+}
+
+/// Count matches in buckets of the given width aligned to the unix epoch, so hourly and daily
+/// buckets start at full hours and at midnight UTC. Buckets are returned in order from the one of
+/// the oldest match to the one of the newest, including empty buckets in between. The matches
+/// don't have to be sorted. The width is truncated to whole seconds and has to be at least one
+/// second. Fails if the matches span more than MAX_BUCKETS buckets.
+pub fn bucket<'a, I>(matches: I, width: Duration) -> Result<Vec<Bucket>>
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    bucket_in(matches, width, FixedOffset::east_opt(0).unwrap())
+}
+
+/// Same as bucket, but buckets are aligned in the given timezone, e.g. daily buckets start at local
+/// midnight
+pub fn bucket_in<'a, I>(matches: I, width: Duration, offset: FixedOffset) -> Result<Vec<Bucket>>
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let width = i64::try_from(width.as_secs())
+        .ok()
+        .filter(|&width| width > 0)
+        .ok_or_else(|| Error::InvalidArgument {
+            parameter: "width",
+            value: format!("{:?}", width),
+            reason: format!(
+                "width {:?} must be between one second and i64::MAX seconds",
+                width
+            ),
+        })?;
+    let offset = offset.local_minus_utc() as i64;
+    let start_of = |index: i64| {
+        DateTime::from_timestamp(index.saturating_mul(width).saturating_sub(offset), 0)
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    };
+
+    let mut buckets = BTreeMap::new();
+    for m in matches {
+        let index = (m.timestamp.timestamp() + offset).div_euclid(width);
+        let bucket = buckets
+            .entry(index)
+            .or_insert_with(|| Bucket::new(start_of(index)));
+        bucket.total += 1;
+        *bucket.floors.entry(m.floor).or_default() += 1;
+        for player in [&m.players.0, &m.players.1] {
+            *bucket.characters.entry(player.character).or_default() += 1;
+        }
+    }
+
+    let (first, last) = match (buckets.keys().next(), buckets.keys().next_back()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return Ok(vec![]),
+    };
+    let count = last.abs_diff(first) + 1;
+    if count > MAX_BUCKETS {
+        return Err(Error::InvalidArgument {
+            parameter: "width",
+            value: format!("{}s", width),
+            reason: format!(
+                "the matches span {} buckets of {}s, more than {}. Use a wider width or leave \
+                 out matches with implausible timestamps.",
+                count, width, MAX_BUCKETS
+            ),
+        });
+    }
+    Ok((first..=last)
+        .map(|index| {
+            buckets
+                .remove(&index)
+                .unwrap_or_else(|| Bucket::new(start_of(index)))
+        })
+        .collect())
+}
+
+/// Match counts per hour of the week in local time, indexed by days from Monday and then by hour
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct HourOfWeek {
+    pub counts: [[u64; 24]; 7],
+}
+
+impl HourOfWeek {
+    /// Matches played at the given hour of the weekday
+    pub fn get(&self, weekday: chrono::Weekday, hour: u32) -> u64 {
+        self.counts[weekday.num_days_from_monday() as usize]
+            .get(hour as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Sum of all cells
+    pub fn total(&self) -> u64 {
+        self.counts.iter().flatten().sum()
+    }
+}
+
+/// Count matches per hour of the week in the given timezone, e.g. for a heatmap of when a floor is
+/// busy. Combine it with a [MatchFilter](crate::filter::MatchFilter) or any other filter by
+/// filtering the matches first.
+pub fn hour_of_week<'a, I>(matches: I, offset: FixedOffset) -> HourOfWeek
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let mut heatmap = HourOfWeek::default();
+    for m in matches {
+        let local = m.timestamp.with_timezone(&offset);
+        heatmap.counts[local.weekday().num_days_from_monday() as usize][local.hour() as usize] += 1;
+    }
+    heatmap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Player;
+    use chrono::TimeZone;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn game(timestamp: &str, floor: Floor, characters: (Character, Character)) -> Match {
+        let players = (Player::test(1, characters.0), Player::test(2, characters.1));
+        Match {
+            timestamp: timestamp.parse().unwrap(),
+            floor,
+            ..Match::test(0, 0, players)
+        }
+    }
+
+    #[test]
+    fn hourly_buckets() {
+        use Character::*;
+        let matches = [
+            game("2022-02-06T13:05:00Z", Floor::F10, (Sol, Ky)),
+            // Exactly on a boundary belongs to the later bucket
+            game("2022-02-06T11:00:00Z", Floor::F10, (Sol, Sol)),
+            game("2022-02-06T10:59:59Z", Floor::Celestial, (May, Ky)),
+        ];
+        let buckets = bucket(&matches, HOUR).unwrap();
+        assert_eq!(
+            buckets.iter().map(|b| b.start).collect::<Vec<_>>(),
+            [
+                Utc.with_ymd_and_hms(2022, 2, 6, 10, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2022, 2, 6, 11, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2022, 2, 6, 12, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2022, 2, 6, 13, 0, 0).unwrap(),
+            ]
+        );
+        assert_eq!(
+            buckets.iter().map(|b| b.total).collect::<Vec<_>>(),
+            [1, 1, 0, 1]
+        );
+        assert_eq!(buckets[1].characters, BTreeMap::from([(Sol, 2)]));
+        assert_eq!(buckets[0].floors, BTreeMap::from([(Floor::Celestial, 1)]));
+        assert_eq!(buckets[2], Bucket::new(buckets[2].start));
+    }
+
+    #[test]
+    fn daily_buckets_in_timezone() {
+        let jst = FixedOffset::east_opt(9 * 3600).unwrap();
+        let day = Duration::from_secs(24 * 3600);
+        let matches = [
+            // 23:30 and 00:30 in Japan
+            game(
+                "2022-02-06T14:30:00Z",
+                Floor::F1,
+                (Character::Sol, Character::Ky),
+            ),
+            game(
+                "2022-02-06T15:30:00Z",
+                Floor::F1,
+                (Character::Sol, Character::Ky),
+            ),
+        ];
+        let utc = bucket(&matches, day).unwrap();
+        assert_eq!(utc.len(), 1);
+
+        let local = bucket_in(&matches, day, jst).unwrap();
+        assert_eq!(local.len(), 2);
+        assert_eq!(
+            local[1].start,
+            jst.with_ymd_and_hms(2022, 2, 7, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn edge_cases() {
+        assert_eq!(bucket(&[], HOUR).unwrap(), []);
+        assert!(bucket(&[], Duration::from_millis(500)).is_err());
+
+        // A far future timestamp fails instead of allocating billions of buckets
+        let corrupted = [
+            game(
+                "2022-02-06T10:00:00Z",
+                Floor::F10,
+                (Character::Sol, Character::Ky),
+            ),
+            game(
+                "9999-12-31T23:59:59Z",
+                Floor::F10,
+                (Character::Sol, Character::Ky),
+            ),
+        ];
+        assert!(matches!(
+            bucket(&corrupted, Duration::from_secs(1)),
+            Err(Error::InvalidArgument { .. })
+        ));
+        let wide = bucket(&corrupted, Duration::from_secs(1000 * 86400)).unwrap();
+        assert!(wide.len() as u64 <= MAX_BUCKETS);
+        assert_eq!(wide.iter().map(|b| b.total).sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn heatmap() {
+        use crate::filter::MatchFilter;
+        use chrono::Weekday;
+        let matches = [
+            game(
+                "2022-02-06T23:30:00Z",
+                Floor::F10,
+                (Character::Sol, Character::Ky),
+            ),
+            game(
+                "2022-02-06T23:45:00Z",
+                Floor::F9,
+                (Character::Sol, Character::Ky),
+            ),
+            game(
+                "2022-02-07T00:10:00Z",
+                Floor::F10,
+                (Character::Sol, Character::Ky),
+            ),
+            game(
+                "2022-02-11T19:00:00Z",
+                Floor::F10,
+                (Character::Sol, Character::Ky),
+            ),
+        ];
+
+        let utc = hour_of_week(&matches, FixedOffset::east_opt(0).unwrap());
+        assert_eq!(utc.get(Weekday::Sun, 23), 2);
+        assert_eq!(utc.get(Weekday::Mon, 0), 1);
+        assert_eq!(utc.get(Weekday::Fri, 19), 1);
+        assert_eq!(utc.total(), 4);
+
+        // Sunday night in UTC is Monday morning in central Europe
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        let local = hour_of_week(&matches, cet);
+        assert_eq!(local.get(Weekday::Sun, 23), 0);
+        assert_eq!(local.get(Weekday::Mon, 0), 2);
+        assert_eq!(local.get(Weekday::Mon, 1), 1);
+
+        // Floor 10 in the evening
+        let evening = MatchFilter::new().timezone(cet).hour_range(18, 2).unwrap();
+        let floor10 = hour_of_week(
+            matches
+                .iter()
+                .filter(|m| m.floor == Floor::F10 && evening.matches(m)),
+            cet,
+        );
+        assert_eq!(floor10.get(Weekday::Mon, 0), 1);
+        assert_eq!(floor10.get(Weekday::Mon, 1), 1);
+        assert_eq!(floor10.get(Weekday::Fri, 20), 1);
+        assert_eq!(floor10.total(), 3);
+        assert_eq!(hour_of_week(&[], cet).total(), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn chart_json() {
+        let matches = [game(
+            "2022-02-06T10:15:00Z",
+            Floor::F10,
+            (Character::Sol, Character::Ky),
+        )];
+        let json = serde_json::to_value(bucket(&matches, HOUR).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "start": "2022-02-06T10:00:00Z",
+                "total": 1,
+                "floors": [["F10", 1]],
+                "characters": [["Sol", 1], ["Ky", 1]],
+            }])
+        );
+
+        let matches = [game(
+            "2022-02-06T10:15:00Z",
+            Floor::Unknown(0x0b),
+            (Character::Sol, Character::Unknown(0x13)),
+        )];
+        let buckets = bucket(&matches, HOUR).unwrap();
+        let json = serde_json::to_string(&buckets).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<Bucket>>(&json).unwrap(), buckets);
+    }
+}
@@ -0,0 +1,247 @@
+//! Tracking the names players used over time, keyed by their stable player id.
+
+use crate::{Match, PlayerIdentity};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Number of previous names kept per player by default
+pub const DEFAULT_MAX_ALIASES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Alias {
+    pub name: String,
+    /// Timestamp of the newest match played under this name
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Names of a single player
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Aliases {
+    /// Name of the newest match of the player
+    pub current: Alias,
+    /// Other names, from most to least recently seen
+    pub previous: Vec<Alias>,
+}
+
+/// Latest and previous names per player id, fed with matches in any order. The name of the match
+/// with the newest timestamp wins, on equal timestamps the name seen first stays current. Only
+/// the most recently seen previous names are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct AliasTracker {
+    max_aliases: usize,
+    players: HashMap<i64, Aliases>,
+}
+
+impl Default for AliasTracker {
+    fn default() -> Self {
+        AliasTracker::new(DEFAULT_MAX_ALIASES)
+    }
+}
+
+impl AliasTracker {
+    /// Create a tracker keeping at most max_aliases previous names per player
+    pub fn new(max_aliases: usize) -> Self {
+        AliasTracker {
+            max_aliases,
+            players: HashMap::new(),
+        }
+    }
+
+    pub fn max_aliases(&self) -> usize {
+        self.max_aliases
+    }
+
+    /// Record the names of both players of the match
+    pub fn observe(&mut self, m: &Match) {
+        for player in [&m.players.0, &m.players.1] {
+            self.observe_name(player.id, player.name(), m.timestamp);
+        }
+    }
+
+    fn observe_name(&mut self, id: i64, name: &str, timestamp: DateTime<Utc>) {
+        let alias = || Alias {
+            name: name.to_owned(),
+            last_seen: timestamp,
+        };
+        let aliases = match self.players.get_mut(&id) {
+            Some(aliases) => aliases,
+            None => {
+                self.players.insert(
+                    id,
+                    Aliases {
+                        current: alias(),
+                        previous: vec![],
+                    },
+                );
+                return;
+            }
+        };
+
+        if aliases.current.name == name {
+            aliases.current.last_seen = aliases.current.last_seen.max(timestamp);
+            return;
+        }
+        let previous = if timestamp > aliases.current.last_seen {
+            std::mem::replace(&mut aliases.current, alias())
+        } else {
+            alias()
+        };
+        aliases.previous.retain(|a| a.name != aliases.current.name);
+        match aliases
+            .previous
+            .iter_mut()
+            .find(|a| a.name == previous.name)
+        {
+            Some(existing) => existing.last_seen = existing.last_seen.max(previous.last_seen),
+            None => aliases.previous.push(previous),
+        }
+        aliases
+            .previous
+            .sort_by(|a, b| b.last_seen.cmp(&a.last_seen).then(a.name.cmp(&b.name)));
+        aliases.previous.truncate(self.max_aliases);
+    }
+
+    /// Names of the player, None if no match of the player was observed
+    pub fn get(&self, id: i64) -> Option<&Aliases> {
+        self.players.get(&id)
+    }
+
+    /// Latest known name of the player
+    pub fn latest_name(&self, id: i64) -> Option<&str> {
+        self.get(id).map(|aliases| aliases.current.name.as_str())
+    }
+
+    /// Number of players observed
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    /// Every observed player with their latest name, in no particular order
+    pub fn identities(&self) -> impl Iterator<Item = PlayerIdentity> + '_ {
+        self.players.iter().map(|(&id, aliases)| PlayerIdentity {
+            id,
+            name: aliases.current.name.as_str().into(),
+            online_id: None,
+        })
+    }
+
+    /// Player ids and their names in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (i64, &Aliases)> {
+        self.players.iter().map(|(&id, aliases)| (id, aliases))
+    }
+}
+
+impl<'a> Extend<&'a Match> for AliasTracker {
+    fn extend<T: IntoIterator<Item = &'a Match>>(&mut self, iter: T) {
+        for m in iter {
+            self.observe(m);
+        }
+    }
+}
+
+impl Extend<Match> for AliasTracker {
+    fn extend<T: IntoIterator<Item = Match>>(&mut self, iter: T) {
+        for m in iter {
+            self.observe(&m);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Character, Player};
+
+    fn game(minute: u32, name: &str) -> Match {
+        let player = |id, name: &str| Player {
+            name: name.into(),
+            ..Player::test(id, Character::Sol)
+        };
+        Match {
+            timestamp: format!("2022-02-06T10:{:02}:00Z", minute).parse().unwrap(),
+            ..Match::test(minute as u64, 0, (player(1, name), player(2, "Opponent")))
+        }
+    }
+
+    fn names(aliases: &Aliases) -> Vec<&str> {
+        aliases.previous.iter().map(|a| a.name.as_str()).collect()
+    }
+
+    #[test]
+    fn latest_name_by_timestamp() {
+        let mut tracker = AliasTracker::default();
+        // Out of order, as pages are usually newest first
+        tracker.extend(&[game(5, "Third"), game(1, "First"), game(3, "Second")]);
+        tracker.observe(&game(2, "First"));
+
+        assert_eq!(tracker.len(), 2);
+        assert_eq!(tracker.latest_name(1), Some("Third"));
+        assert_eq!(tracker.latest_name(3), None);
+        let aliases = tracker.get(1).unwrap();
+        assert_eq!(aliases.current.last_seen, game(5, "").timestamp);
+        assert_eq!(names(aliases), ["Second", "First"]);
+        assert_eq!(aliases.previous[1].last_seen, game(2, "").timestamp);
+
+        // Renaming back moves the current name to the previous ones
+        tracker.observe(&game(6, "First"));
+        let aliases = tracker.get(1).unwrap();
+        assert_eq!(aliases.current.name, "First");
+        assert_eq!(names(aliases), ["Third", "Second"]);
+
+        let mut identities = tracker.identities().collect::<Vec<_>>();
+        identities.sort();
+        assert_eq!(
+            identities
+                .iter()
+                .map(|identity| (identity.id, identity.name()))
+                .collect::<Vec<_>>(),
+            [(1, "First"), (2, "Opponent")]
+        );
+    }
+
+    #[test]
+    fn bounded_aliases() {
+        let mut tracker = AliasTracker::new(2);
+        tracker.extend((0..5).map(|minute| game(minute, &minute.to_string())));
+        let aliases = tracker.get(1).unwrap();
+        assert_eq!(aliases.current.name, "4");
+        assert_eq!(names(aliases), ["3", "2"]);
+
+        // An older name than all kept ones is dropped right away
+        tracker.observe(&game(0, "0"));
+        assert_eq!(names(tracker.get(1).unwrap()), ["3", "2"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn persistence() {
+        let mut tracker = AliasTracker::new(3);
+        tracker.extend(&[game(1, "First"), game(2, "Second")]);
+        let json = serde_json::to_string(&tracker).unwrap();
+        let restored: AliasTracker = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, tracker);
+        assert_eq!(restored.max_aliases(), 3);
+    }
+}
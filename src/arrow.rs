@@ -0,0 +1,244 @@
+//! Export of matches as arrow record batches and parquet files.
+//!
+//! Every match is one row of a batch with the following schema, only the venue is nullable:
+//!
+//! | column            | type                 | content                              |
+//! |-------------------|----------------------|--------------------------------------|
+//! | id                | UInt64               | replay id                            |
+//! | timestamp         | Timestamp(ms, "UTC") | time the match was played            |
+//! | floor             | UInt8                | floor code, see Floor::to_u8         |
+//! | winner            | UInt8                | winner byte, see Winner::to_u8       |
+//! | venue             | Int64                | venue code, see MatchVenue           |
+//! | player1_id        | Int64                | player id                            |
+//! | player1_character | UInt8                | character code, see Character::to_u8 |
+//! | player1_name      | Utf8                 | player name                          |
+//! | player2_id        | Int64                |                                      |
+//! | player2_character | UInt8                |                                      |
+//! | player2_name      | Utf8                 |                                      |
+//!
+//! Characters and floors are stored as the codes used by the game so unknown values survive a
+//! round trip.
+
+use crate::{error::*, Character, Floor, Match, MatchVenue, Player, Winner};
+use arrow_array::{
+    Array, ArrayRef, Int64Array, RecordBatch, StringArray, TimestampMillisecondArray, UInt64Array,
+    UInt8Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use std::io::Write;
+use std::sync::Arc;
+
+/// The schema of batches created by to_record_batch
+pub fn schema() -> SchemaRef {
+    let player = |n| {
+        [
+            Field::new(format!("player{}_id", n), DataType::Int64, false),
+            Field::new(format!("player{}_character", n), DataType::UInt8, false),
+            Field::new(format!("player{}_name", n), DataType::Utf8, false),
+        ]
+    };
+    let mut fields = vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("floor", DataType::UInt8, false),
+        Field::new("winner", DataType::UInt8, false),
+        Field::new("venue", DataType::Int64, true),
+    ];
+    fields.extend(player(1));
+    fields.extend(player(2));
+    Arc::new(Schema::new(fields))
+}
+
+/// Convert the matches into a single record batch, one row per match in the given order
+pub fn to_record_batch(matches: &[Match]) -> RecordBatch {
+    let player_columns = |player: fn(&Match) -> &Player| -> [ArrayRef; 3] {
+        [
+            Arc::new(Int64Array::from_iter_values(
+                matches.iter().map(|m| player(m).id),
+            )),
+            Arc::new(UInt8Array::from_iter_values(
+                matches.iter().map(|m| player(m).character.to_u8()),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                matches.iter().map(|m| player(m).name()),
+            )),
+        ]
+    };
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(matches.iter().map(|m| m.id))),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                matches.iter().map(|m| m.timestamp.timestamp_millis()),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(UInt8Array::from_iter_values(
+            matches.iter().map(|m| m.floor.to_u8()),
+        )),
+        Arc::new(UInt8Array::from_iter_values(
+            matches.iter().map(|m| m.winner.to_u8()),
+        )),
+        Arc::new(Int64Array::from_iter(
+            matches.iter().map(|m| m.venue.map(MatchVenue::code)),
+        )),
+    ];
+    columns.extend(player_columns(|m| &m.players.0));
+    columns.extend(player_columns(|m| &m.players.1));
+    RecordBatch::try_new(schema(), columns).expect("columns match the schema")
+}
+
+fn invalid(field: &'static str, value: impl ToString) -> Error {
+    Error::ParsingBytesError {
+        field,
+        value: value.to_string(),
+    }
+}
+
+fn column<'a, T: Array + 'static>(batch: &'a RecordBatch, name: &'static str) -> Result<&'a T> {
+    batch
+        .column_by_name(name)
+        .filter(|column| column.null_count() == 0)
+        .and_then(|column| column.as_any().downcast_ref::<T>())
+        .ok_or_else(|| invalid("arrow column", name))
+}
+
+type PlayerColumns<'a> = (&'a Int64Array, &'a UInt8Array, &'a StringArray);
+
+fn player_columns<'a>(
+    batch: &'a RecordBatch,
+    names: [&'static str; 3],
+) -> Result<PlayerColumns<'a>> {
+    Ok((
+        column(batch, names[0])?,
+        column(batch, names[1])?,
+        column(batch, names[2])?,
+    ))
+}
+
+/// Convert a batch with the schema of to_record_batch back into matches
+pub fn from_record_batch(batch: &RecordBatch) -> Result<Vec<Match>> {
+    let ids = column::<UInt64Array>(batch, "id")?;
+    let timestamps = column::<TimestampMillisecondArray>(batch, "timestamp")?;
+    let floors = column::<UInt8Array>(batch, "floor")?;
+    let winners = column::<UInt8Array>(batch, "winner")?;
+    let venues = batch
+        .column_by_name("venue")
+        .and_then(|column| column.as_any().downcast_ref::<Int64Array>())
+        .ok_or_else(|| invalid("arrow column", "venue"))?;
+    let one = player_columns(batch, ["player1_id", "player1_character", "player1_name"])?;
+    let two = player_columns(batch, ["player2_id", "player2_character", "player2_name"])?;
+    let player = |(ids, characters, names): PlayerColumns, row| Player {
+        id: ids.value(row),
+        character: Character::from_u8_lossy(characters.value(row)),
+        name: names.value(row).into(),
+    };
+
+    (0..batch.num_rows())
+        .map(|row| {
+            let millis = timestamps.value(row);
+            let timestamp = chrono::DateTime::from_timestamp_millis(millis)
+                .ok_or_else(|| invalid("timestamp", millis))?;
+            Ok(Match {
+                timestamp,
+                floor: Floor::from_u8_lossy(floors.value(row)),
+                players: (player(one, row), player(two, row)),
+                winner: Winner::from_u8(winners.value(row)),
+                id: ids.value(row),
+                venue: venues.is_valid(row).then(|| MatchVenue(venues.value(row))),
+            })
+        })
+        .collect()
+}
+
+/// Write the matches as a parquet file with the schema of to_record_batch
+pub fn write_parquet<W: Write + Send>(matches: &[Match], writer: W) -> Result<()> {
+    let export_error = |e: parquet::errors::ParquetError| Error::Export(Box::new(e));
+    let mut writer =
+        parquet::arrow::ArrowWriter::try_new(writer, schema(), None).map_err(export_error)?;
+    writer
+        .write(&to_record_batch(matches))
+        .map_err(export_error)?;
+    writer.close().map_err(export_error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    const RESPONSE: &[u8] = include_bytes!("../test_data/replay_response_1.msgpack");
+
+    fn matches() -> Vec<Match> {
+        let mut matches = crate::decode_replay_response(RESPONSE, crate::DecodeMode::Lenient)
+            .unwrap()
+            .matches()
+            .cloned()
+            .collect::<Vec<_>>();
+        matches[0].players.0.character = Character::Unknown(99);
+        matches[0].floor = Floor::Unknown(42);
+        matches[1].venue = Some(MatchVenue(1 << 40));
+        matches[2].venue = None;
+        matches
+    }
+
+    fn assert_same(read: &[Match], source: &[Match]) {
+        assert_eq!(read.len(), source.len());
+        for (read, source) in read.iter().zip(source) {
+            assert_eq!(read.id(), source.id());
+            assert_eq!(read.timestamp(), source.timestamp());
+            assert_eq!(read.floor(), source.floor());
+            assert_eq!(read.try_winner(), source.try_winner());
+            assert_eq!(read.venue(), source.venue());
+            assert_eq!(read.players(), source.players());
+            assert_eq!(read.players().0.name(), source.players().0.name());
+            assert_eq!(read.players().1.name(), source.players().1.name());
+        }
+    }
+
+    #[test]
+    fn record_batch_round_trip() {
+        let matches = matches();
+        let batch = to_record_batch(&matches);
+        assert_eq!(batch.schema(), schema());
+        assert_eq!(batch.num_rows(), matches.len());
+        assert_same(&from_record_batch(&batch).unwrap(), &matches);
+
+        let empty = to_record_batch(&[]);
+        assert!(from_record_batch(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let matches = matches();
+        let mut file = vec![];
+        write_parquet(&matches, &mut file).unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(file))
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut read = vec![];
+        for batch in reader {
+            read.extend(from_record_batch(&batch.unwrap()).unwrap());
+        }
+        assert_same(&read, &matches);
+    }
+
+    #[test]
+    fn missing_column() {
+        let batch = to_record_batch(&matches());
+        let batch = batch.project(&[0, 1, 2]).unwrap();
+        assert!(matches!(
+            from_record_batch(&batch),
+            Err(Error::ParsingBytesError {
+                field: "arrow column",
+                ..
+            })
+        ));
+    }
+}
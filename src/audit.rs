@@ -0,0 +1,231 @@
+//! Diagnostics for replays whose winner looks wrong.
+//!
+//! Some replays are reported with the wrong winner. To find out whether the server sends wrong
+//! data, the decoding reads the wrong bytes or the result is reported late, a WinnerAudit records
+//! the raw winner byte and the unknown integers next to it every time a replay is seen. Replays
+//! stay on the first pages for a while, so polling the same query repeatedly shows whether the
+//! server's answer for a replay changes. With the serde feature the audit can be dumped as JSON and
+//! attached to an issue.
+
+use crate::requests::{check_query, endpoints, messagepack};
+use crate::{error::*, Context, QueryParameters};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// A replay as it was contained in one response
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct WinnerObservation {
+    /// Id of the response, to look it up in the server logs
+    pub response_id: String,
+    /// Date of the response header, None if it could not be parsed
+    pub server_date: Option<DateTime<Utc>>,
+    pub page: usize,
+    /// The winner byte as sent, 1 and 2 are the only known values
+    pub winner: u8,
+    /// The unknown integers of the replay, in the order they are sent
+    pub int2: messagepack::UnknownInteger,
+    pub int7: messagepack::UnknownInteger,
+    pub int8: messagepack::UnknownInteger,
+}
+
+/// Every observation of a single replay, in the order they were recorded
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct AuditedReplay {
+    pub replay_id: u64,
+    pub player1_id: String,
+    pub player2_id: String,
+    pub date: DateTime<Utc>,
+    pub observations: Vec<WinnerObservation>,
+}
+
+impl AuditedReplay {
+    /// Whether the server sent different winners for this replay
+    pub fn winner_changed(&self) -> bool {
+        self.observations
+            .windows(2)
+            .any(|pair| pair[0].winner != pair[1].winner)
+    }
+
+    /// Whether any observation has a winner byte other than 1 or 2
+    pub fn anomalous(&self) -> bool {
+        self.observations
+            .iter()
+            .any(|observation| !matches!(observation.winner, 1 | 2))
+    }
+}
+
+/// Raw winner data of every replay seen, ordered by replay id
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct WinnerAudit {
+    replays: BTreeMap<u64, AuditedReplay>,
+}
+
+impl WinnerAudit {
+    pub fn new() -> Self {
+        WinnerAudit::default()
+    }
+
+    /// Record the replays of a response to the given page
+    pub fn record(&mut self, page: usize, response: &messagepack::ReplayResponse) {
+        let server_date = response.header.server_date().ok();
+        for replay in &response.body.replays {
+            self.replays
+                .entry(replay.id)
+                .or_insert_with(|| AuditedReplay {
+                    replay_id: replay.id,
+                    player1_id: replay.player1.id.clone(),
+                    player2_id: replay.player2.id.clone(),
+                    date: replay.date,
+                    observations: vec![],
+                })
+                .observations
+                .push(WinnerObservation {
+                    response_id: response.header.id.to_string(),
+                    server_date,
+                    page,
+                    winner: replay.winner,
+                    int2: replay.int2,
+                    int7: replay.int7,
+                    int8: replay.int8,
+                });
+        }
+    }
+
+    pub fn replays(&self) -> impl Iterator<Item = &AuditedReplay> {
+        self.replays.values()
+    }
+
+    /// The replay with the given id, if it was seen
+    pub fn get(&self, replay_id: u64) -> Option<&AuditedReplay> {
+        self.replays.get(&replay_id)
+    }
+
+    /// Replays for which the server sent different winners
+    pub fn changed(&self) -> impl Iterator<Item = &AuditedReplay> {
+        self.replays().filter(|replay| replay.winner_changed())
+    }
+
+    /// Replays with a winner byte other than 1 or 2
+    pub fn anomalous(&self) -> impl Iterator<Item = &AuditedReplay> {
+        self.replays().filter(|replay| replay.anomalous())
+    }
+}
+
+/// Request the pages like get_replays and record every replay in the audit. Call it repeatedly
+/// with the same audit to see whether the winner of a replay changes between polls. Responses
+/// which can't be decoded are skipped.
+pub async fn poll<A, B, C, D, E>(
+    context: &Context,
+    pages: usize,
+    replays_per_page: usize,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+    audit: &mut WinnerAudit,
+) -> Result<()> {
+    let query = check_query(context, pages, replays_per_page, &request_parameters)?;
+    for index in 0..pages {
+        let response = context
+            .call::<endpoints::Replays>(messagepack::RequestBody::page(
+                index,
+                replays_per_page,
+                query.clone(),
+            ))
+            .await;
+        match response {
+            Ok(response) => audit.record(index, &response),
+            Err(Error::Decode(_)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MockTransport, RawResponse};
+
+    const RESPONSE_1: &[u8] = include_bytes!("../test_data/replay_response_1.msgpack");
+
+    fn response() -> messagepack::ReplayResponse {
+        rmp_serde::from_slice(RESPONSE_1).unwrap()
+    }
+
+    #[test]
+    fn changed_winner() {
+        let mut audit = WinnerAudit::new();
+        let first = response();
+        audit.record(0, &first);
+        assert_eq!(audit.replays().count(), 30);
+        assert_eq!(audit.changed().count(), 0);
+
+        // The next poll sees the replay on the next page with the other winner
+        let mut second = response();
+        second.body.replays[0].winner = 3 - second.body.replays[0].winner;
+        second.body.replays[1].winner = 0;
+        audit.record(1, &second);
+
+        let id = first.body.replays[0].id;
+        assert_eq!(audit.changed().count(), 2);
+        let replay = audit.get(id).unwrap();
+        assert_eq!(replay.observations.len(), 2);
+        assert_eq!(replay.observations[1].page, 1);
+        assert_eq!(replay.observations[0].response_id, "61ff0796545a9");
+        assert_eq!(
+            audit.anomalous().map(|r| r.replay_id).collect::<Vec<_>>(),
+            [first.body.replays[1].id]
+        );
+    }
+
+    #[tokio::test]
+    async fn poll_pages() {
+        let context = Context::default().transport(
+            MockTransport::new()
+                .page(0, RawResponse::ok(RESPONSE_1))
+                .page(1, RawResponse::ok(b"\x92\x01".to_vec())),
+        );
+        let mut audit = WinnerAudit::new();
+        poll(&context, 2, 10, QueryParameters::default(), &mut audit)
+            .await
+            .unwrap();
+        poll(&context, 1, 10, QueryParameters::default(), &mut audit)
+            .await
+            .unwrap();
+        assert!(audit.replays().all(|replay| replay.observations.len() == 2));
+        assert_eq!(audit.changed().count(), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_dump() {
+        let mut audit = WinnerAudit::new();
+        audit.record(0, &response());
+        let json = serde_json::to_value(&audit).unwrap();
+        let replay = json["replays"]
+            .as_object()
+            .unwrap()
+            .values()
+            .next()
+            .unwrap();
+        assert_eq!(replay["observations"][0]["response_id"], "61ff0796545a9");
+        let restored: WinnerAudit = serde_json::from_value(json).unwrap();
+        assert_eq!(restored, audit);
+    }
+}
@@ -0,0 +1,269 @@
+//! Resumable crawls of older replays, see run.
+
+use crate::requests::{check_query, collect_page, MatchCollector};
+use crate::{error::*, sink::MatchSink, Context, QueryParameters, ReplaySet};
+use serde_crate::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Format version of the state file written by this crate
+pub const STATE_VERSION: u32 = 1;
+
+const REPLAYS_PER_PAGE: usize = 127;
+const MAX_PAGES: usize = 100;
+
+/// Progress of a backfill, persisted as JSON between pages. Timestamps are unix seconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct BackfillState {
+    pub version: u32,
+    /// Newest match of all completed runs, a run stops at the first page reaching it
+    pub covered_until: Option<i64>,
+    /// Page the current run continues with
+    pub next_page: usize,
+    /// Newest match written by the current run
+    pub run_newest: Option<i64>,
+    /// Matches written and pages read over all runs
+    pub matches_written: u64,
+    pub pages_read: u64,
+}
+
+impl BackfillState {
+    /// Read the state file, a missing file is a fresh state
+    pub async fn load(path: &Path) -> Result<Self> {
+        let bytes = match tokio::fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(BackfillState {
+                    version: STATE_VERSION,
+                    ..Default::default()
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let state: BackfillState =
+            serde_json::from_slice(&bytes).map_err(|e| Error::ParsingBytesError {
+                field: "backfill state",
+                value: e.to_string(),
+            })?;
+        if state.version == 0 || state.version > STATE_VERSION {
+            return Err(Error::ParsingBytesError {
+                field: "backfill state version",
+                value: state.version.to_string(),
+            });
+        }
+        Ok(state)
+    }
+
+    // Replace the file in one step so an interruption never leaves a partial state behind
+    async fn store(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?;
+        let temporary = path.with_extension("tmp");
+        tokio::fs::write(&temporary, json).await?;
+        tokio::fs::rename(&temporary, path).await?;
+        Ok(())
+    }
+}
+
+/// Outcome of a single call of run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackfillReport {
+    /// Matches passed to the sink
+    pub new_matches: u64,
+    pub pages_read: u64,
+    /// Replays which could not be parsed and were skipped
+    pub parse_errors: u64,
+    /// Whether the run reached the matches of the previous run or the last page. Otherwise it was
+    /// stopped by an error and the next call continues where it stopped.
+    pub complete: bool,
+}
+
+/// Read pages from newest to oldest and pass their matches to the sink until a page reaches the
+/// newest match of the previous run, an empty page or the last page the API serves. The state
+/// file is updated after every page, so the function can be interrupted at any point and called
+/// again to continue. Pages shift while new replays are played, so a few matches may be passed to
+/// the sink again after a restart.
+pub async fn run<A, B, C, D, E, S>(
+    context: &Context,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+    state_path: impl AsRef<Path>,
+    sink: &mut S,
+) -> Result<BackfillReport>
+where
+    S: MatchSink + ?Sized,
+{
+    let state_path = state_path.as_ref();
+    let query = check_query(context, MAX_PAGES, REPLAYS_PER_PAGE, &request_parameters)?;
+    let mut state = BackfillState::load(state_path).await?;
+    let mut report = BackfillReport::default();
+
+    while state.next_page < MAX_PAGES {
+        let mut replays = ReplaySet::default();
+        let mut matches = MatchCollector::default();
+        collect_page(
+            context,
+            state.next_page,
+            REPLAYS_PER_PAGE,
+            &query,
+            &mut replays,
+            &mut matches,
+        )
+        .await?;
+        let (_, errors) = replays.into_parts();
+        let mut errors = errors.collect::<Vec<_>>();
+        if matches.matches.is_empty() && !errors.is_empty() {
+            // The page itself could not be parsed, retry it on the next call
+            return Err(errors.remove(0).into_inner());
+        }
+        report.parse_errors += errors.len() as u64;
+
+        let mut page = matches.matches.into_values().collect::<Vec<_>>();
+        page.sort_unstable();
+        let reached_previous = page.is_empty()
+            || page
+                .first()
+                .zip(state.covered_until)
+                .is_some_and(|(oldest, covered)| oldest.timestamp_unix() <= covered);
+        for m in page {
+            let timestamp = m.timestamp_unix();
+            if state
+                .covered_until
+                .is_some_and(|covered| timestamp <= covered)
+            {
+                continue;
+            }
+            sink.accept(m).await?;
+            state.run_newest = state.run_newest.max(Some(timestamp));
+            state.matches_written += 1;
+            report.new_matches += 1;
+        }
+
+        state.next_page += 1;
+        state.pages_read += 1;
+        report.pages_read += 1;
+        if reached_previous {
+            break;
+        }
+        state.store(state_path).await?;
+    }
+
+    state.covered_until = state.covered_until.max(state.run_newest);
+    state.next_page = 0;
+    state.run_newest = None;
+    state.store(state_path).await?;
+    report.complete = true;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{BoxFuture, MockTransport, RawResponse};
+    use crate::Match;
+    use std::path::PathBuf;
+
+    const RESPONSE_1: &[u8] = include_bytes!("../test_data/replay_response_1.msgpack");
+    const RESPONSE_3: &[u8] = include_bytes!("../test_data/replay_response_3.msgpack");
+
+    fn empty_page() -> Vec<u8> {
+        let mut response: crate::messagepack::ReplayResponse =
+            rmp_serde::from_slice(RESPONSE_1).unwrap();
+        response.body.replays.clear();
+        rmp_serde::to_vec(&response).unwrap()
+    }
+
+    fn count(response: &[u8]) -> u64 {
+        crate::decode_replay_response(response, crate::DecodeMode::Lenient)
+            .unwrap()
+            .matches()
+            .count() as u64
+    }
+
+    fn state_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ggst-api-backfill-{}-{}.json",
+            name,
+            std::process::id()
+        ))
+    }
+
+    // Fails once the given number of matches was accepted
+    struct FailingSink(Vec<Match>, usize);
+
+    impl MatchSink for FailingSink {
+        fn accept(&mut self, m: Match) -> BoxFuture<'_, Result<()>> {
+            let result = if self.0.len() == self.1 {
+                Err(Error::SinkClosed)
+            } else {
+                self.0.push(m);
+                Ok(())
+            };
+            Box::pin(async move { result })
+        }
+    }
+
+    fn context() -> Context {
+        Context::default().transport(
+            MockTransport::new()
+                .page(0, RawResponse::ok(RESPONSE_1))
+                .page(1, RawResponse::ok(RESPONSE_3))
+                .page(2, RawResponse::ok(empty_page())),
+        )
+    }
+
+    #[tokio::test]
+    async fn resumes_and_stops_at_previous_run() {
+        let path = state_path("resume");
+        let ctx = context();
+
+        // Interrupted by the sink while writing the second page
+        let mut sink = FailingSink(vec![], count(RESPONSE_1) as usize + 1);
+        let result = run(&ctx, QueryParameters::default(), &path, &mut sink).await;
+        assert!(matches!(result, Err(Error::SinkClosed)));
+        let state = BackfillState::load(&path).await.unwrap();
+        assert_eq!(state.next_page, 1);
+        assert_eq!(state.matches_written, count(RESPONSE_1));
+
+        // Continues with the second page, which is written again completely
+        let mut sink = FailingSink(vec![], usize::MAX);
+        let report = run(&ctx, QueryParameters::default(), &path, &mut sink)
+            .await
+            .unwrap();
+        assert!(report.complete);
+        assert_eq!(report.pages_read, 2);
+        assert_eq!(report.new_matches, count(RESPONSE_3));
+        let state = BackfillState::load(&path).await.unwrap();
+        assert_eq!(state.next_page, 0);
+        assert_eq!(state.matches_written, count(RESPONSE_1) + count(RESPONSE_3));
+        assert!(state.covered_until.is_some());
+
+        // Nothing newer than the previous run
+        let report = run(&ctx, QueryParameters::default(), &path, &mut sink)
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            BackfillReport {
+                new_matches: 0,
+                pages_read: 1,
+                parse_errors: 0,
+                complete: true
+            }
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unsupported_state_version() {
+        let path = state_path("version");
+        std::fs::write(&path, r#"{"version":2,"covered_until":null,"next_page":0,"run_newest":null,"matches_written":0,"pages_read":0}"#).unwrap();
+        let result = BackfillState::load(&path).await;
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            result,
+            Err(Error::ParsingBytesError {
+                field: "backfill state version",
+                ..
+            })
+        ));
+    }
+}
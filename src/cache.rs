@@ -0,0 +1,252 @@
+//! Remembering which replays were already seen, for pollers that query the same pages repeatedly,
+//! and caching of responses that change slowly, see Context::cache.
+
+use crate::clock::{Clock, SystemClock};
+use bytes::Bytes;
+use derivative::Derivative;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Set of replay ids with a fixed capacity. Once full, inserting a new id evicts the id that was
+/// inserted or refreshed least recently. With the serde feature the cache can be persisted, ids
+/// are stored from least to most recently used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        crate = "serde_crate",
+        from = "PersistedCache",
+        into = "PersistedCache"
+    )
+)]
+pub struct ReplayIdCache {
+    capacity: usize,
+    // Id to the tick of its last use and the reverse, the first entry of by_tick is evicted next
+    ticks: HashMap<u64, u64>,
+    by_tick: BTreeMap<u64, u64>,
+    tick: u64,
+}
+
+impl ReplayIdCache {
+    /// Create an empty cache holding at most capacity ids, a capacity of 0 is raised to 1
+    pub fn new(capacity: usize) -> Self {
+        ReplayIdCache {
+            capacity: capacity.max(1),
+            ticks: HashMap::new(),
+            by_tick: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.ticks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ticks.is_empty()
+    }
+
+    /// Whether the id is in the cache, doesn't count as a use
+    pub fn contains(&self, id: u64) -> bool {
+        self.ticks.contains_key(&id)
+    }
+
+    /// Insert the id or mark it as most recently used if it is already present. Returns true if
+    /// the id was not in the cache.
+    pub fn insert(&mut self, id: u64) -> bool {
+        self.tick += 1;
+        let new = match self.ticks.insert(id, self.tick) {
+            Some(previous) => {
+                self.by_tick.remove(&previous);
+                false
+            }
+            None => true,
+        };
+        self.by_tick.insert(self.tick, id);
+        if self.ticks.len() > self.capacity {
+            if let Some((_, evicted)) = self.by_tick.pop_first() {
+                self.ticks.remove(&evicted);
+            }
+        }
+        new
+    }
+
+    /// Ids from least to most recently used
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.by_tick.values().copied()
+    }
+}
+
+/// Storage for raw response bodies, keyed by the endpoint path and the messagepack encoding of the
+/// request body. Only responses that decoded successfully are inserted, and only for endpoints
+/// with Endpoint::CACHEABLE set, which excludes the replay endpoint.
+pub trait Cache: Send + Sync {
+    /// The stored response, None if there is none or it expired
+    fn get(&self, path: &str, body: &[u8]) -> Option<Bytes>;
+    fn insert(&self, path: &'static str, body: Vec<u8>, response: Bytes);
+}
+
+/// In memory cache which forgets responses ttl after they were inserted
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct TtlCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<Vec<u8>, (Instant, Bytes)>>,
+    #[derivative(Debug = "ignore")]
+    clock: Arc<dyn Clock>,
+}
+
+// Paths don't contain a nul byte, so path and body can't run into each other
+fn key(path: &str, body: &[u8]) -> Vec<u8> {
+    [path.as_bytes(), &[0], body].concat()
+}
+
+impl TtlCache {
+    pub fn new(ttl: Duration) -> Self {
+        TtlCache::with_clock(ttl, SystemClock)
+    }
+
+    /// A cache which measures the age of responses with clock instead of the system clock
+    pub fn with_clock(ttl: Duration, clock: impl Clock + 'static) -> Self {
+        TtlCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            clock: Arc::new(clock),
+        }
+    }
+
+    /// Number of stored responses, including expired ones that weren't removed yet
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Cache for TtlCache {
+    fn get(&self, path: &str, body: &[u8]) -> Option<Bytes> {
+        let now = self.clock.instant();
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&key(path, body))
+            .filter(|(at, _)| now.saturating_duration_since(*at) < self.ttl)
+            .map(|(_, response)| response.clone())
+    }
+
+    fn insert(&self, path: &'static str, body: Vec<u8>, response: Bytes) {
+        let now = self.clock.instant();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (at, _)| now.saturating_duration_since(*at) < self.ttl);
+        entries.insert(key(path, &body), (now, response));
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct PersistedCache {
+    capacity: usize,
+    ids: Vec<u64>,
+}
+
+#[cfg(feature = "serde")]
+impl From<PersistedCache> for ReplayIdCache {
+    fn from(persisted: PersistedCache) -> Self {
+        let mut cache = ReplayIdCache::new(persisted.capacity);
+        for id in persisted.ids {
+            cache.insert(id);
+        }
+        cache
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ReplayIdCache> for PersistedCache {
+    fn from(cache: ReplayIdCache) -> Self {
+        PersistedCache {
+            capacity: cache.capacity,
+            ids: cache.iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ttl_cache_expires() {
+        let cache = TtlCache::new(Duration::from_millis(50));
+        cache.insert("/api/a", vec![1], Bytes::from_static(b"one"));
+        assert_eq!(cache.get("/api/a", &[1]).unwrap(), "one");
+        assert_eq!(cache.get("/api/a", &[2]), None);
+        assert_eq!(cache.get("/api/b", &[1]), None);
+        assert_eq!(cache.len(), 1);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get("/api/a", &[1]), None);
+        // Expired entries are dropped on the next insert
+        cache.insert("/api/b", vec![1], Bytes::from_static(b"two"));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get("/api/b", &[1]).unwrap(), "two");
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ReplayIdCache::new(3);
+        assert!(cache.insert(1));
+        assert!(cache.insert(2));
+        assert!(cache.insert(3));
+        // Refreshing 1 makes 2 the oldest entry
+        assert!(!cache.insert(1));
+        assert!(cache.insert(4));
+
+        assert_eq!(cache.len(), 3);
+        assert!(!cache.contains(2));
+        assert_eq!(cache.iter().collect::<Vec<_>>(), [3, 1, 4]);
+        // contains doesn't refresh, so 3 is evicted next
+        assert!(cache.contains(3));
+        cache.insert(5);
+        assert_eq!(cache.iter().collect::<Vec<_>>(), [1, 4, 5]);
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut cache = ReplayIdCache::new(0);
+        cache.insert(1);
+        cache.insert(2);
+        assert_eq!(cache.iter().collect::<Vec<_>>(), [2]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn persistence_round_trip() {
+        let mut cache = ReplayIdCache::new(3);
+        for id in [5, 6, 7, 5, 8] {
+            cache.insert(id);
+        }
+        let json = serde_json::to_string(&cache).unwrap();
+        assert_eq!(json, r#"{"capacity":3,"ids":[7,5,8]}"#);
+
+        let mut restored: ReplayIdCache = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.iter().collect::<Vec<_>>(), [7, 5, 8]);
+        restored.insert(9);
+        cache.insert(9);
+        assert_eq!(restored.iter().collect::<Vec<_>>(), [5, 8, 9]);
+        assert_eq!(
+            restored.iter().collect::<Vec<_>>(),
+            cache.iter().collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! Tracking which players currently qualify for the celestial floor, combining their profiles with
+//! the matches they played there.
+
+use crate::model::user::User;
+use crate::requests::get_user;
+use crate::{error::*, Context, Floor, Match};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashSet};
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum Qualification {
+    Qualified,
+    NotQualified,
+    /// Neither a profile nor a match of the player was seen
+    Unknown,
+}
+
+/// What is known about one tracked player
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct CelestialStatus {
+    pub player_id: i64,
+    /// Latest profile from the statistics endpoint, with the RankCheck and VipCheck progress in
+    /// User::celestial
+    pub profile: Option<User>,
+    /// When profile was fetched
+    pub refreshed: Option<DateTime<Utc>>,
+    /// Floor of the newest match of the player
+    pub last_match_floor: Option<(DateTime<Utc>, Floor)>,
+    /// Matches of the player on the celestial floor in chronological order
+    pub matches: Vec<Match>,
+}
+
+impl CelestialStatus {
+    fn new(player_id: i64) -> Self {
+        CelestialStatus {
+            player_id,
+            profile: None,
+            refreshed: None,
+            last_match_floor: None,
+            matches: vec![],
+        }
+    }
+
+    /// The floor of the profile decides, without a profile the floor of the newest match is used
+    pub fn qualification(&self) -> Qualification {
+        let floor = match (&self.profile, self.last_match_floor) {
+            (Some(profile), _) => profile.floor,
+            (None, Some((_, floor))) => floor,
+            (None, None) => return Qualification::Unknown,
+        };
+        if floor == Floor::Celestial {
+            Qualification::Qualified
+        } else {
+            Qualification::NotQualified
+        }
+    }
+}
+
+/// Qualification of a fixed set of players. Feed it replays with observe or extend and refresh
+/// the profiles with refresh.
+#[derive(Debug, Clone, Default)]
+pub struct CelestialTracker {
+    players: BTreeMap<i64, CelestialStatus>,
+    seen: HashSet<u64>,
+}
+
+impl CelestialTracker {
+    pub fn new(player_ids: impl IntoIterator<Item = i64>) -> Self {
+        CelestialTracker {
+            players: player_ids
+                .into_iter()
+                .map(|id| (id, CelestialStatus::new(id)))
+                .collect(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Record a match, ignored if no tracked player took part or the replay was already observed
+    pub fn observe(&mut self, m: &Match) {
+        let (p1, p2) = m.players();
+        if !(self.players.contains_key(&p1.id) || self.players.contains_key(&p2.id))
+            || !self.seen.insert(m.id)
+        {
+            return;
+        }
+        for id in [p1.id, p2.id] {
+            let Some(status) = self.players.get_mut(&id) else {
+                continue;
+            };
+            if status
+                .last_match_floor
+                .is_none_or(|(timestamp, _)| timestamp <= m.timestamp)
+            {
+                status.last_match_floor = Some((m.timestamp, m.floor));
+            }
+            if m.floor == Floor::Celestial {
+                let index = status
+                    .matches
+                    .partition_point(|other| other.timestamp <= m.timestamp);
+                status.matches.insert(index, m.clone());
+            }
+        }
+    }
+
+    /// Store a profile fetched elsewhere, ignored for players that aren't tracked
+    pub fn update_profile(&mut self, profile: User, refreshed: DateTime<Utc>) {
+        if let Some(status) = self.players.get_mut(&profile.id) {
+            status.profile = Some(profile);
+            status.refreshed = Some(refreshed);
+        }
+    }
+
+    /// Fetch the profile of every tracked player from the statistics endpoint. A failed request
+    /// keeps the previous profile of that player, the errors are returned with the player id.
+    pub async fn refresh(&mut self, context: &Context) -> Vec<(i64, Error)> {
+        let mut errors = vec![];
+        let ids = self.players.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            match get_user(context, id).await {
+                Ok(profile) => {
+                    let status = self.players.get_mut(&id).expect("tracked player");
+                    status.profile = Some(profile);
+                    status.refreshed = Some(context.now());
+                }
+                Err(e) => errors.push((id, e)),
+            }
+        }
+        errors
+    }
+
+    pub fn get(&self, player_id: i64) -> Option<&CelestialStatus> {
+        self.players.get(&player_id)
+    }
+
+    /// Tracked players in order of their id
+    pub fn iter(&self) -> impl Iterator<Item = &CelestialStatus> {
+        self.players.values()
+    }
+
+    /// Ids of the players that are currently qualified
+    pub fn qualified(&self) -> Vec<i64> {
+        self.iter()
+            .filter(|status| status.qualification() == Qualification::Qualified)
+            .map(|status| status.player_id)
+            .collect()
+    }
+}
+
+impl<'a> Extend<&'a Match> for CelestialTracker {
+    fn extend<T: IntoIterator<Item = &'a Match>>(&mut self, iter: T) {
+        for m in iter {
+            self.observe(m);
+        }
+    }
+}
+
+impl Extend<Match> for CelestialTracker {
+    fn extend<T: IntoIterator<Item = Match>>(&mut self, iter: T) {
+        for m in iter {
+            self.observe(&m);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Character, Player};
+
+    fn game(minute: i64, floor: Floor, players: (i64, i64)) -> Match {
+        let player = |id| Player::test(id, Character::Sol);
+        Match {
+            floor,
+            ..Match::test(
+                minute as u64,
+                minute * 60,
+                (player(players.0), player(players.1)),
+            )
+        }
+    }
+
+    fn profile(id: i64, floor: u8) -> User {
+        User::from_statistics(&serde_json::json!({
+            "UserID": id,
+            "NickName": "GG Player",
+            "PublicComment": "",
+            "LobbyRank": floor,
+            "MaxLobbyRank": 99,
+            "PrevLobbyRank": 10,
+            "TotalPlayTime": 0,
+            "TotalRankMatch": 0,
+            "PlayerWinChainNow": 0,
+            "PlayerWinChainMax": 0,
+            "VipCheckMatch": 4,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_and_profiles() {
+        let mut tracker = CelestialTracker::new([1, 2, 3, 4]);
+        let matches = [
+            game(3, Floor::Celestial, (1, 2)),
+            game(0, Floor::F10, (1, 5)),
+            game(1, Floor::Celestial, (1, 5)),
+            game(2, Floor::F10, (2, 6)),
+            game(4, Floor::Celestial, (7, 8)),
+        ];
+        tracker.extend(&matches);
+        tracker.extend(&matches[..1]);
+
+        let contributed = |id| {
+            tracker
+                .get(id)
+                .unwrap()
+                .matches
+                .iter()
+                .map(|m| m.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(contributed(1), [1, 3]);
+        assert_eq!(contributed(2), [3]);
+        assert!(contributed(3).is_empty());
+        assert_eq!(tracker.qualified(), [1, 2]);
+        assert_eq!(
+            tracker.get(3).unwrap().qualification(),
+            Qualification::Unknown
+        );
+        assert!(tracker.get(7).is_none());
+
+        // The profile wins over the matches
+        let now = Utc::now();
+        tracker.update_profile(profile(2, 10), now);
+        tracker.update_profile(profile(3, 99), now);
+        tracker.update_profile(profile(9, 99), now);
+        assert_eq!(tracker.qualified(), [1, 3]);
+        let status = tracker.get(3).unwrap();
+        assert_eq!(status.refreshed, Some(now));
+        assert_eq!(status.profile.as_ref().unwrap().celestial.vip_attempts, 4);
+        assert_eq!(tracker.iter().count(), 4);
+    }
+
+    #[tokio::test]
+    async fn refresh_errors() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![MockResponse::new(200, b"\x92\x01".to_vec())]).await;
+        let context = Context::new(server.url());
+        let mut tracker = CelestialTracker::new([1, 2]);
+        tracker.update_profile(profile(1, 99), Utc::now());
+        let errors = tracker.refresh(&context).await;
+        assert_eq!(errors.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(server.requests(), 2);
+        assert_eq!(tracker.qualified(), [1]);
+    }
+}
@@ -0,0 +1,134 @@
+//! Source of the current time and of delays, see Context::clock. Everything that depends on the
+//! time, retry delays, the page delay, nonces, the freshness window of a LiveFeed and the ttl of
+//! shared requests, reads it from the clock of the context.
+
+use crate::transport::BoxFuture;
+use chrono::{DateTime, Utc};
+use std::time::{Duration, Instant};
+
+/// Current time for dates and for measuring durations, and a way to wait. Replace it with a
+/// MockClock to test time dependent behavior without waiting.
+pub trait Clock: Send + Sync {
+    /// Current wall clock time, e.g. to compare with the dates of the server
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Current monotonic time, only used to measure durations
+    fn instant(&self) -> Instant;
+
+    /// Wait for duration
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()>;
+}
+
+/// The clock of the system and the timer of tokio, used by Context::default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A clock which only moves when it is told to. Sleeping advances it by the duration instead of
+/// waiting, the durations are recorded for tests to check.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    state: std::sync::Mutex<MockState>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug)]
+struct MockState {
+    now: DateTime<Utc>,
+    elapsed: Duration,
+    sleeps: Vec<Duration>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MockClock {
+    /// A clock standing at now
+    pub fn new(now: DateTime<Utc>) -> Self {
+        MockClock {
+            start: Instant::now(),
+            state: std::sync::Mutex::new(MockState {
+                now,
+                elapsed: Duration::ZERO,
+                sleeps: vec![],
+            }),
+        }
+    }
+
+    /// Move the clock forward by duration
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now = chrono::Duration::from_std(duration)
+            .ok()
+            .and_then(|duration| state.now.checked_add_signed(duration))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        state.elapsed += duration;
+    }
+
+    /// Every duration passed to sleep so far, in order
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.state.lock().unwrap().sleeps.clone()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.state.lock().unwrap().now
+    }
+
+    fn instant(&self) -> Instant {
+        self.start + self.state.lock().unwrap().elapsed
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()> {
+        self.state.lock().unwrap().sleeps.push(duration);
+        self.advance(duration);
+        // Yield so that a loop sleeping on the clock doesn't starve other tasks
+        Box::pin(tokio::task::yield_now())
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for std::sync::Arc<C> {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+
+    fn instant(&self) -> Instant {
+        (**self).instant()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()> {
+        (**self).sleep(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mock_clock_advances_on_sleep() {
+        let start = "2022-02-06T10:45:23Z".parse::<DateTime<Utc>>().unwrap();
+        let clock = MockClock::new(start);
+        let instant = clock.instant();
+        clock.sleep(Duration::from_secs(30)).await;
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(clock.now(), start + chrono::Duration::milliseconds(30_500));
+        assert_eq!(clock.instant() - instant, Duration::from_millis(30_500));
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(30)]);
+    }
+}
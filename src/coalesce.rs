@@ -0,0 +1,225 @@
+//! Sharing one request between identical calls, see Context::coalesce_requests.
+
+use crate::clock::Clock;
+use crate::error::*;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+// Endpoint path and encoded request body. The header is left out so that requests only differing
+// in the player or token of the header are shared.
+pub(crate) type Key = (&'static str, Vec<u8>);
+
+// Status, body and the time it took to read the body
+type Response = (u16, Bytes, Option<Duration>);
+
+enum Entry {
+    InFlight(watch::Receiver<Option<Response>>),
+    Done(Instant, Response),
+}
+
+/// Identical requests issued while one of them is in flight wait for its response instead of being
+/// sent again, and successful responses are reused for ttl. Failed requests are never shared,
+/// waiting callers then send the request themselves.
+pub(crate) struct Coalescer {
+    ttl: Duration,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+// Removes the in flight entry if the sending future is dropped or fails, so that waiting callers
+// take over
+struct Leader<'a> {
+    coalescer: &'a Coalescer,
+    key: Option<Key>,
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.coalescer.entries.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+impl Coalescer {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Coalescer {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run send unless an identical request is in flight or was answered less than ttl ago by
+    /// clock
+    pub(crate) async fn run<F, Fut>(&self, key: Key, clock: &dyn Clock, send: F) -> Result<Response>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Response>>,
+    {
+        let sender = loop {
+            let mut receiver = {
+                let now = clock.instant();
+                let mut entries = self.entries.lock().unwrap();
+                entries.retain(|_, entry| match entry {
+                    Entry::InFlight(_) => true,
+                    Entry::Done(at, _) => now.saturating_duration_since(*at) < self.ttl,
+                });
+                match entries.get(&key) {
+                    Some(Entry::Done(_, response)) => return Ok(response.clone()),
+                    Some(Entry::InFlight(receiver)) => receiver.clone(),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        entries.insert(key.clone(), Entry::InFlight(receiver));
+                        break sender;
+                    }
+                }
+            };
+            // An error means the request failed or was dropped, try again as the leader
+            while receiver.changed().await.is_ok() {
+                if let Some(response) = receiver.borrow().clone() {
+                    return Ok(response);
+                }
+            }
+        };
+
+        let mut leader = Leader {
+            coalescer: self,
+            key: Some(key),
+        };
+        let result = send().await;
+        if let Ok(response) = &result {
+            let key = leader.key.take().expect("key of the leader");
+            let mut entries = self.entries.lock().unwrap();
+            if self.ttl.is_zero() {
+                entries.remove(&key);
+            } else {
+                entries.insert(key, Entry::Done(clock.instant(), response.clone()));
+            }
+            let _ = sender.send(Some(response.clone()));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::{endpoints, Endpoint};
+    use crate::transport::{BoxFuture, RawResponse, Transport};
+    use crate::{get_replays, Context, QueryParameters};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const REPLAY_RESPONSE: &[u8] = include_bytes!("../test_data/replay_response_1.msgpack");
+
+    // Answers every request after a delay and counts what was actually sent
+    #[derive(Default)]
+    struct SlowTransport {
+        sent: AtomicUsize,
+        fail: bool,
+    }
+
+    impl Transport for SlowTransport {
+        fn post<'a>(
+            &'a self,
+            _path: &'static str,
+            _data: &'a str,
+        ) -> BoxFuture<'a, Result<RawResponse>> {
+            Box::pin(async move {
+                self.sent.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                if self.fail {
+                    Ok(RawResponse::new(503, "maintenance"))
+                } else {
+                    Ok(RawResponse::ok(REPLAY_RESPONSE))
+                }
+            })
+        }
+    }
+
+    fn context(transport: &Arc<SlowTransport>, ttl: Duration) -> Context {
+        Context::default()
+            .transport(transport.clone())
+            .retry_policy(crate::RetryPolicy::none())
+            .coalesce_requests(ttl)
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_queries() {
+        let transport = Arc::new(SlowTransport::default());
+        let context = context(&transport, Duration::ZERO);
+        let (a, b, c) = tokio::join!(
+            get_replays(&context, 1, 10, QueryParameters::default()),
+            get_replays(&context, 1, 10, QueryParameters::default()),
+            get_replays(&context, 1, 20, QueryParameters::default()),
+        );
+        assert_eq!(a.unwrap().matches().count(), b.unwrap().matches().count());
+        assert!(c.is_ok());
+        // The third query asks for a different page size
+        assert_eq!(transport.sent.load(Ordering::SeqCst), 2);
+
+        // Without a ttl nothing is kept once the requests are done
+        get_replays(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert_eq!(transport.sent.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn completed_responses_expire() {
+        let transport = Arc::new(SlowTransport::default());
+        let context = context(&transport, Duration::from_millis(200));
+        for _ in 0..3 {
+            get_replays(&context, 1, 10, QueryParameters::default())
+                .await
+                .unwrap();
+        }
+        assert_eq!(transport.sent.load(Ordering::SeqCst), 1);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        get_replays(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert_eq!(transport.sent.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failures_are_not_shared() {
+        let transport = Arc::new(SlowTransport {
+            fail: true,
+            ..Default::default()
+        });
+        let context = context(&transport, Duration::from_secs(60));
+        let (a, b) = tokio::join!(
+            get_replays(&context, 1, 10, QueryParameters::default()),
+            get_replays(&context, 1, 10, QueryParameters::default()),
+        );
+        assert!(matches!(a, Err(Error::HttpStatus { status: 503, .. })));
+        assert!(matches!(b, Err(Error::HttpStatus { status: 503, .. })));
+        // The waiting call sends its own request once the first one failed
+        assert_eq!(transport.sent.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn dropped_leader() {
+        let coalescer = Coalescer::new(Duration::from_secs(60));
+        let key: Key = (endpoints::Replays::PATH, vec![1]);
+        let pending = coalescer.run(
+            key.clone(),
+            &crate::clock::SystemClock,
+            std::future::pending,
+        );
+        assert!(tokio::time::timeout(Duration::from_millis(10), pending)
+            .await
+            .is_err());
+        let response = coalescer
+            .run(key, &crate::clock::SystemClock, || async {
+                Ok((200, Bytes::from_static(b"ok"), None))
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.1, "ok");
+    }
+}
@@ -0,0 +1,97 @@
+//! The signatures of 0.x for crates migrating to ReplaySet, enabled by the compat-0 feature. They
+//! are implemented on top of the current functions and will be removed in a later release.
+
+use crate::{error::*, get_replays_by_page, Context, Match, ParseError, QueryParameters};
+use std::collections::BTreeSet;
+
+/// get_replays as of 0.x, returning the matches in ascending order and the errors in the order of
+/// the pages. Like before, matches are only deduplicated if they are equal, the first decoded one
+/// is kept, and a failed request fails the whole call.
+#[deprecated(
+    since = "0.3.0",
+    note = "use ggst_api::get_replays and ReplaySet::into_parts, or ReplaySet::matches and ReplaySet::errors"
+)]
+pub async fn get_replays<A, B, C, D, E>(
+    context: &Context,
+    pages: usize,
+    replays_per_page: usize,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+) -> Result<(
+    impl Iterator<Item = Match>,
+    impl Iterator<Item = ParseError>,
+)> {
+    let mut matches = BTreeSet::new();
+    let mut errors = vec![];
+    for page in get_replays_by_page(context, pages, replays_per_page, request_parameters).await? {
+        for m in page.matches {
+            // BTreeSet::insert keeps the element already present
+            matches.insert(m);
+        }
+        errors.extend(page.errors);
+    }
+    Ok((matches.into_iter(), errors.into_iter()))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(deprecated)]
+
+    use super::*;
+    use crate::transport::{MockTransport, RawResponse};
+    use crate::{Error, Floor};
+
+    fn context() -> Context {
+        Context::default().transport(
+            MockTransport::new()
+                .page(
+                    0,
+                    RawResponse::ok(&include_bytes!("../test_data/replay_response_1.msgpack")[..]),
+                )
+                .page(
+                    1,
+                    RawResponse::ok(&include_bytes!("../test_data/replay_response_3.msgpack")[..]),
+                )
+                .page(2, RawResponse::ok(&b"not messagepack"[..])),
+        )
+    }
+
+    #[tokio::test]
+    async fn same_as_current_api() {
+        let context = context();
+        let (matches, errors) = get_replays(&context, 3, 127, QueryParameters::default())
+            .await
+            .unwrap();
+        let (current_matches, current_errors) =
+            crate::get_replays(&context, 3, 127, QueryParameters::default())
+                .await
+                .unwrap()
+                .into_parts();
+        let matches = matches.collect::<Vec<_>>();
+        assert!(matches.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(matches, current_matches.collect::<Vec<_>>());
+        let errors = errors.map(|e| e.raw_bytes().to_vec()).collect::<Vec<_>>();
+        assert_eq!(errors, [b"not messagepack".to_vec()]);
+        assert_eq!(
+            errors,
+            current_errors
+                .map(|e| e.raw_bytes().to_vec())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn same_errors() {
+        let context = context();
+        let invalid = QueryParameters::default()
+            .min_floor(Floor::Celestial)
+            .max_floor(Floor::F1);
+        assert!(matches!(
+            get_replays(&context, 1, 127, invalid).await,
+            Err(Error::InvalidArgument { .. })
+        ));
+        // Pages the mock doesn't know fail the request, which fails the whole call
+        assert!(get_replays(&context, 4, 127, QueryParameters::default())
+            .await
+            .is_err());
+    }
+}
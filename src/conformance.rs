@@ -0,0 +1,103 @@
+//! Known good request and response pairs, for contract tests of proxies and other
+//! implementations of the API. Only available with the test-util feature.
+//!
+//! The responses are the captures the tests of this crate are based on. The requests that produced
+//! them weren't captured, request_hex is the request this crate sends for the page with the default
+//! query. A proxy can send request_hex through itself, answer with response_bytes and check that
+//! decode_replay_response returns expected_matches.
+
+use crate::requests::replay_request_hex;
+use crate::requests::{endpoints, Endpoint};
+use crate::*;
+
+const RESPONSES: [(&str, &[u8]); 4] = [
+    (
+        "replay_response_1",
+        include_bytes!("../test_data/replay_response_1.msgpack"),
+    ),
+    (
+        "replay_response_2",
+        include_bytes!("../test_data/replay_response_2.msgpack"),
+    ),
+    (
+        "replay_response_3",
+        include_bytes!("../test_data/replay_response_3.msgpack"),
+    ),
+    (
+        "replay_response_4",
+        include_bytes!("../test_data/replay_response_4.msgpack"),
+    ),
+];
+
+#[derive(Debug, Clone)]
+pub struct ConformanceCase {
+    /// Name of the capture, stable across versions
+    pub name: &'static str,
+    /// Path of the endpoint the request is posted to
+    pub path: &'static str,
+    /// Hex encoded request as sent in the data field of the form
+    pub request_hex: String,
+    /// Raw response body
+    pub response_bytes: &'static [u8],
+    /// Matches decoded with DecodeOptions::default, in the order of ReplaySet::matches
+    pub expected_matches: Vec<Match>,
+    /// Replays of the response that can't be converted to a match
+    pub expected_errors: usize,
+}
+
+/// One case per captured replay response
+pub fn cases() -> Vec<ConformanceCase> {
+    RESPONSES
+        .iter()
+        .enumerate()
+        .map(|(index, &(name, response_bytes))| {
+            let replays = decode_replay_response(response_bytes, DecodeOptions::default())
+                .expect("fixtures are valid replay responses");
+            ConformanceCase {
+                name,
+                path: endpoints::Replays::PATH,
+                request_hex: replay_request_hex(index),
+                response_bytes,
+                expected_matches: replays.matches().cloned().collect(),
+                expected_errors: replays.errors().len(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_cases() {
+        let cases = cases();
+        let summary = cases
+            .iter()
+            .map(|case| {
+                format!(
+                    "{} {} matches, {} errors, first {:?}",
+                    case.name,
+                    case.expected_matches.len(),
+                    case.expected_errors,
+                    case.expected_matches.first().map(|m| m.id),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        expect_test::expect![[r#"
+            replay_response_1 30 matches, 0 errors, first Some(220205225521028492)
+            replay_response_2 10 matches, 0 errors, first Some(220205221432167826)
+            replay_response_3 10 matches, 0 errors, first Some(210622214920900310)
+            replay_response_4 20 matches, 0 errors, first Some(220206103142482599)"#]]
+        .assert_eq(&summary);
+
+        // The request decodes to the page of the case
+        for (index, case) in cases.iter().enumerate() {
+            let bytes = crate::hex::decode(&case.request_hex).unwrap();
+            let request: messagepack::Request<messagepack::RequestBody> =
+                rmp_serde::from_slice(&bytes).unwrap();
+            assert_eq!(request.body.index, index);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Detection of duplicate records of the same match, e.g. rows produced by desyncs and reconnects
+//! or archives holding both the wrong and the corrected record of a match.
+
+use crate::{Character, Floor, Match};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// Two matches with the same players, characters and floor played within the window of each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suspect {
+    /// Index of the older match in the input
+    pub first: usize,
+    /// Index of the newer match in the input
+    pub second: usize,
+    /// Whether the two records disagree on who won
+    pub winner_differs: bool,
+}
+
+/// What resolve does with suspected duplicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Drop the older match of every suspect pair
+    KeepLatest,
+    /// Keep every match, duplicates are only reported by suspects
+    KeepBoth,
+    /// Only drop the older match of pairs that disagree on the winner, i.e. records that were
+    /// corrected later, and keep pairs with the same winner
+    PreferDifferingWinner,
+}
+
+// Players with their characters in a fixed order so that swapped sides compare equal
+fn key(m: &Match) -> (Floor, [(i64, Character); 2]) {
+    let mut players = [
+        (m.players.0.id, m.players.0.character),
+        (m.players.1.id, m.players.1.character),
+    ];
+    players.sort();
+    (m.floor, players)
+}
+
+/// Find pairs of matches that are likely records of the same match. The matches don't have to be
+/// sorted, Suspect refers to them by index. Pairs are ordered by the index of the older match.
+pub fn suspects(matches: &[Match], window: Duration) -> Vec<Suspect> {
+    let window = chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX);
+    let mut order = (0..matches.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| (matches[i].timestamp, i));
+
+    let mut suspects = vec![];
+    for (position, &first) in order.iter().enumerate() {
+        let m = &matches[first];
+        for &second in &order[position + 1..] {
+            let other = &matches[second];
+            if other.timestamp - m.timestamp > window {
+                break;
+            }
+            if key(m) == key(other) {
+                suspects.push(Suspect {
+                    first,
+                    second,
+                    winner_differs: m.try_winner().map(|p| p.id)
+                        != other.try_winner().map(|p| p.id),
+                });
+            }
+        }
+    }
+    suspects.sort_by_key(|s| (s.first, s.second));
+    suspects
+}
+
+/// Remove suspected duplicates according to the resolution, the order of the remaining matches is
+/// kept
+pub fn resolve(matches: Vec<Match>, window: Duration, resolution: Resolution) -> Vec<Match> {
+    let dropped = suspects(&matches, window)
+        .into_iter()
+        .filter(|suspect| match resolution {
+            Resolution::KeepLatest => true,
+            Resolution::KeepBoth => false,
+            Resolution::PreferDifferingWinner => suspect.winner_differs,
+        })
+        .map(|suspect| suspect.first)
+        .collect::<BTreeSet<_>>();
+    matches
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !dropped.contains(i))
+        .map(|(_, m)| m)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Player, Winner};
+
+    const WINDOW: Duration = Duration::from_secs(30);
+
+    fn game(id: u64, second: i64, floor: Floor, winner: Winner) -> Match {
+        let players = (
+            Player::test(1, Character::Sol),
+            Player::test(2, Character::Ky),
+        );
+        Match {
+            floor,
+            winner,
+            ..Match::test(id, second, players)
+        }
+    }
+
+    fn ids(matches: &[Match]) -> Vec<u64> {
+        matches.iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn find_suspects() {
+        let matches = vec![
+            // Reconnect duplicate with swapped sides
+            game(1, 0, Floor::F10, Winner::Player1),
+            game(2, 5, Floor::F10, Winner::Player1).swapped(),
+            // Corrected winner
+            game(3, 200, Floor::F10, Winner::Player1),
+            game(4, 210, Floor::F10, Winner::Player2),
+            // Different floor or too far apart
+            game(5, 215, Floor::F9, Winner::Player2),
+            game(6, 300, Floor::F10, Winner::Player2),
+        ];
+        assert_eq!(
+            suspects(&matches, WINDOW),
+            [
+                Suspect {
+                    first: 0,
+                    second: 1,
+                    winner_differs: false
+                },
+                Suspect {
+                    first: 2,
+                    second: 3,
+                    winner_differs: true
+                },
+            ]
+        );
+
+        assert_eq!(
+            ids(&resolve(matches.clone(), WINDOW, Resolution::KeepLatest)),
+            [2, 4, 5, 6]
+        );
+        assert_eq!(
+            ids(&resolve(matches.clone(), WINDOW, Resolution::KeepBoth)),
+            [1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(
+            ids(&resolve(matches, WINDOW, Resolution::PreferDifferingWinner)),
+            [1, 2, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn unsorted_input() {
+        let matches = vec![
+            game(2, 10, Floor::F10, Winner::Player1),
+            game(1, 0, Floor::F10, Winner::Player1),
+        ];
+        assert_eq!(
+            suspects(&matches, WINDOW),
+            [Suspect {
+                first: 1,
+                second: 0,
+                winner_differs: false
+            }]
+        );
+        assert_eq!(ids(&resolve(matches, WINDOW, Resolution::KeepLatest)), [2]);
+    }
+}
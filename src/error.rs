@@ -1,36 +1,218 @@
 use std::{
     error,
     fmt::{self, Display},
+    time::Duration,
 };
+/// Errors returned by this crate. New variants may be added in minor releases, so matches must
+/// include a wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     ReqwestError(reqwest::Error),
     ChronoParseError(chrono::ParseError),
-    ParsingBytesError(&'static str),
+    /// A decoded field could not be converted into its public representation
+    ParsingBytesError {
+        field: &'static str,
+        value: String,
+    },
     UnexpectedResponse(&'static str),
-    InvalidCharacterCode(&'static str),
-    InvalidArgument(String),
-    InvalidMessagePack(rmp_serde::decode::Error),
+    InvalidCharacterCode(String),
+    /// The winner byte of a replay is neither 1 nor 2, only an error in DecodeMode::Strict
+    InvalidWinner(u8),
+    /// A parameter was outside the range accepted by the API
+    InvalidArgument {
+        parameter: &'static str,
+        value: String,
+        /// The message shown by Display, it names the value itself
+        reason: String,
+    },
+    /// The response could not be decoded as messagepack of the expected shape
+    Decode(rmp_serde::decode::Error),
+    /// A value could not be encoded as messagepack
+    Encode(rmp_serde::encode::Error),
+    /// The server rejected the request and replied with an error code and message instead of the
+    /// expected response
+    Api {
+        code: i64,
+        message: String,
+    },
+    /// The server is throttling requests, retry_after holds the delay it asked for if any
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    /// The server answered with an HTML page, usually an error page from a proxy or CDN
+    UnexpectedHtml {
+        status: u16,
+        snippet: String,
+        /// Headers useful to correlate the failure with server or proxy logs
+        headers: Vec<(String, String)>,
+    },
+    /// The server answered with an empty body
+    EmptyResponse,
+    /// The server answered with a status code other than success
+    HttpStatus {
+        status: u16,
+        body_snippet: String,
+        /// Headers useful to correlate the failure with server or proxy logs
+        headers: Vec<(String, String)>,
+    },
+    /// Reading or writing recorded traffic or a sink failed
+    Io(std::io::Error),
+    /// The receiving end of a sink was dropped
+    SinkClosed,
+    /// A transport failed for a reason of its own, e.g. a middleware rejected the request
+    Transport(Box<dyn error::Error + Send + Sync>),
+    /// Writing or reading an export format such as parquet failed
+    Export(Box<dyn error::Error + Send + Sync>),
+    /// The player search mode only returns results for the player making the request, but the
+    /// context has no credentials, see Context::credentials
+    CredentialsRequired {
+        mode: crate::messagepack::PlayerSearch,
+    },
+    /// The response exceeded a limit of the decoder, see requests::MAX_RESPONSE_BYTES and
+    /// requests::MAX_DECODE_DEPTH. Requesting fewer replays per page keeps responses smaller.
+    ResponseTooLarge {
+        bytes: usize,
+        limit: &'static str,
+    },
+    /// Both slots of a replay hold the player with this id, seen in corrupted rows. Such replays
+    /// are skipped in every DecodeMode since the winner can't be told apart from the loser.
+    DuplicatePlayer {
+        id: String,
+    },
+    /// The context is in dry run mode, nothing was sent. Holds the plan of the request.
+    DryRun(Box<crate::requests::RequestPlan>),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Whether the error is likely transient so that repeating the same request may succeed.
+    /// Timeouts, connection failures, server errors and rate limiting are retryable, while
+    /// invalid arguments, decoding failures and requests rejected by the API are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::ReqwestError(e) => {
+                e.is_timeout()
+                    || e.is_connect()
+                    || e.is_request()
+                    || e.status().is_some_and(|status| status.is_server_error())
+            }
+            Error::RateLimited { .. } => true,
+            Error::HttpStatus { status, .. } | Error::UnexpectedHtml { status, .. } => {
+                is_transient_status(*status)
+            }
+            Error::ChronoParseError(_)
+            | Error::ParsingBytesError { .. }
+            | Error::UnexpectedResponse(_)
+            | Error::InvalidCharacterCode(_)
+            | Error::InvalidWinner(_)
+            | Error::InvalidArgument { .. }
+            | Error::Decode(_)
+            | Error::Encode(_)
+            | Error::Api { .. }
+            | Error::EmptyResponse
+            | Error::Io(_)
+            | Error::SinkClosed
+            | Error::Transport(_)
+            | Error::Export(_)
+            | Error::CredentialsRequired { .. }
+            | Error::ResponseTooLarge { .. }
+            | Error::DuplicatePlayer { .. }
+            | Error::DryRun(_) => false,
+        }
+    }
+}
+
+// Server errors, gateway failures (which includes maintenance pages) and request timeouts
+fn is_transient_status(status: u16) -> bool {
+    status == 408 || status == 429 || (500..600).contains(&status)
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::ReqwestError(e) => write!(f, "Error making request: {}", e),
-            Error::ChronoParseError(e) => write!(f, "Error parsing datetime: {}", e),
-            Error::ParsingBytesError(msg) => write!(f, "{}", msg),
+            // Variants wrapping another error leave it to source(), so that reports walking the
+            // chain don't print it twice
+            Error::ReqwestError(_) => write!(f, "Error making request"),
+            Error::ChronoParseError(_) => write!(f, "Error parsing datetime"),
+            Error::ParsingBytesError { field, value } => {
+                write!(f, "could not parse {} from {:?}", field, value)
+            }
             Error::UnexpectedResponse(msg) => {
                 write!(f, "Unexpected response from API, {}", msg)
             }
             Error::InvalidCharacterCode(code) => write!(f, "{} is not valid character code", code),
-            Error::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
-            Error::InvalidMessagePack(msg) => write!(f, "Invalid messagepack: {}", msg),
+            Error::InvalidWinner(_) => write!(f, "Could not parse winner"),
+            Error::InvalidArgument { reason, .. } => write!(f, "Invalid argument: {}", reason),
+            Error::Decode(_) => write!(f, "Invalid messagepack"),
+            Error::Encode(_) => write!(f, "Could not encode messagepack"),
+            Error::Api { code, message } => {
+                write!(
+                    f,
+                    "API rejected the request with code {}: {}",
+                    code, message
+                )
+            }
+            Error::RateLimited {
+                retry_after: Some(delay),
+            } => write!(f, "Rate limited, retry after {}s", delay.as_secs()),
+            Error::RateLimited { retry_after: None } => write!(f, "Rate limited"),
+            Error::UnexpectedHtml {
+                status,
+                snippet,
+                headers,
+            } => {
+                write!(f, "Unexpected HTML response with status {}", status)?;
+                write_headers(f, headers)?;
+                write!(f, ": {}", snippet)
+            }
+            Error::EmptyResponse => write!(f, "Unexpected empty response from API"),
+            Error::HttpStatus {
+                status,
+                body_snippet,
+                headers,
+            } => {
+                write!(f, "HTTP status {}", status)?;
+                write_headers(f, headers)?;
+                write!(f, ": {}", body_snippet)
+            }
+            Error::Io(_) => write!(f, "I/O error"),
+            Error::SinkClosed => write!(f, "The receiver of the sink was dropped"),
+            Error::Transport(_) => write!(f, "Transport error"),
+            Error::Export(_) => write!(f, "Export error"),
+            Error::CredentialsRequired { mode } => write!(
+                f,
+                "Player search {:?} requires credentials, see Context::credentials",
+                mode
+            ),
+            Error::ResponseTooLarge { bytes, limit } => write!(
+                f,
+                "Response of {} bytes exceeds the {} limit, request fewer replays per page",
+                bytes, limit
+            ),
+            Error::DuplicatePlayer { id } => {
+                write!(f, "Replay has player {} on both sides", id)
+            }
+            Error::DryRun(plan) => write!(f, "Dry run, no request sent to {}", plan.url),
         }
     }
 }
 
+fn write_headers(f: &mut fmt::Formatter<'_>, headers: &[(String, String)]) -> fmt::Result {
+    if headers.is_empty() {
+        return Ok(());
+    }
+    write!(f, " (")?;
+    for (i, (name, value)) in headers.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}: {}", name, value)?;
+    }
+    write!(f, ")")
+}
+
 impl From<reqwest::Error> for Error {
     fn from(e: reqwest::Error) -> Self {
         Error::ReqwestError(e)
@@ -43,37 +225,347 @@ impl From<chrono::ParseError> for Error {
     }
 }
 
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        Error::Encode(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
 impl From<rmp_serde::decode::Error> for Error {
     fn from(e: rmp_serde::decode::Error) -> Self {
-        Error::InvalidMessagePack(e)
+        Error::Decode(e)
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::ReqwestError(e) => Some(e),
+            Error::ChronoParseError(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            Error::Encode(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Transport(e) | Error::Export(e) => Some(&**e),
+            Error::ParsingBytesError { .. }
+            | Error::UnexpectedResponse(_)
+            | Error::InvalidCharacterCode(_)
+            | Error::InvalidWinner(_)
+            | Error::InvalidArgument { .. }
+            | Error::Api { .. }
+            | Error::RateLimited { .. }
+            | Error::UnexpectedHtml { .. }
+            | Error::EmptyResponse
+            | Error::HttpStatus { .. }
+            | Error::SinkClosed
+            | Error::CredentialsRequired { .. }
+            | Error::ResponseTooLarge { .. }
+            | Error::DuplicatePlayer { .. }
+            | Error::DryRun(_) => None,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct ParseError {
-    reply_content: String,
+    raw: Vec<u8>,
+    // Boxed to keep results carrying a ParseError small
+    details: Box<Details>,
     inner: Error,
 }
 
+#[derive(Debug, Default)]
+struct Details {
+    status: Option<u16>,
+    response_id: Option<crate::messagepack::ResponseId>,
+    path: Option<String>,
+    floor_byte: Option<u8>,
+}
+
 impl ParseError {
-    pub fn new(reply_content: String, inner: Error) -> Self {
+    /// Create a parse error from the raw bytes that failed to parse and the cause
+    pub fn new(raw: impl Into<Vec<u8>>, inner: Error) -> Self {
         ParseError {
-            reply_content,
+            raw: raw.into(),
+            details: Box::default(),
             inner,
         }
     }
+
+    pub(crate) fn with_status(mut self, status: u16) -> Self {
+        self.details.status = Some(status);
+        self
+    }
+
+    pub(crate) fn with_response_id(mut self, id: crate::messagepack::ResponseId) -> Self {
+        self.details.response_id = Some(id);
+        self
+    }
+
+    pub(crate) fn with_path(mut self, path: String) -> Self {
+        self.details.path = Some(path);
+        self
+    }
+
+    pub(crate) fn with_floor_byte(mut self, floor: u8) -> Self {
+        self.details.floor_byte = Some(floor);
+        self
+    }
+
+    /// The floor byte of the replay exactly as it was sent, only known for single replays that
+    /// were decoded but rejected, e.g. because of an unknown floor code
+    pub fn floor_byte(&self) -> Option<u8> {
+        self.details.floor_byte
+    }
+
+    /// Location of the element that failed to decode, e.g. body.replays[17].player2.name. Only
+    /// known if the whole response failed to decode, not for single replays that couldn't be
+    /// converted.
+    pub fn path(&self) -> Option<&str> {
+        self.details.path.as_deref()
+    }
+
+    /// The messagepack that could not be parsed, either the full response or the header or replay
+    /// the error is about, exactly as the server sent it
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Approximate number of bytes the error occupies, mostly the raw bytes it keeps
+    pub fn estimated_size(&self) -> usize {
+        std::mem::size_of::<ParseError>()
+            + std::mem::size_of::<Details>()
+            + self.raw.len()
+            + self
+                .details
+                .response_id
+                .as_ref()
+                .map_or(0, |id| id.as_str().len())
+            + self.details.path.as_ref().map_or(0, String::len)
+    }
+
+    /// HTTP status of the response, only known if the whole response failed to parse
+    pub fn status(&self) -> Option<u16> {
+        self.details.status
+    }
+
+    /// Id of the response the bytes belong to, unless the response header couldn't be decoded
+    /// either. Include it when reporting data the server got wrong.
+    pub fn response_id(&self) -> Option<&crate::messagepack::ResponseId> {
+        self.details.response_id.as_ref()
+    }
+
+    /// The error that caused parsing to fail
+    pub fn inner(&self) -> &Error {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> Error {
+        self.inner
+    }
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Could not parse replay: {}\n  bytes: {}",
-            self.inner, self.reply_content
-        )
+        // The inner error is the source
+        write!(f, "Could not parse replay")?;
+        if let Some(status) = self.details.status {
+            write!(f, "\n  status: {}", status)?;
+        }
+        if let Some(id) = &self.details.response_id {
+            write!(f, "\n  response id: {}", id)?;
+        }
+        if let Some(path) = &self.details.path {
+            write!(f, "\n  path: {}", path)?;
+        }
+        if let Some(floor) = self.details.floor_byte {
+            write!(f, "\n  floor byte: {:#04x}", floor)?;
+        }
+        write!(f, "\n  bytes: {}", self.raw.escape_ascii())
     }
 }
 
-impl error::Error for ParseError {}
+impl error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn display_messages() {
+        assert_eq!(
+            Error::InvalidArgument {
+                parameter: "pages",
+                value: "101".into(),
+                reason: "cannot query more than 100 pages, queried 101".into(),
+            }
+            .to_string(),
+            "Invalid argument: cannot query more than 100 pages, queried 101"
+        );
+        assert_eq!(
+            Error::ParsingBytesError {
+                field: "userid",
+                value: "abc".into(),
+            }
+            .to_string(),
+            "could not parse userid from \"abc\""
+        );
+        assert_eq!(
+            Error::HttpStatus {
+                status: 502,
+                body_snippet: "bad gateway".into(),
+                headers: vec![
+                    ("cf-ray".into(), "6d9a1b2c3d4e5f60-NRT".into()),
+                    ("server".into(), "cloudflare".into())
+                ],
+            }
+            .to_string(),
+            "HTTP status 502 (cf-ray: 6d9a1b2c3d4e5f60-NRT, server: cloudflare): bad gateway"
+        );
+        // Messages from before the variants had fields, logs are searched for them
+        assert_eq!(
+            crate::Floor::from_u8(0x0b).unwrap_err().to_string(),
+            "Invalid argument: b is not a valid floor code"
+        );
+        assert_eq!(
+            crate::Character::from_u8(0x13).unwrap_err().to_string(),
+            "Invalid argument: 13 is not a valid character code"
+        );
+        assert_eq!(
+            Error::InvalidWinner(3).to_string(),
+            "Could not parse winner"
+        );
+    }
+
+    #[tokio::test]
+    async fn retryable_errors() {
+        let connection_refused = reqwest::Client::new()
+            .get("http://127.0.0.1:1")
+            .send()
+            .await
+            .unwrap_err();
+        assert!(Error::from(connection_refused).is_retryable());
+
+        assert!(Error::RateLimited { retry_after: None }.is_retryable());
+        for status in [408, 429, 500, 502, 503, 504] {
+            assert!(Error::HttpStatus {
+                status,
+                body_snippet: "".into(),
+                headers: vec![],
+            }
+            .is_retryable());
+        }
+        assert!(Error::UnexpectedHtml {
+            status: 503,
+            snippet: "<html>maintenance</html>".into(),
+            headers: vec![],
+        }
+        .is_retryable());
+    }
+
+    #[test]
+    fn permanent_errors() {
+        let decode_error = rmp_serde::decode::from_slice::<String>(b"\x01").unwrap_err();
+        let errors = [
+            Error::Decode(decode_error),
+            Error::Api {
+                code: 1,
+                message: "".into(),
+            },
+            Error::InvalidArgument {
+                parameter: "pages",
+                value: "101".into(),
+                reason: "".into(),
+            },
+            Error::ParsingBytesError {
+                field: "userid",
+                value: "abc".into(),
+            },
+            Error::InvalidWinner(3),
+            Error::UnexpectedResponse(""),
+            Error::InvalidCharacterCode("XYZ".into()),
+            Error::EmptyResponse,
+            Error::HttpStatus {
+                status: 404,
+                body_snippet: "".into(),
+                headers: vec![],
+            },
+            Error::UnexpectedHtml {
+                status: 200,
+                snippet: "<html></html>".into(),
+                headers: vec![],
+            },
+        ];
+        for error in errors {
+            assert!(!error.is_retryable(), "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn decode_error_chain() {
+        let decode_error = rmp_serde::decode::from_slice::<String>(b"\x01").unwrap_err();
+        let parse_error = ParseError::new(b"\x01".to_vec(), decode_error.into());
+
+        let inner = parse_error.source().expect("ParseError has a source");
+        assert!(matches!(
+            inner.downcast_ref::<Error>(),
+            Some(Error::Decode(_))
+        ));
+        let root = inner.source().expect("Error::Decode has a source");
+        assert!(root.downcast_ref::<rmp_serde::decode::Error>().is_some());
+        assert!(root.source().is_none());
+
+        // Reports print every error of the chain, each message has to appear once
+        assert_eq!(
+            parse_error.to_string(),
+            "Could not parse replay\n  bytes: \\x01"
+        );
+        assert_eq!(inner.to_string(), "Invalid messagepack");
+        assert!(!root.to_string().is_empty());
+    }
+
+    #[test]
+    fn parse_error_accessors() {
+        let parse_error = ParseError::new(&b"\x92\xff\"a"[..], Error::UnexpectedResponse("oops"));
+        assert_eq!(parse_error.raw_bytes(), b"\x92\xff\"a");
+        assert!(matches!(
+            parse_error.inner(),
+            Error::UnexpectedResponse("oops")
+        ));
+        assert_eq!(
+            parse_error.to_string(),
+            "Could not parse replay\n  bytes: \\x92\\xff\\\"a"
+        );
+        assert!(matches!(
+            parse_error.into_inner(),
+            Error::UnexpectedResponse("oops")
+        ));
+    }
+
+    #[test]
+    fn message_variants_have_no_source() {
+        assert!(Error::InvalidArgument {
+            parameter: "pages",
+            value: "101".into(),
+            reason: "cannot query more than 100 pages".into()
+        }
+        .source()
+        .is_none());
+        assert!(Error::Api {
+            code: 1,
+            message: "".into()
+        }
+        .source()
+        .is_none());
+    }
+}
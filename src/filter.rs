@@ -0,0 +1,212 @@
+//! Client-side filters on the time and venue of a match, for questions the API can't answer like
+//! matches on weekday evenings.
+
+use crate::{error::*, Match, MatchVenue};
+use chrono::{DateTime, Datelike, FixedOffset, Timelike, Utc, Weekday};
+
+/// Conditions on the local time of a match. All conditions that are set have to hold, without
+/// any condition every match passes. Combine it with the server side QueryParameters by filtering
+/// the returned matches, e.g. `replays.matches().filter(|m| filter.matches(m))`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchFilter {
+    offset: FixedOffset,
+    hours: Option<(u32, u32)>,
+    // Bit n is set for the weekday n days after Monday
+    weekdays: Option<u8>,
+    venues: Option<Vec<MatchVenue>>,
+    since: Option<DateTime<Utc>>,
+}
+
+impl Default for MatchFilter {
+    fn default() -> Self {
+        MatchFilter {
+            offset: FixedOffset::east_opt(0).unwrap(),
+            hours: None,
+            weekdays: None,
+            venues: None,
+            since: None,
+        }
+    }
+}
+
+impl MatchFilter {
+    /// A filter without conditions evaluated in UTC
+    pub fn new() -> Self {
+        MatchFilter::default()
+    }
+
+    /// Evaluate hours and weekdays in the given timezone instead of UTC
+    pub fn timezone(self, offset: FixedOffset) -> Self {
+        MatchFilter { offset, ..self }
+    }
+
+    /// Only matches played from the hour start up to but excluding the hour end. A range with
+    /// start after end crosses midnight, e.g. 22 to 2 keeps 22:00 to 01:59. Equal hours keep every
+    /// hour. end may be 24 for a range up to midnight.
+    pub fn hour_range(self, start: u32, end: u32) -> Result<Self> {
+        for (parameter, hour, max) in [("start", start, 23), ("end", end, 24)] {
+            if hour > max {
+                return Err(Error::InvalidArgument {
+                    parameter,
+                    value: hour.to_string(),
+                    reason: format!("{} hour {} is larger than {}", parameter, hour, max),
+                });
+            }
+        }
+        Ok(MatchFilter {
+            hours: Some((start, end % 24)),
+            ..self
+        })
+    }
+
+    /// Only matches played on one of the weekdays
+    pub fn weekdays(self, weekdays: &[Weekday]) -> Self {
+        MatchFilter {
+            weekdays: Some(
+                weekdays
+                    .iter()
+                    .fold(0, |mask, day| mask | 1 << day.num_days_from_monday()),
+            ),
+            ..self
+        }
+    }
+
+    /// Only matches played at one of the venue codes. Matches without a venue never pass. What
+    /// the codes stand for is not confirmed, see MatchVenue.
+    pub fn venues(self, venues: &[MatchVenue]) -> Self {
+        MatchFilter {
+            venues: Some(venues.to_vec()),
+            ..self
+        }
+    }
+
+    /// Only matches played at time or later
+    pub fn since(self, time: DateTime<Utc>) -> Self {
+        MatchFilter {
+            since: Some(time),
+            ..self
+        }
+    }
+
+    /// Whether the match passes all conditions
+    pub fn matches(&self, m: &Match) -> bool {
+        let local = m.timestamp.with_timezone(&self.offset);
+        let hour = local.hour();
+        let in_hours = match self.hours {
+            None => true,
+            Some((start, end)) if start < end => (start..end).contains(&hour),
+            Some((start, end)) if start > end => hour >= start || hour < end,
+            Some(_) => true,
+        };
+        let on_weekday = self
+            .weekdays
+            .is_none_or(|mask| mask & 1 << local.weekday().num_days_from_monday() != 0);
+        let at_venue = self
+            .venues
+            .as_ref()
+            .is_none_or(|venues| m.venue.is_some_and(|venue| venues.contains(&venue)));
+        let recent = self.since.is_none_or(|since| m.timestamp >= since);
+        in_hours && on_weekday && at_venue && recent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Character, Player};
+
+    // 2022-02-06 is a Sunday
+    fn game(timestamp: &str) -> Match {
+        let player = |id| Player::test(id, Character::Sol);
+        Match {
+            timestamp: timestamp.parse().unwrap(),
+            ..Match::test(0, 0, (player(1), player(2)))
+        }
+    }
+
+    #[test]
+    fn hours() {
+        let filter = MatchFilter::new().hour_range(18, 22).unwrap();
+        assert!(filter.matches(&game("2022-02-06T18:00:00Z")));
+        assert!(filter.matches(&game("2022-02-06T21:59:59Z")));
+        assert!(!filter.matches(&game("2022-02-06T22:00:00Z")));
+        assert!(!filter.matches(&game("2022-02-06T17:59:59Z")));
+
+        let to_midnight = MatchFilter::new().hour_range(20, 24).unwrap();
+        assert!(to_midnight.matches(&game("2022-02-06T23:59:59Z")));
+        assert!(!to_midnight.matches(&game("2022-02-07T00:00:00Z")));
+
+        assert!(MatchFilter::new()
+            .hour_range(5, 5)
+            .unwrap()
+            .matches(&game("2022-02-06T12:00:00Z")));
+        assert!(MatchFilter::new().hour_range(24, 2).is_err());
+        assert!(MatchFilter::new().hour_range(0, 25).is_err());
+    }
+
+    #[test]
+    fn hours_crossing_midnight() {
+        let filter = MatchFilter::new().hour_range(22, 2).unwrap();
+        assert!(filter.matches(&game("2022-02-06T22:00:00Z")));
+        assert!(filter.matches(&game("2022-02-06T23:30:00Z")));
+        assert!(filter.matches(&game("2022-02-07T00:00:00Z")));
+        assert!(filter.matches(&game("2022-02-07T01:59:59Z")));
+        assert!(!filter.matches(&game("2022-02-07T02:00:00Z")));
+        assert!(!filter.matches(&game("2022-02-06T21:59:59Z")));
+    }
+
+    #[test]
+    fn weekdays_in_timezone() {
+        let weekend = MatchFilter::new().weekdays(&[Weekday::Sat, Weekday::Sun]);
+        assert!(weekend.matches(&game("2022-02-06T23:00:00Z")));
+        assert!(!weekend.matches(&game("2022-02-07T00:00:00Z")));
+
+        // Sunday 23:00 UTC is already Monday in central Europe
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        assert!(!weekend.timezone(cet).matches(&game("2022-02-06T23:00:00Z")));
+
+        // Friday evening, both conditions have to hold
+        let evening = MatchFilter::new()
+            .timezone(cet)
+            .hour_range(23, 1)
+            .unwrap()
+            .weekdays(&[Weekday::Fri]);
+        assert!(evening.matches(&game("2022-02-04T22:30:00Z")));
+        assert!(!evening.matches(&game("2022-02-04T23:30:00Z")));
+        assert!(!MatchFilter::new()
+            .weekdays(&[])
+            .matches(&game("2022-02-06T12:00:00Z")));
+    }
+
+    #[test]
+    fn venues() {
+        let at = |venue| Match {
+            venue,
+            ..game("2022-02-06T12:00:00Z")
+        };
+        let filter = MatchFilter::new().venues(&[MatchVenue(0), MatchVenue(1)]);
+        assert!(filter.matches(&at(Some(MatchVenue(0)))));
+        assert!(filter.matches(&at(Some(MatchVenue(1)))));
+        assert!(!filter.matches(&at(Some(MatchVenue(2)))));
+        assert!(!filter.matches(&at(Some(MatchVenue(256)))));
+        assert!(!filter.matches(&at(None)));
+
+        let large = MatchFilter::new().venues(&[MatchVenue(-1), MatchVenue(1 << 40)]);
+        assert!(large.matches(&at(Some(MatchVenue(1 << 40)))));
+        assert!(!large.matches(&at(Some(MatchVenue(255)))));
+        assert!(MatchFilter::new().matches(&at(None)));
+    }
+
+    #[test]
+    fn since() {
+        let recent = MatchFilter::new().since("2022-02-06T12:00:00Z".parse().unwrap());
+        assert!(recent.matches(&game("2022-02-06T12:00:00Z")));
+        assert!(recent.matches(&game("2022-02-07T00:00:00Z")));
+        assert!(!recent.matches(&game("2022-02-06T11:59:59Z")));
+        // Combined with the other conditions
+        assert!(!recent
+            .hour_range(0, 12)
+            .unwrap()
+            .matches(&game("2022-02-06T13:00:00Z")));
+    }
+}
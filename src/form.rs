@@ -0,0 +1,188 @@
+//! Recent form of a single player, e.g. for a bot command listing someone's last matches.
+
+use crate::{Character, Match, PlayerIdentity};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Result of a match from the perspective of one of its players
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum Outcome {
+    Win,
+    Loss,
+    /// The winner byte of the replay was neither side
+    Unknown,
+}
+
+/// Outcome of the match for the player, None if they didn't play in it
+pub fn outcome(player_id: i64, m: &Match) -> Option<Outcome> {
+    let (p1, p2) = m.players();
+    if p1.id != player_id && p2.id != player_id {
+        return None;
+    }
+    Some(match m.try_winner() {
+        Some(winner) if winner.id == player_id => Outcome::Win,
+        Some(_) => Outcome::Loss,
+        None => Outcome::Unknown,
+    })
+}
+
+/// An opponent of the player within the window
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Opponent {
+    /// With the name of the most recent match against them
+    pub player: PlayerIdentity,
+    pub matches: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct FormSummary {
+    pub player_id: i64,
+    /// Results of the most recent matches, newest first
+    pub results: Vec<Outcome>,
+    /// Wins over decided matches in the window, None without decided matches
+    pub win_rate: Option<f64>,
+    /// Characters played in the window, most played first
+    pub characters: Vec<(Character, u64)>,
+    /// Opponents in the window, most played first
+    pub opponents: Vec<Opponent>,
+}
+
+/// Summarize the last_n matches of the player. The matches don't have to be sorted, matches
+/// without the player and repeated replay ids are ignored. Ties in the character and opponent
+/// lists are ordered by character and by the opponent's id.
+pub fn summary<'a, I>(player_id: i64, matches: I, last_n: usize) -> FormSummary
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let mut seen = HashSet::new();
+    let mut recent = matches
+        .into_iter()
+        .filter(|m| outcome(player_id, m).is_some() && seen.insert(m.id))
+        .collect::<Vec<_>>();
+    recent.sort_by_key(|m| Reverse(m.timestamp));
+    recent.truncate(last_n);
+
+    let results = recent
+        .iter()
+        .filter_map(|m| outcome(player_id, m))
+        .collect::<Vec<_>>();
+    let wins = results.iter().filter(|&&o| o == Outcome::Win).count();
+    let decided = results.iter().filter(|&&o| o != Outcome::Unknown).count();
+
+    let mut characters = BTreeMap::<Character, u64>::new();
+    let mut opponents = BTreeMap::<PlayerIdentity, u64>::new();
+    for m in &recent {
+        let (p1, p2) = m.players();
+        let (me, them) = if p1.id == player_id {
+            (p1, p2)
+        } else {
+            (p2, p1)
+        };
+        *characters.entry(me.character).or_default() += 1;
+        // Newest match comes first, so the key inserted first has the current name
+        *opponents.entry(them.identity()).or_default() += 1;
+    }
+    let mut characters = characters.into_iter().collect::<Vec<_>>();
+    characters.sort_by_key(|&(_, count)| Reverse(count));
+    let mut opponents = opponents
+        .into_iter()
+        .map(|(player, matches)| Opponent { player, matches })
+        .collect::<Vec<_>>();
+    opponents.sort_by_key(|opponent| Reverse(opponent.matches));
+
+    FormSummary {
+        player_id,
+        results,
+        win_rate: (decided > 0).then(|| wins as f64 / decided as f64),
+        characters,
+        opponents,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Player, Winner};
+
+    const ME: i64 = 1;
+
+    fn game(i: u64, me: Character, opponent: (i64, &str), winner: Winner, swap: bool) -> Match {
+        let me = Player {
+            name: "me".into(),
+            ..Player::test(ME, me)
+        };
+        let opponent = Player {
+            name: opponent.1.into(),
+            ..Player::test(opponent.0, Character::Ky)
+        };
+        Match {
+            winner,
+            ..Match::test(
+                i,
+                i as i64 * 60,
+                if swap { (opponent, me) } else { (me, opponent) },
+            )
+        }
+    }
+
+    #[test]
+    fn recent_form() {
+        use Character::*;
+        use Outcome::*;
+        let mut matches = vec![
+            game(0, Sol, (2, "old"), Winner::Player1, false),
+            game(1, Sol, (2, "two"), Winner::Player1, true),
+            game(2, May, (3, "three"), Winner::Player2, true),
+            game(3, Sol, (2, "two"), Winner::Unknown(3), false),
+            game(4, May, (4, "four"), Winner::Player1, false),
+            game(5, Sol, (2, "renamed"), Winner::Player2, false),
+        ];
+        // Unrelated matches and repeated pages are ignored, order doesn't matter
+        let mut unrelated = game(6, Sol, (5, "five"), Winner::Player1, false);
+        unrelated.players.0.id = 6;
+        matches.push(unrelated);
+        matches.push(matches[4].clone());
+        matches.reverse();
+
+        let form = summary(ME, &matches, 5);
+        assert_eq!(form.results, [Loss, Win, Outcome::Unknown, Win, Loss]);
+        assert_eq!(form.win_rate, Some(0.5));
+        assert_eq!(form.characters, [(Sol, 3), (May, 2)]);
+        assert_eq!(
+            form.opponents
+                .iter()
+                .map(|o| (o.player.id, o.player.name(), o.matches))
+                .collect::<Vec<_>>(),
+            [(2, "renamed", 3), (3, "three", 1), (4, "four", 1),]
+        );
+
+        assert_eq!(outcome(6, &matches[0]), None);
+        assert_eq!(summary(ME, &matches, 1).results, [Loss]);
+    }
+
+    #[test]
+    fn no_matches() {
+        let form = summary(ME, &[], 10);
+        assert!(form.results.is_empty());
+        assert_eq!(form.win_rate, None);
+        assert!(form.characters.is_empty() && form.opponents.is_empty());
+    }
+}
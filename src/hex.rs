@@ -0,0 +1,73 @@
+//! Hex encoding of payloads, as found in the data field of captured requests.
+
+use crate::error::*;
+use std::fmt::Write;
+
+/// Encode the bytes as lowercase hex digits
+pub fn encode(bytes: &[u8]) -> String {
+    let mut buf = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(buf, "{:02x}", b).unwrap();
+    }
+    buf
+}
+
+/// Decode a string of hex digits in either case into bytes. Fails on an odd number of digits and
+/// on anything that is not a hex digit, including whitespace.
+pub fn decode(hex: &str) -> Result<Vec<u8>> {
+    let invalid = |reason: &str| {
+        let value = hex.chars().take(32).collect::<String>();
+        Error::InvalidArgument {
+            parameter: "hex",
+            reason: format!("hex {:?} {}", value, reason),
+            value,
+        }
+    };
+    if hex.len() % 2 == 1 {
+        return Err(invalid("has an odd number of digits"));
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match (digit(pair[0]), digit(pair[1])) {
+            (Some(high), Some(low)) => Ok(high << 4 | low),
+            _ => Err(invalid("contains a character that is not a hex digit")),
+        })
+        .collect()
+}
+
+fn digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(decode("00ff7Fa0").unwrap(), vec![0x00, 0xff, 0x7f, 0xa0]);
+        assert_eq!(encode(&[0x00, 0xff, 0x7f, 0xa0]), "00ff7fa0");
+        assert_eq!(decode("").unwrap(), Vec::<u8>::new());
+        assert!(decode("abc").is_err());
+        assert!(decode("zz").is_err());
+        assert!(decode("+1").is_err());
+        assert!(decode("0 ").is_err());
+        assert!(decode("\u{e9}0").is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn encode_then_decode(bytes in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..64)) {
+            proptest::prop_assert_eq!(decode(&encode(&bytes)).unwrap(), bytes.clone());
+            proptest::prop_assert_eq!(decode(&encode(&bytes).to_uppercase()).unwrap(), bytes);
+        }
+
+        #[test]
+        fn decode_arbitrary_input(input in ".{0,64}") {
+            // Must never panic, and anything accepted encodes back to the same digits
+            if let Ok(bytes) = decode(&input) {
+                proptest::prop_assert_eq!(encode(&bytes), input.to_lowercase());
+            }
+        }
+    }
+}
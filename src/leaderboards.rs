@@ -0,0 +1,190 @@
+//! Leaderboards assembled from the profiles of many players.
+
+use crate::model::user::ProfileVisibility;
+use crate::requests::get_statistics_batch;
+use crate::{error::*, Character, Context};
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Statistics requests win_chains keeps in flight at a time
+pub const CONCURRENCY: usize = 4;
+
+/// The best win chain of a player with a character
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum WinChain {
+    Max(u64),
+    /// The player hid their data, the payload's numbers are not shown
+    Hidden,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct WinChainEntry {
+    pub player_id: i64,
+    pub name: String,
+    pub win_chain: WinChain,
+}
+
+/// Result of win_chains
+#[derive(Debug)]
+pub struct WinChainLeaderboard {
+    pub character: Character,
+    /// Longest chains first, ties ordered by player id. Hidden profiles follow all visible ones.
+    pub entries: Vec<WinChainEntry>,
+    /// Players whose statistics could not be retrieved
+    pub errors: Vec<(i64, Error)>,
+}
+
+/// Rank the players by the highest number of consecutive wins with character, read from the
+/// XXX_WinChainMax key of their statistics. Requests are sent with at most CONCURRENCY in flight,
+/// a failed request leaves the player out of the ranking and is reported in errors.
+pub async fn win_chains(
+    context: &Context,
+    player_ids: impl IntoIterator<Item = i64>,
+    character: Character,
+) -> Result<WinChainLeaderboard> {
+    let code = character.to_code().ok_or_else(|| Error::InvalidArgument {
+        parameter: "character",
+        value: character.to_string(),
+        reason: format!("{} has no character code", character),
+    })?;
+    let key = format!("{}_WinChainMax", code);
+
+    let mut entries = vec![];
+    let mut errors = vec![];
+    for (player_id, statistics) in get_statistics_batch(context, player_ids, CONCURRENCY).await {
+        let statistics = match statistics {
+            Ok(statistics) => statistics,
+            Err(e) => {
+                errors.push((player_id, e));
+                continue;
+            }
+        };
+        let win_chain = match statistics.visibility {
+            ProfileVisibility::Hidden => WinChain::Hidden,
+            ProfileVisibility::Visible => {
+                WinChain::Max(statistics.json[&key].as_u64().unwrap_or(0))
+            }
+        };
+        entries.push(WinChainEntry {
+            player_id,
+            name: statistics.json["NickName"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            win_chain,
+        });
+    }
+    entries.sort_by_key(|entry| {
+        let chain = match entry.win_chain {
+            WinChain::Max(chain) => Some(chain),
+            WinChain::Hidden => None,
+        };
+        (std::cmp::Reverse(chain), entry.player_id)
+    });
+    Ok(WinChainLeaderboard {
+        character,
+        entries,
+        errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::{endpoints, messagepack, Endpoint};
+    use crate::transport::{BoxFuture, RawResponse, Transport};
+
+    // Answers every statistics request with a payload of the requested player. Odd ids have no Sol
+    // win chain, ids above 100 hid their data and id 0 fails.
+    struct Players;
+
+    impl Transport for Players {
+        fn post<'a>(
+            &'a self,
+            path: &'static str,
+            data: &'a str,
+        ) -> BoxFuture<'a, crate::error::Result<RawResponse>> {
+            assert_eq!(path, endpoints::Statistics::PATH);
+            let request =
+                messagepack::Request::<messagepack::StatisticsRequest>::from_hex(data).unwrap();
+            let id = request.body.id.parse::<i64>().unwrap();
+            let mut json = serde_json::json!({
+                "UserID": id,
+                "NickName": format!("Player {}", id),
+                "DataHidden": i64::from(id > 100),
+                "KYK_WinChainMax": 99,
+            });
+            if id % 2 == 0 {
+                json["SOL_WinChainMax"] = (id % 100 * 3).into();
+            }
+            let response = messagepack::Response {
+                header: messagepack::ResponseHeader {
+                    id: messagepack::ResponseId("620a390967e4c".into()),
+                    int1: 0,
+                    date: "2022/02/14 11:12:09".into(),
+                    version1: "0.1.0".into(),
+                    version2: "0.0.2".into(),
+                    version3: "0.0.2".into(),
+                    string1: "".into(),
+                    string2: "".into(),
+                },
+                body: messagepack::StatisticsResponse { int1: 0, json },
+            };
+            let body = if id == 0 {
+                vec![]
+            } else {
+                crate::hex::decode(&response.to_hex()).unwrap()
+            };
+            Box::pin(async move { Ok(RawResponse::ok(body)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn ranking() {
+        let context = Context::default().transport(Players);
+        let leaderboard = win_chains(&context, [4, 102, 0, 10, 3, 4, 8], Character::Sol)
+            .await
+            .unwrap();
+        let entries = leaderboard
+            .entries
+            .iter()
+            .map(|entry| (entry.player_id, entry.win_chain))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                (10, WinChain::Max(30)),
+                (8, WinChain::Max(24)),
+                (4, WinChain::Max(12)),
+                (3, WinChain::Max(0)),
+                (102, WinChain::Hidden),
+            ]
+        );
+        assert_eq!(leaderboard.entries[0].name, "Player 10");
+        assert_eq!(leaderboard.errors.len(), 1);
+        assert_eq!(leaderboard.errors[0].0, 0);
+    }
+
+    #[tokio::test]
+    async fn character_without_code() {
+        let context = Context::default().transport(Players);
+        assert!(matches!(
+            win_chains(&context, [1], Character::Unknown(0x40)).await,
+            Err(Error::InvalidArgument {
+                parameter: "character",
+                ..
+            })
+        ));
+    }
+}
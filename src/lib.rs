@@ -1,17 +1,69 @@
+// Emit a tracing event if the tracing feature is enabled, otherwise it expands to nothing and the
+// arguments are not evaluated
+macro_rules! trace {
+    ($level:ident, $($args:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($args)+);
+    };
+}
+
+pub mod activity;
+pub mod aliases;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod audit;
+pub mod backfill;
+pub mod cache;
+pub mod celestial;
+pub mod clock;
+mod coalesce;
+#[cfg(feature = "compat-0")]
+pub mod compat;
+#[cfg(feature = "test-util")]
+pub mod conformance;
+pub mod dedupe;
 pub mod error;
+pub mod filter;
+pub mod form;
+pub mod hex;
+pub mod leaderboards;
+pub mod metrics;
+#[cfg(test)]
+mod mock_server;
+pub mod model;
+pub mod opponents;
+pub mod progression;
+pub mod protocol;
+pub mod rating;
+pub mod relational;
+pub mod reports;
 pub mod requests;
+pub mod sessions;
+pub mod sink;
+pub mod snapshot;
+pub mod streaks;
+pub mod templates;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod transport;
+pub mod watch;
 
 use chrono::prelude::*;
 use derivative::*;
 use error::*;
 use serde_crate::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 // Reexport the functions and structs from requests.rs and parameters.rs
 pub use requests::*;
 
-/// Player information associated with a match
+/// Player information associated with a match. Two players are equal and hash the same if they
+/// have the same id and character, the name is ignored so that a renamed player stays the same
+/// key. Use eq_strict or StrictPlayer to also compare the name, or PlayerIdentity to ignore the
+/// character as well.
 #[derive(Derivative, Clone, Debug, PartialOrd, Ord)]
 #[cfg_attr(
     feature = "serde",
@@ -22,8 +74,50 @@ pub use requests::*;
 pub struct Player {
     pub id: i64,
     pub character: Character,
+    /// Names are shared between all matches of a query in which the player appears
     #[derivative(Hash = "ignore")]
-    pub name: String,
+    pub name: Arc<str>,
+}
+
+impl Player {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The player without the character of this match
+    pub fn identity(&self) -> PlayerIdentity {
+        PlayerIdentity::from(self)
+    }
+
+    /// Whether all fields including the name are equal
+    pub fn eq_strict(&self, other: &Player) -> bool {
+        self == other && self.name == other.name
+    }
+}
+
+/// A Player whose equality and hash include the name, to key by the exact appearance in a match
+#[derive(Clone, Debug, PartialOrd, Ord)]
+pub struct StrictPlayer(pub Player);
+
+impl PartialEq for StrictPlayer {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_strict(&other.0)
+    }
+}
+
+impl Eq for StrictPlayer {}
+
+impl std::hash::Hash for StrictPlayer {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+        self.0.name.hash(state);
+    }
+}
+
+impl From<Player> for StrictPlayer {
+    fn from(player: Player) -> Self {
+        StrictPlayer(player)
+    }
 }
 
 impl PartialEq for Player {
@@ -34,12 +128,109 @@ impl PartialEq for Player {
 
 impl Eq for Player {}
 
+/// Names are written as they are, use sanitize_for_terminal before printing untrusted output
 impl fmt::Display for Player {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} as {}", self.name, self.character)
     }
 }
 
+/// The text without control characters and bidirectional formatting characters, for printing
+/// player names to a terminal or log. Names are chosen freely by the players, an escape sequence
+/// or a right-to-left override in a name could otherwise rewrite the output around it. Japanese,
+/// Korean, fullwidth and emoji characters are kept as they are.
+pub fn sanitize_for_terminal(text: &str) -> std::borrow::Cow<'_, str> {
+    let strip = |c: char| {
+        c.is_control()
+            || matches!(
+                c,
+                '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+            )
+    };
+    if text.contains(strip) {
+        text.chars()
+            .filter(|&c| !strip(c))
+            .collect::<String>()
+            .into()
+    } else {
+        text.into()
+    }
+}
+
+/// A player independent of the character they played, to key aggregations by person. Equality,
+/// hashing and ordering only consider the id, the name is the one last seen and may differ between
+/// identities of the same player.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PlayerIdentity {
+    pub id: i64,
+    pub name: Arc<str>,
+    /// Online id of the platform, only known for sources that send it like the vip ranking
+    pub online_id: Option<String>,
+}
+
+impl PlayerIdentity {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PartialEq for PlayerIdentity {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for PlayerIdentity {}
+
+impl std::hash::Hash for PlayerIdentity {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl PartialOrd for PlayerIdentity {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PlayerIdentity {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl From<&Player> for PlayerIdentity {
+    fn from(player: &Player) -> Self {
+        PlayerIdentity {
+            id: player.id,
+            name: player.name.clone(),
+            online_id: None,
+        }
+    }
+}
+
+impl From<Player> for PlayerIdentity {
+    fn from(player: Player) -> Self {
+        PlayerIdentity {
+            id: player.id,
+            name: player.name,
+            online_id: None,
+        }
+    }
+}
+
+impl fmt::Display for PlayerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
 /// Indicates which player won a match
 #[derive(Hash, PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord)]
 #[cfg_attr(
@@ -50,6 +241,47 @@ impl fmt::Display for Player {
 pub enum Winner {
     Player1,
     Player2,
+    /// A winner byte other than 1 or 2, e.g. if the server reports a draw or a disconnect. Only
+    /// produced in DecodeMode::Lenient, such matches have neither a winner nor a loser
+    Unknown(u8),
+}
+
+impl Winner {
+    /// Create a winner from the byte used by the API, other values than 1 and 2 are kept as
+    /// Winner::Unknown
+    pub fn from_u8(winner: u8) -> Self {
+        match winner {
+            1 => Winner::Player1,
+            2 => Winner::Player2,
+            other => Winner::Unknown(other),
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Winner::Player1 => 1,
+            Winner::Player2 => 2,
+            Winner::Unknown(other) => other,
+        }
+    }
+}
+
+/// Code of where a match was played, the last unknown integer of a replay exactly as the server
+/// sent it. It is 0 for nearly all replays of the captured responses and 1, 2 or 4 for a few.
+/// Presumably the codes tell tower, park and player room matches apart, but no code has been
+/// confirmed against matches of a known venue yet, so they are not given names.
+#[derive(Hash, PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+pub struct MatchVenue(pub i64);
+
+impl MatchVenue {
+    pub fn code(self) -> i64 {
+        self.0
+    }
 }
 
 /// A match received by the get_replay API
@@ -65,48 +297,250 @@ pub struct Match {
     pub floor: Floor,
     pub players: (Player, Player),
     pub winner: Winner,
+    /// Id of the replay, the same match has the same id on every page it appears on
+    pub id: u64,
+    /// None for matches from a source that didn't record the venue
+    pub venue: Option<MatchVenue>,
 }
 
 impl Match {
+    /// Create a match from a timestamp in seconds since the unix epoch, fails if the timestamp is
+    /// out of the supported range. The venue is None.
+    pub fn from_unix(
+        id: u64,
+        timestamp: i64,
+        floor: Floor,
+        players: (Player, Player),
+        winner: Winner,
+    ) -> Result<Self> {
+        Ok(Match {
+            timestamp: DateTime::from_timestamp(timestamp, 0).ok_or_else(|| {
+                Error::InvalidArgument {
+                    parameter: "timestamp",
+                    value: timestamp.to_string(),
+                    reason: format!("timestamp {} is out of the supported range", timestamp),
+                }
+            })?,
+            floor,
+            players,
+            winner,
+            id,
+            venue: None,
+        })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The replay id in the form to share it with other players, see ShareCode
+    pub fn share_code(&self) -> ShareCode {
+        ShareCode(self.id)
+    }
+
+    /// Approximate number of bytes the match occupies including the player names. A name shared
+    /// with other matches is counted for each of them, so sums over many matches are an upper
+    /// bound.
+    pub fn estimated_size(&self) -> usize {
+        std::mem::size_of::<Match>() + self.players.0.name.len() + self.players.1.name.len()
+    }
+
     pub fn floor(&self) -> Floor {
         self.floor
     }
 
+    /// The floor byte exactly as the server sent it. Floor::Unknown keeps codes this crate doesn't
+    /// know, so the byte survives archiving the match in any format that stores the floor.
+    pub fn floor_byte(&self) -> u8 {
+        self.floor.to_u8()
+    }
+
+    pub fn venue(&self) -> Option<MatchVenue> {
+        self.venue
+    }
+
     pub fn timestamp(&self) -> &DateTime<Utc> {
         &self.timestamp
     }
 
+    /// Seconds since the unix epoch, for consumers that don't use chrono
+    pub fn timestamp_unix(&self) -> i64 {
+        self.timestamp.timestamp()
+    }
+
+    /// The timestamp as a time crate value, None if it is outside of the years the time crate
+    /// supports
+    #[cfg(feature = "time")]
+    pub fn timestamp_time(&self) -> Option<time::OffsetDateTime> {
+        time::OffsetDateTime::from_unix_timestamp(self.timestamp_unix()).ok()
+    }
+
     pub fn players(&self) -> (&Player, &Player) {
         (&self.players.0, &self.players.1)
     }
 
     /// Get the player information about the winner
+    ///
+    /// Panics if the winner is Winner::Unknown, which only DecodeMode::Lenient produces. Use
+    /// try_winner for matches decoded leniently.
     pub fn winner(&self) -> &Player {
+        self.try_winner()
+            .unwrap_or_else(|| panic!("match {} has no winner, see Match::try_winner", self.id))
+    }
+
+    /// Get the player information about the loser
+    ///
+    /// Panics if the winner is Winner::Unknown, which only DecodeMode::Lenient produces. Use
+    /// try_loser for matches decoded leniently.
+    pub fn loser(&self) -> &Player {
+        self.try_loser()
+            .unwrap_or_else(|| panic!("match {} has no loser, see Match::try_loser", self.id))
+    }
+
+    /// Get the player information about the winner, None if the winner is Winner::Unknown
+    pub fn try_winner(&self) -> Option<&Player> {
         match self.winner {
-            Winner::Player1 => &self.players.0,
-            Winner::Player2 => &self.players.1,
+            Winner::Player1 => Some(&self.players.0),
+            Winner::Player2 => Some(&self.players.1),
+            Winner::Unknown(_) => None,
         }
     }
 
-    /// Get the player information about the winner
-    pub fn loser(&self) -> &Player {
+    /// Get the player information about the loser, None if the winner is Winner::Unknown
+    pub fn try_loser(&self) -> Option<&Player> {
         match self.winner {
-            Winner::Player1 => &self.players.1,
-            Winner::Player2 => &self.players.0,
+            Winner::Player1 => Some(&self.players.1),
+            Winner::Player2 => Some(&self.players.0),
+            Winner::Unknown(_) => None,
+        }
+    }
+}
+
+// Matches for the tests of the modules, a test overrides what it looks at with struct update
+// syntax
+#[cfg(test)]
+impl Player {
+    /// A player without a name
+    pub(crate) fn test(id: i64, character: Character) -> Self {
+        Player {
+            id,
+            character,
+            name: "".into(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Match {
+    /// A tower match on floor 10 won by player 1, played seconds after 1_644_000_000
+    pub(crate) fn test(id: u64, seconds: i64, players: (Player, Player)) -> Self {
+        Match::from_unix(
+            id,
+            1_644_000_000 + seconds,
+            Floor::F10,
+            players,
+            Winner::Player1,
+        )
+        .unwrap()
+    }
+
+    /// The same match with the players in the other slots
+    pub(crate) fn swapped(&self) -> Self {
+        Match {
+            players: (self.players.1.clone(), self.players.0.clone()),
+            winner: match self.winner {
+                Winner::Player1 => Winner::Player2,
+                Winner::Player2 => Winner::Player1,
+                unknown => unknown,
+            },
+            ..self.clone()
+        }
+    }
+}
+
+/// Textual forms of a replay id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShareFormat {
+    /// The id as the server sends it, e.g. 220206103032458670. The leading digits of captured ids
+    /// read like a date and time in the form YYMMDDhhmmss, within hours of the match timestamp.
+    Decimal,
+    /// Lowercase hexadecimal with a 0x prefix, e.g. 0x30e5448b49f6dae
+    Hex,
+}
+
+/// A replay id to share, e.g. in a bot message that tells players which replay to look up. The
+/// game offers no code of its own for replays, so the canonical form is the decimal id the API
+/// uses and which community tools already show. Parsing accepts every ShareFormat and surrounding
+/// whitespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShareCode(pub u64);
+
+impl ShareCode {
+    /// The form written by Display
+    pub const CANONICAL: ShareFormat = ShareFormat::Decimal;
+
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+
+    pub fn format(&self, format: ShareFormat) -> String {
+        match format {
+            ShareFormat::Decimal => self.0.to_string(),
+            ShareFormat::Hex => format!("{:#x}", self.0),
+        }
+    }
+}
+
+impl fmt::Display for ShareCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(ShareCode::CANONICAL))
+    }
+}
+
+impl std::str::FromStr for ShareCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let err = || Error::ParsingBytesError {
+            field: "share code",
+            value: s.to_string(),
+        };
+        let code = s.trim();
+        let (digits, radix) = match code.strip_prefix("0x").or_else(|| code.strip_prefix("0X")) {
+            Some(hex) => (hex, 16),
+            None => (code, 10),
+        };
+        // from_str_radix would accept a sign
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return Err(err());
         }
+        u64::from_str_radix(digits, radix)
+            .map(ShareCode)
+            .map_err(|_| err())
     }
 }
 
 impl fmt::Display for Match {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{} on floor {:?} {{\n  Winner: {}\n  Loser: {}\n}}",
-            self.timestamp(),
-            self.floor(),
-            self.winner(),
-            self.loser()
-        )
+        match (self.try_winner(), self.try_loser()) {
+            (Some(winner), Some(loser)) => write!(
+                f,
+                "{} on floor {:?} {{\n  Winner: {}\n  Loser: {}\n}}",
+                self.timestamp(),
+                self.floor(),
+                winner,
+                loser
+            ),
+            _ => write!(
+                f,
+                "{} on floor {:?} {{\n  Player 1: {}\n  Player 2: {}\n  Winner: unknown ({})\n}}",
+                self.timestamp(),
+                self.floor(),
+                self.players.0,
+                self.players.1,
+                self.winner.to_u8()
+            ),
+        }
     }
 }
 
@@ -133,6 +567,8 @@ pub enum Character {
     Jacko,
     HappyChaos,
     Baiken,
+    /// A character code this crate doesn't know yet, e.g. a character released after this version
+    Unknown(u8),
 }
 
 impl fmt::Display for Character {
@@ -157,11 +593,35 @@ impl fmt::Display for Character {
             Character::Jacko => write!(f, "Jack-o"),
             Character::HappyChaos => write!(f, "Happy Chaos"),
             Character::Baiken => write!(f, "Baiken"),
+            Character::Unknown(c) => write!(f, "Unknown character {:#04x}", c),
         }
     }
 }
 
 impl Character {
+    /// Every character this crate knows, in order of their code
+    pub const KNOWN: [Character; 19] = [
+        Character::Sol,
+        Character::Ky,
+        Character::May,
+        Character::Axl,
+        Character::Chipp,
+        Character::Potemkin,
+        Character::Faust,
+        Character::Millia,
+        Character::Zato,
+        Character::Ramlethal,
+        Character::Leo,
+        Character::Nagoriyuki,
+        Character::Giovanna,
+        Character::Anji,
+        Character::Ino,
+        Character::Goldlewis,
+        Character::Jacko,
+        Character::HappyChaos,
+        Character::Baiken,
+    ];
+
     /// Convert a byte into a Character enum.
     /// 00: Sol 01: Ky 02: May 03: Axl 04: Chipp 05: Pot 06: Faust 07: Millia
     /// 08: Zato-1 09: Ram 0a: Leo 0b: Nago 0c: Gio 0d: Anji 0e: I-No 0f: Goldlewis 10: Jack-O
@@ -188,13 +648,19 @@ impl Character {
             0x10 => Ok(Character::Jacko),
             0x11 => Ok(Character::HappyChaos),
             0x12 => Ok(Character::Baiken),
-            _ => Err(Error::InvalidArgument(format!(
-                "{:x} is not a valid character code",
-                c
-            ))),
+            _ => Err(Error::InvalidArgument {
+                parameter: "character",
+                value: format!("{:x}", c),
+                reason: format!("{:x} is not a valid character code", c),
+            }),
         }
     }
 
+    /// Same as from_u8 but unknown codes are kept as Character::Unknown instead of failing
+    pub fn from_u8_lossy(c: u8) -> Self {
+        Character::from_u8(c).unwrap_or(Character::Unknown(c))
+    }
+
     /// Convert a Character back to its u8 code
     /// 00: Sol 01: Ky 02: May 03: Axl 04: Chipp 05: Pot 06: Faust 07: Millia
     /// 08: Zato-1 09: Ram 0a: Leo 0b: Nago 0c: Gio 0d: Anji 0e: I-No 0f: Goldlewis 10: Jack-O
@@ -221,12 +687,68 @@ impl Character {
             Character::Jacko => 0x10,
             Character::HappyChaos => 0x11,
             Character::Baiken => 0x12,
+            Character::Unknown(c) => *c,
+        }
+    }
+
+    /// Convert the three letter code used by the game, e.g. in the statistics endpoint, into a
+    /// Character enum. Happy Chaos uses COS.
+    pub fn from_code(code: &str) -> Result<Self> {
+        match code {
+            "SOL" => Ok(Character::Sol),
+            "KYK" => Ok(Character::Ky),
+            "MAY" => Ok(Character::May),
+            "AXL" => Ok(Character::Axl),
+            "CHP" => Ok(Character::Chipp),
+            "POT" => Ok(Character::Potemkin),
+            "FAU" => Ok(Character::Faust),
+            "MLL" => Ok(Character::Millia),
+            "ZAT" => Ok(Character::Zato),
+            "RAM" => Ok(Character::Ramlethal),
+            "LEO" => Ok(Character::Leo),
+            "NAG" => Ok(Character::Nagoriyuki),
+            "GIO" => Ok(Character::Giovanna),
+            "ANJ" => Ok(Character::Anji),
+            "INO" => Ok(Character::Ino),
+            "GLD" => Ok(Character::Goldlewis),
+            "JKO" => Ok(Character::Jacko),
+            "COS" => Ok(Character::HappyChaos),
+            "BKN" => Ok(Character::Baiken),
+            _ => Err(Error::InvalidCharacterCode(code.to_string())),
         }
     }
+
+    /// Convert a Character back to its three letter code, None if the character is unknown
+    pub fn to_code(&self) -> Option<&'static str> {
+        Some(match self {
+            Character::Sol => "SOL",
+            Character::Ky => "KYK",
+            Character::May => "MAY",
+            Character::Axl => "AXL",
+            Character::Chipp => "CHP",
+            Character::Potemkin => "POT",
+            Character::Faust => "FAU",
+            Character::Millia => "MLL",
+            Character::Zato => "ZAT",
+            Character::Ramlethal => "RAM",
+            Character::Leo => "LEO",
+            Character::Nagoriyuki => "NAG",
+            Character::Giovanna => "GIO",
+            Character::Anji => "ANJ",
+            Character::Ino => "INO",
+            Character::Goldlewis => "GLD",
+            Character::Jacko => "JKO",
+            Character::HappyChaos => "COS",
+            Character::Baiken => "BKN",
+            Character::Unknown(_) => return None,
+        })
+    }
 }
 
 /// Enum mapping for floors present in the game
-#[derive(PartialOrd, Ord, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+///
+/// Floors are ordered by their position in the tower, Celestial is always the maximum.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
@@ -244,6 +766,8 @@ pub enum Floor {
     F9,
     F10,
     Celestial,
+    /// A floor code this crate doesn't know
+    Unknown(u8),
 }
 
 impl Floor {
@@ -263,13 +787,19 @@ impl Floor {
             0x09 => Ok(Floor::F9),
             0x0a => Ok(Floor::F10),
             0x63 => Ok(Floor::Celestial),
-            _ => Err(Error::InvalidArgument(format!(
-                "{:x} is not a valid floor code",
-                c
-            ))),
+            _ => Err(Error::InvalidArgument {
+                parameter: "floor",
+                value: format!("{:x}", c),
+                reason: format!("{:x} is not a valid floor code", c),
+            }),
         }
     }
 
+    /// Same as from_u8 but unknown codes are kept as Floor::Unknown instead of failing
+    pub fn from_u8_lossy(c: u8) -> Self {
+        Floor::from_u8(c).unwrap_or(Floor::Unknown(c))
+    }
+
     pub fn to_u8(self) -> u8 {
         match self {
             Floor::F1 => 1,
@@ -283,6 +813,7 @@ impl Floor {
             Floor::F9 => 9,
             Floor::F10 => 10,
             Floor::Celestial => 99,
+            Floor::Unknown(c) => c,
         }
     }
 
@@ -300,8 +831,197 @@ impl Floor {
             Floor::F9 => "09".into(),
             Floor::F10 => "0a".into(),
             Floor::Celestial => "63".into(),
+            Floor::Unknown(c) => format!("{:02x}", c),
+        }
+    }
+
+    /// The floor number as shown in game, None for Celestial which has no number
+    pub fn number(self) -> Option<u8> {
+        match self {
+            Floor::Celestial | Floor::Unknown(_) => None,
+            floor => Some(floor.to_u8()),
+        }
+    }
+
+    /// The floor directly above this one, None for Celestial
+    pub fn next(self) -> Option<Floor> {
+        match self {
+            Floor::F10 => Some(Floor::Celestial),
+            Floor::Celestial | Floor::Unknown(_) => None,
+            floor => Floor::from_u8(floor.to_u8() + 1).ok(),
         }
     }
+
+    /// The floor directly below this one, None for floor 1
+    pub fn prev(self) -> Option<Floor> {
+        match self {
+            Floor::F1 | Floor::Unknown(_) => None,
+            Floor::Celestial => Some(Floor::F10),
+            floor => Floor::from_u8(floor.to_u8() - 1).ok(),
+        }
+    }
+
+    // Position in the tower used for ordering, independent of declaration order. Unknown floors
+    // are ordered by their code, which can't collide with Celestial, and come right after the
+    // known floor with the same code so that ordering agrees with equality
+    fn rank(self) -> (u16, bool) {
+        match self {
+            Floor::Celestial => (u16::from(u8::MAX) + 1, false),
+            Floor::Unknown(c) => (c.into(), true),
+            floor => (floor.to_u8().into(), false),
+        }
+    }
+}
+
+impl Ord for Floor {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+impl PartialOrd for Floor {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Match counts, unique players and character picks for a single floor
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(crate = "serde_crate"))]
+pub struct FloorStats {
+    pub matches: usize,
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "unique_players", serialize_with = "serialize_len")
+    )]
+    players: BTreeSet<i64>,
+    /// Serialized as a list of [character, count] pairs
+    #[cfg_attr(feature = "serde", serde(serialize_with = "pairs::serialize"))]
+    pub characters: BTreeMap<Character, usize>,
+}
+
+impl FloorStats {
+    /// Number of distinct player ids that played a match on this floor
+    pub fn unique_players(&self) -> usize {
+        self.players.len()
+    }
+}
+
+#[cfg(feature = "serde")]
+fn serialize_len<S: serde_crate::Serializer>(
+    set: &BTreeSet<i64>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_u64(set.len() as u64)
+}
+
+// Maps keyed by floors or characters are serialized as a list of [key, value] pairs like the
+// reports do, since JSON only allows strings as keys and Unknown floors and characters don't
+// serialize as strings
+#[cfg(feature = "serde")]
+pub(crate) mod pairs {
+    use serde_crate::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub(crate) fn serialize<S, K, V>(map: &BTreeMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        K: Serialize,
+        V: Serialize,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub(crate) fn deserialize<'de, D, K, V>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        K: Deserialize<'de> + Ord,
+        V: Deserialize<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+/// Aggregates matches per floor, ordered from floor 1 up to Celestial. Serialized as a list of
+/// [floor, stats] pairs.
+///
+/// ```ignore
+/// let distribution = replays.collect::<FloorDistribution>();
+/// for (floor, stats) in distribution.iter() {
+///     println!("{:?}: {} matches", floor, stats.matches);
+/// }
+/// ```
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize),
+    serde(crate = "serde_crate", transparent)
+)]
+pub struct FloorDistribution {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "pairs::serialize"))]
+    floors: BTreeMap<Floor, FloorStats>,
+}
+
+impl FloorDistribution {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a single match towards the floor it was played on
+    pub fn add(&mut self, m: &Match) {
+        let stats = self.floors.entry(m.floor()).or_default();
+        stats.matches += 1;
+        let (p1, p2) = m.players();
+        for player in [p1, p2] {
+            stats.players.insert(player.id);
+            *stats.characters.entry(player.character).or_default() += 1;
+        }
+    }
+
+    /// Statistics for a single floor, None if no match was played there
+    pub fn floor(&self, floor: Floor) -> Option<&FloorStats> {
+        self.floors.get(&floor)
+    }
+
+    /// Iterate over all floors with at least one match, lowest floor first
+    pub fn iter(&self) -> impl Iterator<Item = (Floor, &FloorStats)> {
+        self.floors.iter().map(|(floor, stats)| (*floor, stats))
+    }
+
+    /// Total number of matches counted across all floors
+    pub fn total_matches(&self) -> usize {
+        self.floors.values().map(|stats| stats.matches).sum()
+    }
+}
+
+impl<'a> Extend<&'a Match> for FloorDistribution {
+    fn extend<I: IntoIterator<Item = &'a Match>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|m| self.add(m));
+    }
+}
+
+impl Extend<Match> for FloorDistribution {
+    fn extend<I: IntoIterator<Item = Match>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|m| self.add(&m));
+    }
+}
+
+impl<'a> FromIterator<&'a Match> for FloorDistribution {
+    fn from_iter<I: IntoIterator<Item = &'a Match>>(iter: I) -> Self {
+        let mut distribution = FloorDistribution::new();
+        distribution.extend(iter);
+        distribution
+    }
+}
+
+impl FromIterator<Match> for FloorDistribution {
+    fn from_iter<I: IntoIterator<Item = Match>>(iter: I) -> Self {
+        let mut distribution = FloorDistribution::new();
+        distribution.extend(iter);
+        distribution
+    }
 }
 
 pub enum NoChar1Set {}
@@ -335,6 +1055,7 @@ pub struct QueryParameters<Char1Status, Char2Status, WinnerStatus, MinFloorStatu
     pub(crate) char_1: Option<Character>,
     pub(crate) char_2: Option<Character>,
     pub(crate) winner: Option<Winner>,
+    pub(crate) player_search: messagepack::PlayerSearch,
     phantom1: PhantomData<Char1Status>,
     phantom2: PhantomData<Char2Status>,
     phantom3: PhantomData<WinnerStatus>,
@@ -352,6 +1073,7 @@ impl Default
             char_1: None,
             char_2: None,
             winner: None,
+            player_search: messagepack::PlayerSearch::All,
             phantom1: PhantomData,
             phantom2: PhantomData,
             phantom3: PhantomData,
@@ -361,6 +1083,17 @@ impl Default
     }
 }
 
+impl<A, B, C, D, E> QueryParameters<A, B, C, D, E> {
+    /// Only search the replays of the player making the request or of players related to them,
+    /// e.g. PlayerSearch::Follow. Every mode other than All requires Context::credentials.
+    pub fn player_search(self, player_search: messagepack::PlayerSearch) -> Self {
+        QueryParameters {
+            player_search,
+            ..self
+        }
+    }
+}
+
 impl<A, B, C, E> QueryParameters<A, B, C, NoMinFloorSet, E> {
     /// Set the minimum floor to query for
     pub fn min_floor(self, floor: Floor) -> QueryParameters<A, B, C, MinFloorSet, E> {
@@ -370,6 +1103,7 @@ impl<A, B, C, E> QueryParameters<A, B, C, NoMinFloorSet, E> {
             char_1: self.char_1,
             char_2: self.char_2,
             winner: self.winner,
+            player_search: self.player_search,
             phantom1: PhantomData,
             phantom2: PhantomData,
             phantom3: PhantomData,
@@ -388,6 +1122,7 @@ impl<A, B, C, D> QueryParameters<A, B, C, D, NoMaxFloorSet> {
             char_1: self.char_1,
             char_2: self.char_2,
             winner: self.winner,
+            player_search: self.player_search,
             phantom1: PhantomData,
             phantom2: PhantomData,
             phantom3: PhantomData,
@@ -406,6 +1141,7 @@ impl<B, C, D, E> QueryParameters<NoChar1Set, B, C, D, E> {
             char_1: Some(character),
             char_2: self.char_2,
             winner: self.winner,
+            player_search: self.player_search,
             phantom1: PhantomData,
             phantom2: PhantomData,
             phantom3: PhantomData,
@@ -424,6 +1160,7 @@ impl<C, D, E> QueryParameters<Char1Set, NoChar2Set, C, D, E> {
             char_1: self.char_1,
             char_2: Some(character),
             winner: self.winner,
+            player_search: self.player_search,
             phantom1: PhantomData,
             phantom2: PhantomData,
             phantom3: PhantomData,
@@ -442,6 +1179,7 @@ impl<B, D, E> QueryParameters<Char1Set, B, NoWinnerSet, D, E> {
             char_1: self.char_1,
             char_2: self.char_2,
             winner: Some(winner),
+            player_search: self.player_search,
             phantom1: PhantomData,
             phantom2: PhantomData,
             phantom3: PhantomData,
@@ -481,8 +1219,392 @@ mod test {
         assert_eq!(p1, p2);
     }
 
+    #[test]
+    fn strict_player() {
+        use std::collections::HashMap;
+
+        let player = |name: &str| Player {
+            id: 2,
+            character: Character::Sol,
+            name: name.into(),
+        };
+        let (old, new) = (player("old"), player("new"));
+        assert_eq!(old, new);
+        assert!(!old.eq_strict(&new));
+        assert!(old.eq_strict(&player("old")));
+
+        let mut by_player = HashMap::<Player, u32>::new();
+        let mut by_appearance = HashMap::<StrictPlayer, u32>::new();
+        for p in [&old, &new, &old] {
+            *by_player.entry(p.clone()).or_default() += 1;
+            *by_appearance.entry(p.clone().into()).or_default() += 1;
+        }
+        assert_eq!(by_player.len(), 1);
+        assert_eq!(by_player[&new], 3);
+        assert_eq!(by_appearance.len(), 2);
+        assert_eq!(by_appearance[&StrictPlayer(old)], 2);
+        assert_eq!(by_appearance[&StrictPlayer(new)], 1);
+    }
+
+    #[test]
+    fn player_identity() {
+        use std::collections::HashMap;
+
+        let player = |character, name: &str| Player {
+            id: 2,
+            character,
+            name: name.into(),
+        };
+        let sol = player(Character::Sol, "old");
+        let ky = player(Character::Ky, "new");
+        assert_ne!(sol, ky);
+        assert_eq!(sol.identity(), ky.identity());
+
+        let mut matches = HashMap::<PlayerIdentity, u32>::new();
+        for p in [&sol, &ky, &sol] {
+            *matches.entry(p.identity()).or_default() += 1;
+        }
+        assert_eq!(matches.len(), 1);
+        let (identity, count) = matches.into_iter().next().unwrap();
+        assert_eq!((identity.name(), count), ("old", 3));
+        assert_eq!(PlayerIdentity::from(ky).to_string(), "new");
+    }
+
+    fn all_floors() -> Vec<Floor> {
+        (0..=u8::MAX)
+            .filter_map(|b| Floor::from_u8(b).ok())
+            .collect()
+    }
+
+    #[test]
+    fn character_codes() {
+        for c in 0..=0x12 {
+            let character = Character::from_u8(c).unwrap();
+            assert_eq!(
+                Character::from_code(character.to_code().unwrap()).unwrap(),
+                character
+            );
+        }
+        assert_eq!(Character::from_code("COS").unwrap(), Character::HappyChaos);
+        match Character::from_code("sol") {
+            Err(Error::InvalidCharacterCode(code)) => assert_eq!(code, "sol"),
+            other => panic!("expected Error::InvalidCharacterCode, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn floor_ordering() {
+        let floors = all_floors();
+        assert_eq!(floors.len(), 11);
+        for a in &floors {
+            assert!(*a <= Floor::Celestial);
+            assert!(*a >= Floor::F1);
+            for b in &floors {
+                assert_eq!(a.cmp(b), a.to_u8().cmp(&b.to_u8()), "{:?} {:?}", a, b);
+                if let (Some(n), Some(m)) = (a.number(), b.number()) {
+                    assert_eq!(n.cmp(&m), a.cmp(b));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_codes() {
+        assert_eq!(Floor::from_u8_lossy(0x0b), Floor::Unknown(0x0b));
+        assert_eq!(Floor::from_u8_lossy(0x63), Floor::Celestial);
+        assert!(Floor::Unknown(0x0b) > Floor::F10);
+        assert!(Floor::Unknown(0xff) < Floor::Celestial);
+        assert_eq!(Floor::Unknown(0x0b).as_hex(), "0b");
+
+        // Unknown can hold the code of a known floor, both must stay separate keys
+        assert!(Floor::Unknown(5) > Floor::F5);
+        assert!(Floor::Unknown(5) < Floor::F6);
+        let floors = BTreeMap::from([(Floor::F5, 1), (Floor::Unknown(5), 2)]);
+        assert_eq!(floors.len(), 2);
+        assert_eq!(floors[&Floor::F5], 1);
+        assert_eq!(floors[&Floor::Unknown(5)], 2);
+
+        let character = Character::from_u8_lossy(0x13);
+        assert_eq!(character, Character::Unknown(0x13));
+        assert_eq!(character.to_u8(), 0x13);
+        assert_eq!(character.to_code(), None);
+        assert_eq!(character.to_string(), "Unknown character 0x13");
+    }
+
+    #[test]
+    fn floor_next_prev() {
+        for floor in all_floors() {
+            if let Some(next) = floor.next() {
+                assert!(next > floor);
+                assert_eq!(next.prev(), Some(floor));
+            }
+            if let Some(prev) = floor.prev() {
+                assert!(prev < floor);
+                assert_eq!(prev.next(), Some(floor));
+            }
+        }
+        assert_eq!(Floor::F10.next(), Some(Floor::Celestial));
+        assert_eq!(Floor::Celestial.next(), None);
+        assert_eq!(Floor::F1.prev(), None);
+        assert_eq!(Floor::F7.number(), Some(7));
+        assert_eq!(Floor::Unknown(0x0b).next(), None);
+        assert_eq!(Floor::Unknown(0x0b).prev(), None);
+        assert_eq!(Floor::Celestial.number(), None);
+
+        let mut floor = Floor::F1;
+        let mut steps = 0;
+        while let Some(next) = floor.next() {
+            floor = next;
+            steps += 1;
+        }
+        assert_eq!((floor, steps), (Floor::Celestial, 10));
+    }
+
+    fn test_match(floor: Floor, p1: (i64, Character), p2: (i64, Character)) -> Match {
+        Match {
+            timestamp: Utc.with_ymd_and_hms(2022, 2, 6, 10, 30, 0).unwrap(),
+            floor,
+            players: (
+                Player {
+                    id: p1.0,
+                    character: p1.1,
+                    name: format!("player{}", p1.0).into(),
+                },
+                Player {
+                    id: p2.0,
+                    character: p2.1,
+                    name: format!("player{}", p2.0).into(),
+                },
+            ),
+            winner: Winner::Player1,
+            id: 1,
+            venue: None,
+        }
+    }
+
+    #[test]
+    fn winner_and_loser() {
+        let m = test_match(Floor::F5, (1, Character::Sol), (2, Character::Ky));
+        assert_eq!(m.winner().id, 1);
+        assert_eq!(m.loser().id, 2);
+        assert_eq!(m.try_winner().map(|p| p.id), Some(1));
+
+        let swapped = Match {
+            winner: Winner::Player2,
+            ..m.clone()
+        };
+        assert_eq!(swapped.winner().id, 2);
+        assert_eq!(swapped.try_loser().map(|p| p.id), Some(1));
+
+        let unknown = Match {
+            winner: Winner::Unknown(3),
+            ..m
+        };
+        assert!(unknown.try_winner().is_none() && unknown.try_loser().is_none());
+        assert!(std::panic::catch_unwind(|| unknown.winner().id).is_err());
+    }
+
+    #[test]
+    fn unix_timestamps() {
+        let m = test_match(Floor::F5, (1, Character::Sol), (2, Character::Ky));
+        assert_eq!(m.timestamp_unix(), 1644143400);
+        let rebuilt = Match::from_unix(
+            m.id,
+            m.timestamp_unix(),
+            m.floor,
+            m.players.clone(),
+            m.winner,
+        )
+        .unwrap();
+        assert_eq!(rebuilt, m);
+        assert!(Match::from_unix(1, i64::MAX, m.floor, m.players, m.winner).is_err());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn time_crate_timestamp() {
+        let m = test_match(Floor::F5, (1, Character::Sol), (2, Character::Ky));
+        let timestamp = m.timestamp_time().unwrap();
+        assert_eq!(timestamp.unix_timestamp(), m.timestamp_unix());
+        assert_eq!(timestamp.offset(), time::UtcOffset::UTC);
+    }
+
+    #[test]
+    fn floor_distribution() {
+        let matches = [
+            test_match(Floor::Celestial, (1, Character::Sol), (2, Character::Ky)),
+            test_match(Floor::F10, (1, Character::Sol), (3, Character::Sol)),
+            test_match(Floor::F10, (3, Character::May), (1, Character::Sol)),
+            test_match(Floor::F1, (4, Character::Baiken), (5, Character::Faust)),
+        ];
+        let distribution = matches.iter().collect::<FloorDistribution>();
+
+        assert_eq!(distribution.total_matches(), 4);
+        assert_eq!(
+            distribution.iter().map(|(f, _)| f).collect::<Vec<_>>(),
+            vec![Floor::F1, Floor::F10, Floor::Celestial]
+        );
+        let f10 = distribution.floor(Floor::F10).unwrap();
+        assert_eq!(f10.matches, 2);
+        assert_eq!(f10.unique_players(), 2);
+        assert_eq!(f10.characters.get(&Character::Sol), Some(&3));
+        assert_eq!(f10.characters.get(&Character::May), Some(&1));
+        assert!(distribution.floor(Floor::F5).is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn floor_distribution_json() {
+        let distribution = vec![
+            test_match(Floor::F10, (1, Character::Sol), (3, Character::Sol)),
+            test_match(Floor::Celestial, (1, Character::Sol), (2, Character::Ky)),
+        ]
+        .into_iter()
+        .collect::<FloorDistribution>();
+
+        expect_test::expect![[r#"[["F10",{"matches":1,"unique_players":2,"characters":[["Sol",2]]}],["Celestial",{"matches":1,"unique_players":2,"characters":[["Sol",1],["Ky",1]]}]]"#]]
+            .assert_eq(&serde_json::to_string(&distribution).unwrap());
+
+        let distribution = vec![
+            test_match(
+                Floor::F10,
+                (1, Character::Sol),
+                (2, Character::Unknown(0x13)),
+            ),
+            test_match(
+                Floor::Unknown(0x0b),
+                (1, Character::Sol),
+                (3, Character::Ky),
+            ),
+        ]
+        .into_iter()
+        .collect::<FloorDistribution>();
+        let json = serde_json::to_string(&distribution).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            serde_json::json!([
+                ["F10", {
+                    "matches": 1,
+                    "unique_players": 2,
+                    "characters": [["Sol", 1], [{ "Unknown": 0x13 }, 1]],
+                }],
+                [{ "Unknown": 0x0b }, {
+                    "matches": 1,
+                    "unique_players": 2,
+                    "characters": [["Sol", 1], ["Ky", 1]],
+                }],
+            ])
+        );
+    }
+
+    #[test]
+    fn multibyte_names() {
+        let replays = decode_replay_response(
+            include_bytes!("../test_data/replay_response_4.msgpack"),
+            DecodeOptions::default(),
+        )
+        .unwrap();
+        let m = replays
+            .matches()
+            .find(|m| [m.players.0.name(), m.players.1.name()].contains(&"信州無敵の桃太郎"))
+            .unwrap();
+        assert!(m.to_string().contains("信州無敵の桃太郎 as "));
+        for player in replays.matches().flat_map(|m| [&m.players.0, &m.players.1]) {
+            assert_eq!(sanitize_for_terminal(player.name()), player.name());
+            assert!(matches!(
+                sanitize_for_terminal(player.name()),
+                std::borrow::Cow::Borrowed(_)
+            ));
+        }
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(m).unwrap();
+            assert!(json.contains(r#""name":"信州無敵の桃太郎""#));
+            assert!(!json.contains("\\u"));
+            assert_eq!(&serde_json::from_str::<Match>(&json).unwrap(), m);
+        }
+    }
+
+    #[test]
+    fn share_codes() {
+        let fixtures: [&[u8]; 4] = [
+            include_bytes!("../test_data/replay_response_1.msgpack"),
+            include_bytes!("../test_data/replay_response_2.msgpack"),
+            include_bytes!("../test_data/replay_response_3.msgpack"),
+            include_bytes!("../test_data/replay_response_4.msgpack"),
+        ];
+        let mut ids = 0;
+        for fixture in fixtures {
+            let replays = decode_replay_response(fixture, DecodeOptions::default()).unwrap();
+            for m in replays.matches() {
+                let code = m.share_code();
+                assert_eq!(code.to_string(), m.id().to_string());
+                for format in [ShareFormat::Decimal, ShareFormat::Hex] {
+                    assert_eq!(code.format(format).parse::<ShareCode>().unwrap(), code);
+                }
+                ids += 1;
+            }
+        }
+        assert_eq!(ids, 70);
+
+        let code = ShareCode(220206103032458670);
+        assert_eq!(code.to_string(), "220206103032458670");
+        assert_eq!(code.format(ShareFormat::Hex), "0x30e5448b49f6dae");
+        assert_eq!(" 0X30E5448B49F6DAE\n".parse::<ShareCode>().unwrap(), code);
+        for invalid in ["", "0x", "+1", "-1", "12a", "0x1g", "18446744073709551616"] {
+            assert!(invalid.parse::<ShareCode>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn sanitize_names() {
+        assert_eq!(sanitize_for_terminal("ふわ🔥"), "ふわ🔥");
+        assert_eq!(sanitize_for_terminal("KOIBITO！"), "KOIBITO！");
+        assert_eq!(sanitize_for_terminal("\u{202E}evil\u{202C}"), "evil");
+        assert_eq!(sanitize_for_terminal("a\u{2067}b\u{2069}\u{200F}c"), "abc");
+        assert_eq!(sanitize_for_terminal("\x1b[2Jclear\r\n"), "[2Jclear");
+        assert_eq!(sanitize_for_terminal("tab\tname\u{7f}"), "tabname");
+    }
+
     #[tokio::test]
     async fn query_replays() {
+        use crate::transport::*;
+        let fixtures: [&[u8]; 4] = [
+            include_bytes!("../test_data/replay_response_1.msgpack"),
+            include_bytes!("../test_data/replay_response_2.msgpack"),
+            include_bytes!("../test_data/replay_response_3.msgpack"),
+            include_bytes!("../test_data/replay_response_4.msgpack"),
+        ];
+        let transport = fixtures
+            .iter()
+            .enumerate()
+            .fold(MockTransport::new(), |transport, (i, fixture)| {
+                transport.page(i, RawResponse::ok(fixture.to_vec()))
+            });
+        let transport = std::sync::Arc::new(transport);
+        let ctx = Context::default().transport(transport.clone());
+        let (replays, errors) = get_replays(
+            &ctx,
+            fixtures.len(),
+            127,
+            QueryParameters::default()
+                .min_floor(Floor::F1)
+                .max_floor(Floor::Celestial),
+        )
+        .await
+        .unwrap()
+        .into_parts();
+
+        assert_eq!(transport.requests().len(), fixtures.len());
+        assert_eq!(replays.count(), 60);
+        assert_eq!(errors.count(), 0);
+    }
+
+    // Queries the real API, run with `cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn query_replays_live() {
         use crate::*;
         let ctx = Context::default();
         let n_pages = 100;
@@ -496,7 +1618,8 @@ mod test {
                 .max_floor(Floor::Celestial),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .into_parts();
         let replays = replays
             .filter(|m| m.timestamp() < &Utc::now())
             .collect::<Vec<_>>();
@@ -0,0 +1,125 @@
+//! Counters for monitoring a client, see Context::metrics.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Receives counts from the request and parse paths. Every method defaults to doing nothing, so
+/// an implementation only has to override what it records. endpoint is the path of the API
+/// endpoint, e.g. /api/catalog/get_replay.
+pub trait Metrics: Send + Sync {
+    /// A request is about to be sent, including every retry
+    fn on_request(&self, endpoint: &'static str) {
+        let _ = endpoint;
+    }
+
+    /// A response body of the given size was received
+    fn on_bytes_received(&self, endpoint: &'static str, bytes: usize) {
+        let _ = (endpoint, bytes);
+    }
+
+    /// Matches which were added to the result of a replay query, duplicates are not counted
+    fn on_matches_parsed(&self, count: usize) {
+        let _ = count;
+    }
+
+    /// A response or a single replay could not be parsed
+    fn on_parse_error(&self, endpoint: &'static str) {
+        let _ = endpoint;
+    }
+
+    /// A failed request is going to be retried
+    fn on_retry(&self, endpoint: &'static str) {
+        let _ = endpoint;
+    }
+}
+
+/// Metrics which discard every count, the default of Context
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
+
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+    fn on_request(&self, endpoint: &'static str) {
+        (**self).on_request(endpoint)
+    }
+
+    fn on_bytes_received(&self, endpoint: &'static str, bytes: usize) {
+        (**self).on_bytes_received(endpoint, bytes)
+    }
+
+    fn on_matches_parsed(&self, count: usize) {
+        (**self).on_matches_parsed(count)
+    }
+
+    fn on_parse_error(&self, endpoint: &'static str) {
+        (**self).on_parse_error(endpoint)
+    }
+
+    fn on_retry(&self, endpoint: &'static str) {
+        (**self).on_retry(endpoint)
+    }
+}
+
+/// Totals across all endpoints kept in atomics. Pass it to Context::metrics wrapped in an Arc to
+/// read the counters while the context is in use.
+#[derive(Debug, Default)]
+pub struct AtomicMetrics {
+    requests: AtomicU64,
+    bytes_received: AtomicU64,
+    matches_parsed: AtomicU64,
+    parse_errors: AtomicU64,
+    retries: AtomicU64,
+}
+
+impl AtomicMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub fn matches_parsed(&self) -> u64 {
+        self.matches_parsed.load(Ordering::Relaxed)
+    }
+
+    pub fn parse_errors(&self) -> u64 {
+        self.parse_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+}
+
+impl Metrics for AtomicMetrics {
+    fn on_request(&self, _: &'static str) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_bytes_received(&self, _: &'static str, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn on_matches_parsed(&self, count: usize) {
+        self.matches_parsed
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn on_parse_error(&self, _: &'static str) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_retry(&self, _: &'static str) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+}
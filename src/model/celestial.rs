@@ -0,0 +1,92 @@
+//! Progress towards the celestial floor.
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+// Keys of the statistics payload which belong to the celestial checks
+const KEYS: [&str; 8] = [
+    "RankCheckMatch",
+    "RankCheckPt",
+    "RankCheckTest",
+    "VipCheckMatch",
+    "VipCheckPt",
+    "VipStatus",
+    "MaxVipStatus",
+    "PrevVipStatus",
+];
+
+/// State of the matches a player has to win on floor 10 to be promoted to celestial, and the
+/// matches a celestial player has to win to stay there. The meaning is inferred from the key
+/// names and a few captured payloads, use raw for anything not covered by the typed fields.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct CelestialProgress {
+    /// Matches played in the current promotion attempt, RankCheckMatch
+    pub attempts: u64,
+    /// Points collected in the current promotion attempt, RankCheckPt
+    pub points: i64,
+    /// Stage of the promotion check, RankCheckTest. Observed as 7 for a floor 9 player who never
+    /// attempted it, the other values are unknown
+    pub phase: i64,
+    /// Matches and points of the check for staying celestial, VipCheckMatch and VipCheckPt
+    pub vip_attempts: u64,
+    pub vip_points: i64,
+    /// All celestial related keys as sent, including VipStatus, MaxVipStatus and PrevVipStatus
+    pub raw: BTreeMap<String, i64>,
+}
+
+impl CelestialProgress {
+    /// Read the RankCheck and Vip keys of the statistics_type 7 payload. Missing keys are left at
+    /// 0 and out of raw.
+    pub fn from_statistics(json: &serde_json::Value) -> Self {
+        let raw = KEYS
+            .iter()
+            .filter_map(|key| Some((key.to_string(), json[key].as_i64()?)))
+            .collect::<BTreeMap<_, _>>();
+        let value = |key: &str| raw.get(key).copied().unwrap_or(0);
+        CelestialProgress {
+            attempts: value("RankCheckMatch").try_into().unwrap_or(0),
+            points: value("RankCheckPt"),
+            phase: value("RankCheckTest"),
+            vip_attempts: value("VipCheckMatch").try_into().unwrap_or(0),
+            vip_points: value("VipCheckPt"),
+            raw,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_from_statistics() {
+        let json = serde_json::json!({
+            "RankCheckMatch": 3,
+            "RankCheckPt": -2,
+            "RankCheckTest": 1,
+            "VipStatus": 2,
+            "NickName": "GG Player",
+        });
+        let progress = CelestialProgress::from_statistics(&json);
+        assert_eq!(progress.attempts, 3);
+        assert_eq!(progress.points, -2);
+        assert_eq!(progress.phase, 1);
+        assert_eq!(progress.vip_attempts, 0);
+        assert_eq!(
+            progress.raw.keys().collect::<Vec<_>>(),
+            [
+                "RankCheckMatch",
+                "RankCheckPt",
+                "RankCheckTest",
+                "VipStatus"
+            ]
+        );
+    }
+}
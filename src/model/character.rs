@@ -0,0 +1,141 @@
+//! Statistics the game keeps for every character of a player.
+
+use crate::Character;
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Progress of a player with a single character
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Stats {
+    pub level: u64,
+    /// Wins the game counts for the character, taken from the PM_Wins key. It stays 0 in every
+    /// captured response, which all belong to a player who hid their data, see
+    /// user::ProfileVisibility.
+    pub wins: u64,
+    /// Experience collected so far, the next level is reached at next_level_exp
+    pub exp: u64,
+    pub next_level_exp: u64,
+    /// Consecutive wins up to now
+    pub win_chain: u64,
+    /// Highest number of consecutive wins ever reached
+    pub max_win_chain: u64,
+}
+
+/// Collect the stats of every character from the payload of statistics_type 7. Its keys are
+/// prefixed with the character code, e.g. COS_Lv or GIO_WinChainMax. Keys of unknown characters
+/// and values which aren't unsigned integers are skipped.
+pub fn from_statistics(json: &serde_json::Value) -> HashMap<Character, Stats> {
+    let mut stats = HashMap::<Character, Stats>::new();
+    let Some(object) = json.as_object() else {
+        return stats;
+    };
+    for (key, value) in object {
+        let Some((code, field)) = key.split_once('_') else {
+            continue;
+        };
+        let (Ok(character), Some(value)) = (Character::from_code(code), value.as_u64()) else {
+            continue;
+        };
+        let entry = stats.entry(character);
+        match field {
+            "Lv" => entry.or_default().level = value,
+            "PM_Wins" => entry.or_default().wins = value,
+            "Exp" => entry.or_default().exp = value,
+            "NextLvExp" => entry.or_default().next_level_exp = value,
+            "WinChainNow" => entry.or_default().win_chain = value,
+            "WinChainMax" => entry.or_default().max_win_chain = value,
+            _ => {}
+        }
+    }
+    stats
+}
+
+// Maps keyed by character are written with the three letter codes as keys, which works for
+// formats that only allow string keys and keeps the output ordered
+#[cfg(feature = "serde")]
+pub(crate) mod code_map {
+    use crate::Character;
+    use serde_crate::{
+        de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer,
+    };
+    use std::collections::{BTreeMap, HashMap};
+
+    pub(crate) fn serialize<S, V>(
+        map: &HashMap<Character, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        map.iter()
+            .map(|(character, value)| {
+                let code = character.to_code().ok_or_else(|| {
+                    S::Error::custom(format!("{} has no character code", character))
+                })?;
+                Ok((code, value))
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()?
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D, V>(deserializer: D) -> Result<HashMap<Character, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        BTreeMap::<String, V>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(code, value)| {
+                let character = Character::from_code(&code).map_err(D::Error::custom)?;
+                Ok((character, value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_from_statistics() {
+        let json = serde_json::json!({
+            "GIO_Lv": 893,
+            "GIO_Exp": 18017260,
+            "GIO_NextLvExp": 18042500,
+            "GIO_PM_Wins": 0,
+            "GIO_WinChainMax": 53,
+            "GIO_WinChainNow": 7,
+            "GIO_Badge1": 503009,
+            "SOL_Lv": 1,
+            "XYZ_Lv": 12,
+            "Update_Day": 13,
+            "Demoted_RC": 0,
+            "NickName": "GG Player",
+            "RAM_Lv": -1,
+        });
+        let stats = from_statistics(&json);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats[&Character::Giovanna],
+            Stats {
+                level: 893,
+                wins: 0,
+                exp: 18017260,
+                next_level_exp: 18042500,
+                win_chain: 7,
+                max_win_chain: 53,
+            }
+        );
+        assert_eq!(stats[&Character::Sol].level, 1);
+        assert!(from_statistics(&serde_json::json!([1, 2])).is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+//! Player profiles as shown in the game, assembled from the statistics endpoint.
+
+pub mod celestial;
+pub mod character;
+pub mod user;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Number of matches played and won
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct MatchStats {
+    pub total: u64,
+    pub wins: u64,
+}
+
+impl MatchStats {
+    pub fn losses(&self) -> u64 {
+        self.total.saturating_sub(self.wins)
+    }
+
+    /// Share of matches won between 0 and 1, None if no match was played
+    pub fn winrate(&self) -> Option<f64> {
+        if self.total == 0 {
+            None
+        } else {
+            Some(self.wins as f64 / self.total as f64)
+        }
+    }
+
+    /// Add the matches of other, e.g. to combine the stats of several sources or characters
+    pub fn merge(&mut self, other: MatchStats) {
+        self.total += other.total;
+        self.wins += other.wins;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_stats() {
+        let mut stats = MatchStats { total: 8, wins: 6 };
+        assert_eq!(stats.losses(), 2);
+        assert_eq!(stats.winrate(), Some(0.75));
+
+        stats.merge(MatchStats { total: 2, wins: 0 });
+        assert_eq!(stats, MatchStats { total: 10, wins: 6 });
+        assert_eq!(stats.winrate(), Some(0.6));
+
+        let empty = MatchStats::default();
+        assert_eq!(empty.losses(), 0);
+        assert_eq!(empty.winrate(), None);
+        assert_eq!(MatchStats { total: 1, wins: 3 }.losses(), 0);
+    }
+}
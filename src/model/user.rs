@@ -0,0 +1,380 @@
+//! A player's profile.
+
+use super::{celestial::CelestialProgress, character, MatchStats};
+use crate::{error::*, Character, Floor};
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Profile of a player
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub comment: String,
+    pub floor: Floor,
+    /// Highest floor the player ever reached
+    pub max_floor: Floor,
+    /// Floor before the last promotion or demotion, see floor_change
+    pub prev_floor: Floor,
+    /// Time spent in the game
+    pub playtime: Duration,
+    /// Number of ranked matches played
+    pub rank_matches: u64,
+    /// Consecutive wins across all characters up to now
+    pub win_chain: u64,
+    /// Highest number of consecutive wins across all characters
+    pub max_win_chain: u64,
+    /// Matches played in the tower
+    pub stats: MatchStats,
+    /// Matches played on the celestial floor
+    pub celestial_stats: MatchStats,
+    /// Promotion to and relegation from the celestial floor
+    pub celestial: CelestialProgress,
+    #[cfg_attr(feature = "serde", serde(with = "character::code_map"))]
+    pub char_stats: HashMap<Character, character::Stats>,
+    /// Hidden profiles still show levels and win chains, but the per character wins are zeroes
+    /// rather than real numbers
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub visibility: ProfileVisibility,
+}
+
+impl User {
+    /// Build a profile from the payload of statistics_type 7. The payload doesn't contain match
+    /// counts, stats and celestial_stats are left empty.
+    pub fn from_statistics(json: &serde_json::Value) -> Result<Self> {
+        Ok(User {
+            id: field(json, "UserID", serde_json::Value::as_i64)?,
+            name: field(json, "NickName", serde_json::Value::as_str)?.to_string(),
+            comment: field(json, "PublicComment", serde_json::Value::as_str)?.to_string(),
+            floor: floor(json, "LobbyRank")?,
+            max_floor: floor(json, "MaxLobbyRank")?,
+            prev_floor: floor(json, "PrevLobbyRank")?,
+            playtime: Duration::from_secs(field(json, "TotalPlayTime", serde_json::Value::as_u64)?),
+            rank_matches: field(json, "TotalRankMatch", serde_json::Value::as_u64)?,
+            win_chain: field(json, "PlayerWinChainNow", serde_json::Value::as_u64)?,
+            max_win_chain: field(json, "PlayerWinChainMax", serde_json::Value::as_u64)?,
+            stats: MatchStats::default(),
+            celestial_stats: MatchStats::default(),
+            celestial: CelestialProgress::from_statistics(json),
+            char_stats: character::from_statistics(json),
+            visibility: ProfileVisibility::from_statistics(json),
+        })
+    }
+
+    /// Whether the player hid their R-code data, see visibility
+    pub fn is_hidden(&self) -> bool {
+        self.visibility == ProfileVisibility::Hidden
+    }
+
+    /// Whether the player was promoted or demoted the last time their floor changed
+    pub fn floor_change(&self) -> FloorChange {
+        match self.floor.cmp(&self.prev_floor) {
+            std::cmp::Ordering::Greater => FloorChange::Promoted,
+            std::cmp::Ordering::Less => FloorChange::Demoted,
+            std::cmp::Ordering::Equal => FloorChange::Unchanged,
+        }
+    }
+
+    /// The n characters with the highest level, ties are broken by wins
+    pub fn best_characters(&self, n: usize) -> Vec<(Character, &character::Stats)> {
+        let mut characters = self
+            .char_stats
+            .iter()
+            .map(|(character, stats)| (*character, stats))
+            .collect::<Vec<_>>();
+        characters.sort_by(|(a, a_stats), (b, b_stats)| {
+            (b_stats.level, b_stats.wins)
+                .cmp(&(a_stats.level, a_stats.wins))
+                .then(a.cmp(b))
+        });
+        characters.truncate(n);
+        characters
+    }
+}
+
+/// Whether the R-code data of a player is public. Hidden profiles are marked with DataHidden 1 in
+/// the statistics payload, which still contains the name, floors, levels and win chains but none
+/// of the numbers that are only shown in the R-code.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum ProfileVisibility {
+    #[default]
+    Visible,
+    Hidden,
+}
+
+impl ProfileVisibility {
+    /// Read the DataHidden key of the statistics payload, a missing key counts as visible
+    pub fn from_statistics(json: &serde_json::Value) -> Self {
+        match json["DataHidden"].as_i64() {
+            Some(hidden) if hidden != 0 => ProfileVisibility::Hidden,
+            _ => ProfileVisibility::Visible,
+        }
+    }
+}
+
+/// The statistics payload of a player as it was sent, see requests::get_statistics
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statistics {
+    pub user_id: i64,
+    pub visibility: ProfileVisibility,
+    pub json: serde_json::Value,
+}
+
+impl Statistics {
+    pub fn new(user_id: i64, json: serde_json::Value) -> Self {
+        Statistics {
+            user_id,
+            visibility: ProfileVisibility::from_statistics(&json),
+            json,
+        }
+    }
+
+    /// The profile contained in the payload
+    pub fn user(&self) -> Result<User> {
+        User::from_statistics(&self.json)
+    }
+}
+
+/// Movement between the previous and the current floor of a player
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum FloorChange {
+    Promoted,
+    Demoted,
+    Unchanged,
+}
+
+fn floor(json: &serde_json::Value, key: &'static str) -> Result<Floor> {
+    field(json, key, |value| value.as_u64()?.try_into().ok()).map(Floor::from_u8_lossy)
+}
+
+// Look up a key of the statistics payload and convert its value
+fn field<'a, T>(
+    json: &'a serde_json::Value,
+    key: &'static str,
+    convert: impl FnOnce(&'a serde_json::Value) -> Option<T>,
+) -> Result<T> {
+    let value = &json[key];
+    convert(value).ok_or_else(|| Error::ParsingBytesError {
+        field: key,
+        value: value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields() {
+        let json = serde_json::json!({
+            "UserID": 220120010822189979u64,
+            "NickName": "GG Player",
+            "LobbyRank": 9,
+        });
+        assert!(matches!(
+            User::from_statistics(&json),
+            Err(Error::ParsingBytesError {
+                field: "PublicComment",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn visibility() {
+        use serde_json::json;
+        let visibility = |json| ProfileVisibility::from_statistics(&json);
+        assert_eq!(
+            visibility(json!({"DataHidden": 1})),
+            ProfileVisibility::Hidden
+        );
+        assert_eq!(
+            visibility(json!({"DataHidden": 0})),
+            ProfileVisibility::Visible
+        );
+        assert_eq!(visibility(json!({})), ProfileVisibility::Visible);
+
+        let statistics = Statistics::new(1, json!({"DataHidden": 1, "UserID": 1}));
+        assert_eq!(statistics.visibility, ProfileVisibility::Hidden);
+        assert!(statistics.user().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn visibility_defaults_to_visible() {
+        let mut json = serde_json::to_value(test_user()).unwrap();
+        json.as_object_mut().unwrap().remove("visibility");
+        let user = serde_json::from_value::<User>(json).unwrap();
+        assert_eq!(user.visibility, ProfileVisibility::Visible);
+    }
+
+    #[test]
+    fn best_characters() {
+        let stats = |level, wins| character::Stats {
+            level,
+            wins,
+            ..Default::default()
+        };
+        let user = User {
+            id: 1,
+            name: "".into(),
+            comment: "".into(),
+            floor: Floor::F10,
+            max_floor: Floor::F10,
+            prev_floor: Floor::F9,
+            playtime: Duration::ZERO,
+            rank_matches: 0,
+            win_chain: 0,
+            max_win_chain: 0,
+            stats: MatchStats::default(),
+            celestial_stats: MatchStats::default(),
+            celestial: CelestialProgress::default(),
+            visibility: ProfileVisibility::Visible,
+            char_stats: HashMap::from([
+                (Character::Sol, stats(1, 0)),
+                (Character::Ky, stats(40, 3)),
+                (Character::May, stats(40, 9)),
+                (Character::Axl, stats(12, 50)),
+            ]),
+        };
+        let best = |n| {
+            user.best_characters(n)
+                .into_iter()
+                .map(|(character, _)| character)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(best(3), [Character::May, Character::Ky, Character::Axl]);
+        assert_eq!(best(10).len(), 4);
+        assert!(best(0).is_empty());
+    }
+
+    #[test]
+    fn floor_change() {
+        let mut user = User {
+            id: 1,
+            name: "".into(),
+            comment: "".into(),
+            floor: Floor::Celestial,
+            max_floor: Floor::Celestial,
+            prev_floor: Floor::F10,
+            playtime: Duration::ZERO,
+            rank_matches: 0,
+            win_chain: 0,
+            max_win_chain: 0,
+            stats: MatchStats::default(),
+            celestial_stats: MatchStats::default(),
+            celestial: CelestialProgress::default(),
+            char_stats: HashMap::new(),
+            visibility: ProfileVisibility::Visible,
+        };
+        assert_eq!(user.floor_change(), FloorChange::Promoted);
+        user.floor = Floor::F8;
+        assert_eq!(user.floor_change(), FloorChange::Demoted);
+        user.prev_floor = Floor::F8;
+        assert_eq!(user.floor_change(), FloorChange::Unchanged);
+    }
+
+    #[cfg(feature = "serde")]
+    fn test_user() -> User {
+        User {
+            id: 220120010822189979,
+            name: "GG Player".into(),
+            comment: "Good luck!".into(),
+            floor: Floor::F9,
+            max_floor: Floor::F9,
+            prev_floor: Floor::F9,
+            playtime: Duration::from_secs(30989481),
+            rank_matches: 2020,
+            win_chain: 10,
+            max_win_chain: 118,
+            stats: MatchStats {
+                total: 2020,
+                wins: 1071,
+            },
+            celestial_stats: MatchStats { total: 7, wins: 3 },
+            celestial: CelestialProgress {
+                phase: 7,
+                raw: [
+                    ("RankCheckTest".to_string(), 7),
+                    ("VipStatus".to_string(), 2),
+                ]
+                .into(),
+                ..Default::default()
+            },
+            char_stats: HashMap::from([
+                (
+                    Character::HappyChaos,
+                    character::Stats {
+                        level: 1546,
+                        wins: 0,
+                        exp: 75887154,
+                        next_level_exp: 75922500,
+                        win_chain: 10,
+                        max_win_chain: 118,
+                    },
+                ),
+                (
+                    Character::Giovanna,
+                    character::Stats {
+                        level: 893,
+                        wins: 0,
+                        exp: 18017260,
+                        next_level_exp: 18042500,
+                        win_chain: 7,
+                        max_win_chain: 53,
+                    },
+                ),
+                (
+                    Character::Sol,
+                    character::Stats {
+                        level: 1,
+                        next_level_exp: 100,
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            visibility: ProfileVisibility::Hidden,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn user_json() {
+        let user = test_user();
+        let json = serde_json::to_string_pretty(&user).unwrap();
+        expect_test::expect_file!["../../test_data/user.json"].assert_eq(&json);
+        assert_eq!(serde_json::from_str::<User>(&json).unwrap(), user);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn unknown_character_key() {
+        let mut user = test_user();
+        user.char_stats
+            .insert(Character::Unknown(0x13), character::Stats::default());
+        assert!(serde_json::to_string(&user).is_err());
+
+        let json = serde_json::to_string(&test_user())
+            .unwrap()
+            .replace("\"SOL\"", "\"XYZ\"");
+        assert!(serde_json::from_str::<User>(&json).is_err());
+    }
+}
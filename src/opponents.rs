@@ -0,0 +1,132 @@
+//! Opponent diversity of a single player, how many different players they meet and how often they
+//! stay for a rematch.
+
+use crate::{sessions, Match};
+use std::collections::HashSet;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct OpponentReport {
+    pub matches: u64,
+    pub distinct_opponents: u64,
+    /// Sessions as grouped by sessions::group_with_gap
+    pub sessions: u64,
+    /// Matches that continue a session, every match of a session except the first
+    pub rematches: u64,
+    /// rematches / matches, None without matches
+    pub rematch_rate: Option<f64>,
+    /// Most matches in a single session
+    pub longest_run: u64,
+    /// Opponent of the first session with longest_run matches
+    pub longest_run_opponent: Option<i64>,
+}
+
+/// Report with sessions grouped with sessions::DEFAULT_GAP, see report_with_gap
+pub fn report<'a, I>(player_id: i64, matches: I) -> OpponentReport
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    report_with_gap(player_id, matches, sessions::DEFAULT_GAP)
+}
+
+/// Count the opponents of the player and how often they played again right away. The matches
+/// don't have to be sorted, matches without the player and repeated replay ids are ignored.
+pub fn report_with_gap<'a, I>(player_id: i64, matches: I, gap: Duration) -> OpponentReport
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let mut seen = HashSet::new();
+    let own = matches.into_iter().filter(|m| {
+        (m.players.0.id == player_id || m.players.1.id == player_id) && seen.insert(m.id)
+    });
+
+    let mut report = OpponentReport::default();
+    let mut opponents = HashSet::new();
+    for session in sessions::group_with_gap(own, gap) {
+        let length = session.matches.len() as u64;
+        let opponent = if session.players.0 == player_id {
+            session.players.1
+        } else {
+            session.players.0
+        };
+        opponents.insert(opponent);
+        report.matches += length;
+        report.sessions += 1;
+        report.rematches += length - 1;
+        if length > report.longest_run {
+            report.longest_run = length;
+            report.longest_run_opponent = Some(opponent);
+        }
+    }
+    report.distinct_opponents = opponents.len() as u64;
+    report.rematch_rate =
+        (report.matches > 0).then(|| report.rematches as f64 / report.matches as f64);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Character, Player};
+
+    const ME: i64 = 1;
+
+    fn game(minute: i64, opponent: i64) -> Match {
+        let player = |id| Player::test(id, Character::Sol);
+        Match::test(minute as u64, minute * 60, (player(ME), player(opponent)))
+    }
+
+    #[test]
+    fn diversity_and_rematches() {
+        let mut matches = vec![
+            game(0, 2),
+            game(3, 2),
+            game(6, 2),
+            game(9, 3),
+            game(12, 4),
+            game(15, 4),
+            // Too long after the last match against 2 for a rematch
+            game(40, 2),
+            game(43, 2),
+            game(46, 2),
+            game(49, 2),
+        ];
+        // Unrelated matches and repeated pages are ignored, order doesn't matter
+        let mut unrelated = game(50, 5);
+        unrelated.players.0.id = 6;
+        matches.push(unrelated);
+        matches.push(matches[0].clone());
+        matches.reverse();
+
+        let report = report(ME, &matches);
+        assert_eq!(
+            report,
+            OpponentReport {
+                matches: 10,
+                distinct_opponents: 3,
+                sessions: 4,
+                rematches: 6,
+                rematch_rate: Some(0.6),
+                longest_run: 4,
+                longest_run_opponent: Some(2),
+            }
+        );
+
+        let report = report_with_gap(ME, &matches, Duration::from_secs(60 * 60));
+        assert_eq!(report.sessions, 3);
+        assert_eq!(report.longest_run, 7);
+    }
+
+    #[test]
+    fn no_matches() {
+        assert_eq!(report(ME, &[]), OpponentReport::default());
+    }
+}
@@ -0,0 +1,171 @@
+//! Floor changes of a single player inferred from the floors their matches were played on.
+
+use crate::{Floor, Match};
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Number of consecutive matches on a new floor before it counts as a change. Single matches on
+/// another floor happen in parties and rooms and are ignored.
+pub const MIN_MATCHES_ON_FLOOR: usize = 2;
+
+/// The player was on floor from timestamp on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct FloorChange {
+    /// Time of the first match on the new floor
+    pub timestamp: DateTime<Utc>,
+    pub floor: Floor,
+    /// None for the floor of the player's first matches
+    pub previous: Option<Floor>,
+}
+
+impl FloorChange {
+    pub fn is_promotion(&self) -> bool {
+        self.previous.is_some_and(|previous| self.floor > previous)
+    }
+
+    pub fn is_demotion(&self) -> bool {
+        self.previous.is_some_and(|previous| self.floor < previous)
+    }
+}
+
+/// Infer the floors of the player over time. The first entry is the floor the player started on,
+/// every following entry is a change confirmed by MIN_MATCHES_ON_FLOOR consecutive matches. The
+/// matches don't have to be sorted, matches without the player and repeated replay ids are ignored.
+/// If no floor is confirmed the floor of the first match is used.
+pub fn floor_history<'a, I>(player_id: i64, matches: I) -> Vec<FloorChange>
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let mut seen = HashSet::new();
+    let mut matches = matches
+        .into_iter()
+        .filter(|m| {
+            (m.players.0.id == player_id || m.players.1.id == player_id) && seen.insert(m.id)
+        })
+        .collect::<Vec<_>>();
+    matches.sort_by_key(|m| m.timestamp);
+
+    // Runs of consecutive matches on the same floor as (floor, first timestamp, length)
+    let mut runs: Vec<(Floor, DateTime<Utc>, usize)> = vec![];
+    for m in &matches {
+        match runs.last_mut() {
+            Some((floor, _, length)) if *floor == m.floor => *length += 1,
+            _ => runs.push((m.floor, m.timestamp, 1)),
+        }
+    }
+
+    let mut history: Vec<FloorChange> = vec![];
+    for &(floor, timestamp, length) in &runs {
+        let previous = history.last().map(|change| change.floor);
+        if length >= MIN_MATCHES_ON_FLOOR && previous != Some(floor) {
+            history.push(FloorChange {
+                timestamp,
+                floor,
+                previous,
+            });
+        }
+    }
+    if let Some(&(floor, timestamp, _)) = runs.first().filter(|_| history.is_empty()) {
+        history.push(FloorChange {
+            timestamp,
+            floor,
+            previous: None,
+        });
+    }
+    history
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Character, Player};
+    use chrono::TimeZone;
+
+    const ME: i64 = 1;
+
+    fn game(i: u64, floor: Floor) -> Match {
+        let player = |id| Player::test(id, Character::Sol);
+        let players = if i % 2 == 1 {
+            (player(2), player(ME))
+        } else {
+            (player(ME), player(2))
+        };
+        Match {
+            floor,
+            ..Match::test(i, i as i64 * 60, players)
+        }
+    }
+
+    fn at(i: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_644_000_000 + i * 60, 0).unwrap()
+    }
+
+    #[test]
+    fn promotion_demotion_and_celestial() {
+        use Floor::*;
+        let floors = [
+            F8, F8, F8, // start
+            F9, F9, // promotion
+            F5, // room match, ignored
+            F9, F8, F8, // demotion
+            F9, F9, F10, F10, F10, // two promotions
+            Celestial, Celestial, // qualification
+            F10,       // single blip at the end isn't confirmed
+        ];
+        let mut matches = floors
+            .iter()
+            .enumerate()
+            .map(|(i, &floor)| game(i as u64, floor))
+            .collect::<Vec<_>>();
+        let mut unrelated = game(100, F1);
+        unrelated.players.1.id = 3;
+        unrelated.players.0.id = 3;
+        matches.push(unrelated);
+        matches.push(matches[0].clone());
+        matches.reverse();
+
+        let history = floor_history(ME, &matches);
+        let change = |i, floor, previous| FloorChange {
+            timestamp: at(i),
+            floor,
+            previous,
+        };
+        assert_eq!(
+            history,
+            [
+                change(0, F8, None),
+                change(3, F9, Some(F8)),
+                change(7, F8, Some(F9)),
+                change(9, F9, Some(F8)),
+                change(11, F10, Some(F9)),
+                change(14, Celestial, Some(F10)),
+            ]
+        );
+        assert!(history[1].is_promotion() && !history[1].is_demotion());
+        assert!(history[2].is_demotion());
+        assert!(history[5].is_promotion());
+        assert!(!history[0].is_promotion() && !history[0].is_demotion());
+    }
+
+    #[test]
+    fn unconfirmed_history() {
+        assert_eq!(floor_history(ME, &[]), []);
+        let matches = [game(0, Floor::F3), game(1, Floor::F4)];
+        assert_eq!(
+            floor_history(ME, &matches),
+            [FloorChange {
+                timestamp: at(0),
+                floor: Floor::F3,
+                previous: None
+            }]
+        );
+    }
+}
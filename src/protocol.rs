@@ -0,0 +1,212 @@
+//! Encoding of requests and decoding of responses without sending anything, e.g. to reuse them in
+//! a proxy between the game and the server. Context and the transports are layered on top of
+//! these functions, they don't depend on an HTTP client.
+
+use crate::requests::{messagepack, parse_response, Credentials, MatchCollector};
+use crate::{error::*, DecodeOptions, Endpoint, ReplaySet};
+use serde_crate::{Deserialize, Serialize};
+
+/// Largest response body that is decoded, about a hundred times a full page of 127 replays.
+/// Larger bodies fail with Error::ResponseTooLarge.
+pub const MAX_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+
+/// How deeply arrays and maps of a response may be nested. Known responses nest five levels, the
+/// rest is left for elements a game update may append. Deeper responses fail with
+/// Error::ResponseTooLarge instead of exhausting the stack.
+pub const MAX_DECODE_DEPTH: usize = 32;
+
+/// The header the game sends. Without credentials a fixed player is used, the server doesn't check
+/// it for the player search All. Credentials replace the nonce with their token.
+pub fn request_header(
+    credentials: Option<&Credentials>,
+    nonce: messagepack::Nonce,
+) -> messagepack::RequestHeader {
+    let (player_id, string2) = match credentials {
+        Some(credentials) => (
+            credentials.player_id.clone(),
+            messagepack::Nonce(credentials.token.clone()),
+        ),
+        None => ("211027113123008384".into(), nonce),
+    };
+    messagepack::RequestHeader {
+        player_id,
+        string2,
+        int1: 2,
+        version: "0.1.0".into(),
+        platform: messagepack::Platform::PC,
+    }
+}
+
+/// The hex encoded messagepack of a request, ready to be posted as the data field of a form
+pub fn encode_request<T: Serialize>(header: messagepack::RequestHeader, body: T) -> String {
+    messagepack::Request { header, body }.to_hex()
+}
+
+/// encode_request for the replay endpoint
+pub fn encode_replay_request(
+    header: messagepack::RequestHeader,
+    body: messagepack::RequestBody,
+) -> String {
+    encode_request(header, body)
+}
+
+/// Decode a raw response body of the endpoint E. A response the server sent to reject the request
+/// is an Error::Api, a body of another shape an Error::Decode.
+pub fn decode<E: Endpoint>(bytes: &[u8]) -> Result<messagepack::Response<E::Response>> {
+    decode_response(bytes, messagepack::Fields::of(E::PATH))?.map_err(ParseError::into_inner)
+}
+
+/// Decode a raw response body of the replay endpoint, e.g. from a packet capture or a logged
+/// response. Replays which can't be converted are returned as errors next to the matches, same as
+/// in get_replays. Only a response the server sent to reject the request is an Err.
+pub fn decode_replay_response(
+    bytes: &[u8],
+    options: impl Into<DecodeOptions>,
+) -> Result<ReplaySet> {
+    let mut replays = ReplaySet::default();
+    let mut matches = MatchCollector::default();
+    match decode_response(bytes, &messagepack::REPLAY_RESPONSE_FIELDS)? {
+        Ok(response) => parse_response(
+            &mut replays,
+            &mut matches,
+            0,
+            response,
+            bytes,
+            &options.into(),
+        ),
+        Err(err) => replays.errors.push(err),
+    }
+    replays.set_matches(matches);
+    Ok(replays)
+}
+
+/// Decode a raw response body of the vip ranking endpoint
+pub fn decode_vip_response(
+    bytes: &[u8],
+) -> Result<messagepack::Response<messagepack::VipResponse>> {
+    decode_response(bytes, &messagepack::VIP_RESPONSE_FIELDS)?.map_err(ParseError::into_inner)
+}
+
+/// Decode a raw response body of the statistics endpoint
+pub fn decode_statistics_response(
+    bytes: &[u8],
+) -> Result<messagepack::Response<messagepack::StatisticsResponse>> {
+    decode_response(bytes, &messagepack::STATISTICS_RESPONSE_FIELDS)?
+        .map_err(ParseError::into_inner)
+}
+
+fn from_slice_limited<'de, T: Deserialize<'de>>(
+    bytes: &'de [u8],
+) -> std::result::Result<T, rmp_serde::decode::Error> {
+    let mut de = rmp_serde::decode::Deserializer::from_read_ref(bytes);
+    de.set_max_depth(MAX_DECODE_DEPTH);
+    T::deserialize(&mut de)
+}
+
+// Decode a response body, falling back to the error shape the server uses when it rejects a
+// request. Only a successful ApiError decode is reported as a request failure, anything else is
+// a ParseError for the caller to collect, with the path of the element that failed named after
+// fields.
+pub(crate) fn decode_response<U>(
+    bytes: &[u8],
+    fields: &messagepack::Fields,
+) -> Result<std::result::Result<messagepack::Response<U>, ParseError>>
+where
+    for<'de> U: Deserialize<'de>,
+{
+    if bytes.len() > MAX_RESPONSE_BYTES {
+        return Err(Error::ResponseTooLarge {
+            bytes: bytes.len(),
+            limit: "size",
+        });
+    }
+    match from_slice_limited(bytes) {
+        Ok(response) => Ok(Ok(response)),
+        Err(rmp_serde::decode::Error::DepthLimitExceeded) => Err(Error::ResponseTooLarge {
+            bytes: bytes.len(),
+            limit: "nesting depth",
+        }),
+        Err(e) => {
+            if let Ok(api_error) =
+                from_slice_limited::<messagepack::Response<messagepack::ApiError>>(bytes)
+            {
+                return Err(Error::Api {
+                    code: api_error.body.int1,
+                    message: api_error.body.string1,
+                });
+            }
+            let mut error = ParseError::new(bytes, e.into());
+            // Decoding again to track the path is only paid for on failure
+            let mut de = rmp_serde::decode::Deserializer::from_read_ref(bytes);
+            de.set_max_depth(MAX_DECODE_DEPTH);
+            if let Err(e) = serde_path_to_error::deserialize::<_, messagepack::Response<U>>(&mut de)
+            {
+                error = error.with_path(fields.name(e.path()));
+            }
+            // The header may still be intact if only the body has an unexpected shape
+            Ok(Err(
+                match from_slice_limited::<(messagepack::ResponseHeader, serde_crate::de::IgnoredAny)>(
+                    bytes,
+                ) {
+                    Ok((header, _)) => error.with_response_id(header.id),
+                    Err(_) => error,
+                },
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::endpoints;
+    use crate::{Context, QueryParameters};
+
+    // A request captured from the game client
+    const CAPTURED_REPLAY_REQUEST: &str = "9295b2323130363131303733303536313037353337ad3631666639366131653762353902a5302e312e30039401000a9aff02016390ffff000101";
+    const RESPONSE_4: &[u8] = include_bytes!("../test_data/replay_response_4.msgpack");
+
+    #[test]
+    fn encode_captured_request() {
+        let request = messagepack::ReplayRequest::from_hex(CAPTURED_REPLAY_REQUEST).unwrap();
+        assert_eq!(
+            encode_replay_request(request.header, request.body),
+            CAPTURED_REPLAY_REQUEST
+        );
+    }
+
+    #[test]
+    fn context_encodes_the_same() {
+        let nonce = messagepack::Nonce::from("61a5ed4f461c2");
+        let context = Context::default().fixed_nonce(nonce.clone());
+        let plan = crate::get_replays_plan(&context, 1, 127, QueryParameters::default()).unwrap();
+        let body = messagepack::RequestBody {
+            int1: 1,
+            index: 0,
+            replays_per_page: 127,
+            query: messagepack::RequestQuery::from(&QueryParameters::default()),
+        };
+        assert_eq!(
+            plan.first_request.unwrap(),
+            encode_replay_request(request_header(None, nonce), body)
+        );
+    }
+
+    #[test]
+    fn decode_endpoint() {
+        let response = decode::<endpoints::Replays>(RESPONSE_4).unwrap();
+        assert_eq!(response.body.replays.len(), 20);
+        let replays = decode_replay_response(RESPONSE_4, DecodeOptions::default()).unwrap();
+        assert_eq!(replays.matches().count(), 20);
+
+        assert!(matches!(
+            decode::<endpoints::Vip>(RESPONSE_4),
+            Err(Error::Decode(_))
+        ));
+        let rejected = encode_request(
+            request_header(None, messagepack::Nonce::from("61a5ed4f461c2")),
+            (),
+        );
+        assert!(decode::<endpoints::Replays>(&crate::hex::decode(&rejected).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! Elo ratings computed from match histories, keyed by player and character like the ratings of
+//! community sites.
+
+use crate::{Character, Match, Winner};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// A player playing a specific character, ratings are tracked separately for each character
+pub type RatingKey = (i64, Character);
+
+/// Rating of one participant before and after a match
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct RatingChange {
+    pub match_id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub key: RatingKey,
+    pub before: f64,
+    pub after: f64,
+}
+
+/// Elo updater. Matches have to be passed in chronological order, the result only depends on the
+/// order of the input.
+#[derive(Debug, Clone)]
+pub struct Elo {
+    k_factor: f64,
+    initial_rating: f64,
+    ratings: BTreeMap<RatingKey, f64>,
+    history: Vec<RatingChange>,
+}
+
+impl Default for Elo {
+    fn default() -> Self {
+        Elo {
+            k_factor: 32.0,
+            initial_rating: 1500.0,
+            ratings: BTreeMap::new(),
+            history: vec![],
+        }
+    }
+}
+
+impl Elo {
+    /// Ratings start at 1500 and move by at most 32 per match
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum change of a rating in a single match
+    pub fn k_factor(self, k_factor: f64) -> Self {
+        Elo { k_factor, ..self }
+    }
+
+    /// Rating of a player and character before their first match
+    pub fn initial_rating(self, initial_rating: f64) -> Self {
+        Elo {
+            initial_rating,
+            ..self
+        }
+    }
+
+    /// Update the ratings of both participants with the result of the match and return their
+    /// changes, player 1 first
+    pub fn update(&mut self, m: &Match) -> (RatingChange, RatingChange) {
+        let (p1, p2) = m.players();
+        let key1 = (p1.id, p1.character);
+        let key2 = (p2.id, p2.character);
+        let rating1 = self.rating(key1);
+        let rating2 = self.rating(key2);
+
+        // Expected score of player 1, player 2 expects the rest
+        let expected = 1.0 / (1.0 + 10f64.powf((rating2 - rating1) / 400.0));
+        let score = match m.winner {
+            Winner::Player1 => 1.0,
+            Winner::Player2 => 0.0,
+            // Without a result the ratings stay as they are
+            Winner::Unknown(_) => expected,
+        };
+        let delta = self.k_factor * (score - expected);
+
+        let change = |key, before: f64, delta: f64| RatingChange {
+            match_id: m.id,
+            timestamp: m.timestamp,
+            key,
+            before,
+            after: before + delta,
+        };
+        let changes = (change(key1, rating1, delta), change(key2, rating2, -delta));
+        self.ratings.insert(key1, changes.0.after);
+        self.ratings.insert(key2, changes.1.after);
+        self.history.push(changes.0.clone());
+        self.history.push(changes.1.clone());
+        changes
+    }
+
+    /// Current rating, the initial rating if the player never played the character
+    pub fn rating(&self, key: RatingKey) -> f64 {
+        self.ratings
+            .get(&key)
+            .copied()
+            .unwrap_or(self.initial_rating)
+    }
+
+    /// All ratings ordered by player id and character
+    pub fn ratings(&self) -> impl Iterator<Item = (RatingKey, f64)> + '_ {
+        self.ratings.iter().map(|(&key, &rating)| (key, rating))
+    }
+
+    /// Every change in the order the matches were processed, two per match
+    pub fn history(&self) -> &[RatingChange] {
+        &self.history
+    }
+
+    /// Changes of a single player and character, e.g. to draw a graph
+    pub fn history_of(&self, key: RatingKey) -> impl Iterator<Item = &RatingChange> {
+        self.history.iter().filter(move |change| change.key == key)
+    }
+}
+
+impl<'a> Extend<&'a Match> for Elo {
+    fn extend<T: IntoIterator<Item = &'a Match>>(&mut self, iter: T) {
+        for m in iter {
+            self.update(m);
+        }
+    }
+}
+
+impl Extend<Match> for Elo {
+    fn extend<T: IntoIterator<Item = Match>>(&mut self, iter: T) {
+        for m in iter {
+            self.update(&m);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Floor, Player};
+
+    fn game(id: u64, winner: (i64, Character), loser: (i64, Character)) -> Match {
+        let player = |(id, character)| Player {
+            name: format!("player{}", id).into(),
+            ..Player::test(id, character)
+        };
+        Match {
+            floor: Floor::Celestial,
+            ..Match::test(id, id as i64, (player(winner), player(loser)))
+        }
+    }
+
+    const SOL: (i64, Character) = (1, Character::Sol);
+    const KY: (i64, Character) = (2, Character::Ky);
+    const MAY: (i64, Character) = (3, Character::May);
+
+    #[test]
+    fn first_match() {
+        let mut elo = Elo::new();
+        let (winner, loser) = elo.update(&game(1, SOL, KY));
+        assert_eq!((winner.before, winner.after), (1500.0, 1516.0));
+        assert_eq!((loser.before, loser.after), (1500.0, 1484.0));
+        assert_eq!(elo.rating(SOL), 1516.0);
+        assert_eq!(elo.rating(MAY), 1500.0);
+    }
+
+    #[test]
+    fn swapping_sides_is_symmetric() {
+        let matches = [
+            game(1, SOL, KY),
+            game(2, KY, MAY),
+            game(3, SOL, MAY),
+            game(4, MAY, SOL),
+            game(5, SOL, KY),
+        ];
+        let mut elo = Elo::new().k_factor(24.0);
+        elo.extend(&matches);
+        let mut swapped_elo = Elo::new().k_factor(24.0);
+        swapped_elo.extend(matches.iter().map(Match::swapped));
+
+        assert_eq!(
+            elo.ratings().collect::<Vec<_>>(),
+            swapped_elo.ratings().collect::<Vec<_>>()
+        );
+        // Rating is only moved between the players
+        let total: f64 = elo.ratings().map(|(_, rating)| rating).sum();
+        assert!((total - 4500.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn characters_are_rated_separately() {
+        let sol_as_ky = (SOL.0, Character::Ky);
+        let mut elo = Elo::new().initial_rating(1000.0);
+        elo.extend(&[game(1, SOL, KY), game(2, KY, sol_as_ky)]);
+        assert_eq!(elo.rating(SOL), 1016.0);
+        assert!(elo.rating(sol_as_ky) < 1000.0);
+        assert_eq!(elo.history().len(), 4);
+        assert_eq!(
+            elo.history_of(KY)
+                .map(|change| change.match_id)
+                .collect::<Vec<_>>(),
+            [1, 2]
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Flat, normalized records of matches for loading into relational databases.
+
+use crate::{Character, Floor, Match, Winner};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// One row per player id
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PlayerRecord {
+    pub id: i64,
+    /// Name used in the newest match of the player
+    pub name: String,
+}
+
+/// One row per replay id
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct MatchRecord {
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub floor: Floor,
+    /// 1 or 2, or the raw byte of Winner::Unknown
+    pub winner_side: u8,
+}
+
+/// One row per player of a match, references MatchRecord::id and PlayerRecord::id
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ParticipationRecord {
+    pub match_id: u64,
+    pub player_id: i64,
+    pub character: Character,
+    /// 1 or 2
+    pub side: u8,
+    pub won: bool,
+}
+
+/// Records of a set of matches. Players are ordered by id, matches by id and participations by
+/// match id and side.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct RecordSet {
+    pub players: Vec<PlayerRecord>,
+    pub matches: Vec<MatchRecord>,
+    pub participations: Vec<ParticipationRecord>,
+}
+
+/// Split matches into player, match and participation records. A replay id occurring more than
+/// once is only recorded the first time, a player gets the name of their newest match. Replays
+/// don't contain the platform of a player, so there is no platform column.
+pub fn explode<'a, I>(matches: I) -> RecordSet
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let mut players = HashMap::<i64, (DateTime<Utc>, &str)>::new();
+    let mut records = BTreeMap::<u64, &Match>::new();
+    for m in matches {
+        records.entry(m.id).or_insert(m);
+        for player in [&m.players.0, &m.players.1] {
+            let entry = players
+                .entry(player.id)
+                .or_insert((m.timestamp, player.name()));
+            if m.timestamp > entry.0 {
+                *entry = (m.timestamp, player.name());
+            }
+        }
+    }
+
+    let mut set = RecordSet {
+        players: players
+            .into_iter()
+            .map(|(id, (_, name))| PlayerRecord {
+                id,
+                name: name.to_owned(),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    set.players.sort_unstable_by_key(|p| p.id);
+    for m in records.into_values() {
+        set.matches.push(MatchRecord {
+            id: m.id,
+            timestamp: m.timestamp,
+            floor: m.floor,
+            winner_side: m.winner.to_u8(),
+        });
+        for (player, slot) in [
+            (&m.players.0, Winner::Player1),
+            (&m.players.1, Winner::Player2),
+        ] {
+            set.participations.push(ParticipationRecord {
+                match_id: m.id,
+                player_id: player.id,
+                character: player.character,
+                side: slot.to_u8(),
+                won: m.winner == slot,
+            });
+        }
+    }
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Player;
+
+    fn game(id: u64, minute: u32, players: [(i64, &str); 2], winner: Winner) -> Match {
+        let player = |(id, name): (i64, &str)| Player {
+            name: name.into(),
+            ..Player::test(id, Character::Ky)
+        };
+        Match {
+            timestamp: format!("2022-02-06T10:{:02}:00Z", minute).parse().unwrap(),
+            floor: Floor::Celestial,
+            winner,
+            ..Match::test(id, 0, (player(players[0]), player(players[1])))
+        }
+    }
+
+    #[test]
+    fn normalized_records() {
+        let matches = [
+            game(20, 5, [(1, "Renamed"), (2, "Two")], Winner::Player2),
+            game(10, 1, [(3, "Three"), (1, "Old")], Winner::Player1),
+            // Same replay returned by an overlapping page
+            game(20, 5, [(1, "Renamed"), (2, "Two")], Winner::Player2),
+        ];
+        let set = explode(&matches);
+        assert_eq!(
+            set.players,
+            [
+                PlayerRecord {
+                    id: 1,
+                    name: "Renamed".into()
+                },
+                PlayerRecord {
+                    id: 2,
+                    name: "Two".into()
+                },
+                PlayerRecord {
+                    id: 3,
+                    name: "Three".into()
+                },
+            ]
+        );
+        assert_eq!(
+            set.matches.iter().map(|m| m.id).collect::<Vec<_>>(),
+            [10, 20]
+        );
+        assert_eq!(set.matches[1].winner_side, 2);
+        assert_eq!(set.participations.len(), 4);
+        assert_eq!(
+            set.participations[1],
+            ParticipationRecord {
+                match_id: 10,
+                player_id: 1,
+                character: Character::Ky,
+                side: 2,
+                won: false,
+            }
+        );
+        assert!(set.participations[3].won);
+        assert_eq!(explode(&[]), RecordSet::default());
+    }
+}
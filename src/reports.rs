@@ -0,0 +1,548 @@
+//! Aggregated per character statistics for tier list style charts and the win rates of the two
+//! sides.
+
+use crate::{error::*, Character, Floor, Match, Winner};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Time range of the matches a report is built from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// Every match
+    All,
+    /// Matches with a timestamp in [start, end)
+    Fixed {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+    /// Matches at most this long before the newest match, so the window slides along as newer
+    /// matches are added
+    Trailing(Duration),
+}
+
+/// How a match in which both players picked the same character is counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MirrorCounting {
+    /// Once per player, a mirror match is one win and one loss for the character
+    #[default]
+    PerSide,
+    /// Once per match, a mirror match is neither a win nor a loss for the character
+    PerMatch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct CharacterUsage {
+    pub character: Character,
+    /// Number of times the character was counted, see MirrorCounting
+    pub plays: u64,
+    pub wins: u64,
+    pub losses: u64,
+    pub mirrors: u64,
+    /// Share of the plays of all characters, the shares of a report add up to one
+    pub usage: f64,
+    /// wins / (wins + losses), None without any decided match. Matches with an unknown winner
+    /// count as neither
+    pub win_rate: Option<f64>,
+    /// Plays per floor, ordered by floor
+    pub floors: Vec<(Floor, u64)>,
+}
+
+/// Character usage over all matches in a window. Characters are ordered by character and only
+/// characters with at least one play are listed, including Unknown ones.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct UsageReport {
+    /// Timestamps of the oldest and newest match counted, None without any match
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    pub matches: u64,
+    pub characters: Vec<CharacterUsage>,
+}
+
+#[derive(Default)]
+struct Tally {
+    plays: u64,
+    wins: u64,
+    losses: u64,
+    mirrors: u64,
+    floors: BTreeMap<Floor, u64>,
+}
+
+/// Count plays and wins per character of the matches in the window, counting mirror matches once
+/// per side. The matches don't have to be sorted.
+pub fn character_usage<'a, I>(matches: I, window: Window) -> Result<UsageReport>
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    character_usage_with(matches, window, MirrorCounting::default())
+}
+
+/// Same as character_usage with the given counting of mirror matches
+pub fn character_usage_with<'a, I>(
+    matches: I,
+    window: Window,
+    mirrors: MirrorCounting,
+) -> Result<UsageReport>
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let matches = matches.into_iter().collect::<Vec<_>>();
+    let (start, end) = match window {
+        Window::All => (None, None),
+        Window::Fixed { start, end } if start < end => (Some(start), Some(end)),
+        Window::Fixed { start, end } => {
+            return Err(Error::InvalidArgument {
+                parameter: "window",
+                value: format!("{}..{}", start, end),
+                reason: format!("window start {} has to be before end {}", start, end),
+            })
+        }
+        Window::Trailing(length) => {
+            let length =
+                chrono::Duration::from_std(length).map_err(|_| Error::InvalidArgument {
+                    parameter: "window",
+                    value: format!("{:?}", length),
+                    reason: format!("window length {:?} is out of range", length),
+                })?;
+            let newest = matches.iter().map(|m| m.timestamp).max();
+            (
+                newest.and_then(|newest| newest.checked_sub_signed(length)),
+                None,
+            )
+        }
+    };
+
+    let mut report = UsageReport {
+        first: None,
+        last: None,
+        matches: 0,
+        characters: vec![],
+    };
+    let mut tallies = BTreeMap::<Character, Tally>::new();
+    for m in matches {
+        if start.is_some_and(|start| m.timestamp < start)
+            || end.is_some_and(|end| m.timestamp >= end)
+        {
+            continue;
+        }
+        report.matches += 1;
+        report.first = Some(
+            report
+                .first
+                .map_or(m.timestamp, |first| first.min(m.timestamp)),
+        );
+        report.last = report.last.max(Some(m.timestamp));
+
+        let (one, two) = (m.players.0.character, m.players.1.character);
+        if one == two {
+            let tally = tallies.entry(one).or_default();
+            tally.mirrors += 1;
+            let plays = match mirrors {
+                MirrorCounting::PerSide => {
+                    if !matches!(m.winner, Winner::Unknown(_)) {
+                        tally.wins += 1;
+                        tally.losses += 1;
+                    }
+                    2
+                }
+                MirrorCounting::PerMatch => 1,
+            };
+            tally.plays += plays;
+            *tally.floors.entry(m.floor).or_default() += plays;
+            continue;
+        }
+        for (character, side) in [(one, Winner::Player1), (two, Winner::Player2)] {
+            let tally = tallies.entry(character).or_default();
+            tally.plays += 1;
+            if m.winner == side {
+                tally.wins += 1;
+            } else if !matches!(m.winner, Winner::Unknown(_)) {
+                tally.losses += 1;
+            }
+            *tally.floors.entry(m.floor).or_default() += 1;
+        }
+    }
+
+    let total = tallies.values().map(|t| t.plays).sum::<u64>();
+    report.characters = tallies
+        .into_iter()
+        .map(|(character, t)| CharacterUsage {
+            character,
+            plays: t.plays,
+            wins: t.wins,
+            losses: t.losses,
+            mirrors: t.mirrors,
+            usage: t.plays as f64 / total as f64,
+            win_rate: (t.wins + t.losses > 0).then(|| t.wins as f64 / (t.wins + t.losses) as f64),
+            floors: t.floors.into_iter().collect(),
+        })
+        .collect();
+    Ok(report)
+}
+
+/// z of a two sided 95% confidence interval
+pub const Z_95: f64 = 1.959963984540054;
+
+/// Wilson score interval of the rate successes / trials, z selects the confidence level, e.g.
+/// Z_95. Unlike the normal approximation it stays within 0 and 1 and is usable for few trials.
+/// None without trials.
+pub fn wilson_interval(successes: u64, trials: u64, z: f64) -> Option<(f64, f64)> {
+    if trials == 0 {
+        return None;
+    }
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let z2 = z * z;
+    let denominator = 1.0 + z2 / n;
+    let center = (p + z2 / (2.0 * n)) / denominator;
+    let half = z / denominator * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+    Some(((center - half).max(0.0), (center + half).min(1.0)))
+}
+
+/// Wins of the player 1 and player 2 side over a set of decided matches
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct SideRecord {
+    pub player1_wins: u64,
+    pub player2_wins: u64,
+}
+
+impl SideRecord {
+    pub fn matches(&self) -> u64 {
+        self.player1_wins + self.player2_wins
+    }
+
+    /// Share of the matches player 1 won, the rate of player 2 is the rest. None without matches
+    pub fn player1_win_rate(&self) -> Option<f64> {
+        (self.matches() > 0).then(|| self.player1_wins as f64 / self.matches() as f64)
+    }
+
+    /// 95% Wilson score interval of player1_win_rate. A side advantage is only supported by the
+    /// data if the interval doesn't contain 0.5.
+    pub fn player1_interval(&self) -> Option<(f64, f64)> {
+        wilson_interval(self.player1_wins, self.matches(), Z_95)
+    }
+
+    fn add(&mut self, winner: Winner) {
+        match winner {
+            Winner::Player1 => self.player1_wins += 1,
+            Winner::Player2 => self.player2_wins += 1,
+            Winner::Unknown(_) => {}
+        }
+    }
+}
+
+/// Result of side_bias
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct SideBiasReport {
+    pub overall: SideRecord,
+    /// Per character of player 1 and character of player 2. The two orientations of a matchup
+    /// are separate pairs, see balanced_player1_win_rate to compare them.
+    pub pairs: Vec<((Character, Character), SideRecord)>,
+    /// Matches whose winner byte was neither 1 nor 2. They are not counted as a win of either
+    /// side.
+    pub unknown_winners: u64,
+    /// Explains how matches with unknown winners affect the report, None if there were none
+    pub note: Option<String>,
+}
+
+impl SideBiasReport {
+    /// The record of player 1 playing player1 against player2
+    pub fn pair(&self, player1: Character, player2: Character) -> Option<&SideRecord> {
+        self.pairs
+            .iter()
+            .find(|(pair, _)| *pair == (player1, player2))
+            .map(|(_, record)| record)
+    }
+
+    /// Win rate of player 1 in the matchup of a and b with both orientations weighted equally.
+    /// The advantage of the stronger character raises the rate of one orientation as much as it
+    /// lowers the other, so it cancels out even if the matchup is mostly played from one side,
+    /// and what is left is the advantage of the side. None unless both orientations were played.
+    pub fn balanced_player1_win_rate(&self, a: Character, b: Character) -> Option<f64> {
+        let one = self.pair(a, b)?.player1_win_rate()?;
+        let two = self.pair(b, a)?.player1_win_rate()?;
+        Some((one + two) / 2.0)
+    }
+}
+
+/// Compare how often the player 1 and player 2 sides win, overall and per character pair. The
+/// report depends on the winner byte meaning what this crate assumes, so matches with any other
+/// winner byte are left out and mentioned in the note of the report.
+pub fn side_bias<'a, I>(matches: I) -> SideBiasReport
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let mut overall = SideRecord::default();
+    let mut pairs = BTreeMap::<(Character, Character), SideRecord>::new();
+    let mut unknown_winners = 0;
+    for m in matches {
+        if let Winner::Unknown(_) = m.winner {
+            unknown_winners += 1;
+            continue;
+        }
+        overall.add(m.winner);
+        pairs
+            .entry((m.players.0.character, m.players.1.character))
+            .or_default()
+            .add(m.winner);
+    }
+    let note = (unknown_winners > 0).then(|| {
+        format!(
+            "{} of {} matches had a winner byte other than 1 or 2 and were left out. If there are \
+             many, the meaning of the byte may have changed and the rates can't be trusted.",
+            unknown_winners,
+            unknown_winners + overall.matches()
+        )
+    });
+    SideBiasReport {
+        overall,
+        pairs: pairs.into_iter().collect(),
+        unknown_winners,
+        note,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Player;
+    use Character::*;
+
+    fn game(
+        minute: u32,
+        floor: Floor,
+        characters: (Character, Character),
+        winner: Winner,
+    ) -> Match {
+        let players = (Player::test(1, characters.0), Player::test(2, characters.1));
+        Match {
+            timestamp: format!("2022-02-06T10:{:02}:00Z", minute).parse().unwrap(),
+            floor,
+            winner,
+            ..Match::test(minute as u64, 0, players)
+        }
+    }
+
+    fn matches() -> Vec<Match> {
+        vec![
+            game(0, Floor::F10, (Sol, Ky), Winner::Player1),
+            game(1, Floor::F10, (Sol, Ky), Winner::Player2),
+            game(2, Floor::Celestial, (Sol, Unknown(40)), Winner::Player1),
+            game(3, Floor::Celestial, (Sol, Sol), Winner::Player2),
+        ]
+    }
+
+    fn usage(report: &UsageReport, character: Character) -> &CharacterUsage {
+        report
+            .characters
+            .iter()
+            .find(|c| c.character == character)
+            .unwrap()
+    }
+
+    #[test]
+    fn per_side() {
+        let report = character_usage(&matches(), Window::All).unwrap();
+        assert_eq!(report.matches, 4);
+        assert_eq!(
+            report
+                .characters
+                .iter()
+                .map(|c| c.character)
+                .collect::<Vec<_>>(),
+            [Sol, Ky, Unknown(40)]
+        );
+        let sol = usage(&report, Sol);
+        assert_eq!((sol.plays, sol.wins, sol.losses, sol.mirrors), (5, 3, 2, 1));
+        assert_eq!(sol.usage, 5.0 / 8.0);
+        assert_eq!(sol.win_rate, Some(0.6));
+        assert_eq!(sol.floors, [(Floor::F10, 2), (Floor::Celestial, 3)]);
+        assert_eq!(usage(&report, Ky).usage, 0.25);
+        assert_eq!(usage(&report, Ky).win_rate, Some(0.5));
+        assert_eq!(usage(&report, Unknown(40)).win_rate, Some(0.0));
+    }
+
+    #[test]
+    fn per_match() {
+        let report =
+            character_usage_with(&matches(), Window::All, MirrorCounting::PerMatch).unwrap();
+        let sol = usage(&report, Sol);
+        assert_eq!((sol.plays, sol.wins, sol.losses, sol.mirrors), (4, 2, 1, 1));
+        assert_eq!(sol.usage, 4.0 / 7.0);
+        assert_eq!(sol.win_rate, Some(2.0 / 3.0));
+        assert_eq!(sol.floors, [(Floor::F10, 2), (Floor::Celestial, 2)]);
+    }
+
+    #[test]
+    fn windows() {
+        let matches = matches();
+        let trailing =
+            character_usage(&matches, Window::Trailing(Duration::from_secs(60))).unwrap();
+        assert_eq!(trailing.matches, 2);
+        assert_eq!(trailing.first, Some(matches[2].timestamp));
+        assert_eq!(usage(&trailing, Unknown(40)).usage, 0.25);
+
+        let fixed = character_usage(
+            &matches,
+            Window::Fixed {
+                start: matches[0].timestamp,
+                end: matches[1].timestamp,
+            },
+        )
+        .unwrap();
+        assert_eq!(fixed.matches, 1);
+        assert_eq!(usage(&fixed, Ky).win_rate, Some(0.0));
+
+        let empty = character_usage(&[], Window::Trailing(Duration::from_secs(60))).unwrap();
+        assert_eq!((empty.matches, empty.first), (0, None));
+        assert!(empty.characters.is_empty());
+        assert!(character_usage(
+            &matches,
+            Window::Fixed {
+                start: matches[1].timestamp,
+                end: matches[1].timestamp,
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn wilson_intervals() {
+        let close = |(low, high): (f64, f64), expected: (f64, f64)| {
+            assert!(
+                (low - expected.0).abs() < 1e-4 && (high - expected.1).abs() < 1e-4,
+                "{:?} != {:?}",
+                (low, high),
+                expected
+            );
+        };
+        close(wilson_interval(50, 100, Z_95).unwrap(), (0.4038, 0.5962));
+        close(wilson_interval(0, 10, Z_95).unwrap(), (0.0, 0.2775));
+        close(wilson_interval(10, 10, Z_95).unwrap(), (0.7225, 1.0));
+        close(wilson_interval(81, 263, Z_95).unwrap(), (0.2553, 0.3662));
+        close(wilson_interval(5, 10, 1.0).unwrap(), (0.3492, 0.6508));
+        assert_eq!(wilson_interval(0, 0, Z_95), None);
+    }
+
+    #[test]
+    fn sides() {
+        let mut matches = matches();
+        let report = side_bias(&matches);
+        assert_eq!(
+            report.overall,
+            SideRecord {
+                player1_wins: 2,
+                player2_wins: 2
+            }
+        );
+        assert_eq!(report.overall.player1_win_rate(), Some(0.5));
+        let (low, high) = report.overall.player1_interval().unwrap();
+        assert!(low < 0.5 && high > 0.5);
+        assert_eq!(
+            report.pairs,
+            [
+                (
+                    (Sol, Sol),
+                    SideRecord {
+                        player1_wins: 0,
+                        player2_wins: 1
+                    }
+                ),
+                (
+                    (Sol, Ky),
+                    SideRecord {
+                        player1_wins: 1,
+                        player2_wins: 1
+                    }
+                ),
+                (
+                    (Sol, Unknown(40)),
+                    SideRecord {
+                        player1_wins: 1,
+                        player2_wins: 0
+                    }
+                ),
+            ]
+        );
+        assert_eq!((report.unknown_winners, report.note), (0, None));
+
+        matches.push(game(5, Floor::F10, (Ky, Sol), Winner::Unknown(3)));
+        let report = side_bias(&matches);
+        assert_eq!(report.overall.matches(), 4);
+        assert_eq!(report.unknown_winners, 1);
+        assert!(report.note.unwrap().starts_with("1 of 5 matches"));
+        assert_eq!(side_bias(&[]).overall.player1_win_rate(), None);
+    }
+
+    #[test]
+    fn lopsided_matchup_is_not_side_bias() {
+        // Sol wins 80% against Ky from either side, but is mostly played as player 1
+        let mut matches = vec![];
+        for i in 0..40 {
+            let winner = if i < 32 {
+                Winner::Player1
+            } else {
+                Winner::Player2
+            };
+            matches.push(game(i, Floor::F10, (Sol, Ky), winner));
+        }
+        for i in 40..50 {
+            let winner = if i < 48 {
+                Winner::Player2
+            } else {
+                Winner::Player1
+            };
+            matches.push(game(i, Floor::F10, (Ky, Sol), winner));
+        }
+        let report = side_bias(&matches);
+        assert_eq!(report.overall.player1_win_rate(), Some(0.68));
+        assert_eq!(report.pair(Sol, Ky).unwrap().player1_win_rate(), Some(0.8));
+        assert_eq!(report.pair(Ky, Sol).unwrap().player1_win_rate(), Some(0.2));
+        assert_eq!(report.balanced_player1_win_rate(Sol, Ky), Some(0.5));
+        assert_eq!(report.balanced_player1_win_rate(Ky, Sol), Some(0.5));
+        assert_eq!(report.balanced_player1_win_rate(Sol, May), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn chart_json() {
+        let matches = [game(0, Floor::F10, (Ky, Unknown(40)), Winner::Player2)];
+        let json = serde_json::to_value(character_usage(&matches, Window::All).unwrap()).unwrap();
+        assert_eq!(
+            json["characters"][1],
+            serde_json::json!({
+                "character": { "Unknown": 40 },
+                "plays": 1,
+                "wins": 1,
+                "losses": 0,
+                "mirrors": 0,
+                "usage": 0.5,
+                "win_rate": 1.0,
+                "floors": [["F10", 1]],
+            })
+        );
+    }
+}
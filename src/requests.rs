@@ -1,22 +1,67 @@
-use crate::{error::*, *};
+use crate::{error::*, metrics::*, transport::*, *};
 
-use chrono::{DateTime, NaiveDateTime, Utc};
+pub use crate::protocol::{
+    decode_replay_response, decode_statistics_response, decode_vip_response, MAX_DECODE_DEPTH,
+    MAX_RESPONSE_BYTES,
+};
+use crate::protocol::{decode_response, request_header};
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
 use reqwest::{self, header};
-use std::collections::BTreeSet;
+use std::collections::{HashMap, HashSet};
 use std::str;
+use std::sync::Arc;
+use std::time::Duration;
 
-const DEFAULT_BASE_URL: &str = "https://ggst-game.guiltygear.com";
+/// Base url of the official API, used by Context::default
+pub const DEFAULT_BASE_URL: &str = "https://ggst-game.guiltygear.com";
 
-/// Context struct which contains the base urls used for api requests. Use the associated methods
-/// to overwrite urls if necessary.
+/// Context struct which contains the transport used for api requests and the settings applied to
+/// them. Use the associated methods to overwrite them if necessary.
 pub struct Context {
-    base_url: String,
+    transport: Arc<dyn Transport>,
+    retry_policy: RetryPolicy,
+    decode_options: DecodeOptions,
+    on_error: Option<Arc<ErrorHook>>,
+    metrics: Arc<dyn Metrics>,
+    credentials: Option<Credentials>,
+    coalescer: Option<Arc<crate::coalesce::Coalescer>>,
+    cache: Option<Arc<dyn crate::cache::Cache>>,
+    supported_characters: Vec<Character>,
+    nonce: Option<messagepack::Nonce>,
+    // None if the transport was replaced, a plan then only shows the path
+    base_url: Option<String>,
+    dry_run: bool,
+    memory_budget: Option<usize>,
+    clock: Arc<dyn crate::clock::Clock>,
 }
 
+type ErrorHook = dyn Fn(ErrorContext, ErrorEvent<'_>) + Send + Sync;
+
+// A response of Context::send with the bytes it was decoded from, or the bytes that could not be
+// decoded, with the time it took
+type Sent<T> = (
+    std::result::Result<(messagepack::Response<T>, bytes::Bytes), ParseError>,
+    PageTiming,
+);
+
 impl Default for Context {
     fn default() -> Self {
         Context {
-            base_url: DEFAULT_BASE_URL.to_string(),
+            transport: Arc::new(HttpTransport::new(DEFAULT_BASE_URL)),
+            retry_policy: RetryPolicy::default(),
+            decode_options: DecodeOptions::default(),
+            on_error: None,
+            metrics: Arc::new(NoMetrics),
+            credentials: None,
+            coalescer: None,
+            cache: None,
+            supported_characters: Character::KNOWN.to_vec(),
+            nonce: None,
+            base_url: Some(DEFAULT_BASE_URL.into()),
+            dry_run: false,
+            memory_budget: None,
+            clock: Arc::new(crate::clock::SystemClock),
         }
     }
 }
@@ -25,670 +70,5082 @@ impl Context {
     /// Overwrite the url used for api requests. The default is https://ggst-game.guiltygear.com
     /// You can modify this to a proxy in your area for faster requests
     pub fn new(base_url: String) -> Self {
-        Context { base_url }
+        Context {
+            transport: Arc::new(HttpTransport::new(base_url.clone())),
+            base_url: Some(base_url),
+            ..Default::default()
+        }
     }
-}
-
-fn id_from_bytes(bytes: &[u8]) -> Result<i64> {
-    let s =
-        str::from_utf8(bytes).map_err(|_| Error::ParsingBytesError("could not parse userid"))?;
-    s.parse::<i64>()
-        .map_err(|_| Error::ParsingBytesError("could not parse userid from String"))
-}
 
-/// Retrieve the latest set of replays. Each page contains approximately 10 replays by default, however this is not
-/// guaranteed. Indicate the min and maximum floor you want to query.
-/// No more than 100 pages can be queried at a time and only 127 replays per page max.
-/// If no matches can be found the parsing will fail.
-/// Usually a few replays have weird timestamps from the future. It is recommended to apply a
-/// filter on the current time before using any matches, like `.filter(|m| m.timestamp() < &chrono::Utc::now())`
-pub async fn get_replays<A, B, C, D, E>(
-    context: &Context,
-    pages: usize,
-    replays_per_page: usize,
-    request_parameters: QueryParameters<A, B, C, D, E>,
-) -> Result<(
-    impl Iterator<Item = Match>,
-    impl Iterator<Item = ParseError>,
-)> {
-    // Check for invalid inputs
-    if pages > 100 {
-        return Err(Error::InvalidArgument(format!(
-            "cannot query more than 100 pages, queried {}",
-            pages
-        )));
-    }
-    if replays_per_page > 127 {
-        return Err(Error::InvalidArgument(format!(
-            "cannot query more than 127 replays per page, queried {}",
-            replays_per_page
-        )));
+    /// Send user_agent with every request instead of the one of the game client. Only applies to
+    /// the default transport, a transport set with Context::transport keeps its own, see
+    /// HttpTransport::user_agent.
+    pub fn user_agent(self, user_agent: UserAgent) -> Self {
+        match &self.base_url {
+            Some(base_url) => Context {
+                transport: Arc::new(HttpTransport::new(base_url.clone()).user_agent(user_agent)),
+                ..self
+            },
+            None => self,
+        }
     }
 
-    if request_parameters.min_floor > request_parameters.max_floor {
-        return Err(Error::InvalidArgument(format!(
-            "min_floor {:?} is larger than max_floor {:?}",
-            request_parameters.min_floor, request_parameters.max_floor
-        )));
+    /// Stop crawls early once the matches, errors and metadata they collected are estimated to
+    /// take more than budget bytes, see ReplaySet::budget_exceeded. Applies to get_replays,
+    /// get_replays_shard and get_replays_unbounded. The page that exceeded the budget is kept.
+    pub fn memory_budget(self, budget: usize) -> Self {
+        Context {
+            memory_budget: Some(budget),
+            ..self
+        }
     }
 
-    let client = reqwest::Client::new();
-
-    // Assume at most 10 replays per page for pre allocation
-    let mut matches = BTreeSet::new();
-    let mut errors = vec![];
-    for i in 0..pages {
-        // Construct the query string
-        let request = messagepack::ReplayRequest {
-            header: messagepack::RequestHeader {
-                player_id: "211027113123008384".into(),
-                string2: "61a5ed4f461c2".into(),
-                int1: 2,
-                version: "0.1.0".into(),
-                platform: messagepack::Platform::PC,
-            },
-            body: messagepack::RequestBody {
-                int1: 1,
-                index: i,
-                replays_per_page,
-                query: messagepack::RequestQuery::from(&request_parameters),
-            },
-        };
-        match api_request(&client, &context.base_url, request).await? {
-            Ok(response) => {
-                parse_response(&mut matches, &mut errors, response);
-            }
-            Err(err) => {
-                errors.push(err);
-            }
+    /// Overwrite the source of the current time and of delays, e.g. with a clock::MockClock to test
+    /// retries and the freshness window of a LiveFeed without waiting. The default is the system
+    /// clock.
+    pub fn clock<C>(self, clock: C) -> Self
+    where
+        C: crate::clock::Clock + 'static,
+    {
+        Context {
+            clock: Arc::new(clock),
+            ..self
         }
     }
-    Ok((matches.into_iter(), errors.into_iter()))
-}
 
-async fn api_request<T, U>(
-    client: &reqwest::Client,
-    base_url: &str,
-    request: messagepack::Request<T>,
-) -> Result<std::result::Result<messagepack::Response<U>, ParseError>>
-where
-    T: messagepack::ApiRequest,
-    for<'de> U: Deserialize<'de>,
-{
-    let response = client
-        .post(String::from(base_url) + T::PATH)
-        .header(header::USER_AGENT, "Steam")
-        .header(header::CACHE_CONTROL, "no-cache")
-        .form(&[("data", request.to_hex())])
-        .send()
-        .await?;
+    /// Current time of the clock of the context, see Context::clock
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
 
-    // Convert the response to raw bytes
-    let bytes = response.bytes().await?;
-    Ok(rmp_serde::decode::from_slice(&bytes)
-        .map_err(|e| ParseError::new(show_buf(&bytes), e.into())))
-}
+    pub(crate) fn clock_ref(&self) -> &dyn crate::clock::Clock {
+        &*self.clock
+    }
 
-fn parse_response(
-    matches: &mut BTreeSet<Match>,
-    errors: &mut Vec<ParseError>,
-    response: messagepack::ReplayResponse,
-) {
-    for replay in response.body.replays {
-        match match_from_replay(replay.clone()) {
-            Ok(m) => {
-                matches.insert(m);
-            }
-            Err(e) => {
-                errors.push(ParseError::new(format!("{:#?}", replay), e));
-            }
+    /// Overwrite how requests are delivered, e.g. with a MockTransport to work offline
+    pub fn transport<T>(self, transport: T) -> Self
+    where
+        T: Transport + 'static,
+    {
+        Context {
+            transport: Arc::new(transport),
+            base_url: None,
+            ..self
         }
     }
-}
-
-fn match_from_replay(replay: messagepack::Replay) -> Result<Match> {
-    Ok(Match {
-        floor: replay.floor,
-        timestamp: replay.date,
-        players: (
-            Player::try_from((replay.player1_character, replay.player1))?,
-            Player::try_from((replay.player2_character, replay.player2))?,
-        ),
-        winner: match replay.winner {
-            1 => Winner::Player1,
-            2 => Winner::Player2,
-            _ => return Err(Error::ParsingBytesError("Could not parse winner")),
-        },
-    })
-}
 
-impl TryFrom<(Character, messagepack::Player)> for Player {
-    type Error = Error;
-    fn try_from((character, player): (Character, messagepack::Player)) -> Result<Self> {
-        Ok(Player {
-            id: id_from_bytes(player.id.as_bytes())?,
-            name: player.name,
-            character,
-        })
+    /// Overwrite the policy used to retry requests that failed with a transient error
+    pub fn retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Context {
+            retry_policy,
+            ..self
+        }
     }
-}
-
-#[cfg(test)]
-fn from_hex(hex: &str) -> Vec<u8> {
-    (0..hex.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
-        .collect::<Vec<_>>()
-}
-mod messagepack {
-    use super::*;
-
-    use serde_crate::{
-        de::{Deserializer, Error as _},
-        ser::Serializer,
-        Deserialize,
-    };
-
-    use crate::Character;
 
-    // An integer that we don't know the purpose of in the format. Signed and large to prevent unexpectedly large values from causing errors
-    pub type UnknownInteger = i64;
-
-    pub type ReplayRequest = Request<RequestBody>;
+    /// Overwrite how strictly responses are checked against the known format
+    pub fn decode_mode(mut self, mode: DecodeMode) -> Self {
+        self.decode_options.mode = mode;
+        self
+    }
 
-    pub trait ApiRequest: Serialize {
-        const PATH: &'static str;
+    /// Overwrite the UTC offset of the clock the server uses for replay dates, see
+    /// DecodeOptions::server_offset
+    pub fn server_offset(mut self, server_offset: FixedOffset) -> Self {
+        self.decode_options.server_offset = server_offset;
+        self
     }
 
-    impl<T> Request<T>
+    /// Register a callback which is invoked with every error as soon as it occurs, including
+    /// failed attempts that are retried afterwards. Useful to feed metrics without inspecting the
+    /// returned errors at every call site. The errors are still returned as usual.
+    pub fn on_error<F>(self, on_error: F) -> Self
     where
-        for<'de> T: Deserialize<'de>,
+        F: Fn(ErrorContext, ErrorEvent<'_>) + Send + Sync + 'static,
     {
-        #[cfg(test)]
-        pub fn from_hex(hex: &str) -> Result<Self> {
-            let bytes = from_hex(hex);
-            Ok(rmp_serde::decode::from_slice(&bytes)?)
+        Context {
+            on_error: Some(Arc::new(on_error)),
+            ..self
         }
     }
 
-    impl<T> Request<T>
+    /// Record requests, received bytes, parsed matches, parse errors and retries, see
+    /// metrics::AtomicMetrics for a simple implementation
+    pub fn metrics<M>(self, metrics: M) -> Self
     where
-        T: Serialize,
+        M: Metrics + 'static,
     {
-        pub fn to_hex(&self) -> String {
-            use std::fmt::Write;
+        Context {
+            metrics: Arc::new(metrics),
+            ..self
+        }
+    }
 
-            let mut buf = String::new();
-            for b in rmp_serde::encode::to_vec(self).unwrap() {
-                write!(buf, "{:02X}", b).unwrap();
-            }
-            buf
+    /// Send requests as the given player instead of the anonymous default, needed for the player
+    /// search modes other than PlayerSearch::All
+    pub fn credentials(self, credentials: Credentials) -> Self {
+        Context {
+            credentials: Some(credentials),
+            ..self
         }
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct Request<T> {
-        pub header: RequestHeader,
-        pub body: T,
+    /// Whether credentials are set, e.g. to disable the follow and rival searches in a UI
+    pub fn has_credentials(&self) -> bool {
+        self.credentials.is_some()
     }
 
-    impl<T> Response<T>
-    where
-        for<'de> T: Deserialize<'de>,
-    {
-        #[cfg(test)]
-        pub fn from_hex(hex: &str) -> Result<Self> {
-            let bytes = from_hex(hex);
-            Ok(rmp_serde::decode::from_slice(&bytes)?)
+    /// Share identical requests: a call with the same endpoint and body as a call that is still in
+    /// flight waits for its response instead of sending the request again, and successful
+    /// responses are reused for ttl. The header is not part of the comparison. Failed requests are
+    /// never shared. A ttl of zero only shares requests in flight.
+    pub fn coalesce_requests(self, ttl: Duration) -> Self {
+        Context {
+            coalescer: Some(Arc::new(crate::coalesce::Coalescer::new(ttl))),
+            ..self
         }
     }
 
-    impl<T> Response<T>
+    /// Keep successful responses of the endpoints that change slowly, the statistics and vip
+    /// ranking endpoints, and answer identical requests from the cache, see cache::TtlCache. Replay
+    /// queries are never cached.
+    pub fn cache<C>(self, cache: C) -> Self
     where
-        T: Serialize,
+        C: crate::cache::Cache + 'static,
     {
-        #[cfg(test)]
-        #[allow(dead_code)]
-        pub fn to_hex(&self) -> String {
-            use std::fmt::Write;
-
-            let mut buf = String::new();
-            for b in rmp_serde::encode::to_vec(self).unwrap() {
-                write!(buf, "{:02X}", b).unwrap();
-            }
-            buf
+        Context {
+            cache: Some(Arc::new(cache)),
+            ..self
         }
     }
 
-    #[derive(Debug, Clone, Deserialize, Serialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct Response<T> {
-        pub header: ResponseHeader,
-        pub body: T,
+    /// Overwrite the characters the server accepts in queries, e.g. after a game update removed or
+    /// added one. Queries for other characters fail before a request is sent. The default is
+    /// Character::KNOWN.
+    pub fn with_supported_characters(
+        self,
+        characters: impl IntoIterator<Item = Character>,
+    ) -> Self {
+        Context {
+            supported_characters: characters.into_iter().collect(),
+            ..self
+        }
     }
 
-    #[derive(PartialEq, Eq, Clone, Serialize, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct Platform(u8);
+    /// Characters accepted in queries, see with_supported_characters
+    pub fn supported_characters(&self) -> &[Character] {
+        &self.supported_characters
+    }
 
-    impl fmt::Debug for Platform {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            match *self {
-                Self::PC => "PC".fmt(f),
-                Self::PLAYSTATION => "Playstation".fmt(f),
-                Self(x) => f.debug_tuple("Platform").field(&x).finish(),
-            }
+    /// Send the same nonce with every request instead of a fresh one, e.g. for deterministic
+    /// request bytes in tests
+    pub fn fixed_nonce(self, nonce: messagepack::Nonce) -> Self {
+        Context {
+            nonce: Some(nonce),
+            ..self
         }
     }
 
-    impl Platform {
-        pub const PC: Platform = Platform(3);
-        pub const PLAYSTATION: Platform = Platform(1);
+    /// Don't send any request. get_replays and get_replays_shard return an empty ReplaySet with the
+    /// RequestPlan of the query attached, every other call fails with Error::DryRun holding the
+    /// plan of the request it would have sent. Arguments are checked as usual.
+    pub fn dry_run(self, dry_run: bool) -> Self {
+        Context { dry_run, ..self }
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct RequestHeader {
-        // The id of the player making the request (so the server can figure out the follow/rival etc for `PlayerSearch`)
-        pub player_id: String,
-        pub string2: String,
-        pub int1: UnknownInteger,
-        pub version: String,
-        pub platform: Platform, // 3 == PC, 1 == PS ?
+    /// Whether dry_run is enabled
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
     }
 
-    impl ApiRequest for RequestBody {
-        const PATH: &'static str = "/api/catalog/get_replay";
+    // Describe sending requests to E, the first one with the given body
+    fn plan<E: Endpoint>(&self, requests: usize, first: Option<E::Request>) -> RequestPlan {
+        RequestPlan {
+            url: match &self.base_url {
+                Some(base_url) => base_url.clone() + E::PATH,
+                None => E::PATH.into(),
+            },
+            requests,
+            first_request: first.map(|body| self.encode(body)),
+        }
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct RequestBody {
-        pub int1: UnknownInteger,
-        pub index: usize,
-        pub replays_per_page: usize,
-        pub query: RequestQuery,
+    // The hex encoded data of a request, as posted to the transport
+    fn encode<T: Serialize>(&self, body: T) -> String {
+        let nonce = self
+            .nonce
+            .clone()
+            .unwrap_or_else(|| messagepack::Nonce::at(self.clock.now()));
+        crate::protocol::encode_request(request_header(self.credentials.as_ref(), nonce), body)
     }
 
-    impl<A, B, C, D, E> From<&QueryParameters<A, B, C, D, E>> for RequestQuery {
-        fn from(query: &QueryParameters<A, B, C, D, E>) -> Self {
-            RequestQuery {
-                int1: -1,
-                player_search: PlayerSearch::All,
-                min_floor: query.min_floor,
-                max_floor: query.max_floor,
-                seq: vec![],
-                char_1: query.char_1,
-                char_2: query.char_2,
-                winner: query.winner.map_or_else(
-                    || 0x00,
-                    |w| match w {
-                        Winner::Player1 => 0x01,
-                        Winner::Player2 => 0x02,
-                    },
-                ),
-                prioritize_best_bout: 0,
-                int9: 1,
-            }
-        }
+    // The cache if responses of E may be cached
+    fn cache_for<E: Endpoint>(&self) -> Option<&dyn crate::cache::Cache> {
+        self.cache.as_deref().filter(|_| E::CACHEABLE)
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub enum PlayerSearch {
-        All,
-        Self_,
-        Follow,
-        Rival,
-        Favorite,
+    fn report(&self, context: ErrorContext, event: ErrorEvent<'_>) {
+        if let Some(on_error) = &self.on_error {
+            on_error(context, event);
+        }
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub enum RequestWinner {
-        Undesignated = -1,
-        PlayerOne,
+    /// Send a request to the endpoint E and decode the response. Encoding, retries, rate limiting
+    /// and error classification are the same for every endpoint. A response which doesn't have
+    /// the shape of E::Response is an Error::Decode.
+    pub async fn call<E: Endpoint>(
+        &self,
+        body: E::Request,
+    ) -> Result<messagepack::Response<E::Response>> {
+        self.send::<E>(body, None)
+            .await?
+            .0
+            .map(|(response, _)| response)
+            .map_err(ParseError::into_inner)
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct RequestQuery {
-        pub int1: UnknownInteger,
-        pub player_search: PlayerSearch,
-        #[serde(with = "floor")]
-        pub min_floor: Floor,
-        #[serde(with = "floor")]
-        pub max_floor: Floor,
-        pub seq: Vec<()>,
-        #[serde(with = "character")]
-        pub char_1: Option<Character>,
-        #[serde(with = "character")]
-        pub char_2: Option<Character>,
-        // 0 for undesignated, 1 for player 1
-        pub winner: u8,
-        // 0/1 for false/true
-        pub prioritize_best_bout: u8,
-        pub int9: UnknownInteger,
+    // Like call, but responses which can't be decoded are returned as a ParseError holding the
+    // raw bytes for the caller to collect. The timing is the one of the last attempt.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(endpoint = E::PATH, page = ?page))
+    )]
+    async fn send<E: Endpoint>(
+        &self,
+        body: E::Request,
+        page: Option<usize>,
+    ) -> Result<Sent<E::Response>> {
+        let mut timing = PageTiming {
+            page: page.unwrap_or(0),
+            ..Default::default()
+        };
+        if self.dry_run {
+            return Err(Error::DryRun(Box::new(self.plan::<E>(1, Some(body)))));
+        }
+        let fields = messagepack::Fields::of(E::PATH);
+        // The body without the header identifies the request for caching and coalescing
+        let encoded = if self.coalescer.is_some() || self.cache_for::<E>().is_some() {
+            Some(rmp_serde::encode::to_vec(&body)?)
+        } else {
+            None
+        };
+        if let (Some(cache), Some(encoded)) = (self.cache_for::<E>(), &encoded) {
+            if let Some(bytes) = cache.get(E::PATH, encoded) {
+                let started = self.clock.instant();
+                if let Ok(Ok(response)) = decode_response(&bytes, fields) {
+                    timing.decode = self.clock.instant() - started;
+                    return Ok((Ok((response, bytes)), timing));
+                }
+            }
+        }
+        let data = self.encode(body);
+        let mut attempt = 0;
+        loop {
+            let error_context = ErrorContext {
+                endpoint: E::PATH,
+                page,
+                attempt,
+            };
+            let started = self.clock.instant();
+            self.metrics.on_request(E::PATH);
+            let sent = match (&self.coalescer, &encoded) {
+                (Some(coalescer), Some(encoded)) => {
+                    coalescer
+                        .run((E::PATH, encoded.clone()), &*self.clock, || {
+                            send_request(&*self.transport, E::PATH, &data)
+                        })
+                        .await
+                }
+                _ => send_request(&*self.transport, E::PATH, &data).await,
+            };
+            let result = match sent {
+                Ok((status, bytes, body_time)) => {
+                    let body_time = body_time.unwrap_or_default();
+                    let elapsed = self.clock.instant() - started;
+                    timing.first_byte = elapsed.saturating_sub(body_time);
+                    timing.body = body_time;
+                    self.metrics.on_bytes_received(E::PATH, bytes.len());
+                    trace!(
+                        debug,
+                        attempt,
+                        status,
+                        bytes = bytes.len(),
+                        elapsed_ms = elapsed.as_millis() as u64,
+                        "response received"
+                    );
+                    let decoding = self.clock.instant();
+                    let decoded = decode_response(&bytes, fields)
+                        .map(|r| r.map_err(|e| e.with_status(status)));
+                    timing.decode = self.clock.instant() - decoding;
+                    if let (Ok(Ok(_)), Some(cache), Some(encoded)) =
+                        (&decoded, self.cache_for::<E>(), &encoded)
+                    {
+                        cache.insert(E::PATH, encoded.clone(), bytes.clone());
+                    }
+                    decoded.map(|r| r.map(|response| (response, bytes)))
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(Ok(response)) => return Ok((Ok(response), timing)),
+                Ok(Err(e)) => {
+                    trace!(
+                        warn,
+                        attempt,
+                        bytes = e.raw_bytes().len(),
+                        error = e.inner() as &(dyn std::error::Error + 'static),
+                        path = e.path(),
+                        "response could not be parsed"
+                    );
+                    self.metrics.on_parse_error(E::PATH);
+                    self.report(error_context, ErrorEvent::Parse(&e));
+                    return Ok((Err(e), timing));
+                }
+                Err(e) => {
+                    trace!(
+                        warn,
+                        attempt,
+                        error = &e as &(dyn std::error::Error + 'static),
+                        "request failed"
+                    );
+                    self.report(error_context, ErrorEvent::Request(&e));
+                    match self.retry_policy.delay(attempt, &e) {
+                        Some(delay) => {
+                            self.metrics.on_retry(E::PATH);
+                            self.clock.sleep(delay).await;
+                            attempt += 1;
+                        }
+                        None => return Err(e),
+                    }
+                }
+            }
+        }
     }
+}
 
-    pub type ReplayResponse = Response<ResponseBody>;
+/// An endpoint of the API, ties its path to the types of the request and response bodies. See
+/// the endpoints module for the known ones.
+pub trait Endpoint {
+    const PATH: &'static str;
+    /// Whether responses may be answered from Context::cache
+    const CACHEABLE: bool = false;
+    type Request: Serialize + Send;
+    type Response: for<'de> Deserialize<'de> + Send;
+}
 
-    #[derive(Debug, Clone, Deserialize, Serialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct ResponseHeader {
-        pub id: String,
-        pub int1: UnknownInteger,
-        pub date: String,
-        pub version1: String,
-        pub version2: String,
-        pub version3: String,
-        pub string1: String,
-        pub string2: String,
+/// Endpoints which can be passed to Context::call
+pub mod endpoints {
+    use super::{messagepack, Endpoint};
+
+    /// Latest replays matching a query, paginated
+    #[derive(Debug, Clone, Copy)]
+    pub struct Replays;
+
+    impl Endpoint for Replays {
+        const PATH: &'static str = "/api/catalog/get_replay";
+        type Request = messagepack::RequestBody;
+        type Response = messagepack::ResponseBody;
     }
 
-    #[derive(Debug, Clone, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct ResponseBody {
-        pub int1: UnknownInteger,
-        pub int2: UnknownInteger,
-        pub int3: UnknownInteger,
-        pub replays: Vec<Replay>,
+    /// Ranking of the players with the most wins
+    #[derive(Debug, Clone, Copy)]
+    pub struct Vip;
+
+    impl Endpoint for Vip {
+        const PATH: &'static str = "/api/ranking/vip";
+        const CACHEABLE: bool = true;
+        type Request = messagepack::VipRequest;
+        type Response = messagepack::VipResponse;
     }
 
-    #[derive(Debug, Clone, Deserialize)]
-    #[serde(crate = "serde_crate")]
+    /// Player statistics, the kind is selected by StatisticsRequest::statistics_type
+    #[derive(Debug, Clone, Copy)]
+    pub struct Statistics;
+
+    impl Endpoint for Statistics {
+        const PATH: &'static str = "/api/statistics/get";
+        const CACHEABLE: bool = true;
+        type Request = messagepack::StatisticsRequest;
+        type Response = messagepack::StatisticsResponse;
+    }
+}
+
+/// The player a request is sent as, taken from the header of a request of the logged in game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// Id of the player, the same id that is found in replays
+    pub player_id: String,
+    /// The second string of the request header of the session, sent instead of a generated
+    /// Nonce
+    pub token: String,
+}
+
+/// Where an error passed to the on_error callback happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// Path of the API endpoint, e.g. /api/catalog/get_replay
+    pub endpoint: &'static str,
+    /// Index of the page that was requested, if the endpoint is paginated
+    pub page: Option<usize>,
+    /// Number of previous attempts of the same request
+    pub attempt: usize,
+}
+
+/// An error passed to the on_error callback
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorEvent<'a> {
+    /// The request failed, it is either retried or the error is returned
+    Request(&'a Error),
+    /// A response or a single replay could not be parsed and is collected with the other parse
+    /// errors
+    Parse(&'a ParseError),
+}
+
+/// How to treat responses which deviate from the format this crate knows about.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// Reject replays with unknown trailing fields, unknown floor or character codes, a winner
+    /// byte other than 1 or 2 or names which aren't valid UTF-8. Useful to notice format changes
+    /// in tests and data pipelines
+    Strict,
+    /// Keep such replays: trailing fields are ignored (they are kept in
+    /// messagepack::Replay::extra for inspection), unknown codes become Floor::Unknown,
+    /// Character::Unknown and Winner::Unknown and invalid UTF-8 in names is replaced
+    #[default]
+    Lenient,
+}
+
+/// Settings applied when converting responses into matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    pub mode: DecodeMode,
+    /// Offset of the wall clock time the server sends replay dates in. The server clock itself
+    /// is UTC, the id of every response header is a PHP uniqid which encodes the same time as the
+    /// header date. Recent replays in captured responses agree with that, so the default is UTC.
+    /// Replays dated in the future show up nonetheless, if the server ever switches to local time
+    /// this can be adjusted without waiting for a release.
+    pub server_offset: FixedOffset,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            mode: DecodeMode::default(),
+            server_offset: FixedOffset::east_opt(0).unwrap(),
+        }
+    }
+}
+
+impl From<DecodeMode> for DecodeOptions {
+    fn from(mode: DecodeMode) -> Self {
+        DecodeOptions {
+            mode,
+            ..Default::default()
+        }
+    }
+}
+
+/// Controls how often and how long a request is retried after a transient failure, see
+/// Error::is_retryable.
+/// A Retry-After hint from the server always takes precedence over the computed backoff, unless
+/// it exceeds max_delay in which case the error is returned instead of waiting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: usize,
+    /// Delay before the first retry, doubled for every further attempt
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry, errors are returned immediately
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    // How long to wait before the given retry attempt, None if we should give up
+    fn delay(&self, attempt: usize, error: &Error) -> Option<Duration> {
+        if attempt >= self.max_retries || !error.is_retryable() {
+            return None;
+        }
+        let delay = match error {
+            Error::RateLimited {
+                retry_after: Some(retry_after),
+            } => *retry_after,
+            _ => self
+                .initial_delay
+                .saturating_mul(2u32.saturating_pow(attempt as u32)),
+        };
+        (delay <= self.max_delay).then_some(delay)
+    }
+}
+
+fn id_from_bytes(bytes: &[u8]) -> Result<i64> {
+    str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .ok_or_else(|| Error::ParsingBytesError {
+            field: "userid",
+            value: String::from_utf8_lossy(bytes).into_owned(),
+        })
+}
+
+/// Result of a replay query: the unique matches of all pages, the replays and pages that could not
+/// be parsed and the response header of every page that was decoded.
+#[derive(Debug, Default)]
+pub struct ReplaySet {
+    matches: Vec<Match>,
+    pub(crate) errors: Vec<ParseError>,
+    metadata: Vec<ResponseMetadata>,
+    overlaps: Vec<PageOverlap>,
+    plan: Option<RequestPlan>,
+    timings: Vec<PageTiming>,
+    budget_exceeded: Option<BudgetExceeded>,
+    cancelled: bool,
+}
+
+impl ReplaySet {
+    fn planned(plan: RequestPlan) -> Self {
+        ReplaySet {
+            plan: Some(plan),
+            ..Default::default()
+        }
+    }
+    pub(crate) fn set_matches(&mut self, collector: MatchCollector) {
+        self.matches = collector.matches.into_values().collect();
+        self.matches.sort_unstable();
+    }
+
+    /// Matches in ascending order of their timestamp. A replay which appeared on several pages is
+    /// only contained once, as it was decoded last.
+    pub fn matches(&self) -> impl Iterator<Item = &Match> {
+        self.matches.iter()
+    }
+
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Header information of each decoded page, in the order the pages were requested
+    pub fn metadata(&self) -> &[ResponseMetadata] {
+        &self.metadata
+    }
+
+    /// How every page after the first overlapped with the pages before it, in the order the
+    /// pages were requested
+    pub fn overlaps(&self) -> &[PageOverlap] {
+        &self.overlaps
+    }
+
+    /// How long each page with a response took, in the order the pages were requested. Pages
+    /// whose request failed have no timing.
+    pub fn timings(&self) -> &[PageTiming] {
+        &self.timings
+    }
+
+    /// Median and 95th percentile of timings, None if no page has a timing
+    pub fn timing_summary(&self) -> Option<TimingSummary> {
+        TimingSummary::new(&self.timings)
+    }
+
+    /// Approximate number of bytes held by the set, see Match::estimated_size and
+    /// ParseError::estimated_size
+    pub fn estimated_size(&self) -> usize {
+        self.matches
+            .iter()
+            .map(Match::estimated_size)
+            .sum::<usize>()
+            + self
+                .errors
+                .iter()
+                .map(ParseError::estimated_size)
+                .sum::<usize>()
+            + self
+                .metadata
+                .iter()
+                .map(ResponseMetadata::estimated_size)
+                .sum::<usize>()
+            + self.overlaps.len() * std::mem::size_of::<PageOverlap>()
+            + self.timings.len() * std::mem::size_of::<PageTiming>()
+    }
+
+    /// Set if the crawl stopped early because it exceeded Context::memory_budget
+    pub fn budget_exceeded(&self) -> Option<BudgetExceeded> {
+        self.budget_exceeded
+    }
+
+    /// Whether the crawl was cancelled before all pages were read, see get_replays_cancellable
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// What the query would have sent, only set if the context was in dry_run mode
+    pub fn plan(&self) -> Option<&RequestPlan> {
+        self.plan.as_ref()
+    }
+
+    /// Drop the matches whose id is in the cache and add the ids of the remaining ones, so that
+    /// polling the same pages again only yields replays that weren't returned before
+    pub fn retain_new(&mut self, cache: &mut cache::ReplayIdCache) {
+        self.matches.retain(|m| cache.insert(m.id));
+    }
+
+    /// Combine the results of several queries, e.g. the shards of get_replays_shard. A replay
+    /// contained in several sets is only kept once, as it appears in the last of them. Errors are
+    /// kept in the order of the sets, metadata and overlaps are ordered by page. Plans of dry runs
+    /// add up their requests and keep the first request of the first plan. budget_exceeded is
+    /// taken from the first set that exceeded its budget, the merged set is cancelled if any set
+    /// was.
+    pub fn merge(sets: Vec<ReplaySet>) -> ReplaySet {
+        let mut merged = ReplaySet::default();
+        let mut matches = MatchCollector::default();
+        for set in sets {
+            matches
+                .matches
+                .extend(set.matches.into_iter().map(|m| (m.id, m)));
+            merged.errors.extend(set.errors);
+            merged.metadata.extend(set.metadata);
+            merged.overlaps.extend(set.overlaps);
+            merged.timings.extend(set.timings);
+            merged.budget_exceeded = merged.budget_exceeded.or(set.budget_exceeded);
+            merged.cancelled |= set.cancelled;
+            merged.plan = match (merged.plan, set.plan) {
+                (Some(plan), Some(other)) => Some(RequestPlan {
+                    requests: plan.requests + other.requests,
+                    ..plan
+                }),
+                (plan, other) => plan.or(other),
+            };
+        }
+        merged.metadata.sort_by_key(|metadata| metadata.page);
+        merged.overlaps.sort_by_key(|overlap| overlap.page);
+        merged.timings.sort_by_key(|timing| timing.page);
+        merged.set_matches(matches);
+        merged
+    }
+
+    /// Split into the matches and errors, dropping the metadata
+    pub fn into_parts(
+        self,
+    ) -> (
+        impl Iterator<Item = Match>,
+        impl Iterator<Item = ParseError>,
+    ) {
+        (self.matches.into_iter(), self.errors.into_iter())
+    }
+}
+
+/// Marks a crawl that stopped early because it exceeded Context::memory_budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetExceeded {
+    pub budget: usize,
+    /// Estimated bytes held when the crawl stopped
+    pub used: usize,
+    /// Number of pages read, including the one that exceeded the budget
+    pub pages: usize,
+}
+
+// Whether a crawl in progress holds more than Context::memory_budget. The matches are still in
+// the collector, the rest is already in replays.
+fn over_budget(
+    context: &Context,
+    replays: &ReplaySet,
+    matches: &MatchCollector,
+    pages: usize,
+) -> Option<BudgetExceeded> {
+    let budget = context.memory_budget?;
+    let used = replays.estimated_size()
+        + matches
+            .matches
+            .values()
+            .map(Match::estimated_size)
+            .sum::<usize>();
+    (used > budget).then_some(BudgetExceeded {
+        budget,
+        used,
+        pages,
+    })
+}
+
+/// Where the time of a page went. Only the last attempt is measured, retries and the delays
+/// between them are not included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PageTiming {
+    /// Index of the page
+    pub page: usize,
+    /// From sending the request until the headers of the response arrived. Includes the body if
+    /// the transport doesn't report RawResponse::body_time.
+    pub first_byte: Duration,
+    /// Reading the body once the headers had arrived
+    pub body: Duration,
+    /// Decoding the messagepack and converting the replays into matches
+    pub decode: Duration,
+}
+
+impl PageTiming {
+    pub fn total(&self) -> Duration {
+        self.first_byte + self.body + self.decode
+    }
+}
+
+/// Median and 95th percentile of a duration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+}
+
+impl Percentiles {
+    // Nearest rank percentiles, durations must not be empty
+    fn new(mut durations: Vec<Duration>) -> Self {
+        durations.sort_unstable();
+        let rank = |p: usize| durations[(durations.len() * p).div_ceil(100).max(1) - 1];
+        Percentiles {
+            p50: rank(50),
+            p95: rank(95),
+        }
+    }
+}
+
+/// Summary of the page timings of a ReplaySet, to tell whether the network, the server or decoding
+/// is slow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingSummary {
+    /// Number of pages with a timing
+    pub pages: usize,
+    pub first_byte: Percentiles,
+    pub body: Percentiles,
+    pub decode: Percentiles,
+    pub total: Percentiles,
+}
+
+impl TimingSummary {
+    fn new(timings: &[PageTiming]) -> Option<Self> {
+        if timings.is_empty() {
+            return None;
+        }
+        let percentiles =
+            |f: fn(&PageTiming) -> Duration| Percentiles::new(timings.iter().map(f).collect());
+        Some(TimingSummary {
+            pages: timings.len(),
+            first_byte: percentiles(|timing| timing.first_byte),
+            body: percentiles(|timing| timing.body),
+            decode: percentiles(|timing| timing.decode),
+            total: percentiles(PageTiming::total),
+        })
+    }
+}
+
+/// How a page relates to the pages requested before it. Replays played while paginating push
+/// older replays to later pages, so a page repeats replays of the previous one. If replays are
+/// played faster than pages are requested some may be skipped, requesting more replays per page
+/// makes that less likely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageOverlap {
+    /// Index of the page
+    pub page: usize,
+    /// Replays of the page that were already contained in an earlier page
+    pub duplicates: usize,
+    /// The page shares no replay with the earlier pages although the previous page was full,
+    /// and the time between the oldest match of the previous page and the newest of this page
+    /// is longer than the average time between the matches of the previous page
+    pub possible_gap: bool,
+}
+
+impl PageOverlap {
+    fn new(
+        page: usize,
+        previous: &[Match],
+        current: &[Match],
+        duplicates: usize,
+        replays_per_page: usize,
+    ) -> Self {
+        // Both pages are sorted in ascending order of timestamp
+        let possible_gap = match (previous, current.last()) {
+            ([oldest, .., newest], Some(current_newest))
+                if duplicates == 0 && previous.len() >= replays_per_page =>
+            {
+                let spacing = (newest.timestamp - oldest.timestamp) / (previous.len() as i32 - 1);
+                oldest.timestamp - current_newest.timestamp > spacing
+            }
+            _ => false,
+        };
+        PageOverlap {
+            page,
+            duplicates,
+            possible_gap,
+        }
+    }
+}
+
+/// Overlap of each page with the pages before it, see PageOverlap. The pages have to be in the
+/// order they were requested, as returned by get_replays_by_page.
+pub fn page_overlaps(pages: &[PageResult], replays_per_page: usize) -> Vec<PageOverlap> {
+    let mut seen = HashSet::new();
+    let mut overlaps = vec![];
+    for (i, page) in pages.iter().enumerate() {
+        let duplicates = page.matches.iter().filter(|m| !seen.insert(m.id)).count();
+        if i > 0 {
+            overlaps.push(PageOverlap::new(
+                page.index,
+                &pages[i - 1].matches,
+                &page.matches,
+                duplicates,
+                replays_per_page,
+            ));
+        }
+    }
+    overlaps
+}
+
+// Like collect_page, but also records how the page overlaps with the pages collected before.
+// previous holds the matches of the last page.
+async fn collect_page_with_overlap(
+    context: &Context,
+    page: usize,
+    replays_per_page: usize,
+    query: &messagepack::RequestQuery,
+    replays: &mut ReplaySet,
+    matches: &mut MatchCollector,
+    previous: &mut Vec<Match>,
+) -> Result<()> {
+    // The page is collected separately so that cancelling the request leaves matches intact. The
+    // interned names are lent to it, a cancelled crawl only loses the interning.
+    let mut page_matches = MatchCollector {
+        matches: HashMap::new(),
+        names: std::mem::take(&mut matches.names),
+    };
+    let result = request_page(
+        context,
+        page,
+        replays_per_page,
+        query,
+        replays,
+        &mut page_matches,
+    )
+    .await;
+    matches.names = page_matches.names;
+    let mut current = page_matches.matches.into_values().collect::<Vec<_>>();
+    current.sort_unstable();
+    let mut duplicates = 0;
+    for m in &current {
+        if matches.matches.insert(m.id, m.clone()).is_some() {
+            duplicates += 1;
+        }
+    }
+    if result? {
+        // Counted after merging so that matches of earlier pages aren't counted again
+        context
+            .metrics
+            .on_matches_parsed(current.len() - duplicates);
+    }
+
+    if page > 0 {
+        let overlap = PageOverlap::new(page, previous, &current, duplicates, replays_per_page);
+        if overlap.possible_gap {
+            trace!(
+                warn,
+                page,
+                "no overlap with the previous page, replays may have been skipped"
+            );
+        }
+        replays.overlaps.push(overlap);
+    }
+    *previous = current;
+    Ok(())
+}
+
+/// Information from the header of a response, mostly useful to debug unexpected data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseMetadata {
+    /// Index of the page the response belongs to
+    pub page: usize,
+    /// Id the server assigned to the response
+    pub request_id: messagepack::ResponseId,
+    /// Time the server created the response
+    pub server_date: DateTime<Utc>,
+    /// The three version strings, the first one matches the version of the game client
+    pub server_versions: [String; 3],
+    /// Number of replays in the response. Zero means the server answered the query without an
+    /// error but had no replays, e.g. past the last page or for a query nobody matches
+    pub replays: usize,
+    /// Hash of the replay ids and winner bytes of the page, independent of the order of the
+    /// replays and stable across runs and versions of this crate. If the hash of the same query
+    /// and page differs between two crawls, diff_pages shows what changed.
+    pub content_hash: u64,
+    /// Page size the page was requested with, which together with page locates it. None if the
+    /// response was decoded without a request, e.g. with decode_replay_response.
+    pub replays_per_page: Option<usize>,
+}
+
+impl ResponseMetadata {
+    fn new(page: usize, response: &messagepack::ReplayResponse) -> Result<Self> {
+        let header = &response.header;
+        Ok(ResponseMetadata {
+            page,
+            replays: response.body.replays.len(),
+            content_hash: content_hash(&response.body.replays),
+            replays_per_page: None,
+            request_id: header.id.clone(),
+            server_date: header.server_date()?,
+            server_versions: [
+                header.version1.clone(),
+                header.version2.clone(),
+                header.version3.clone(),
+            ],
+        })
+    }
+
+    /// Whether the server accepted the query but had no replays for the page, e.g. past the last
+    /// page or for a query nobody matches. A rejected query has no metadata, it fails with an
+    /// Error::Api instead.
+    pub fn is_empty_result(&self) -> bool {
+        self.replays == 0
+    }
+
+    /// server_versions parsed, see messagepack::Version
+    pub fn versions(&self) -> Result<[messagepack::Version; 3]> {
+        Ok([
+            self.server_versions[0].parse()?,
+            self.server_versions[1].parse()?,
+            self.server_versions[2].parse()?,
+        ])
+    }
+
+    /// How far the server clock was ahead of the local one, negative if it is behind. Measured
+    /// when this is called, so only meaningful right after the response arrived.
+    pub fn server_clock_skew(&self) -> chrono::Duration {
+        self.server_date - Utc::now()
+    }
+
+    /// Approximate number of bytes the metadata occupies including its strings
+    pub fn estimated_size(&self) -> usize {
+        std::mem::size_of::<ResponseMetadata>()
+            + self.request_id.as_str().len()
+            + self.server_versions.iter().map(String::len).sum::<usize>()
+    }
+}
+
+// Sum of the FNV-1a hashes of every replay, so that the order doesn't matter
+fn content_hash(replays: &[messagepack::Replay]) -> u64 {
+    replays
+        .iter()
+        .map(|replay| {
+            let mut bytes = replay.id.to_le_bytes().to_vec();
+            bytes.push(replay.winner);
+            bytes.iter().fold(0xcbf29ce484222325, |hash: u64, &byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+            })
+        })
+        .fold(0, u64::wrapping_add)
+}
+
+/// Differences between two answers for the same page, see diff_pages
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageDiff {
+    /// Matches only contained in the new page
+    pub added: Vec<Match>,
+    /// Matches only contained in the old page
+    pub removed: Vec<Match>,
+    /// Matches contained in both pages that differ, as (old, new), e.g. after the server
+    /// corrected the winner
+    pub changed: Vec<(Match, Match)>,
+}
+
+impl PageDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two answers for the same query and page, e.g. of get_replays_by_page a day apart.
+/// Matches are paired by id, every list is in ascending order of the timestamp.
+pub fn diff_pages(old: &PageResult, new: &PageResult) -> PageDiff {
+    let old_matches = old
+        .matches
+        .iter()
+        .map(|m| (m.id, m))
+        .collect::<HashMap<_, _>>();
+    let new_ids = new.matches.iter().map(|m| m.id).collect::<HashSet<_>>();
+    let mut diff = PageDiff::default();
+    for m in &new.matches {
+        match old_matches.get(&m.id) {
+            None => diff.added.push(m.clone()),
+            Some(&previous) if previous != m => diff.changed.push((previous.clone(), m.clone())),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old
+        .matches
+        .iter()
+        .filter(|m| !new_ids.contains(&m.id))
+        .cloned()
+        .collect();
+    diff
+}
+
+/// Retrieve the latest set of replays. Each page contains approximately 10 replays by default, however this is not
+/// guaranteed. Indicate the min and maximum floor you want to query.
+/// No more than 100 pages can be queried at a time and only 127 replays per page max.
+/// If no matches can be found the parsing will fail.
+/// Usually a few replays have weird timestamps from the future. It is recommended to apply a
+/// filter on the current time before using any matches, like `.filter(|m| m.timestamp() < &chrono::Utc::now())`
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(context, request_parameters))
+)]
+pub async fn get_replays<A, B, C, D, E>(
+    context: &Context,
+    pages: usize,
+    replays_per_page: usize,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+) -> Result<ReplaySet> {
+    get_replays_cancellable(
+        context,
+        pages,
+        replays_per_page,
+        request_parameters,
+        std::future::pending::<()>(),
+    )
+    .await
+}
+
+/// Same as get_replays, but stops once cancel completes, e.g. the `cancelled()` future of a
+/// tokio_util CancellationToken or a shutdown signal. A request in flight is dropped, which
+/// aborts it even if the server never answers, and so is a pending retry. The pages read until
+/// then are returned with ReplaySet::cancelled set instead of an error.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(context, request_parameters, cancel))
+)]
+pub async fn get_replays_cancellable<A, B, C, D, E>(
+    context: &Context,
+    pages: usize,
+    replays_per_page: usize,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+    cancel: impl std::future::Future,
+) -> Result<ReplaySet> {
+    let query = check_query(context, pages, replays_per_page, &request_parameters)?;
+    if context.dry_run {
+        return Ok(ReplaySet::planned(replays_plan(
+            context,
+            0..pages,
+            replays_per_page,
+            query,
+        )));
+    }
+    let mut replays = ReplaySet::default();
+    let mut matches = MatchCollector::default();
+    let mut previous = vec![];
+    tokio::pin!(cancel);
+    for i in 0..pages {
+        let page = collect_page_with_overlap(
+            context,
+            i,
+            replays_per_page,
+            &query,
+            &mut replays,
+            &mut matches,
+            &mut previous,
+        );
+        let cancelled = tokio::select! {
+            biased;
+            _ = &mut cancel => true,
+            result = page => {
+                result?;
+                false
+            }
+        };
+        if cancelled {
+            trace!(info, page = i, "crawl cancelled");
+            replays.cancelled = true;
+            break;
+        }
+        replays.budget_exceeded = over_budget(context, &replays, &matches, i + 1);
+        if replays.budget_exceeded.is_some() {
+            break;
+        }
+    }
+    trace!(
+        info,
+        matches = matches.matches.len(),
+        errors = replays.errors.len(),
+        "replays collected"
+    );
+    replays.set_matches(matches);
+    Ok(replays)
+}
+
+/// Same as get_replays, but matches are handed to the sink page by page instead of being collected.
+/// The next page is only requested once the sink accepted all matches of the previous one. Matches
+/// of a page are passed in ascending order of their timestamp and replays which already appeared on
+/// an earlier page are skipped. Returns the replays and pages which could not be parsed.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(context, request_parameters, sink))
+)]
+pub async fn get_replays_into<A, B, C, D, E, S>(
+    context: &Context,
+    pages: usize,
+    replays_per_page: usize,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+    sink: &mut S,
+) -> Result<Vec<ParseError>>
+where
+    S: sink::MatchSink + ?Sized,
+{
+    let query = check_query(context, pages, replays_per_page, &request_parameters)?;
+    let mut replays = ReplaySet::default();
+    let mut seen = HashSet::new();
+    for i in 0..pages {
+        let mut matches = MatchCollector::default();
+        collect_page(
+            context,
+            i,
+            replays_per_page,
+            &query,
+            &mut replays,
+            &mut matches,
+        )
+        .await?;
+        let mut page = matches
+            .matches
+            .into_values()
+            .filter(|m| seen.insert(m.id))
+            .collect::<Vec<_>>();
+        page.sort_unstable();
+        for m in page {
+            sink.accept(m).await?;
+        }
+    }
+    Ok(replays.errors)
+}
+
+/// Matches and errors of a single page, see get_replays_by_page
+#[derive(Debug)]
+pub struct PageResult {
+    /// Index of the page
+    pub index: usize,
+    /// Matches of the page in ascending order of their timestamp
+    pub matches: Vec<Match>,
+    pub errors: Vec<ParseError>,
+    /// Header of the response, None if the page could not be decoded
+    pub metadata: Option<ResponseMetadata>,
+    /// None if the request failed
+    pub timing: Option<PageTiming>,
+}
+
+/// Same as get_replays, but the matches are kept separate per page instead of being merged into
+/// one set. A replay which appears on several pages is contained in each of them, which helps to
+/// debug the ordering and overlap of pages.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(context, request_parameters))
+)]
+pub async fn get_replays_by_page<A, B, C, D, E>(
+    context: &Context,
+    pages: usize,
+    replays_per_page: usize,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+) -> Result<Vec<PageResult>> {
+    let query = check_query(context, pages, replays_per_page, &request_parameters)?;
+    let mut results = Vec::with_capacity(pages);
+    for index in 0..pages {
+        let mut replays = ReplaySet::default();
+        let mut matches = MatchCollector::default();
+        collect_page(
+            context,
+            index,
+            replays_per_page,
+            &query,
+            &mut replays,
+            &mut matches,
+        )
+        .await?;
+        replays.set_matches(matches);
+        results.push(PageResult {
+            index,
+            matches: replays.matches,
+            errors: replays.errors,
+            metadata: replays.metadata.pop(),
+            timing: replays.timings.pop(),
+        });
+    }
+    Ok(results)
+}
+
+/// The pages start..end of a replay query, see shard_pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PageRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl PageRange {
+    pub fn len(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Split the first total_pages pages into at most workers ranges which neither overlap nor leave
+/// gaps, in ascending order. Sizes differ by at most one page. Empty if there are no pages or no
+/// workers.
+pub fn shard_pages(total_pages: usize, workers: usize) -> Vec<PageRange> {
+    if workers == 0 {
+        return vec![];
+    }
+    let (size, remainder) = (total_pages / workers, total_pages % workers);
+    let mut start = 0;
+    (0..workers)
+        .map(|worker| {
+            let end = start + size + usize::from(worker < remainder);
+            let range = PageRange { start, end };
+            start = end;
+            range
+        })
+        .filter(|range| !range.is_empty())
+        .collect()
+}
+
+/// Same as get_replays, but only requests the pages of range, e.g. one of shard_pages. Overlaps
+/// are recorded from the second page of the range on. The results of all shards can be combined
+/// with ReplaySet::merge.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(context, request_parameters))
+)]
+pub async fn get_replays_shard<A, B, C, D, E>(
+    context: &Context,
+    range: PageRange,
+    replays_per_page: usize,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+) -> Result<ReplaySet> {
+    let query = check_query(context, range.end, replays_per_page, &request_parameters)?;
+    if context.dry_run {
+        return Ok(ReplaySet::planned(replays_plan(
+            context,
+            range.start..range.end,
+            replays_per_page,
+            query,
+        )));
+    }
+    let mut replays = ReplaySet::default();
+    let mut matches = MatchCollector::default();
+    let mut previous = vec![];
+    for i in range.start..range.end {
+        collect_page_with_overlap(
+            context,
+            i,
+            replays_per_page,
+            &query,
+            &mut replays,
+            &mut matches,
+            &mut previous,
+        )
+        .await?;
+        replays.budget_exceeded = over_budget(context, &replays, &matches, i + 1 - range.start);
+        if replays.budget_exceeded.is_some() {
+            break;
+        }
+    }
+    // The page before the range belongs to another shard
+    replays
+        .overlaps
+        .retain(|overlap| overlap.page != range.start);
+    replays.set_matches(matches);
+    Ok(replays)
+}
+
+/// What a call sends, see get_replays_plan and Context::dry_run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestPlan {
+    /// Url the requests are posted to, only the path of the endpoint if the context uses a
+    /// custom transport
+    pub url: String,
+    /// Number of requests, not counting retries
+    pub requests: usize,
+    /// Hex encoded data of the first request as it would be posted, None if nothing is sent
+    pub first_request: Option<String>,
+}
+
+/// Describe the requests get_replays would send for the same arguments without sending any. The
+/// arguments are checked the same way. Unless Context::fixed_nonce or credentials are set the
+/// header of first_request holds a nonce generated for this call.
+pub fn get_replays_plan<A, B, C, D, E>(
+    context: &Context,
+    pages: usize,
+    replays_per_page: usize,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+) -> Result<RequestPlan> {
+    let query = check_query(context, pages, replays_per_page, &request_parameters)?;
+    Ok(replays_plan(context, 0..pages, replays_per_page, query))
+}
+
+fn replays_plan(
+    context: &Context,
+    pages: std::ops::Range<usize>,
+    replays_per_page: usize,
+    query: messagepack::RequestQuery,
+) -> RequestPlan {
+    let first = (!pages.is_empty()).then_some(messagepack::RequestBody::page(
+        pages.start,
+        replays_per_page,
+        query,
+    ));
+    context.plan::<endpoints::Replays>(pages.len(), first)
+}
+
+/// Bounds of get_replays_unbounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchLimits {
+    /// Number of unique matches to collect, the newest ones are kept if a page overshoots
+    pub max_matches: usize,
+    /// At most 127
+    pub replays_per_page: usize,
+    /// Stop after this many consecutive pages without a new match, at least one
+    pub empty_pages: usize,
+    /// Pause between two pages in addition to the retry policy, to stay below rate limits
+    pub page_delay: Duration,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        FetchLimits {
+            max_matches: 1000,
+            replays_per_page: 127,
+            empty_pages: 2,
+            page_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// Why get_replays_unbounded stopped requesting pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchStop {
+    /// FetchLimits::max_matches were collected
+    TargetReached,
+    /// FetchLimits::empty_pages consecutive pages had no new matches
+    NoMoreData,
+    /// The last of the 100 pages the API serves was requested
+    PageLimit,
+    /// Context::memory_budget was exceeded, see ReplaySet::budget_exceeded
+    BudgetExceeded,
+}
+
+/// Result of get_replays_unbounded
+#[derive(Debug)]
+pub struct UnboundedReplays {
+    pub replays: ReplaySet,
+    /// Number of pages requested, which is also the index of the next page
+    pub pages: usize,
+    /// Timestamp of the oldest collected match, i.e. how far back the query reached
+    pub oldest: Option<DateTime<Utc>>,
+    pub stop: FetchStop,
+}
+
+/// Collect up to FetchLimits::max_matches matches without having to split the query into calls
+/// that respect the page limits of the API. Pages are requested one after another until enough
+/// matches were collected, the server runs out of matches or the last page the API serves was
+/// reached.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(context, request_parameters))
+)]
+pub async fn get_replays_unbounded<A, B, C, D, E>(
+    context: &Context,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+    limits: FetchLimits,
+) -> Result<UnboundedReplays> {
+    let query = check_query(context, 0, limits.replays_per_page, &request_parameters)?;
+    let mut replays = ReplaySet::default();
+    let mut matches = MatchCollector::default();
+    let mut previous = vec![];
+    let mut pages = 0;
+    let mut empty = 0;
+    let stop = loop {
+        if matches.matches.len() >= limits.max_matches {
+            break FetchStop::TargetReached;
+        }
+        if pages == 100 {
+            break FetchStop::PageLimit;
+        }
+        if pages > 0 && !limits.page_delay.is_zero() {
+            context.clock.sleep(limits.page_delay).await;
+        }
+        let collected = matches.matches.len();
+        collect_page_with_overlap(
+            context,
+            pages,
+            limits.replays_per_page,
+            &query,
+            &mut replays,
+            &mut matches,
+            &mut previous,
+        )
+        .await?;
+        pages += 1;
+        replays.budget_exceeded = over_budget(context, &replays, &matches, pages);
+        if replays.budget_exceeded.is_some() {
+            break FetchStop::BudgetExceeded;
+        }
+        if matches.matches.len() > collected {
+            empty = 0;
+        } else {
+            empty += 1;
+            if empty >= limits.empty_pages.max(1) {
+                break FetchStop::NoMoreData;
+            }
+        }
+    };
+    trace!(
+        info,
+        matches = matches.matches.len(),
+        errors = replays.errors.len(),
+        pages,
+        "replays collected"
+    );
+    replays.set_matches(matches);
+    let surplus = replays.matches.len().saturating_sub(limits.max_matches);
+    replays.matches.drain(..surplus);
+    Ok(UnboundedReplays {
+        oldest: replays.matches.first().map(|m| m.timestamp),
+        replays,
+        pages,
+        stop,
+    })
+}
+
+/// Page sizes of get_replays_adaptive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptivePaging {
+    /// Replays per page of the first request, at least one
+    pub initial_per_page: usize,
+    /// Largest page size to request, at most 127
+    pub max_per_page: usize,
+    /// How long a page should take from sending the request until its replays are decoded, see
+    /// PageTiming::total
+    pub target_latency: Duration,
+}
+
+impl Default for AdaptivePaging {
+    fn default() -> Self {
+        AdaptivePaging {
+            initial_per_page: 16,
+            max_per_page: 64,
+            target_latency: Duration::from_secs(1),
+        }
+    }
+}
+
+impl AdaptivePaging {
+    // The size of the page after one that took latency and returned that many replays. Pages are
+    // located by their index times their size, so a size is only switched to where it divides the
+    // offset of the next page, otherwise replays would be skipped or requested twice.
+    fn next(&self, per_page: usize, offset: usize, latency: Duration, returned: usize) -> usize {
+        let candidate = if latency > self.target_latency {
+            per_page / 2
+        } else if latency * 2 <= self.target_latency && returned >= per_page {
+            (per_page * 2).min(self.max_per_page)
+        } else {
+            per_page
+        };
+        if candidate > 0 && offset.is_multiple_of(candidate) {
+            candidate
+        } else {
+            per_page
+        }
+    }
+}
+
+/// Same as get_replays, but the page size adapts to the latency of the pages. Pages are requested
+/// until the given number of the newest replays of the query is covered. A page that took more than
+/// AdaptivePaging::target_latency halves the size, one that took at most half of it and was full
+/// doubles it up to AdaptivePaging::max_per_page. Pages with fewer replays than requested don't
+/// grow the size, the server doesn't return more that way. The size only changes where the pages
+/// of both sizes line up, which powers of two always do.
+///
+/// The size each page was requested with is ResponseMetadata::replays_per_page. Stops early at a
+/// page without replays or the last page the API serves.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(context, request_parameters))
+)]
+pub async fn get_replays_adaptive<A, B, C, D, E>(
+    context: &Context,
+    replays: usize,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+    paging: AdaptivePaging,
+) -> Result<ReplaySet> {
+    let query = check_query(context, 0, paging.max_per_page, &request_parameters)?;
+    if paging.initial_per_page == 0 || paging.initial_per_page > paging.max_per_page {
+        return Err(Error::InvalidArgument {
+            parameter: "initial_per_page",
+            value: paging.initial_per_page.to_string(),
+            reason: format!(
+                "initial_per_page {} must be between 1 and max_per_page {}",
+                paging.initial_per_page, paging.max_per_page
+            ),
+        });
+    }
+    let mut set = ReplaySet::default();
+    let mut matches = MatchCollector::default();
+    let mut previous = vec![];
+    let mut per_page = paging.initial_per_page;
+    let mut offset = 0;
+    while offset < replays && offset / per_page < 100 {
+        let decoded = set.metadata.len();
+        collect_page_with_overlap(
+            context,
+            offset / per_page,
+            per_page,
+            &query,
+            &mut set,
+            &mut matches,
+            &mut previous,
+        )
+        .await?;
+        offset += per_page;
+        let (Some(metadata), Some(timing)) = (set.metadata.get(decoded), set.timings.last()) else {
+            continue;
+        };
+        if metadata.replays == 0 {
+            break;
+        }
+        let next = paging.next(per_page, offset, timing.total(), metadata.replays);
+        if next != per_page {
+            trace!(debug, per_page = next, latency = ?timing.total(), "page size adapted");
+        }
+        per_page = next;
+    }
+    set.set_matches(matches);
+    Ok(set)
+}
+
+// Encoded request for a full page of the default query, as sent without credentials
+#[cfg(feature = "test-util")]
+pub(crate) fn replay_request_hex(page: usize) -> String {
+    messagepack::Request {
+        header: request_header(None, messagepack::Nonce::from("61a5ed4f461c2")),
+        body: messagepack::RequestBody::page(
+            page,
+            127,
+            messagepack::RequestQuery::from(&QueryParameters::default()),
+        ),
+    }
+    .to_hex()
+}
+
+pub(crate) fn check_query<A, B, C, D, E>(
+    context: &Context,
+    pages: usize,
+    replays_per_page: usize,
+    request_parameters: &QueryParameters<A, B, C, D, E>,
+) -> Result<messagepack::RequestQuery> {
+    // Check for invalid inputs
+    if pages > 100 {
+        return Err(Error::InvalidArgument {
+            parameter: "pages",
+            value: pages.to_string(),
+            reason: format!("cannot query more than 100 pages, queried {}", pages),
+        });
+    }
+    if replays_per_page > 127 {
+        return Err(Error::InvalidArgument {
+            parameter: "replays_per_page",
+            value: replays_per_page.to_string(),
+            reason: format!(
+                "cannot query more than 127 replays per page, queried {}",
+                replays_per_page
+            ),
+        });
+    }
+
+    if request_parameters.min_floor > request_parameters.max_floor {
+        return Err(Error::InvalidArgument {
+            parameter: "min_floor",
+            value: format!("{:?}", request_parameters.min_floor),
+            reason: format!(
+                "min_floor {:?} is larger than max_floor {:?}",
+                request_parameters.min_floor, request_parameters.max_floor
+            ),
+        });
+    }
+    // The server answers unsupported characters with an error or an empty result
+    for character in [request_parameters.char_1, request_parameters.char_2]
+        .into_iter()
+        .flatten()
+    {
+        if !context.supported_characters.contains(&character) {
+            return Err(Error::InvalidArgument {
+                parameter: "character",
+                value: format!("{:?}", character),
+                reason: format!(
+                    "{:?} is not supported by the server, supported are {}",
+                    character,
+                    context
+                        .supported_characters
+                        .iter()
+                        .map(|c| format!("{:?}", c))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
+    }
+    // Without credentials the server answers for the fixed anonymous player with an empty result
+    let mode = request_parameters.player_search;
+    if !matches!(mode, messagepack::PlayerSearch::All) && !context.has_credentials() {
+        return Err(Error::CredentialsRequired { mode });
+    }
+    Ok(messagepack::RequestQuery::from(request_parameters))
+}
+
+// Request a single page of replays, the matches go to the collector and everything that couldn't
+// be parsed to the errors of replays
+pub(crate) async fn collect_page(
+    context: &Context,
+    page: usize,
+    replays_per_page: usize,
+    query: &messagepack::RequestQuery,
+    replays: &mut ReplaySet,
+    matches: &mut MatchCollector,
+) -> Result<()> {
+    let parsed = matches.matches.len();
+    if request_page(context, page, replays_per_page, query, replays, matches).await? {
+        context
+            .metrics
+            .on_matches_parsed(matches.matches.len() - parsed);
+    }
+    Ok(())
+}
+
+// collect_page without reporting the parsed matches to the metrics, returns whether a response was
+// parsed
+async fn request_page(
+    context: &Context,
+    page: usize,
+    replays_per_page: usize,
+    query: &messagepack::RequestQuery,
+    replays: &mut ReplaySet,
+    matches: &mut MatchCollector,
+) -> Result<bool> {
+    let request = messagepack::RequestBody::page(page, replays_per_page, query.clone());
+    let reported = replays.errors.len();
+    let (response, mut timing) = context
+        .send::<endpoints::Replays>(request, Some(page))
+        .await?;
+    let parsed = match response {
+        Ok((response, bytes)) => {
+            let started = context.clock.instant();
+            let decoded = replays.metadata.len();
+            parse_response(
+                replays,
+                matches,
+                page,
+                response,
+                &bytes,
+                &context.decode_options,
+            );
+            timing.decode += context.clock.instant() - started;
+            if let Some(metadata) = replays.metadata.get_mut(decoded) {
+                metadata.replays_per_page = Some(replays_per_page);
+            }
+            let error_context = ErrorContext {
+                endpoint: <endpoints::Replays as Endpoint>::PATH,
+                page: Some(page),
+                attempt: 0,
+            };
+            for err in &replays.errors[reported..] {
+                trace!(
+                    warn,
+                    page,
+                    bytes = err.raw_bytes().len(),
+                    error = err.inner() as &(dyn std::error::Error + 'static),
+                    "replay could not be parsed"
+                );
+                context.metrics.on_parse_error(error_context.endpoint);
+                context.report(error_context, ErrorEvent::Parse(err));
+            }
+            true
+        }
+        Err(err) => {
+            replays.errors.push(err);
+            false
+        }
+    };
+    replays.timings.push(timing);
+    Ok(parsed)
+}
+
+/// Retrieve the profile of a player, user_id is the id found in replays. Check User::visibility
+/// before showing numbers that hidden profiles don't share.
+pub async fn get_user(context: &Context, user_id: i64) -> Result<model::user::User> {
+    get_statistics(context, user_id).await?.user()
+}
+
+/// An entry of the vip ranking with the player in the form used by matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct VipEntry {
+    /// Position in the ranking, starting at 1
+    pub rank: u64,
+    pub points: u64,
+    /// The character is taken from the second integer of the entry. It is below 19 for every
+    /// captured entry and matches the highest level character of the one player whose statistics
+    /// were captured, but it is not confirmed to be the character.
+    pub player: Player,
+    pub steam_id: String,
+    pub online_id: String,
+}
+
+impl VipEntry {
+    /// The player with their online id
+    pub fn identity(&self) -> PlayerIdentity {
+        PlayerIdentity {
+            online_id: Some(self.online_id.clone()),
+            ..self.player.identity()
+        }
+    }
+}
+
+impl TryFrom<&messagepack::VipPlayer> for VipEntry {
+    type Error = Error;
+
+    fn try_from(vip: &messagepack::VipPlayer) -> Result<Self> {
+        let number = |field, value: messagepack::UnknownInteger| {
+            u64::try_from(value).map_err(|_| Error::ParsingBytesError {
+                field,
+                value: value.to_string(),
+            })
+        };
+        Ok(VipEntry {
+            rank: number("rank", vip.int1)?,
+            points: number("points", vip.int3)?,
+            player: Player::try_from(vip)?,
+            steam_id: vip.string1.clone(),
+            online_id: vip.string2.clone(),
+        })
+    }
+}
+
+impl TryFrom<&messagepack::VipPlayer> for Player {
+    type Error = Error;
+
+    /// Fails if the id is not a number or the character code doesn't fit a byte
+    fn try_from(vip: &messagepack::VipPlayer) -> Result<Self> {
+        Ok(Player {
+            id: vip.id.parse().map_err(|_| Error::ParsingBytesError {
+                field: "id",
+                value: vip.id.clone(),
+            })?,
+            character: u8::try_from(vip.int2)
+                .map(Character::from_u8_lossy)
+                .map_err(|_| Error::ParsingBytesError {
+                    field: "character",
+                    value: vip.int2.to_string(),
+                })?,
+            name: vip.name.as_str().into(),
+        })
+    }
+}
+
+impl TryFrom<messagepack::VipPlayer> for Player {
+    type Error = Error;
+
+    fn try_from(vip: messagepack::VipPlayer) -> Result<Self> {
+        Player::try_from(&vip)
+    }
+}
+
+/// Retrieve the current vip ranking, the request is the one the game sends for the first page
+pub async fn get_vip_ranking(context: &Context) -> Result<Vec<VipEntry>> {
+    let response = context
+        .call::<endpoints::Vip>(messagepack::VipRequest::first_page())
+        .await?;
+    response
+        .body
+        .ranking
+        .iter()
+        .map(VipEntry::try_from)
+        .collect()
+}
+
+/// Retrieve the statistics payload of a player without converting it into a profile, for keys
+/// that User doesn't cover
+pub async fn get_statistics(context: &Context, user_id: i64) -> Result<model::user::Statistics> {
+    let response = context
+        .call::<endpoints::Statistics>(messagepack::StatisticsRequest::profile(user_id))
+        .await?;
+    Ok(model::user::Statistics::new(user_id, response.body.json))
+}
+
+/// Retrieve the statistics of several players with at most concurrency requests in flight. The
+/// results are in the order of user_ids, repeated ids are only requested once.
+pub async fn get_statistics_batch(
+    context: &Context,
+    user_ids: impl IntoIterator<Item = i64>,
+    concurrency: usize,
+) -> Vec<(i64, Result<model::user::Statistics>)> {
+    let mut seen = HashSet::new();
+    let user_ids = user_ids
+        .into_iter()
+        .filter(|id| seen.insert(*id))
+        .collect::<Vec<_>>();
+    let requests = user_ids
+        .iter()
+        .map(|&id| Box::pin(get_statistics(context, id)) as BoxFuture<_>)
+        .collect();
+    user_ids
+        .into_iter()
+        .zip(buffered(requests, concurrency).await)
+        .collect()
+}
+
+// Run the futures with at most limit of them polled at a time, the outputs are in the order of
+// the futures
+async fn buffered<T>(futures: Vec<BoxFuture<'_, T>>, limit: usize) -> Vec<T> {
+    let mut outputs = futures.iter().map(|_| None).collect::<Vec<_>>();
+    let mut pending = futures.into_iter().enumerate();
+    let mut running = vec![];
+    loop {
+        running.extend(pending.by_ref().take(limit.max(1) - running.len()));
+        if running.is_empty() {
+            break;
+        }
+        std::future::poll_fn(|cx| {
+            let before = running.len();
+            running.retain_mut(|(index, future)| match future.as_mut().poll(cx) {
+                std::task::Poll::Ready(output) => {
+                    outputs[*index] = Some(output);
+                    false
+                }
+                std::task::Poll::Pending => true,
+            });
+            if running.len() < before {
+                std::task::Poll::Ready(())
+            } else {
+                std::task::Poll::Pending
+            }
+        })
+        .await;
+    }
+    outputs
+        .into_iter()
+        .map(|output| output.expect("every future ran to completion"))
+        .collect()
+}
+
+async fn send_request(
+    transport: &dyn Transport,
+    path: &'static str,
+    data: &str,
+) -> Result<(u16, bytes::Bytes, Option<Duration>)> {
+    let response = transport.post(path, data).await?;
+
+    if response.status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        // Only the delay-seconds form of Retry-After is supported, dates are ignored
+        let retry_after = response
+            .headers
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        return Err(Error::RateLimited { retry_after });
+    }
+
+    let status = response.status;
+    let headers = diagnostic_headers(&response.headers);
+    let is_html = response
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/html"));
+    let bytes = response.body;
+
+    // Proxies and CDNs answer with error pages instead of messagepack, report those before
+    // attempting to decode them
+    if is_html || bytes.trim_ascii_start().starts_with(b"<") {
+        return Err(Error::UnexpectedHtml {
+            status,
+            snippet: snippet(&bytes),
+            headers,
+        });
+    }
+    if !(200..300).contains(&status) {
+        return Err(Error::HttpStatus {
+            status,
+            body_snippet: snippet(&bytes),
+            headers,
+        });
+    }
+    if bytes.is_empty() {
+        return Err(Error::EmptyResponse);
+    }
+    Ok((status, bytes, response.body_time))
+}
+
+// Headers worth keeping in errors to correlate failures with server side or proxy logs
+const DIAGNOSTIC_HEADERS: &[&str] = &[
+    "retry-after",
+    "cf-ray",
+    "x-amz-cf-id",
+    "x-amzn-requestid",
+    "x-request-id",
+    "server",
+    "via",
+];
+
+fn diagnostic_headers(headers: &header::HeaderMap) -> Vec<(String, String)> {
+    DIAGNOSTIC_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = headers.get(*name)?.to_str().ok()?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+// Beginning of a response body for error messages, long bodies are cut off
+fn snippet(bytes: &[u8]) -> String {
+    const MAX_SNIPPET_LEN: usize = 256;
+    String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_SNIPPET_LEN)]).into_owned()
+}
+
+// Convert a decoded replay response, bytes are the messagepack it was decoded from. Errors carry
+// the bytes of the header or of the replay they are about.
+pub(crate) fn parse_response(
+    replays: &mut ReplaySet,
+    matches: &mut MatchCollector,
+    page: usize,
+    response: messagepack::ReplayResponse,
+    bytes: &[u8],
+    options: &DecodeOptions,
+) {
+    // Splitting the response is only paid for on failure. Should the response not consist of
+    // arrays, errors carry all of it.
+    let elements = std::cell::OnceCell::new();
+    let raw = |replay: Option<usize>| {
+        let elements = elements.get_or_init(|| split_replay_response(bytes));
+        let element = match (elements, replay) {
+            (Some((header, _)), None) => Some(*header),
+            (Some((_, replays)), Some(i)) => replays.get(i).copied(),
+            (None, _) => None,
+        };
+        element.unwrap_or(bytes).to_vec()
+    };
+    match ResponseMetadata::new(page, &response) {
+        Ok(metadata) => replays.metadata.push(metadata),
+        Err(e) => replays.errors.push(ParseError::new(raw(None), e)),
+    }
+    let id = &response.header.id;
+    for (i, replay) in response.body.replays.iter().enumerate() {
+        let error = |e| {
+            ParseError::new(raw(Some(i)), e)
+                .with_response_id(id.clone())
+                .with_floor_byte(replay.floor_byte())
+        };
+        if options.mode == DecodeMode::Strict {
+            if let Some(reason) = strict_violation(replay) {
+                replays
+                    .errors
+                    .push(error(Error::UnexpectedResponse(reason)));
+                continue;
+            }
+            if !matches!(replay.winner, 1 | 2) {
+                replays
+                    .errors
+                    .push(error(Error::InvalidWinner(replay.winner)));
+                continue;
+            }
+        }
+        match match_from_replay(replay, options.server_offset, &mut matches.names) {
+            Ok(m) => {
+                matches.matches.insert(m.id, m);
+            }
+            Err(e) => {
+                replays.errors.push(error(e));
+            }
+        }
+    }
+}
+
+// The messagepack of the header and of every replay of a replay response, None if the response
+// or its body isn't an array
+fn split_replay_response(bytes: &[u8]) -> Option<(&[u8], Vec<&[u8]>)> {
+    let mut rd = bytes;
+    if array_len(&mut rd)? < 2 {
+        return None;
+    }
+    let header = next_value(&mut rd)?;
+    // The replays follow three integers in the body
+    if array_len(&mut rd)? < 4 {
+        return None;
+    }
+    for _ in 0..3 {
+        next_value(&mut rd)?;
+    }
+    let replays = (0..array_len(&mut rd)?)
+        .map(|_| next_value(&mut rd))
+        .collect::<Option<Vec<_>>>()?;
+    Some((header, replays))
+}
+
+// Read the marker of an array and return its length
+fn array_len(rd: &mut &[u8]) -> Option<usize> {
+    let (&marker, rest) = rd.split_first()?;
+    let (len, size) = match marker {
+        0x90..=0x9f => (usize::from(marker & 0x0f), 0),
+        0xdc => (
+            usize::from(u16::from_be_bytes(rest.get(..2)?.try_into().ok()?)),
+            2,
+        ),
+        0xdd => (
+            u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize,
+            4,
+        ),
+        _ => return None,
+    };
+    *rd = &rest[size..];
+    Some(len)
+}
+
+// Skip a single value and return its bytes
+fn next_value<'a>(rd: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let start = *rd;
+    rmpv::decode::read_value(rd).ok()?;
+    Some(&start[..start.len() - rd.len()])
+}
+
+// The decoder always falls back to lenient representations, strict mode rejects them afterwards
+fn strict_violation(replay: &messagepack::Replay) -> Option<&'static str> {
+    if !replay.extra.is_empty() {
+        Some("replay has unknown trailing fields")
+    } else if matches!(replay.floor, Floor::Unknown(_)) {
+        Some("replay has an unknown floor")
+    } else if matches!(replay.player1_character, Character::Unknown(_))
+        || matches!(replay.player2_character, Character::Unknown(_))
+    {
+        Some("replay has an unknown character")
+    } else if !replay.player1.name.is_utf8() || !replay.player2.name.is_utf8() {
+        Some("player name is not valid UTF-8")
+    } else {
+        None
+    }
+}
+
+// Replays are collected by id, comparing whole matches to deduplicate them is much slower and
+// would keep both copies of a replay whose winner was corrected between pages. Player names are
+// interned so that a player appearing in many replays shares one allocation.
+#[derive(Default)]
+pub(crate) struct MatchCollector {
+    pub(crate) matches: HashMap<u64, Match>,
+    names: HashSet<Arc<str>>,
+}
+
+fn match_from_replay(
+    replay: &messagepack::Replay,
+    server_offset: FixedOffset,
+    names: &mut HashSet<Arc<str>>,
+) -> Result<Match> {
+    // A mirror of the same character is fine, the same player on both sides is not
+    if replay.player1.id == replay.player2.id {
+        return Err(Error::DuplicatePlayer {
+            id: replay.player1.id.clone(),
+        });
+    }
+    Ok(Match {
+        floor: replay.floor,
+        // Only fails if applying the offset leaves the range of representable dates
+        timestamp: server_offset
+            .from_local_datetime(&replay.date.naive_utc())
+            .single()
+            .ok_or_else(|| Error::ParsingBytesError {
+                field: "date",
+                value: replay.date.naive_utc().to_string(),
+            })?
+            .with_timezone(&Utc),
+        players: (
+            player_from(replay.player1_character, &replay.player1, names)?,
+            player_from(replay.player2_character, &replay.player2, names)?,
+        ),
+        id: replay.id,
+        winner: Winner::from_u8(replay.winner),
+        venue: Some(MatchVenue(replay.int8)),
+    })
+}
+
+impl TryFrom<(Character, messagepack::Player)> for Player {
+    type Error = Error;
+    fn try_from((character, player): (Character, messagepack::Player)) -> Result<Self> {
+        Player::try_from((character, &player))
+    }
+}
+
+impl TryFrom<(Character, &messagepack::Player)> for Player {
+    type Error = Error;
+    fn try_from((character, player): (Character, &messagepack::Player)) -> Result<Self> {
+        Ok(Player {
+            id: id_from_bytes(player.id.as_bytes())?,
+            name: player.name.to_string_lossy().into(),
+            character,
+        })
+    }
+}
+
+fn player_from(
+    character: Character,
+    player: &messagepack::Player,
+    names: &mut HashSet<Arc<str>>,
+) -> Result<Player> {
+    let name = player.name.to_string_lossy();
+    let name = match names.get(&*name) {
+        Some(name) => name.clone(),
+        None => {
+            let name: Arc<str> = name.into();
+            names.insert(name.clone());
+            name
+        }
+    };
+    Ok(Player {
+        id: id_from_bytes(player.id.as_bytes())?,
+        name,
+        character,
+    })
+}
+
+/// Convert a hex string, as found in the data field of captured requests, into bytes
+#[deprecated(note = "use hex::decode")]
+pub fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    crate::hex::decode(hex)
+}
+
+/// Types mirroring the messagepack structure of requests and responses as sent by the game.
+/// Fields named after their type are not understood yet and may be renamed once they are.
+pub mod messagepack {
+    use super::*;
+
+    use serde_crate::{
+        de::{Deserializer, Error as _, SeqAccess, Visitor},
+        ser::{SerializeSeq, Serializer},
+        Deserialize,
+    };
+
+    use crate::Character;
+
+    // An integer that we don't know the purpose of in the format. Signed and large to prevent unexpectedly large values from causing errors
+    pub type UnknownInteger = i64;
+
+    pub type ReplayRequest = Request<RequestBody>;
+
+    impl<T> Request<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        /// Decode a request from the hex string sent in the data field
+        pub fn from_hex(hex: &str) -> Result<Self> {
+            let bytes = crate::hex::decode(hex)?;
+            Ok(rmp_serde::decode::from_slice(&bytes)?)
+        }
+    }
+
+    impl<T> Request<T>
+    where
+        T: Serialize,
+    {
+        pub fn to_hex(&self) -> String {
+            crate::hex::encode(&rmp_serde::encode::to_vec(self).unwrap())
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct Request<T> {
+        pub header: RequestHeader,
+        pub body: T,
+    }
+
+    impl<T> Response<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        /// Decode a response from a hex dump of its body
+        pub fn from_hex(hex: &str) -> Result<Self> {
+            let bytes = crate::hex::decode(hex)?;
+            Ok(rmp_serde::decode::from_slice(&bytes)?)
+        }
+    }
+
+    impl<T> Response<T>
+    where
+        T: Serialize,
+    {
+        pub fn to_hex(&self) -> String {
+            crate::hex::encode(&rmp_serde::encode::to_vec(self).unwrap())
+        }
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct Response<T> {
+        pub header: ResponseHeader,
+        pub body: T,
+    }
+
+    #[derive(PartialEq, Eq, Clone, Serialize, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct Platform(u8);
+
+    impl fmt::Debug for Platform {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match *self {
+                Self::PC => "PC".fmt(f),
+                Self::PLAYSTATION => "Playstation".fmt(f),
+                Self(x) => f.debug_tuple("Platform").field(&x).finish(),
+            }
+        }
+    }
+
+    impl Platform {
+        pub const PC: Platform = Platform(3);
+        pub const PLAYSTATION: Platform = Platform(1);
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct RequestHeader {
+        // The id of the player making the request (so the server can figure out the follow/rival etc for `PlayerSearch`)
+        pub player_id: String,
+        pub string2: Nonce,
+        pub int1: UnknownInteger,
+        pub version: String,
+        pub platform: Platform, // 3 == PC, 1 == PS ?
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct RequestBody {
+        pub int1: UnknownInteger,
+        pub index: usize,
+        pub replays_per_page: usize,
+        pub query: RequestQuery,
+    }
+
+    impl RequestBody {
+        /// The request for the page at index, as sent by get_replays
+        pub fn page(index: usize, replays_per_page: usize, query: RequestQuery) -> Self {
+            RequestBody {
+                int1: 1,
+                index,
+                replays_per_page,
+                query,
+            }
+        }
+    }
+
+    impl<A, B, C, D, E> From<&QueryParameters<A, B, C, D, E>> for RequestQuery {
+        fn from(query: &QueryParameters<A, B, C, D, E>) -> Self {
+            RequestQuery {
+                int1: -1,
+                player_search: query.player_search,
+                min_floor: query.min_floor,
+                max_floor: query.max_floor,
+                seq: vec![],
+                char_1: query.char_1,
+                char_2: query.char_2,
+                winner: query.winner.map_or(0x00, Winner::to_u8),
+                prioritize_best_bout: 0,
+                int9: 1,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub enum PlayerSearch {
+        All,
+        Self_,
+        Follow,
+        Rival,
+        Favorite,
+    }
+
+    // The game sends the index, the derived implementation would send the variant name
+    impl Serialize for PlayerSearch {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            (*self as u8).serialize(serializer)
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub enum RequestWinner {
+        Undesignated = -1,
+        PlayerOne,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct RequestQuery {
+        pub int1: UnknownInteger,
+        pub player_search: PlayerSearch,
+        #[serde(with = "floor")]
+        pub min_floor: Floor,
+        #[serde(with = "floor")]
+        pub max_floor: Floor,
+        pub seq: Vec<()>,
+        #[serde(with = "character")]
+        pub char_1: Option<Character>,
+        #[serde(with = "character")]
+        pub char_2: Option<Character>,
+        // 0 for undesignated, 1 for player 1
+        pub winner: u8,
+        // 0/1 for false/true
+        pub prioritize_best_bout: u8,
+        pub int9: UnknownInteger,
+    }
+
+    pub type ReplayResponse = Response<ResponseBody>;
+
+    /// Names of the elements of the messagepack arrays, in order. Used to turn the path of a
+    /// decoding error from array indices like [1][3][17][6][1] into body.replays[17].player2.name.
+    #[derive(Debug)]
+    pub(crate) enum Fields {
+        /// Not described further, indices below are kept as they are
+        Unnamed,
+        Struct(&'static [(&'static str, Fields)]),
+        List(&'static Fields),
+    }
+
+    const HEADER_FIELDS: Fields = Fields::Struct(&[
+        ("id", Fields::Unnamed),
+        ("int1", Fields::Unnamed),
+        ("date", Fields::Unnamed),
+        ("version1", Fields::Unnamed),
+        ("version2", Fields::Unnamed),
+        ("version3", Fields::Unnamed),
+        ("string1", Fields::Unnamed),
+        ("string2", Fields::Unnamed),
+    ]);
+
+    const PLAYER_FIELDS: Fields = Fields::Struct(&[
+        ("id", Fields::Unnamed),
+        ("name", Fields::Unnamed),
+        ("string1", Fields::Unnamed),
+        ("string2", Fields::Unnamed),
+        ("int1", Fields::Unnamed),
+    ]);
+
+    const REPLAY_ELEMENT_FIELDS: Fields = Fields::Struct(&[
+        ("id", Fields::Unnamed),
+        ("int2", Fields::Unnamed),
+        ("floor", Fields::Unnamed),
+        ("player1_character", Fields::Unnamed),
+        ("player2_character", Fields::Unnamed),
+        ("player1", PLAYER_FIELDS),
+        ("player2", PLAYER_FIELDS),
+        ("winner", Fields::Unnamed),
+        ("date", Fields::Unnamed),
+        ("int7", Fields::Unnamed),
+        ("views", Fields::Unnamed),
+        ("int8", Fields::Unnamed),
+        ("likes", Fields::Unnamed),
+    ]);
+
+    pub(crate) const REPLAY_RESPONSE_FIELDS: Fields = Fields::Struct(&[
+        ("header", HEADER_FIELDS),
+        (
+            "body",
+            Fields::Struct(&[
+                ("int1", Fields::Unnamed),
+                ("int2", Fields::Unnamed),
+                ("int3", Fields::Unnamed),
+                ("replays", Fields::List(&REPLAY_ELEMENT_FIELDS)),
+            ]),
+        ),
+    ]);
+
+    pub(crate) const VIP_RESPONSE_FIELDS: Fields = Fields::Struct(&[
+        ("header", HEADER_FIELDS),
+        (
+            "body",
+            Fields::Struct(&[
+                ("int1", Fields::Unnamed),
+                ("int2", Fields::Unnamed),
+                ("int3", Fields::Unnamed),
+                ("int4", Fields::Unnamed),
+                ("ranking", Fields::List(&Fields::Unnamed)),
+                ("struct1", Fields::Unnamed),
+                ("int5", Fields::Unnamed),
+            ]),
+        ),
+    ]);
+
+    pub(crate) const STATISTICS_RESPONSE_FIELDS: Fields = Fields::Struct(&[
+        ("header", HEADER_FIELDS),
+        (
+            "body",
+            Fields::Struct(&[("int1", Fields::Unnamed), ("json", Fields::Unnamed)]),
+        ),
+    ]);
+
+    const RESPONSE_FIELDS: Fields =
+        Fields::Struct(&[("header", HEADER_FIELDS), ("body", Fields::Unnamed)]);
+
+    impl Fields {
+        /// The fields of the response of the endpoint with the given path
+        pub(crate) fn of(path: &str) -> &'static Fields {
+            match path {
+                super::endpoints::Replays::PATH => &REPLAY_RESPONSE_FIELDS,
+                super::endpoints::Vip::PATH => &VIP_RESPONSE_FIELDS,
+                super::endpoints::Statistics::PATH => &STATISTICS_RESPONSE_FIELDS,
+                _ => &RESPONSE_FIELDS,
+            }
+        }
+
+        /// Name the segments of the path, e.g. body.replays[17].player2.name
+        pub(crate) fn name(&self, path: &serde_path_to_error::Path) -> String {
+            let mut name = String::new();
+            let mut fields = self;
+            for segment in path.iter() {
+                let index = match segment {
+                    serde_path_to_error::Segment::Seq { index } => *index,
+                    other => {
+                        name += &format!(".{}", other);
+                        fields = &Fields::Unnamed;
+                        continue;
+                    }
+                };
+                match fields {
+                    Fields::Struct(names) if index < names.len() => {
+                        if !name.is_empty() {
+                            name.push('.');
+                        }
+                        name += names[index].0;
+                        fields = &names[index].1;
+                    }
+                    Fields::List(element) => {
+                        name += &format!("[{}]", index);
+                        fields = element;
+                    }
+                    _ => {
+                        name += &format!("[{}]", index);
+                        fields = &Fields::Unnamed;
+                    }
+                }
+            }
+            name
+        }
+    }
+
+    /// Id the server generates for every response, e.g. "61ff0796545a9". It is the only handle to
+    /// correlate a response with the server logs, so include it when reporting wrong data.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate", transparent)]
+    pub struct ResponseId(pub String);
+
+    impl ResponseId {
+        pub fn as_str(&self) -> &str {
+            &self.0
+        }
+
+        /// Time the id was generated. The id is a PHP uniqid, eight hex digits of unix seconds
+        /// followed by five of microseconds. None if the id doesn't have that format.
+        pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+            uniqid_timestamp(&self.0)
+        }
+    }
+
+    fn uniqid_timestamp(id: &str) -> Option<DateTime<Utc>> {
+        let hex = |digits: Option<&str>| u32::from_str_radix(digits?, 16).ok();
+        let seconds = hex(id.get(..8))?;
+        let micros = hex(id.get(8..13)).filter(|&micros| micros < 1_000_000)?;
+        if id.len() != 13 {
+            return None;
+        }
+        DateTime::from_timestamp(seconds.into(), micros * 1000)
+    }
+
+    /// The second string of the request header, e.g. "61ff96a1e7b59". It has the same uniqid
+    /// format as ResponseId. Captures show the game keeping one value for a session, this crate
+    /// generates a fresh one for every request unless Context::fixed_nonce is set.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate", transparent)]
+    pub struct Nonce(pub String);
+
+    impl Nonce {
+        /// A nonce for the current time
+        pub fn generate() -> Self {
+            Nonce::at(Utc::now())
+        }
+
+        /// The nonce generated at time, eight hex digits of unix seconds followed by five of
+        /// microseconds
+        pub fn at(time: DateTime<Utc>) -> Self {
+            Nonce(format!(
+                "{:08x}{:05x}",
+                time.timestamp(),
+                time.timestamp_subsec_micros().min(999_999)
+            ))
+        }
+
+        pub fn as_str(&self) -> &str {
+            &self.0
+        }
+
+        /// Time the nonce was generated, None if it doesn't have the uniqid format
+        pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+            uniqid_timestamp(&self.0)
+        }
+    }
+
+    impl fmt::Display for Nonce {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(&self.0)
+        }
+    }
+
+    impl From<&str> for Nonce {
+        fn from(nonce: &str) -> Self {
+            Nonce(nonce.into())
+        }
+    }
+
+    impl fmt::Display for ResponseId {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(&self.0)
+        }
+    }
+
+    impl From<&str> for ResponseId {
+        fn from(id: &str) -> Self {
+            ResponseId(id.into())
+        }
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct ResponseHeader {
+        pub id: ResponseId,
+        pub int1: UnknownInteger,
+        // Unlike replay dates this uses slashes, e.g. 2022/02/06 10:45:23
+        pub date: String,
+        pub version1: String,
+        pub version2: String,
+        pub version3: String,
+        pub string1: String,
+        pub string2: String,
+    }
+
+    impl ResponseHeader {
+        /// The date of the header, which is in UTC. Unlike replay dates this is certain: the
+        /// response id carries the same second as a unix timestamp.
+        pub fn server_date(&self) -> Result<DateTime<Utc>> {
+            Ok(NaiveDateTime::parse_from_str(&self.date, "%Y/%m/%d %H:%M:%S")?.and_utc())
+        }
+
+        /// version1, version2 and version3 parsed
+        pub fn versions(&self) -> Result<[Version; 3]> {
+            Ok([
+                self.version1.parse()?,
+                self.version2.parse()?,
+                self.version3.parse()?,
+            ])
+        }
+
+        /// How far the server clock is ahead of the local one, negative if it is behind. The
+        /// header date only has whole seconds and includes the time the response took to arrive,
+        /// so expect an error of a second or two.
+        pub fn server_clock_skew(&self) -> Result<chrono::Duration> {
+            Ok(self.server_date()? - Utc::now())
+        }
+    }
+
+    /// A dotted version string of a response header, e.g. "0.1.0". Missing parts are 0 so "0.1"
+    /// equals "0.1.0", comparison is numeric part by part.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+    pub struct Version {
+        pub major: u32,
+        pub minor: u32,
+        pub patch: u32,
+    }
+
+    impl Version {
+        pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+            Version {
+                major,
+                minor,
+                patch,
+            }
+        }
+    }
+
+    impl std::str::FromStr for Version {
+        type Err = Error;
+
+        fn from_str(s: &str) -> Result<Self> {
+            let err = || Error::ParsingBytesError {
+                field: "version",
+                value: s.to_string(),
+            };
+            let mut parts = [0; 3];
+            for (index, part) in s.split('.').enumerate() {
+                if index == parts.len()
+                    || part.is_empty()
+                    || !part.bytes().all(|b| b.is_ascii_digit())
+                {
+                    return Err(err());
+                }
+                parts[index] = part.parse().map_err(|_| err())?;
+            }
+            Ok(Version::new(parts[0], parts[1], parts[2]))
+        }
+    }
+
+    impl std::fmt::Display for Version {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+        }
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct ResponseBody {
+        /// 0 in every captured response, including empty pages. Whether another value signals a
+        /// rejected query is unknown, no rejected response has been captured.
+        pub int1: UnknownInteger,
+        pub int2: UnknownInteger,
+        /// Equals the number of replays in this response in every capture, not a total of the
+        /// query. No field carrying a total count has been found.
+        pub int3: UnknownInteger,
+        pub replays: Vec<Replay>,
+    }
+
+    /// A replay as sent by the server. None of the fields holds the rounds each player won or how
+    /// long the match took: the only unnamed integers are int2, which grows with the date of the
+    /// replay, int7, which is 1 in every capture, and int8, see MatchVenue. A round count would vary
+    /// between replays of the same day and be at most 2 for the winner.
+    #[derive(Debug, Clone)]
     pub struct Replay {
-        pub int1: u64,
+        /// Id of the replay, unique across pages
+        pub id: u64,
+        /// Increases with the date of the replay, from 3 in June 2021 to 9 in February 2022, see
+        /// version_code
+        pub int2: UnknownInteger,
+        pub floor: Floor,
+        pub player1_character: Character,
+        pub player2_character: Character,
+        pub player1: Player,
+        pub player2: Player,
+        pub winner: u8,
+        /// Server wall clock time, stored as UTC, see DecodeOptions::server_offset
+        pub date: chrono::DateTime<Utc>,
+        /// 1 in every capture
+        pub int7: UnknownInteger,
+        pub views: u64,
+        /// Most likely where the match was played, see MatchVenue
+        pub int8: UnknownInteger,
+        pub likes: u64,
+        /// Elements following the known fields, only present if the format was extended
+        pub extra: Vec<rmpv::Value>,
+    }
+
+    const REPLAY_FIELDS: usize = 13;
+
+    impl Replay {
+        /// The floor byte as it was sent, see Match::floor_byte
+        pub fn floor_byte(&self) -> u8 {
+            self.floor.to_u8()
+        }
+
+        /// int2, most likely the version of the game or of the replay format the match was
+        /// recorded with. Replays of one day share the value and it never decreased in captures
+        /// spanning eight months, so it is neither a round count nor a duration.
+        pub fn version_code(&self) -> UnknownInteger {
+            self.int2
+        }
+    }
+
+    // Wrappers to decode single elements with the same helpers as the derived implementations
+    #[derive(Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    struct FloorElement(#[serde(with = "floor")] Floor);
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    struct CharacterElement(#[serde(with = "player_character")] Character);
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    struct DateElement(#[serde(with = "date_time")] chrono::DateTime<Utc>);
+
+    // Replays are decoded by hand so that elements appended to the array by a game update end up
+    // in extra instead of failing the whole page
+    impl<'de> Deserialize<'de> for Replay {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            struct ReplayVisitor;
+
+            impl<'de> Visitor<'de> for ReplayVisitor {
+                type Value = Replay;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "an array of at least {} replay fields", REPLAY_FIELDS)
+                }
+
+                fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Replay, A::Error>
+                where
+                    A: SeqAccess<'de>,
+                {
+                    let replay = Replay {
+                        id: element(&mut seq, 0)?,
+                        int2: element(&mut seq, 1)?,
+                        floor: element::<_, FloorElement>(&mut seq, 2)?.0,
+                        player1_character: element::<_, CharacterElement>(&mut seq, 3)?.0,
+                        player2_character: element::<_, CharacterElement>(&mut seq, 4)?.0,
+                        player1: element(&mut seq, 5)?,
+                        player2: element(&mut seq, 6)?,
+                        winner: element(&mut seq, 7)?,
+                        date: element::<_, DateElement>(&mut seq, 8)?.0,
+                        int7: element(&mut seq, 9)?,
+                        views: element(&mut seq, 10)?,
+                        int8: element(&mut seq, 11)?,
+                        likes: element(&mut seq, 12)?,
+                        extra: vec![],
+                    };
+                    let mut extra = vec![];
+                    while let Some(value) = seq.next_element()? {
+                        extra.push(value);
+                    }
+                    Ok(Replay { extra, ..replay })
+                }
+            }
+
+            fn element<'de, A, T>(seq: &mut A, index: usize) -> std::result::Result<T, A::Error>
+            where
+                A: SeqAccess<'de>,
+                T: Deserialize<'de>,
+            {
+                seq.next_element()?
+                    .ok_or_else(|| A::Error::invalid_length(index, &ReplayVisitor))
+            }
+
+            deserializer.deserialize_seq(ReplayVisitor)
+        }
+    }
+
+    impl Serialize for Replay {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let mut seq = serializer.serialize_seq(Some(REPLAY_FIELDS + self.extra.len()))?;
+            seq.serialize_element(&self.id)?;
+            seq.serialize_element(&self.int2)?;
+            seq.serialize_element(&FloorElement(self.floor))?;
+            seq.serialize_element(&CharacterElement(self.player1_character))?;
+            seq.serialize_element(&CharacterElement(self.player2_character))?;
+            seq.serialize_element(&self.player1)?;
+            seq.serialize_element(&self.player2)?;
+            seq.serialize_element(&self.winner)?;
+            seq.serialize_element(&DateElement(self.date))?;
+            seq.serialize_element(&self.int7)?;
+            seq.serialize_element(&self.views)?;
+            seq.serialize_element(&self.int8)?;
+            seq.serialize_element(&self.likes)?;
+            for value in &self.extra {
+                seq.serialize_element(value)?;
+            }
+            seq.end()
+        }
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct Player {
+        pub id: String,
+        pub name: RawString,
+        pub string1: String,
+        pub string2: String,
+        pub int1: UnknownInteger,
+    }
+
+    /// A string entered by a player. The game client doesn't guarantee valid UTF-8, so the bytes
+    /// are kept as sent and only converted when needed.
+    #[derive(Clone, PartialEq, Eq, Default)]
+    pub struct RawString(pub Vec<u8>);
+
+    impl RawString {
+        pub fn as_bytes(&self) -> &[u8] {
+            &self.0
+        }
+
+        /// The string with invalid sequences replaced by U+FFFD
+        pub fn to_string_lossy(&self) -> std::borrow::Cow<'_, str> {
+            String::from_utf8_lossy(&self.0)
+        }
+
+        pub fn is_utf8(&self) -> bool {
+            str::from_utf8(&self.0).is_ok()
+        }
+    }
+
+    impl fmt::Debug for RawString {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match str::from_utf8(&self.0) {
+                Ok(s) => s.fmt(f),
+                Err(_) => write!(f, "b\"{}\"", self.0.escape_ascii()),
+            }
+        }
+    }
+
+    impl From<&str> for RawString {
+        fn from(s: &str) -> Self {
+            RawString(s.as_bytes().to_vec())
+        }
+    }
+
+    impl From<String> for RawString {
+        fn from(s: String) -> Self {
+            RawString(s.into_bytes())
+        }
+    }
+
+    // rmp_serde hands strings that aren't valid UTF-8 to visit_bytes
+    impl<'de> Deserialize<'de> for RawString {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            struct RawStringVisitor;
+
+            impl<'de> Visitor<'de> for RawStringVisitor {
+                type Value = RawString;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "a string")
+                }
+
+                fn visit_str<E>(self, v: &str) -> std::result::Result<RawString, E> {
+                    Ok(v.into())
+                }
+
+                fn visit_string<E>(self, v: String) -> std::result::Result<RawString, E> {
+                    Ok(v.into())
+                }
+
+                fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<RawString, E> {
+                    Ok(RawString(v.to_vec()))
+                }
+
+                fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<RawString, E> {
+                    Ok(RawString(v))
+                }
+            }
+
+            deserializer.deserialize_string(RawStringVisitor)
+        }
+    }
+
+    // Invalid UTF-8 can't be written as a messagepack str through serde and becomes bin instead
+    impl Serialize for RawString {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match str::from_utf8(&self.0) {
+                Ok(s) => serializer.serialize_str(s),
+                Err(_) => serializer.serialize_bytes(&self.0),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct VipRequest {
+        pub int1: UnknownInteger,
+        pub int2: UnknownInteger,
+        pub int3: UnknownInteger,
+        pub int4: UnknownInteger,
+    }
+
+    impl VipRequest {
+        /// The request the game sends for the first page of the ranking
+        pub fn first_page() -> Self {
+            VipRequest {
+                int1: 0,
+                int2: 0,
+                int3: -1,
+                int4: 0,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct VipResponse {
+        pub int1: UnknownInteger,
+        pub int2: UnknownInteger,
+        pub int3: UnknownInteger,
+        pub int4: UnknownInteger,
+        pub ranking: Vec<VipPlayer>,
+        pub struct1: VipStruct1,
+        pub int5: UnknownInteger,
+    }
+
+    /// An entry of the vip ranking, see VipEntry for the meaning of the fields
+    #[derive(Debug, Clone, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct VipPlayer {
+        /// Rank, starting at 1
+        pub int1: UnknownInteger,
+        /// Most likely the character code, see VipEntry::player
+        pub int2: UnknownInteger,
+        /// Points, descending with the rank
+        pub int3: UnknownInteger,
+        pub id: String,
+        pub name: String,
+        /// Steam id, like Player::string1 of replays
+        pub string1: String,
+        /// Online id, like Player::string2 of replays
+        pub string2: String,
+    }
+
+    #[derive(Debug, Clone, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct VipStruct1 {
+        pub int1: UnknownInteger,
         pub int2: UnknownInteger,
-        #[serde(with = "floor")]
-        pub floor: Floor,
-        pub player1_character: Character,
-        pub player2_character: Character,
-        pub player1: Player,
-        pub player2: Player,
-        pub winner: u8,
+        pub int3: UnknownInteger,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct StatisticsRequest {
+        pub id: String,
+        // 1: Match stats (RC usage, FD usage, perfects, etc)
+        // 2: Post match diagram
+        // 3, 4: Attack stats
+        // 5: Match stats
+        // 6: Challenge progress
+        // 7: Character badge, XP statistics
+        // 8: Some numbers
+        // 9: News
+        pub statistics_type: UnknownInteger,
+        pub int2: UnknownInteger,
+        pub int3: UnknownInteger,
+        pub int4: UnknownInteger,
+        pub int5: UnknownInteger,
+    }
+
+    impl StatisticsRequest {
+        /// The request for the character badges and levels of a player, which get_statistics
+        /// sends
+        pub fn profile(user_id: i64) -> Self {
+            StatisticsRequest {
+                id: user_id.to_string(),
+                statistics_type: 7,
+                int2: -1,
+                int3: -1,
+                int4: -1,
+                int5: -1,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct StatisticsResponse {
+        pub int1: UnknownInteger,
+        #[serde(with = "json")]
+        pub json: serde_json::Value,
+    }
+
+    // Returned as the response body when the api is misused, surfaced as Error::Api
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    pub struct ApiError {
+        pub int1: UnknownInteger,
+        pub string1: String,
+    }
+
+    mod json {
+        use super::*;
+
+        use serde_json::Value;
+
+        pub(crate) fn deserialize<'de, D>(deserializer: D) -> std::result::Result<Value, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let b = String::deserialize(deserializer)?;
+            serde_json::from_str(&b).map_err(D::Error::custom)
+        }
+
+        pub(crate) fn serialize<S>(
+            value: &Value,
+            serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            value.to_string().serialize(serializer)
+        }
+    }
+
+    mod date_time {
+        use super::*;
+
+        const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+        pub(crate) fn deserialize<'de, D>(
+            deserializer: D,
+        ) -> std::result::Result<chrono::DateTime<chrono::Utc>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            // Parse the borrowed string in place instead of allocating a String for every replay
+            struct DateVisitor;
+
+            impl Visitor<'_> for DateVisitor {
+                type Value = chrono::DateTime<chrono::Utc>;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "a date formatted as {}", FORMAT)
+                }
+
+                fn visit_str<E>(self, v: &str) -> std::result::Result<Self::Value, E>
+                where
+                    E: serde_crate::de::Error,
+                {
+                    Ok(NaiveDateTime::parse_from_str(v, FORMAT)
+                        .map_err(E::custom)?
+                        .and_utc())
+                }
+            }
+
+            deserializer.deserialize_str(DateVisitor)
+        }
+
+        pub(crate) fn serialize<S>(
+            value: &chrono::DateTime<chrono::Utc>,
+            serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            value.format(FORMAT).to_string().serialize(serializer)
+        }
+    }
+
+    mod floor {
+        use super::*;
+
+        pub(crate) fn deserialize<'de, D>(deserializer: D) -> std::result::Result<Floor, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let b = u8::deserialize(deserializer)?;
+            Ok(Floor::from_u8_lossy(b))
+        }
+
+        pub(crate) fn serialize<S>(
+            value: &Floor,
+            serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            value.to_u8().serialize(serializer)
+        }
+    }
+
+    // Characters are sent as their code, the derived implementation would serialize the name
+    mod player_character {
+        use super::*;
+
+        pub(crate) fn deserialize<'de, D>(
+            deserializer: D,
+        ) -> std::result::Result<Character, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let b = u8::deserialize(deserializer)?;
+            Ok(Character::from_u8_lossy(b))
+        }
+
+        pub(crate) fn serialize<S>(
+            value: &Character,
+            serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            value.to_u8().serialize(serializer)
+        }
+    }
+
+    mod character {
+        use super::*;
+
+        pub(crate) fn deserialize<'de, D>(
+            deserializer: D,
+        ) -> std::result::Result<Option<Character>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let b = i8::deserialize(deserializer)?;
+            Ok(if b == -1 {
+                None
+            } else {
+                Some(Character::from_u8_lossy(b as u8))
+            })
+        }
+
+        pub(crate) fn serialize<S>(
+            value: &Option<Character>,
+            serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            value
+                .map_or_else(|| -1, |c| c.to_u8() as i8)
+                .serialize(serializer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::messagepack::*;
+    use super::*;
+
+    const REPLAY_RESPONSE_1: &[u8] = include_bytes!("../test_data/replay_response_1.msgpack");
+
+    const REPLAY_RESPONSE_2: &[u8] = include_bytes!("../test_data/replay_response_2.msgpack");
+
+    const REPLAY_RESPONSE_3: &[u8] = include_bytes!("../test_data/replay_response_3.msgpack");
+
+    const REPLAY_RESPONSE_4: &[u8] = include_bytes!("../test_data/replay_response_4.msgpack");
+
+    #[test]
+    fn test_parse_response() {
+        let replays = decode_replay_response(REPLAY_RESPONSE_1, DecodeMode::Strict).unwrap();
+
+        assert!(
+            replays.errors().is_empty(),
+            "Got errors: {:#?}",
+            replays.errors()
+        );
+
+        expect_test::expect_file!["../test_data/replay_response.txt"]
+            .assert_debug_eq(&replays.matches().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_parse_response_2() {
+        // This test used to miss one replay before true messagepack parsing
+
+        let replays = decode_replay_response(REPLAY_RESPONSE_2, DecodeMode::Strict).unwrap();
+
+        assert!(
+            replays.errors().is_empty(),
+            "Got errors: {:#?}",
+            replays.errors()
+        );
+
+        expect_test::expect_file!["../test_data/replay_response_2.txt"]
+            .assert_debug_eq(&replays.matches().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_parse_response_3() {
+        // This test used to miss one replay before true messagepack parsing
+
+        let result =
+            rmp_serde::decode::from_slice::<messagepack::ReplayResponse>(REPLAY_RESPONSE_3);
+
+        expect_test::expect_file!["../test_data/replay_response_3.txt"].assert_debug_eq(&result);
+    }
+
+    // Evidence for the documentation of Replay: no unnamed integer behaves like a round count or
+    // a duration
+    #[test]
+    fn unnamed_replay_integers() {
+        let mut replays = vec![];
+        for fixture in [
+            REPLAY_RESPONSE_1,
+            REPLAY_RESPONSE_2,
+            REPLAY_RESPONSE_3,
+            REPLAY_RESPONSE_4,
+        ] {
+            let response: ReplayResponse = rmp_serde::from_slice(fixture).unwrap();
+            replays.extend(response.body.replays);
+        }
+        assert!(replays.iter().all(|replay| replay.int7 == 1));
+        replays.sort_by_key(|replay| replay.date);
+        assert!(replays
+            .windows(2)
+            .all(|pair| pair[0].version_code() <= pair[1].version_code()));
+        let versions = replays
+            .iter()
+            .map(|replay| replay.version_code())
+            .collect::<HashSet<_>>();
+        assert!(versions.len() > 2 && versions.iter().all(|&v| (3..=9).contains(&v)));
+    }
+
+    #[test]
+    fn test_parse_response_4() {
+        // This test used to miss one replay before true messagepack parsing
+
+        let mut de = rmp_serde::decode::Deserializer::from_read_ref(REPLAY_RESPONSE_4);
+        let result = serde_path_to_error::deserialize::<_, messagepack::ReplayResponse>(&mut de)
+            .map_err(|err| err.to_string());
+
+        expect_test::expect_file!["../test_data/replay_response_4.txt"].assert_debug_eq(&result);
+    }
+
+    // Replace an element of a captured response, addressed by array indices
+    fn mutate_response(bytes: &[u8], indices: &[usize], value: rmpv::Value) -> Vec<u8> {
+        let mut root = rmpv::decode::read_value(&mut &bytes[..]).unwrap();
+        let mut element = &mut root;
+        for &index in indices {
+            element = match element {
+                rmpv::Value::Array(elements) => &mut elements[index],
+                other => panic!("{} is not an array", other),
+            };
+        }
+        *element = value;
+        let mut buf = vec![];
+        rmpv::encode::write_value(&mut buf, &root).unwrap();
+        buf
+    }
+
+    #[test]
+    fn decode_error_path() {
+        let bytes = mutate_response(REPLAY_RESPONSE_1, &[1, 3, 17, 6, 1], 5.into());
+        let replays = decode_replay_response(&bytes, DecodeOptions::default()).unwrap();
+        assert_eq!(replays.errors().len(), 1);
+        let error = &replays.errors()[0];
+        assert_eq!(error.path(), Some("body.replays[17].player2.name"));
+        assert!(error
+            .to_string()
+            .contains("\n  path: body.replays[17].player2.name"));
+        assert!(error.response_id().is_some());
+
+        let bytes = mutate_response(REPLAY_RESPONSE_1, &[0, 2], 5.into());
+        let replays = decode_replay_response(&bytes, DecodeOptions::default()).unwrap();
+        assert_eq!(replays.errors()[0].path(), Some("header.date"));
+
+        // Elements appended by an update and types without names keep their indices
+        let bytes = mutate_response(REPLAY_RESPONSE_1, &[1, 3, 2, 2], "zz".into());
+        let replays = decode_replay_response(&bytes, DecodeOptions::default()).unwrap();
+        assert_eq!(replays.errors()[0].path(), Some("body.replays[2].floor"));
+        let bytes = mutate_response(REPLAY_RESPONSE_1, &[1, 3, 2, 6, 1], 5.into());
+        let error = decode_response::<ResponseBody>(&bytes, messagepack::Fields::of("/api/other"))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.path(), Some("body[3][2][6][1]"));
+    }
+
+    fn test_response_header() -> ResponseHeader {
+        ResponseHeader {
+            id: "61ff0796545a9".into(),
+            int1: 0,
+            date: "2022/02/05 23:26:14".into(),
+            version1: "0.1.0".into(),
+            version2: "0.0.2".into(),
+            version3: "0.0.2".into(),
+            string1: "".into(),
+            string2: "".into(),
+        }
+    }
+
+    // No rejected response has been captured yet, so the payload is built by hand in the shape of
+    // messagepack::ApiError. The live_error tests write captures to replace it.
+    #[test]
+    fn decode_api_error() {
+        let bytes = rmp_serde::encode::to_vec(&Response {
+            header: test_response_header(),
+            body: ApiError {
+                int1: 3,
+                string1: "invalid request".into(),
+            },
+        })
+        .unwrap();
+
+        match decode_response::<ResponseBody>(&bytes, &REPLAY_RESPONSE_FIELDS) {
+            Err(Error::Api { code, message }) => {
+                assert_eq!(code, 3);
+                assert_eq!(message, "invalid request");
+            }
+            other => panic!("expected Error::Api, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_unrelated_body_is_parse_error() {
+        let bytes = rmp_serde::encode::to_vec(&Response {
+            header: test_response_header(),
+            body: VipRequest {
+                int1: 0,
+                int2: 0,
+                int3: -1,
+                int4: 0,
+            },
+        })
+        .unwrap();
+
+        let error = match decode_response::<ResponseBody>(&bytes, &REPLAY_RESPONSE_FIELDS) {
+            Ok(Err(error)) => error,
+            other => panic!("expected a ParseError, got {:?}", other),
+        };
+        // The intact header still identifies the response
+        assert_eq!(error.response_id().unwrap().as_str(), "61ff0796545a9");
+        assert!(error
+            .to_string()
+            .contains("\n  response id: 61ff0796545a9\n"));
+        assert!(
+            decode_response::<ResponseBody>(b"\x92\x01", &REPLAY_RESPONSE_FIELDS)
+                .unwrap()
+                .unwrap_err()
+                .response_id()
+                .is_none()
+        );
+    }
+
+    fn empty_replay_response() -> Vec<u8> {
+        rmp_serde::encode::to_vec(&Response {
+            header: test_response_header(),
+            body: (0, 0, 0, Vec::<()>::new()),
+        })
+        .unwrap()
+    }
+
+    // Re-encode REPLAY_RESPONSE_1 after modifying the elements of its first replay
+    fn edit_first_replay(edit: impl FnOnce(&mut Vec<rmpv::Value>)) -> Vec<u8> {
+        fn array(value: &mut rmpv::Value) -> &mut Vec<rmpv::Value> {
+            match value {
+                rmpv::Value::Array(array) => array,
+                other => panic!("expected an array, got {}", other),
+            }
+        }
+
+        let mut value = rmpv::decode::read_value(&mut &REPLAY_RESPONSE_1[..]).unwrap();
+        let body = &mut array(&mut value)[1];
+        let replays = &mut array(body)[3];
+        edit(array(&mut array(replays)[0]));
+        let mut bytes = vec![];
+        rmpv::encode::write_value(&mut bytes, &value).unwrap();
+        bytes
+    }
 
-        #[serde(deserialize_with = "deserialize_date_time")]
-        pub date: chrono::DateTime<Utc>,
-        pub int7: UnknownInteger,
-        pub views: u64,
-        pub int8: UnknownInteger,
-        pub likes: u64,
+    // Answers like the inner transport after a delay, of which body_time is reported as reading the
+    // body
+    struct Slow {
+        inner: MockTransport,
+        delay: Duration,
+        body_time: Option<Duration>,
     }
 
-    #[derive(Debug, Clone, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct Player {
-        pub id: String,
-        pub name: String,
-        pub string1: String,
-        pub string2: String,
-        pub int1: UnknownInteger,
+    impl Transport for Slow {
+        fn post<'a>(
+            &'a self,
+            path: &'static str,
+            data: &'a str,
+        ) -> BoxFuture<'a, Result<RawResponse>> {
+            Box::pin(async move {
+                tokio::time::sleep(self.delay).await;
+                let mut response = self.inner.post(path, data).await?;
+                response.body_time = self.body_time;
+                Ok(response)
+            })
+        }
     }
 
-    #[derive(Debug, Clone, Deserialize, Serialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct VipRequest {
-        pub int1: UnknownInteger,
-        pub int2: UnknownInteger,
-        pub int3: UnknownInteger,
-        pub int4: UnknownInteger,
+    #[tokio::test]
+    async fn page_timings() {
+        let pages = || {
+            MockTransport::new()
+                .page(0, RawResponse::ok(REPLAY_RESPONSE_1))
+                .page(1, RawResponse::ok(REPLAY_RESPONSE_2))
+        };
+        let context = Context::default().transport(Slow {
+            inner: pages(),
+            delay: Duration::from_millis(30),
+            body_time: Some(Duration::from_millis(10)),
+        });
+        let replays = get_replays(&context, 2, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        let timings = replays.timings();
+        assert_eq!(timings.iter().map(|t| t.page).collect::<Vec<_>>(), [0, 1]);
+        for timing in timings {
+            assert!(timing.first_byte >= Duration::from_millis(20));
+            assert_eq!(timing.body, Duration::from_millis(10));
+            assert!(timing.total() >= Duration::from_millis(30));
+        }
+        let summary = replays.timing_summary().unwrap();
+        assert_eq!(summary.pages, 2);
+        assert_eq!(summary.body.p50, Duration::from_millis(10));
+        assert!(summary.total.p50 <= summary.total.p95);
+        assert!(summary.total.p50 >= Duration::from_millis(30));
+
+        // Without body_time the whole transport time counts as waiting for the first byte
+        let context = Context::default().transport(Slow {
+            inner: pages(),
+            delay: Duration::from_millis(10),
+            body_time: None,
+        });
+        let pages = get_replays_by_page(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        let timing = pages[0].timing.unwrap();
+        assert!(timing.first_byte >= Duration::from_millis(10));
+        assert_eq!(timing.body, Duration::ZERO);
+
+        assert!(ReplaySet::default().timing_summary().is_none());
     }
 
-    impl ApiRequest for VipRequest {
-        const PATH: &'static str = "/api/ranking/vip";
+    #[test]
+    fn percentiles() {
+        let millis = |ms| Duration::from_millis(ms);
+        let percentiles = Percentiles::new((1..=20).rev().map(millis).collect());
+        assert_eq!(percentiles.p50, millis(10));
+        assert_eq!(percentiles.p95, millis(19));
+        let single = Percentiles::new(vec![millis(7)]);
+        assert_eq!((single.p50, single.p95), (millis(7), millis(7)));
     }
 
-    #[derive(Debug, Clone, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct VipResponse {
-        pub int1: UnknownInteger,
-        pub int2: UnknownInteger,
-        pub int3: UnknownInteger,
-        pub int4: UnknownInteger,
-        pub ranking: Vec<VipPlayer>,
-        pub struct1: VipStruct1,
-        pub int5: UnknownInteger,
+    #[tokio::test]
+    async fn response_limits() {
+        let mut nested = rmpv::Value::from(1);
+        for _ in 0..MAX_DECODE_DEPTH {
+            nested = rmpv::Value::Array(vec![nested]);
+        }
+        let bytes = edit_first_replay(|replay| replay.push(nested));
+        let error = decode_replay_response(&bytes, DecodeMode::Lenient).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::ResponseTooLarge {
+                limit: "nesting depth",
+                ..
+            }
+        ));
+        assert!(!error.is_retryable());
+        assert!(error.to_string().contains("request fewer replays per page"));
+
+        let transport = Arc::new(
+            MockTransport::new().page(0, RawResponse::ok(vec![0; MAX_RESPONSE_BYTES + 1])),
+        );
+        let context = Context::default().transport(transport.clone());
+        assert!(matches!(
+            get_replays(&context, 1, 127, QueryParameters::default()).await,
+            Err(Error::ResponseTooLarge { limit: "size", bytes }) if bytes == MAX_RESPONSE_BYTES + 1
+        ));
+        assert_eq!(transport.requests().len(), 1);
+
+        // A replay array claiming four billion elements fails on the missing data without
+        // reserving memory for them
+        let mut bytes = edit_first_replay(|_| ());
+        let mut value = rmpv::decode::read_value(&mut &bytes[..]).unwrap();
+        if let rmpv::Value::Array(response) = &mut value {
+            if let rmpv::Value::Array(body) = &mut response[1] {
+                body[3] = rmpv::Value::Array(vec![]);
+            }
+        }
+        bytes.clear();
+        rmpv::encode::write_value(&mut bytes, &value).unwrap();
+        assert_eq!(bytes.pop(), Some(0x90));
+        bytes.extend_from_slice(&[0xdd, 0xff, 0xff, 0xff, 0xff]);
+        let replays = decode_replay_response(&bytes, DecodeMode::Lenient).unwrap();
+        assert_eq!(replays.matches().count(), 0);
+        assert_eq!(replays.errors().len(), 1);
     }
 
-    #[derive(Debug, Clone, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct VipPlayer {
-        pub int1: UnknownInteger,
-        pub int2: UnknownInteger,
-        pub int3: UnknownInteger,
-        pub id: String,
-        pub name: String,
-        pub string1: String,
-        pub string2: String,
+    #[test]
+    fn page_content_hash() {
+        let hash = |bytes: &[u8]| {
+            decode_replay_response(bytes, DecodeMode::Lenient)
+                .unwrap()
+                .metadata()[0]
+                .content_hash
+        };
+        let original = hash(REPLAY_RESPONSE_1);
+        assert_eq!(original, hash(REPLAY_RESPONSE_1));
+        assert_ne!(original, hash(REPLAY_RESPONSE_2));
+
+        // The order of the replays doesn't matter
+        let mut value = rmpv::decode::read_value(&mut &REPLAY_RESPONSE_1[..]).unwrap();
+        if let rmpv::Value::Array(response) = &mut value {
+            if let rmpv::Value::Array(body) = &mut response[1] {
+                if let rmpv::Value::Array(replays) = &mut body[3] {
+                    replays.reverse();
+                }
+            }
+        }
+        let mut reversed = vec![];
+        rmpv::encode::write_value(&mut reversed, &value).unwrap();
+        assert_ne!(reversed, REPLAY_RESPONSE_1);
+        assert_eq!(hash(&reversed), original);
+
+        // A corrected winner does
+        let corrected = edit_first_replay(|replay| {
+            replay[7] = rmpv::Value::from(3 - replay[7].as_u64().unwrap());
+        });
+        assert_ne!(hash(&corrected), original);
     }
 
-    #[derive(Debug, Clone, Deserialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct VipStruct1 {
-        pub int1: UnknownInteger,
-        pub int2: UnknownInteger,
-        pub int3: UnknownInteger,
+    #[test]
+    fn page_diff() {
+        let page = |bytes: &[u8]| {
+            let replays = decode_replay_response(bytes, DecodeMode::Lenient).unwrap();
+            PageResult {
+                index: 0,
+                matches: replays.matches().cloned().collect(),
+                errors: vec![],
+                metadata: replays.metadata().first().cloned(),
+                timing: None,
+            }
+        };
+        let old = page(REPLAY_RESPONSE_1);
+        assert!(diff_pages(&old, &old).is_empty());
+
+        let corrected = edit_first_replay(|replay| {
+            replay[7] = rmpv::Value::from(3 - replay[7].as_u64().unwrap());
+        });
+        let mut new = page(&corrected);
+        let removed = new.matches.remove(0);
+        let added = page(REPLAY_RESPONSE_4).matches.pop().unwrap();
+        new.matches.push(added.clone());
+        new.matches.sort_unstable();
+
+        let diff = diff_pages(&old, &new);
+        assert_eq!(diff.added, [added]);
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].id, removed.id);
+        assert_eq!(diff.changed.len(), 1);
+        let (before, after) = &diff.changed[0];
+        assert_eq!(before.id, after.id);
+        assert_ne!(before.winner, after.winner);
+        assert_eq!(diff_pages(&new, &old).added.len(), 1);
+    }
+
+    #[test]
+    fn trailing_replay_fields() {
+        // As a game update might append new fields
+        let bytes = edit_first_replay(|replay| {
+            replay.push(rmpv::Value::from(7));
+            replay.push(rmpv::Value::from("new field"));
+        });
+        let response = rmp_serde::decode::from_slice::<ReplayResponse>(&bytes).unwrap();
+        assert_eq!(
+            response.body.replays[0].extra,
+            vec![rmpv::Value::from(7), rmpv::Value::from("new field")]
+        );
+        assert!(response.body.replays[1].extra.is_empty());
+        assert_eq!(rmp_serde::encode::to_vec(&response).unwrap(), bytes);
+
+        let replays = decode_replay_response(&bytes, DecodeMode::Lenient).unwrap();
+        assert_eq!(replays.matches().count(), 30);
+        assert!(replays.errors().is_empty());
+
+        let replays = decode_replay_response(&bytes, DecodeMode::Strict).unwrap();
+        assert_eq!(replays.matches().count(), 29);
+        assert_eq!(replays.errors().len(), 1);
+        assert!(matches!(
+            replays.errors()[0].inner(),
+            Error::UnexpectedResponse("replay has unknown trailing fields")
+        ));
+    }
+
+    #[test]
+    fn invalid_utf8_name() {
+        // "limon" with an invalid byte, the string keeps its length so the rest of the page is intact
+        let mut bytes = REPLAY_RESPONSE_1.to_vec();
+        let offset = bytes.windows(6).position(|w| w == b"\xa5limon").unwrap();
+        bytes[offset + 3] = 0xff;
+
+        let response = rmp_serde::decode::from_slice::<ReplayResponse>(&bytes).unwrap();
+        let name = &response.body.replays[0].player1.name;
+        assert_eq!(name.as_bytes(), b"li\xffon");
+        assert!(!name.is_utf8());
+        assert_eq!(format!("{:?}", name), "b\"li\\xffon\"");
+
+        let replays = decode_replay_response(&bytes, DecodeMode::Lenient).unwrap();
+        assert!(replays.errors().is_empty());
+        assert_eq!(replays.matches().count(), 30);
+        let replays = decode_replay_response(&bytes, DecodeMode::Strict).unwrap();
+        assert_eq!(replays.errors().len(), 1);
+        assert_eq!(replays.matches().count(), 29);
+        let replays = decode_replay_response(&bytes, DecodeMode::Lenient).unwrap();
+        assert!(replays
+            .matches()
+            .any(|m| m.players().0.name() == "li\u{fffd}on"
+                || m.players().1.name() == "li\u{fffd}on"));
+    }
+
+    #[test]
+    fn duplicate_player() {
+        let bytes = edit_first_replay(|replay| replay[6] = replay[5].clone());
+        let response = rmp_serde::decode::from_slice::<ReplayResponse>(&bytes).unwrap();
+        let player = &response.body.replays[0].player1;
+        assert_eq!(response.body.replays[0].player2.id, player.id);
+
+        for mode in [DecodeMode::Strict, DecodeMode::Lenient] {
+            let replays = decode_replay_response(&bytes, mode).unwrap();
+            assert_eq!(replays.matches().count(), 29);
+            assert_eq!(replays.errors().len(), 1);
+            match replays.errors()[0].inner() {
+                Error::DuplicatePlayer { id } => assert_eq!(id, &player.id),
+                e => panic!("expected Error::DuplicatePlayer, got {:?}", e),
+            }
+            assert!(!replays
+                .matches()
+                .any(|m| m.id == response.body.replays[0].id));
+        }
+    }
+
+    #[test]
+    fn unknown_codes() {
+        let bytes = edit_first_replay(|replay| {
+            replay[2] = rmpv::Value::from(0x0b);
+            replay[4] = rmpv::Value::from(0x20);
+        });
+        let replays = decode_replay_response(&bytes, DecodeMode::Lenient).unwrap();
+        assert!(replays.errors().is_empty());
+        let m = replays.matches().last().unwrap();
+        assert_eq!(m.floor(), Floor::Unknown(0x0b));
+        assert_eq!(m.players().1.character, Character::Unknown(0x20));
+
+        assert_eq!(m.floor_byte(), 0x0b);
+
+        let replays = decode_replay_response(&bytes, DecodeMode::Strict).unwrap();
+        assert_eq!(replays.matches().count(), 29);
+        assert!(matches!(
+            replays.errors()[0].inner(),
+            Error::UnexpectedResponse("replay has an unknown floor")
+        ));
+        assert_eq!(replays.errors()[0].floor_byte(), Some(0x0b));
+        assert!(replays.errors()[0]
+            .to_string()
+            .contains("\n  floor byte: 0x0b"));
+
+        let response = rmp_serde::decode::from_slice::<ReplayResponse>(&bytes).unwrap();
+        assert_eq!(rmp_serde::encode::to_vec(&response).unwrap(), bytes);
+    }
+
+    #[test]
+    fn unknown_winner() {
+        // E.g. a draw, which the game never reported so far
+        let bytes = edit_first_replay(|replay| replay[7] = rmpv::Value::from(3));
+        let replays = decode_replay_response(&bytes, DecodeMode::Lenient).unwrap();
+        assert!(replays.errors().is_empty());
+        let m = replays.matches().last().unwrap();
+        assert_eq!(m.winner, Winner::Unknown(3));
+        assert!(m.try_winner().is_none() && m.try_loser().is_none());
+        assert!(m.to_string().ends_with("Winner: unknown (3)\n}"));
+
+        let replays = decode_replay_response(&bytes, DecodeMode::Strict).unwrap();
+        assert_eq!(replays.matches().count(), 29);
+        assert!(matches!(
+            replays.errors()[0].inner(),
+            Error::InvalidWinner(3)
+        ));
+    }
+
+    #[test]
+    fn errors_carry_element_bytes() {
+        let bytes = edit_first_replay(|replay| replay[7] = rmpv::Value::from(3));
+        let replays = decode_replay_response(&bytes, DecodeMode::Strict).unwrap();
+        let raw = replays.errors()[0].raw_bytes();
+        assert_eq!(rmp_serde::from_slice::<Replay>(raw).unwrap().winner, 3);
+        assert!(bytes.windows(raw.len()).any(|window| window == raw));
+
+        let mut response =
+            rmp_serde::decode::from_slice::<ReplayResponse>(REPLAY_RESPONSE_2).unwrap();
+        response.header.date = "yesterday".into();
+        let bytes = rmp_serde::encode::to_vec(&response).unwrap();
+        let replays = decode_replay_response(&bytes, DecodeMode::Lenient).unwrap();
+        assert_eq!(replays.errors().len(), 1);
+        assert_eq!(
+            replays.errors()[0].raw_bytes(),
+            rmp_serde::encode::to_vec(&response.header).unwrap()
+        );
+    }
+
+    #[test]
+    fn truncated_replay() {
+        let bytes = edit_first_replay(|replay| {
+            replay.pop();
+        });
+        assert!(rmp_serde::decode::from_slice::<ReplayResponse>(&bytes).is_err());
+    }
+
+    const REPLAY_FIXTURES: [&[u8]; 4] = [
+        REPLAY_RESPONSE_1,
+        REPLAY_RESPONSE_2,
+        REPLAY_RESPONSE_3,
+        REPLAY_RESPONSE_4,
+    ];
+
+    #[test]
+    fn replay_response_round_trip() {
+        for bytes in REPLAY_FIXTURES {
+            let response = rmp_serde::decode::from_slice::<ReplayResponse>(bytes).unwrap();
+            assert_eq!(rmp_serde::encode::to_vec(&response).unwrap(), bytes);
+        }
+    }
+
+    fn arb_player() -> impl proptest::strategy::Strategy<Value = messagepack::Player> {
+        use proptest::prelude::*;
+        ("[0-9]{18}", ".*", "[0-9]{17}", "[0-9a-f]{15}", any::<i64>()).prop_map(
+            |(id, name, string1, string2, int1)| messagepack::Player {
+                id,
+                name: name.into(),
+                string1,
+                string2,
+                int1,
+            },
+        )
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn replay_round_trip(
+            ints in proptest::array::uniform6(proptest::prelude::any::<i64>()),
+            replay_id in proptest::prelude::any::<u64>(),
+            floor in proptest::prelude::any::<u8>(),
+            characters in proptest::prelude::any::<(u8, u8)>(),
+            players in (arb_player(), arb_player()),
+            winner in 1u8..=2,
+            timestamp in 0i64..4_102_444_800,
+            extra in proptest::collection::vec(proptest::prelude::any::<i64>(), 0..3),
+        ) {
+            let response = Response {
+                header: test_response_header(),
+                body: ResponseBody {
+                    int1: ints[0],
+                    int2: ints[1],
+                    int3: ints[2],
+                    replays: vec![Replay {
+                        id: replay_id,
+                        int2: ints[3],
+                        floor: Floor::from_u8_lossy(floor),
+                        player1_character: Character::from_u8_lossy(characters.0),
+                        player2_character: Character::from_u8_lossy(characters.1),
+                        player1: players.0,
+                        player2: players.1,
+                        winner,
+                        date: DateTime::from_timestamp(timestamp, 0).unwrap(),
+                        int7: ints[4],
+                        views: replay_id,
+                        int8: ints[5],
+                        likes: replay_id,
+                        extra: extra.into_iter().map(rmpv::Value::from).collect(),
+                    }],
+                },
+            };
+            let bytes = rmp_serde::encode::to_vec(&response).unwrap();
+            let decoded = rmp_serde::decode::from_slice::<ReplayResponse>(&bytes).unwrap();
+            proptest::prop_assert_eq!(rmp_serde::encode::to_vec(&decoded).unwrap(), bytes);
+        }
+    }
+
+    // Byte level edits applied to a captured response, indices wrap around its length
+    #[derive(Debug, Clone)]
+    enum Mutation {
+        Set(usize, u8),
+        Insert(usize, u8),
+        Remove(usize),
+        Truncate(usize),
+    }
+
+    fn arb_mutation() -> impl proptest::strategy::Strategy<Value = Mutation> {
+        use proptest::prelude::*;
+        prop_oneof![
+            (any::<usize>(), any::<u8>()).prop_map(|(i, b)| Mutation::Set(i, b)),
+            (any::<usize>(), any::<u8>()).prop_map(|(i, b)| Mutation::Insert(i, b)),
+            any::<usize>().prop_map(Mutation::Remove),
+            any::<usize>().prop_map(Mutation::Truncate),
+        ]
+    }
+
+    fn mutate(bytes: &[u8], mutations: &[Mutation]) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        for mutation in mutations {
+            if bytes.is_empty() {
+                break;
+            }
+            let len = bytes.len();
+            match *mutation {
+                Mutation::Set(i, b) => bytes[i % len] = b,
+                Mutation::Insert(i, b) => bytes.insert(i % (len + 1), b),
+                Mutation::Remove(i) => {
+                    bytes.remove(i % len);
+                }
+                Mutation::Truncate(i) => bytes.truncate(i % len),
+            }
+        }
+        bytes
+    }
+
+    proptest::proptest! {
+        // The decoder is fed whatever the network delivers, corrupted responses have to end up as
+        // errors instead of panics
+        #[test]
+        fn mutated_responses_never_panic(
+            fixture in 0..REPLAY_FIXTURES.len(),
+            mutations in proptest::collection::vec(arb_mutation(), 1..8),
+            offset in -86_399i32..86_400,
+        ) {
+            let bytes = mutate(REPLAY_FIXTURES[fixture], &mutations);
+            for mode in [DecodeMode::Strict, DecodeMode::Lenient] {
+                let options = DecodeOptions {
+                    mode,
+                    server_offset: FixedOffset::east_opt(offset).unwrap(),
+                };
+                let _ = decode_replay_response(&bytes, options);
+            }
+            let _ = decode_vip_response(&bytes);
+            let _ = decode_statistics_response(&bytes);
+        }
+    }
+
+    #[test]
+    fn extreme_dates_do_not_panic() {
+        let bytes = edit_first_replay(|replay| {
+            replay[8] = rmpv::Value::from("+262142-12-31 23:59:59");
+        });
+        let replays = decode_replay_response(
+            &bytes,
+            DecodeOptions {
+                mode: DecodeMode::Lenient,
+                server_offset: FixedOffset::west_opt(86_399).unwrap(),
+            },
+        )
+        .unwrap();
+        assert_eq!(replays.errors().len(), 1);
+    }
+
+    #[test]
+    fn body_counts_replays_of_the_page() {
+        for bytes in REPLAY_FIXTURES {
+            let body = rmp_serde::decode::from_slice::<ReplayResponse>(bytes)
+                .unwrap()
+                .body;
+            assert_eq!(body.int3, body.replays.len() as i64);
+        }
+    }
+
+    #[test]
+    fn header_id_encodes_header_date() {
+        for bytes in REPLAY_FIXTURES {
+            let header = rmp_serde::decode::from_slice::<ReplayResponse>(bytes)
+                .unwrap()
+                .header;
+            let seconds = i64::from_str_radix(&header.id.as_str()[..8], 16).unwrap();
+            let date = NaiveDateTime::parse_from_str(&header.date, "%Y/%m/%d %H:%M:%S").unwrap();
+            assert_eq!(
+                DateTime::from_timestamp(seconds, 0).unwrap(),
+                date.and_utc()
+            );
+            assert_eq!(
+                header.id.timestamp().unwrap().timestamp(),
+                date.and_utc().timestamp()
+            );
+        }
+        assert_eq!(
+            ResponseId::from("61ff0796545a9").timestamp(),
+            DateTime::from_timestamp(0x61ff0796, 0x545a9 * 1000)
+        );
+        assert!(ResponseId::from("61ff0796").timestamp().is_none());
+        assert!(ResponseId::from("61ff0796fffff").timestamp().is_none());
+    }
+
+    #[test]
+    fn response_metadata() {
+        let replays = decode_replay_response(REPLAY_RESPONSE_4, DecodeMode::Strict).unwrap();
+        expect_test::expect![[r#"
+            [
+                ResponseMetadata {
+                    page: 0,
+                    request_id: ResponseId(
+                        "61ffa6c3dce48",
+                    ),
+                    server_date: 2022-02-06T10:45:23Z,
+                    server_versions: [
+                        "0.1.0",
+                        "0.0.2",
+                        "0.0.2",
+                    ],
+                    replays: 20,
+                    content_hash: 1885338857158486087,
+                    replays_per_page: None,
+                },
+            ]
+        "#]]
+        .assert_debug_eq(&replays.metadata());
+    }
+
+    #[test]
+    fn header_versions() {
+        let response: ReplayResponse = rmp_serde::from_slice(REPLAY_RESPONSE_4).unwrap();
+        let versions = response.header.versions().unwrap();
+        assert_eq!(
+            versions,
+            [
+                Version::new(0, 1, 0),
+                Version::new(0, 0, 2),
+                Version::new(0, 0, 2)
+            ]
+        );
+        assert!(versions[1] < versions[0]);
+        assert_eq!(versions[0].to_string(), "0.1.0");
+
+        assert_eq!("1.10".parse::<Version>().unwrap(), Version::new(1, 10, 0));
+        assert!(Version::new(0, 9, 9) < "0.10".parse().unwrap());
+        for invalid in ["", "1..0", "1.0.0.0", "v1", "1.-2", "1.0 "] {
+            assert!(invalid.parse::<Version>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn clock_skew() {
+        let response: ReplayResponse = rmp_serde::from_slice(REPLAY_RESPONSE_4).unwrap();
+        assert_eq!(
+            response.header.server_date().unwrap().to_string(),
+            "2022-02-06 10:45:23 UTC"
+        );
+        // The fixture is from the past, the server appears to be far behind
+        let skew = response.header.server_clock_skew().unwrap();
+        assert!(skew < -chrono::Duration::days(365));
+
+        let mut header = response.header;
+        header.date = (Utc::now() + chrono::Duration::seconds(90))
+            .format("%Y/%m/%d %H:%M:%S")
+            .to_string();
+        let skew = header.server_clock_skew().unwrap();
+        assert!(skew > chrono::Duration::seconds(85) && skew <= chrono::Duration::seconds(90));
+    }
+
+    #[test]
+    fn empty_result_and_rejection() {
+        let replays = decode_replay_response(&empty_replay_response(), DecodeMode::Strict).unwrap();
+        assert!(replays.errors().is_empty());
+        assert!(replays.metadata()[0].is_empty_result());
+        let replays = decode_replay_response(REPLAY_RESPONSE_2, DecodeMode::Strict).unwrap();
+        assert!(!replays.metadata()[0].is_empty_result());
+
+        // Built by hand like in decode_api_error, no rejected response has been captured yet
+        let rejected = rmp_serde::encode::to_vec(&Response {
+            header: test_response_header(),
+            body: ApiError {
+                int1: 3,
+                string1: "invalid request".into(),
+            },
+        })
+        .unwrap();
+        assert!(matches!(
+            decode_replay_response(&rejected, DecodeMode::Lenient),
+            Err(Error::Api { code: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn server_offset() {
+        let utc = decode_replay_response(REPLAY_RESPONSE_4, DecodeMode::Strict).unwrap();
+        let jst = DecodeOptions {
+            mode: DecodeMode::Strict,
+            server_offset: FixedOffset::east_opt(9 * 3600).unwrap(),
+        };
+        let shifted = decode_replay_response(REPLAY_RESPONSE_4, jst).unwrap();
+        assert_eq!(utc.matches().count(), shifted.matches().count());
+        for (utc, shifted) in utc.matches().zip(shifted.matches()) {
+            assert_eq!(
+                *utc.timestamp() - *shifted.timestamp(),
+                chrono::Duration::hours(9)
+            );
+        }
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let request =
+            messagepack::Request::<messagepack::VipRequest>::from_hex(CAPTURED_VIP_REQUEST)
+                .unwrap();
+        assert_eq!(
+            hex::decode(&request.to_hex()).unwrap(),
+            rmp_serde::encode::to_vec(&request).unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_other_endpoints() {
+        let vip = decode_vip_response(&hex::decode(VIP_RESPONSE).unwrap()).unwrap();
+        assert_eq!(vip.body.ranking.len(), vip.body.int4 as usize);
+        let statistics =
+            decode_statistics_response(&hex::decode(STATISTICS_RESPONSE).unwrap()).unwrap();
+        assert!(statistics.body.json.is_object());
+        assert!(decode_vip_response(&hex::decode(STATISTICS_RESPONSE).unwrap()).is_err());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_request_is_retried_after_hint() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![
+            MockResponse::new(429, vec![]).header("Retry-After", "1"),
+            MockResponse::new(200, empty_replay_response()),
+        ])
+        .await;
+        let context = Context::new(server.url());
+
+        let start = std::time::Instant::now();
+        let (matches, errors) = get_replays(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap()
+            .into_parts();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.requests(), 2);
+        assert_eq!(matches.count(), 0);
+        assert_eq!(errors.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn retries_wait_on_the_clock() {
+        use crate::clock::MockClock;
+        use crate::mock_server::*;
+
+        // The hint of the server replaces the backoff of the second attempt, the third attempt
+        // waits the doubled initial delay
+        let server = MockServer::start(vec![
+            MockResponse::new(503, vec![]),
+            MockResponse::new(429, vec![]).header("Retry-After", "30"),
+            MockResponse::new(503, vec![]),
+            MockResponse::new(200, empty_replay_response()),
+        ])
+        .await;
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let context = Context::new(server.url()).clock(clock.clone());
+
+        let start = std::time::Instant::now();
+        let replays = get_replays(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(server.requests(), 4);
+        assert!(replays.errors().is_empty());
+        assert_eq!(clock.sleeps(), [1, 30, 4].map(Duration::from_secs).to_vec());
+
+        // A hint above max_delay is returned instead of waited for
+        let server = MockServer::start(vec![
+            MockResponse::new(429, vec![]).header("Retry-After", "120")
+        ])
+        .await;
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let context = Context::new(server.url()).clock(clock.clone());
+        assert!(matches!(
+            get_replays(&context, 1, 10, QueryParameters::default()).await,
+            Err(Error::RateLimited { .. })
+        ));
+        assert!(clock.sleeps().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_request_without_retries() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![
+            MockResponse::new(429, vec![]).header("Retry-After", "120")
+        ])
+        .await;
+        let context = Context::new(server.url()).retry_policy(RetryPolicy::none());
+
+        match get_replays(&context, 1, 10, QueryParameters::default()).await {
+            Err(Error::RateLimited { retry_after }) => {
+                assert_eq!(retry_after, Some(Duration::from_secs(120)))
+            }
+            Err(e) => panic!("expected Error::RateLimited, got {:?}", e),
+            Ok(_) => panic!("expected Error::RateLimited"),
+        }
+        assert_eq!(server.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn html_response() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![MockResponse::new(
+            200,
+            b"<!DOCTYPE html><html><body>Service Unavailable</body></html>".to_vec(),
+        )
+        .header("Content-Type", "text/html")])
+        .await;
+        let context = Context::new(server.url());
+
+        match get_replays(&context, 1, 10, QueryParameters::default()).await {
+            Err(Error::UnexpectedHtml {
+                status, snippet, ..
+            }) => {
+                assert_eq!(status, 200);
+                assert!(snippet.contains("Service Unavailable"));
+            }
+            Err(e) => panic!("expected Error::UnexpectedHtml, got {:?}", e),
+            Ok(_) => panic!("expected Error::UnexpectedHtml"),
+        }
+    }
+
+    #[tokio::test]
+    async fn html_response_without_content_type() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![MockResponse::new(
+            502,
+            b"\r\n<html><head><title>502 Bad Gateway</title></head></html>".to_vec(),
+        )])
+        .await;
+        let context = Context::new(server.url());
+
+        match get_replays(&context, 1, 10, QueryParameters::default()).await {
+            Err(Error::UnexpectedHtml { status, .. }) => assert_eq!(status, 502),
+            Err(e) => panic!("expected Error::UnexpectedHtml, got {:?}", e),
+            Ok(_) => panic!("expected Error::UnexpectedHtml"),
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_response() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![MockResponse::new(200, vec![])]).await;
+        let context = Context::new(server.url());
+
+        match get_replays(&context, 1, 10, QueryParameters::default()).await {
+            Err(Error::EmptyResponse) => (),
+            Err(e) => panic!("expected Error::EmptyResponse, got {:?}", e),
+            Ok(_) => panic!("expected Error::EmptyResponse"),
+        }
+    }
+
+    #[tokio::test]
+    async fn error_status() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![MockResponse::new(503, b"maintenance".to_vec())
+            .header("CF-Ray", "6d9a1b2c3d4e5f60-NRT")
+            .header("X-Unrelated", "ignored")])
+        .await;
+        let context = Context::new(server.url()).retry_policy(RetryPolicy::none());
+
+        match get_replays(&context, 1, 10, QueryParameters::default()).await {
+            Err(Error::HttpStatus {
+                status,
+                body_snippet,
+                headers,
+            }) => {
+                assert_eq!(status, 503);
+                assert_eq!(body_snippet, "maintenance");
+                assert_eq!(
+                    headers,
+                    vec![("cf-ray".to_string(), "6d9a1b2c3d4e5f60-NRT".to_string())]
+                );
+            }
+            Err(e) => panic!("expected Error::HttpStatus, got {:?}", e),
+            Ok(_) => panic!("expected Error::HttpStatus"),
+        }
+    }
+
+    #[test]
+    fn retry_policy_delays() {
+        let policy = RetryPolicy::default();
+        let rate_limited = Error::RateLimited { retry_after: None };
+        assert_eq!(policy.delay(0, &rate_limited), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(2, &rate_limited), Some(Duration::from_secs(4)));
+        assert_eq!(policy.delay(3, &rate_limited), None);
+
+        let hinted = Error::RateLimited {
+            retry_after: Some(Duration::from_secs(5)),
+        };
+        assert_eq!(policy.delay(1, &hinted), Some(Duration::from_secs(5)));
+        let too_long = Error::RateLimited {
+            retry_after: Some(Duration::from_secs(3600)),
+        };
+        assert_eq!(policy.delay(0, &too_long), None);
+        assert_eq!(policy.delay(0, &Error::UnexpectedResponse("")), None);
+
+        let server_error = Error::HttpStatus {
+            status: 502,
+            body_snippet: "".into(),
+            headers: vec![],
+        };
+        assert_eq!(policy.delay(1, &server_error), Some(Duration::from_secs(2)));
+        let api_error = Error::Api {
+            code: 1,
+            message: "".into(),
+        };
+        assert_eq!(policy.delay(0, &api_error), None);
+    }
+
+    #[tokio::test]
+    async fn decode_error_carries_status() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![MockResponse::new(200, b"\x92\x01".to_vec())]).await;
+        let context = Context::new(server.url());
+
+        let replays = get_replays(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        let errors = replays.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].status(), Some(200));
+        assert_eq!(errors[0].raw_bytes(), b"\x92\x01");
+    }
+
+    #[tokio::test]
+    async fn on_error_reports_every_error() {
+        use crate::mock_server::*;
+        use std::sync::Mutex;
+
+        let server = MockServer::start(vec![
+            MockResponse::new(200, b"\x92\x01".to_vec()),
+            MockResponse::new(502, vec![]),
+            MockResponse::new(404, vec![]),
+        ])
+        .await;
+        let reported = Arc::new(Mutex::new(vec![]));
+        let sink = reported.clone();
+        let context = Context::new(server.url())
+            .retry_policy(RetryPolicy {
+                initial_delay: Duration::ZERO,
+                ..Default::default()
+            })
+            .on_error(move |context, event| {
+                let kind = match event {
+                    ErrorEvent::Request(_) => "request",
+                    ErrorEvent::Parse(_) => "parse",
+                };
+                sink.lock()
+                    .unwrap()
+                    .push((context.page, context.attempt, kind));
+            });
+
+        assert!(get_replays(&context, 2, 10, QueryParameters::default())
+            .await
+            .is_err());
+        assert_eq!(
+            *reported.lock().unwrap(),
+            vec![
+                (Some(0), 0, "parse"),
+                (Some(1), 0, "request"),
+                (Some(1), 1, "request")
+            ]
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn tracing_events() {
+        use crate::mock_server::*;
+        use std::sync::Mutex;
+
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .without_time()
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let server = MockServer::start(vec![
+            MockResponse::new(502, vec![]),
+            MockResponse::new(200, edit_first_replay(|replay| replay[7] = 3.into())),
+        ])
+        .await;
+        // Strict mode rejects the replay with the unknown winner, so a parse error is traced
+        let context = Context::new(server.url())
+            .retry_policy(RetryPolicy {
+                initial_delay: Duration::ZERO,
+                ..Default::default()
+            })
+            .decode_mode(DecodeMode::Strict);
+        get_replays(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        // Leave out the events of hyper
+        let lines = logs
+            .lines()
+            .filter(|line| line.contains("ggst_api"))
+            .collect::<Vec<_>>();
+        assert!(lines[0].contains("request failed attempt=0 error=HTTP status 502"));
+        assert!(lines[0].contains("send{endpoint=\"/api/catalog/get_replay\" page=Some(0)}"));
+        assert!(lines[1].contains("response received attempt=1 status=200"));
+        assert!(lines[2].contains("replay could not be parsed page=0"));
+        assert!(lines[3].contains("replays collected matches=29 errors=1"));
+        assert!(lines[3].contains("get_replays{pages=1 replays_per_page=10}"));
+    }
+
+    #[tokio::test]
+    async fn atomic_metrics() {
+        use crate::mock_server::*;
+
+        let response = edit_first_replay(|replay| replay[7] = 3.into());
+        let server = MockServer::start(vec![
+            MockResponse::new(502, vec![]),
+            MockResponse::new(200, response.clone()),
+            MockResponse::new(200, b"\x92\x01".to_vec()),
+        ])
+        .await;
+        let metrics = Arc::new(AtomicMetrics::new());
+        // Strict mode rejects the replay with the unknown winner, so it is counted as a parse
+        // error instead of a match
+        let context = Context::new(server.url())
+            .retry_policy(RetryPolicy {
+                initial_delay: Duration::ZERO,
+                ..Default::default()
+            })
+            .metrics(metrics.clone())
+            .decode_mode(DecodeMode::Strict);
+        get_replays(&context, 2, 10, QueryParameters::default())
+            .await
+            .unwrap();
+
+        assert_eq!(metrics.requests(), 3);
+        assert_eq!(metrics.retries(), 1);
+        assert_eq!(metrics.bytes_received(), response.len() as u64 + 2);
+        assert_eq!(metrics.matches_parsed(), 29);
+        assert_eq!(metrics.parse_errors(), 2);
+    }
+
+    // Provoke API errors to find out what their codes mean, run with `cargo test -- --ignored` and
+    // look at the printed codes. Each test sends a request the game client never would and fails
+    // unless the server rejects it with an Error::Api whose code isn't the success status 0. None
+    // of them has been run against the server yet, so no code is tied to a cause. The response is
+    // written to test_data/<capture>.msgpack to be checked in as a fixture.
+    async fn live_api_error(capture: &str, header: messagepack::RequestHeader, body: RequestBody) {
+        let data = messagepack::Request { header, body }.to_hex();
+        let transport = HttpTransport::new(DEFAULT_BASE_URL);
+        let error = match send_request(&transport, endpoints::Replays::PATH, &data).await {
+            Ok((_, bytes, _)) => {
+                let path = format!(
+                    "{}/test_data/{}.msgpack",
+                    env!("CARGO_MANIFEST_DIR"),
+                    capture
+                );
+                std::fs::write(path, &bytes).unwrap();
+                match decode_response::<ResponseBody>(&bytes, &REPLAY_RESPONSE_FIELDS) {
+                    Err(e) => e,
+                    Ok(response) => panic!("expected an API error, got {:?}", response),
+                }
+            }
+            Err(e) => e,
+        };
+        println!("{}", error);
+        match error {
+            Error::Api { code, .. } => assert_ne!(code, 0),
+            other => panic!("expected Error::Api, got {:?}", other),
+        }
+    }
+
+    fn live_replay_request(index: usize) -> RequestBody {
+        RequestBody {
+            int1: 1,
+            index,
+            replays_per_page: 10,
+            query: RequestQuery::from(&QueryParameters::default()),
+        }
     }
 
-    #[derive(Debug, Clone, Deserialize, Serialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct StatisticsRequest {
-        pub id: String,
-        // 1: Match stats (RC usage, FD usage, perfects, etc)
-        // 2: Post match diagram
-        // 3, 4: Attack stats
-        // 5: Match stats
-        // 6: Challenge progress
-        // 7: Character badge, XP statistics
-        // 8: Some numbers
-        // 9: News
-        pub statistics_type: UnknownInteger,
-        pub int2: UnknownInteger,
-        pub int3: UnknownInteger,
-        pub int4: UnknownInteger,
-        pub int5: UnknownInteger,
+    #[tokio::test]
+    #[ignore]
+    async fn live_error_wrong_version() {
+        // The game rejects clients that are not on the current version
+        let header = RequestHeader {
+            version: "0.0.0".into(),
+            ..request_header(None, Nonce::generate())
+        };
+        live_api_error("api_error_wrong_version", header, live_replay_request(0)).await;
     }
 
-    impl ApiRequest for StatisticsRequest {
-        const PATH: &'static str = "/api/statistics/get";
+    #[tokio::test]
+    #[ignore]
+    async fn live_error_bogus_player_id() {
+        let header = RequestHeader {
+            player_id: "0".into(),
+            ..request_header(None, Nonce::generate())
+        };
+        live_api_error("api_error_bogus_player_id", header, live_replay_request(0)).await;
     }
 
-    #[derive(Debug, Clone, Deserialize, Serialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct StatisticsResponse {
-        pub int1: UnknownInteger,
-        #[serde(with = "json")]
-        pub json: serde_json::Value,
+    #[tokio::test]
+    #[ignore]
+    async fn live_error_page_out_of_range() {
+        live_api_error(
+            "api_error_page_out_of_range",
+            request_header(None, Nonce::generate()),
+            live_replay_request(1000),
+        )
+        .await;
     }
 
-    // Returned when the api is misused
-    #[derive(Debug, Clone, Deserialize, Serialize)]
-    #[serde(crate = "serde_crate")]
-    pub struct ApiError {
-        pub int1: UnknownInteger,
-        pub string1: String,
+    #[tokio::test]
+    async fn unsupported_character() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![MockResponse::new(200, vec![])]).await;
+        let context = Context::new(server.url());
+        assert_eq!(context.supported_characters(), Character::KNOWN);
+        let unknown = QueryParameters::default().character(Character::Unknown(0x20));
+        match get_replays(&context, 1, 10, unknown).await {
+            Err(Error::InvalidArgument {
+                parameter: "character",
+                value,
+                reason,
+            }) => {
+                assert_eq!(value, "Unknown(32)");
+                assert!(reason.ends_with("HappyChaos, Baiken"), "{}", reason);
+            }
+            other => panic!("expected InvalidArgument, got {:?}", other),
+        }
+
+        // A character removed by an update fails as player 2 as well
+        let context = context.with_supported_characters([Character::Sol, Character::Ky]);
+        let query = QueryParameters::default()
+            .character(Character::Sol)
+            .character(Character::May);
+        assert!(matches!(
+            get_replays(&context, 1, 10, query).await,
+            Err(Error::InvalidArgument {
+                parameter: "character",
+                ..
+            })
+        ));
+        let query = QueryParameters::default()
+            .character(Character::Sol)
+            .character(Character::Ky);
+        assert!(check_query(&context, 1, 10, &query).is_ok());
+        assert_eq!(server.requests(), 0);
     }
 
-    mod json {
-        use super::*;
+    #[tokio::test]
+    async fn credentials_required() {
+        use crate::mock_server::*;
 
-        use serde_json::Value;
+        let server = MockServer::start(vec![MockResponse::new(200, vec![])]).await;
+        let context = Context::new(server.url());
+        assert!(!context.has_credentials());
+        let follow = QueryParameters::default().player_search(PlayerSearch::Follow);
+        assert!(matches!(
+            get_replays(&context, 1, 10, follow).await,
+            Err(Error::CredentialsRequired {
+                mode: PlayerSearch::Follow
+            })
+        ));
+        let rival = QueryParameters::default().player_search(PlayerSearch::Rival);
+        assert!(matches!(
+            get_replays_by_page(&context, 1, 10, rival).await,
+            Err(Error::CredentialsRequired { .. })
+        ));
+        assert_eq!(server.requests(), 0);
 
-        pub(crate) fn deserialize<'de, D>(deserializer: D) -> std::result::Result<Value, D::Error>
-        where
-            D: Deserializer<'de>,
-        {
-            let b = String::deserialize(deserializer)?;
-            Ok(serde_json::from_str(&b).map_err(D::Error::custom)?)
+        let credentials = Credentials {
+            player_id: "210611073056107537".into(),
+            token: "62000ad7d8bda".into(),
+        };
+        let context = context.credentials(credentials.clone());
+        assert!(context.has_credentials());
+        let follow = QueryParameters::default().player_search(PlayerSearch::Follow);
+        assert_eq!(
+            check_query(&context, 1, 10, &follow).unwrap().player_search,
+            PlayerSearch::Follow
+        );
+        let header = request_header(Some(&credentials), Nonce::generate());
+        assert_eq!(header.player_id, credentials.player_id);
+        assert_eq!(header.string2.as_str(), credentials.token);
+    }
+
+    #[tokio::test]
+    async fn replays_by_page() {
+        let context = Context::default().transport(
+            MockTransport::new()
+                .page(0, RawResponse::ok(REPLAY_RESPONSE_1))
+                .page(1, RawResponse::ok(REPLAY_RESPONSE_2))
+                .page(2, RawResponse::ok(b"\x92\x01".to_vec())),
+        );
+        let pages = get_replays_by_page(&context, 3, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            pages.iter().map(|page| page.index).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        for (page, fixture) in pages.iter().zip([REPLAY_RESPONSE_1, REPLAY_RESPONSE_2]) {
+            let expected = decode_replay_response(fixture, DecodeMode::default()).unwrap();
+            assert!(page.matches.iter().eq(expected.matches()));
+            assert!(page.errors.is_empty());
+            let metadata = page.metadata.as_ref().unwrap();
+            assert_eq!(metadata.page, page.index);
+            assert_eq!(metadata.request_id, expected.metadata()[0].request_id);
         }
+        // The fixtures overlap, every page keeps its copy
+        let flattened = get_replays(&context, 2, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert!(flattened.matches().count() < pages[0].matches.len() + pages[1].matches.len());
 
-        pub(crate) fn serialize<S>(
-            value: &Value,
-            serializer: S,
-        ) -> std::result::Result<S::Ok, S::Error>
-        where
-            S: Serializer,
-        {
-            value.to_string().serialize(serializer)
+        assert!(pages[2].matches.is_empty());
+        assert_eq!(pages[2].errors.len(), 1);
+        assert!(pages[2].metadata.is_none());
+    }
+
+    #[test]
+    fn shards() {
+        let ranges = |total, workers| {
+            shard_pages(total, workers)
+                .iter()
+                .map(|range| (range.start, range.end))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges(10, 3), [(0, 4), (4, 7), (7, 10)]);
+        assert_eq!(ranges(100, 4), [(0, 25), (25, 50), (50, 75), (75, 100)]);
+        assert_eq!(ranges(2, 5), [(0, 1), (1, 2)]);
+        assert_eq!(ranges(0, 3), []);
+        assert_eq!(ranges(10, 0), []);
+    }
+
+    #[tokio::test]
+    async fn merged_shards() {
+        let context = Context::default().transport(
+            MockTransport::new()
+                .page(0, RawResponse::ok(REPLAY_RESPONSE_1))
+                .page(1, RawResponse::ok(REPLAY_RESPONSE_2))
+                .page(2, RawResponse::ok(REPLAY_RESPONSE_3)),
+        );
+        let mut shards = vec![];
+        for range in shard_pages(3, 2) {
+            shards.push(
+                get_replays_shard(&context, range, 10, QueryParameters::default())
+                    .await
+                    .unwrap(),
+            );
         }
+        let merged = ReplaySet::merge(shards);
+        let all = get_replays(&context, 3, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert!(merged.matches().eq(all.matches()));
+        assert_eq!(
+            merged.metadata().iter().map(|m| m.page).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        // Page 2 starts the second shard and has no overlap
+        assert_eq!(
+            merged.overlaps().iter().map(|o| o.page).collect::<Vec<_>>(),
+            [1]
+        );
+
+        let out_of_range = PageRange {
+            start: 99,
+            end: 101,
+        };
+        assert!(matches!(
+            get_replays_shard(&context, out_of_range, 10, QueryParameters::default()).await,
+            Err(Error::InvalidArgument {
+                parameter: "pages",
+                ..
+            })
+        ));
     }
 
-    fn deserialize_date_time<'de, D>(
-        deserializer: D,
-    ) -> std::result::Result<chrono::DateTime<chrono::Utc>, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let time = String::deserialize(deserializer)?;
-        Ok(DateTime::<Utc>::from_utc(
-            NaiveDateTime::parse_from_str(&time, "%Y-%m-%d %H:%M:%S").map_err(D::Error::custom)?,
-            Utc,
-        ))
+    #[tokio::test]
+    async fn overlapping_pages() {
+        let context = Context::default().transport(
+            MockTransport::new()
+                .page(0, RawResponse::ok(REPLAY_RESPONSE_2))
+                .page(1, RawResponse::ok(REPLAY_RESPONSE_1)),
+        );
+        let ids = |fixture| {
+            decode_replay_response(fixture, DecodeMode::default())
+                .unwrap()
+                .matches()
+                .map(|m| m.id)
+                .collect::<HashSet<_>>()
+        };
+        let shared = ids(REPLAY_RESPONSE_2)
+            .intersection(&ids(REPLAY_RESPONSE_1))
+            .count();
+        assert!(shared > 0);
+
+        let replays = get_replays(&context, 2, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        let expected = [PageOverlap {
+            page: 1,
+            duplicates: shared,
+            possible_gap: false,
+        }];
+        assert_eq!(replays.overlaps(), expected);
+        let pages = get_replays_by_page(&context, 2, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert_eq!(page_overlaps(&pages, 10), expected);
     }
 
-    mod floor {
-        use super::*;
+    #[tokio::test]
+    async fn overlapping_pages_counted_once() {
+        let metrics = Arc::new(AtomicMetrics::new());
+        let context = Context::default()
+            .transport(
+                MockTransport::new()
+                    .page(0, RawResponse::ok(REPLAY_RESPONSE_2))
+                    .page(1, RawResponse::ok(REPLAY_RESPONSE_1)),
+            )
+            .metrics(metrics.clone());
+        let replays = get_replays(&context, 2, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert!(replays.overlaps()[0].duplicates > 0);
+        assert_eq!(metrics.matches_parsed(), replays.matches().count() as u64);
+    }
 
-        pub(crate) fn deserialize<'de, D>(deserializer: D) -> std::result::Result<Floor, D::Error>
-        where
-            D: Deserializer<'de>,
-        {
-            let b = u8::deserialize(deserializer)?;
-            Ok(Floor::from_u8(b).map_err(D::Error::custom)?)
+    #[tokio::test]
+    async fn names_interned_across_pages() {
+        // The second page has the same replays under other ids, so every name is on both pages
+        let mut response =
+            rmp_serde::decode::from_slice::<ReplayResponse>(REPLAY_RESPONSE_1).unwrap();
+        for replay in &mut response.body.replays {
+            replay.id += 1;
         }
-
-        pub(crate) fn serialize<S>(
-            value: &Floor,
-            serializer: S,
-        ) -> std::result::Result<S::Ok, S::Error>
-        where
-            S: Serializer,
-        {
-            value.to_u8().serialize(serializer)
+        let context = Context::default().transport(
+            MockTransport::new()
+                .page(0, RawResponse::ok(REPLAY_RESPONSE_1))
+                .page(
+                    1,
+                    RawResponse::ok(rmp_serde::encode::to_vec(&response).unwrap()),
+                ),
+        );
+        let replays = get_replays(&context, 2, 30, QueryParameters::default())
+            .await
+            .unwrap();
+        assert_eq!(replays.matches().count(), 60);
+        let mut interned = HashMap::<&str, &Arc<str>>::new();
+        for m in replays.matches() {
+            for name in [&m.players.0.name, &m.players.1.name] {
+                assert!(Arc::ptr_eq(interned.entry(&**name).or_insert(name), name));
+            }
         }
     }
 
-    mod character {
-        use super::*;
+    #[test]
+    fn page_gaps() {
+        // Full pages of one match every 10 seconds
+        let page = |newest: i64| {
+            (0..10)
+                .rev()
+                .map(|i| {
+                    let player = |id| crate::Player {
+                        id,
+                        character: Character::Sol,
+                        name: "".into(),
+                    };
+                    Match::from_unix(
+                        (newest - i * 10) as u64,
+                        newest - i * 10,
+                        Floor::F10,
+                        (player(1), player(2)),
+                        Winner::Player1,
+                    )
+                    .unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = page(10_000);
+        let adjacent = page(first[0].timestamp.timestamp() - 10);
+        let distant = page(first[0].timestamp.timestamp() - 300);
+        assert!(!PageOverlap::new(1, &first, &adjacent, 0, 10).possible_gap);
+        assert!(PageOverlap::new(1, &first, &distant, 0, 10).possible_gap);
+        // Neither with a previous page that wasn't full nor with repeated replays
+        assert!(!PageOverlap::new(1, &first, &distant, 0, 11).possible_gap);
+        assert!(!PageOverlap::new(1, &first, &distant, 1, 10).possible_gap);
+        assert!(!PageOverlap::new(1, &first, &[], 0, 10).possible_gap);
+    }
 
-        pub(crate) fn deserialize<'de, D>(
-            deserializer: D,
-        ) -> std::result::Result<Option<Character>, D::Error>
-        where
-            D: Deserializer<'de>,
-        {
-            let b = i8::deserialize(deserializer)?;
-            Ok(if b == -1 {
-                None
-            } else {
-                Some(Character::from_u8(b as u8).map_err(D::Error::custom)?)
+    #[tokio::test]
+    async fn unbounded_replays() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .page(0, RawResponse::ok(REPLAY_RESPONSE_1))
+                .page(1, RawResponse::ok(REPLAY_RESPONSE_3))
+                .page(2, RawResponse::ok(empty_replay_response()))
+                .page(3, RawResponse::ok(empty_replay_response())),
+        );
+        let context = Context::default().transport(transport.clone());
+        let page_1 = decode_replay_response(REPLAY_RESPONSE_1, DecodeMode::Lenient).unwrap();
+        let page_1 = page_1.matches().count();
+
+        // Stops as soon as enough matches were collected and keeps the newest
+        let result = get_replays_unbounded(
+            &context,
+            QueryParameters::default(),
+            FetchLimits {
+                max_matches: page_1 + 1,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.stop, FetchStop::TargetReached);
+        assert_eq!(result.pages, 2);
+        assert_eq!(result.replays.matches().count(), page_1 + 1);
+        assert_eq!(
+            result.oldest,
+            result.replays.matches().next().map(|m| m.timestamp)
+        );
+
+        // Runs until two pages in a row have no new matches
+        let result = get_replays_unbounded(
+            &context,
+            QueryParameters::default(),
+            FetchLimits {
+                max_matches: usize::MAX,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.stop, FetchStop::NoMoreData);
+        assert_eq!(result.pages, 4);
+        assert_eq!(transport.requests().len(), 6);
+
+        let result = get_replays_unbounded(
+            &context,
+            QueryParameters::default(),
+            FetchLimits {
+                replays_per_page: 128,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::InvalidArgument {
+                parameter: "replays_per_page",
+                ..
             })
-        }
+        ));
+    }
 
-        pub(crate) fn serialize<S>(
-            value: &Option<Character>,
-            serializer: S,
-        ) -> std::result::Result<S::Ok, S::Error>
-        where
-            S: Serializer,
-        {
-            value
-                .map_or_else(|| -1, |c| c.to_u8() as i8)
-                .serialize(serializer)
-        }
+    #[tokio::test]
+    async fn server_error_is_retried() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![
+            MockResponse::new(503, b"try again".to_vec()),
+            MockResponse::new(200, empty_replay_response()),
+        ])
+        .await;
+        let context = Context::new(server.url()).retry_policy(RetryPolicy {
+            initial_delay: Duration::from_millis(10),
+            ..Default::default()
+        });
+
+        let replays = get_replays(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert_eq!(replays.matches().count(), 0);
+        assert_eq!(replays.metadata().len(), 1);
+        assert_eq!(server.requests(), 2);
     }
-}
 
-// Helper function for constructing error messages to avoid issues with the borrow checker
-fn show_buf<B: AsRef<[u8]>>(buf: B) -> String {
-    use std::ascii::escape_default;
-    String::from_utf8(
-        buf.as_ref()
-            .iter()
-            .flat_map(|b| escape_default(*b))
-            .collect(),
-    )
-    .unwrap()
-}
-#[cfg(test)]
-mod tests {
-    use super::messagepack::*;
-    use super::*;
+    #[tokio::test]
+    async fn call_endpoint() {
+        use crate::mock_server::*;
 
-    fn parse_response_from_bytes(
-        matches: &mut BTreeSet<Match>,
-        errors: &mut Vec<ParseError>,
-        bytes: &[u8],
-    ) -> bool {
-        match rmp_serde::decode::from_slice::<messagepack::ReplayResponse>(bytes) {
-            Ok(response) => {
-                for replay in response.body.replays {
-                    match match_from_replay(replay.clone()) {
-                        Ok(m) => {
-                            matches.insert(m);
-                        }
-                        Err(e) => {
-                            errors.push(ParseError::new(show_buf(bytes), e));
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                errors.push(ParseError::new(show_buf(bytes), e.into()));
-            }
-        }
+        let server = MockServer::start(vec![
+            MockResponse::new(503, vec![]),
+            MockResponse::new(200, hex::decode(VIP_RESPONSE).unwrap()),
+        ])
+        .await;
+        let context = Context::new(server.url()).retry_policy(RetryPolicy {
+            initial_delay: Duration::ZERO,
+            ..Default::default()
+        });
+
+        let response = context
+            .call::<endpoints::Vip>(VipRequest {
+                int1: 0,
+                int2: 0,
+                int3: -1,
+                int4: 0,
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.body.ranking.len(), 20);
+        assert_eq!(server.requests(), 2);
 
-        true
+        let ranking = get_vip_ranking(&context).await.unwrap();
+        assert_eq!(ranking.len(), 20);
+        assert_eq!(ranking[1].rank, 2);
+        assert_eq!(ranking[1].points, 1237);
+        assert_eq!(ranking[1].player.id, 220120010822189979);
+        assert_eq!(ranking[1].player.character, Character::HappyChaos);
+        assert_eq!(ranking[1].player.name(), "GG Player");
+        assert_eq!(ranking[1].steam_id, "76561197960456542");
+        assert_eq!(
+            ranking[1].identity().online_id.as_deref(),
+            Some("11000010002e95e")
+        );
+        assert!(ranking
+            .windows(2)
+            .all(|pair| pair[0].points >= pair[1].points));
+
+        let mut vip = response.body.ranking[0].clone();
+        vip.id = "not a number".into();
+        assert!(matches!(
+            crate::Player::try_from(vip),
+            Err(Error::ParsingBytesError { field: "id", .. })
+        ));
+
+        // A body of another endpoint doesn't decode as a vip ranking
+        let server =
+            MockServer::start(vec![MockResponse::new(200, REPLAY_RESPONSE_1.to_vec())]).await;
+        let context = Context::new(server.url());
+        assert!(matches!(
+            context
+                .call::<endpoints::Vip>(VipRequest {
+                    int1: 0,
+                    int2: 0,
+                    int3: -1,
+                    int4: 0,
+                })
+                .await,
+            Err(Error::Decode(_))
+        ));
     }
 
-    #[test]
-    fn test_parse_response() {
-        const RESPONSE: &[u8] = b"\x92\x98\xad61ff0796545a9\0\xb32022/02/05 23:26:14\xa50.1.0\xa50.0.2\xa50.0.2\xa0\xa0\x94\0\0\x1e\xdc\0\x1e\x9d\xcf\x03\x0eS}\x9f\x8ds\xbf\t\x08\x0c\x0b\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x95\xb2210818223745601103\xafSamuraiPizzaCat\xb176561199149925226\xaf110000146e8c36a\x07\x02\xb32022-02-06 04:07:59\x01\0\0\0\x9d\xcf\x03\x0eS|v\xbc6N\t\x08\x11\x0c\x95\xb2210905181006143473\xa8Haratura\xb176561198148293594\xaf11000010b3513da\x07\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x01\xb32022-02-06 03:58:19\x01\0\0\0\x9d\xcf\x03\x0eS|lr}\xc1\t\x08\x11\x0c\x95\xb2210905181006143473\xa8Haratura\xb176561198148293594\xaf11000010b3513da\x07\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x01\xb32022-02-06 03:56:46\x01\0\0\0\x9d\xcf\x03\x0eS|du\xac>\t\x08\x11\x0c\x95\xb2210905181006143473\xa8Haratura\xb176561198148293594\xaf11000010b3513da\x07\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x01\xb32022-02-06 03:55:12\x01\0\0\0\x9d\xcf\x03\x0eSy?\x93\x83\x86\t\x06\x04\0\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2211128031436376804\xa9BundleBox\xb176561198103224698\xaf11000010885617a\x05\x01\xb32022-02-06 03:29:31\x01\0\0\0\x9d\xcf\x03\x0eSy/\xfbL\xaa\t\x06\x04\0\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2211128031436376804\xa9BundleBox\xb176561198103224698\xaf11000010885617a\x05\x01\xb32022-02-06 03:27:10\x01\0\0\0\x9d\xcf\x03\x0eSy\"\xfc\x1d\x85\t\x06\x04\0\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2211128031436376804\xa9BundleBox\xb176561198103224698\xaf11000010885617a\x05\x02\xb32022-02-06 03:24:52\x01\0\0\0\x9d\xcf\x03\x0eSx\xf9\x8c\xd2\r\t\x06\x04\x12\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2210719021019879063\xa9Sebastard\xb176561198354593280\xaf11000011780f600\x05\x01\xb32022-02-06 03:17:56\x01\0\0\0\x9d\xcf\x03\x0eSx\xedf\x1f\xf4\t\x06\x04\x12\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2210719021019879063\xa9Sebastard\xb176561198354593280\xaf11000011780f600\x05\x01\xb32022-02-06 03:15:53\x01\0\0\0\x9d\xcf\x03\x0eS{q&\x8d\x92\t\x07\x05\x0c\x95\xb2220117205818084945\xa8Bugabalu\xb176561198136737187\xaf11000010a84bda3\x05\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x02\xb32022-02-06 03:14:30\x01\0\0\0\x9d\xcf\x03\x0eSx\xe0+\xf8\xf7\t\x06\x04\x12\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2210719021019879063\xa9Sebastard\xb176561198354593280\xaf11000011780f600\x05\x02\xb32022-02-06 03:13:31\x01\0\0\0\x9d\xcf\x03\x0eS{c\xba\xc9z\t\x07\x05\x0c\x95\xb2220117205818084945\xa8Bugabalu\xb176561198136737187\xaf11000010a84bda3\x05\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x01\xb32022-02-06 03:12:05\x01\0\0\0\x9d\xcf\x03\x0eS{T\xd4\\\x90\t\x07\x05\x0c\x95\xb2220117205818084945\xa8Bugabalu\xb176561198136737187\xaf11000010a84bda3\x05\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x02\xb32022-02-06 03:09:55\x01\0\0\0\x9d\xcf\x03\x0eS{Ab\xacm\t\x07\x0c\t\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x95\xb2210811193631829778\xaeF4ulty_R4ilgun\xb176561198351152593\xaf1100001174c75d1\x06\x02\xb32022-02-06 03:06:29\x01\0\0\0\x9d\xcf\x03\x0eS{3\xde\xb6\xa2\t\x07\x0c\t\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x95\xb2210811193631829778\xaeF4ulty_R4ilgun\xb176561198351152593\xaf1100001174c75d1\x06\x01\xb32022-02-06 03:04:02\x01\0\0\0\x9d\xcf\x03\x0eS{)\x03G\xe2\t\x07\x0c\t\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x95\xb2210811193631829778\xaeF4ulty_R4ilgun\xb176561198351152593\xaf1100001174c75d1\x06\x02\xb32022-02-06 03:02:20\x01\0\0\0\x9d\xcf\x03\x0eS}\xfct\x97\x16\t\x08\0\x12\x95\xb2210615035914519825\xa5BL4DE\xb176561199083465035\xaf110000142f2a94b\x07\x95\xb2210612062056984376\xb0TwitchTV/VRDante\xb176561198067414364\xaf11000010662f55c\x07\x01\xb32022-02-06 02:24:18\x01\0\0\0\x9d\xcf\x03\x0eS}\xf3\xeb\x0c\x8a\t\x08\0\x12\x95\xb2210615035914519825\xa5BL4DE\xb176561199083465035\xaf110000142f2a94b\x07\x95\xb2210612062056984376\xb0TwitchTV/VRDante\xb176561198067414364\xaf11000010662f55c\x07\x02\xb32022-02-06 02:22:34\x01\0\0\0\x9d\xcf\x03\x0eS}\xdb{XM\tc\0\x0e\x95\xb2210611113829735658\xa3Eli\xb176561198449379262\xaf11000011d2747be\t\x95\xb2210612045332227791\xa8R34 I-NO\xb176561198046971684\xaf1100001052b0724\t\x02\xb32022-02-06 02:22:08\x01\0\0\0\x9d\xcf\x03\x0eSy?\xd2\x135\tc\0\x07\x95\xb2210611092701986372\xa3tms\xb176561198223056552\xaf11000010fa9dea8\t\x95\xb2210611184101935607\xb0Shaco Arrombardo\xb176561198019472843\xaf110000103876dcb\t\x02\xb32022-02-06 02:19:53\x01\0\0\0\x9d\xcf\x03\x0eS}\xca\xaeev\tc\0\x0e\x95\xb2210611113829735658\xa3Eli\xb176561198449379262\xaf11000011d2747be\t\x95\xb2210612045332227791\xa8R34 I-NO\xb176561198046971684\xaf1100001052b0724\t\x02\xb32022-02-06 02:19:26\x01\0\0\0\x9d\xcf\x03\x0eSy0\x12\xfd\x84\tc\0\x07\x95\xb2210611092701986372\xa3tms\xb176561198223056552\xaf11000010fa9dea8\t\x95\xb2210611184101935607\xb0Shaco Arrombardo\xb176561198019472843\xaf110000103876dcb\t\x01\xb32022-02-06 02:17:29\x01\0\0\0\x9d\xcf\x03\x0eSy$#\xb0\xfc\tc\0\x07\x95\xb2210611092701986372\xa3tms\xb176561198223056552\xaf11000010fa9dea8\t\x95\xb2210611184101935607\xb0Shaco Arrombardo\xb176561198019472843\xaf110000103876dcb\t\x01\xb32022-02-06 02:15:28\x01\0\0\0\x9d\xcf\x03\x0eS}\xc5\x15\xcf\xf1\t\x08\x12\x12\x95\xb2210612062056984376\xb0TwitchTV/VRDante\xb176561198067414364\xaf11000010662f55c\x07\x95\xb2210611172901281375\xa4g5h3\xb176561198066767737\xaf110000106591779\x07\x02\xb32022-02-06 02:14:49\x01\0\0\0\x9d\xcf\x03\x0eS}\xb9w\xc3_\t\x08\x12\x12\x95\xb2210612062056984376\xb0TwitchTV/VRDante\xb176561198067414364\xaf11000010662f55c\x07\x95\xb2210611172901281375\xa4g5h3\xb176561198066767737\xaf110000106591779\x07\x01\xb32022-02-06 02:12:53\x01\0\0\0\x9d\xcf\x03\x0eS}\x95\x1a\x14\xd0\tc\r\0\x95\xb2210611163406897038\xabKidSusSauce\xb176561198796113273\xaf110000131d20579\t\x95\xb2210611113829735658\xa3Eli\xb176561198449379262\xaf11000011d2747be\t\x01\xb32022-02-06 02:10:27\x01\0\0\0\x9d\xcf\x03\x0eS}\xa7$\x04\x91\t\x08\x12\x12\x95\xb2210612062056984376\xb0TwitchTV/VRDante\xb176561198067414364\xaf11000010662f55c\x07\x95\xb2210611172901281375\xa4g5h3\xb176561198066767737\xaf110000106591779\x07\x01\xb32022-02-06 02:09:46\x01\0\0\0\x9d\xcf\x03\x0eS|x.;\xd4\tc\x01\0\x95\xb2210612195532158554\xa7Nowhere\xb176561198108655731\xaf110000108d84073\t\x95\xb2210611113829735658\xa3Eli\xb176561198449379262\xaf11000011d2747be\t\x02\xb32022-02-06 02:02:47\x01\0\0\0\x9d\xcf\x03\x0eS}re;\xfc\t\x08\x12\x07\x95\xb2210612062056984376\xb0TwitchTV/VRDante\xb176561198067414364\xaf11000010662f55c\x07\x95\xb2211222194227494329\xacEpicKittyCat\xb176561198040006360\xaf110000104c0bed8\x07\x01\xb32022-02-06 02:01:01\x01\0\0\0\x9d\xcf\x03\x0eS|d\xdd\x9d\x8c\t\x08\x02\x12\x95\xb2211224234141126253\xa6Fakuto\xb176561198387121965\xaf110000119714f2d\x07\x95\xb2210612062056984376\xb0TwitchTV/VRDante\xb176561198067414364\xaf11000010662f55c\x07\x02\xb32022-02-06 01:55:39\x01\0\0\0";
-        let mut matches = BTreeSet::new();
-        let mut errors = Vec::new();
-        parse_response_from_bytes(&mut matches, &mut errors, &RESPONSE);
+    #[tokio::test]
+    async fn cached_responses() {
+        use crate::cache::TtlCache;
+        use crate::mock_server::*;
 
-        assert!(errors.is_empty(), "Got errors: {:#?}", errors);
+        let api_error = rmp_serde::encode::to_vec(&Response {
+            header: test_response_header(),
+            body: ApiError {
+                int1: 3,
+                string1: "invalid request".into(),
+            },
+        })
+        .unwrap();
+        let server = MockServer::start(vec![
+            MockResponse::new(200, api_error),
+            MockResponse::new(200, hex::decode(STATISTICS_RESPONSE).unwrap()),
+        ])
+        .await;
+        let context = Context::new(server.url())
+            .retry_policy(RetryPolicy::none())
+            .cache(TtlCache::new(Duration::from_secs(60)));
 
-        expect_test::expect_file!["../test_data/replay_response.txt"].assert_debug_eq(&matches);
+        // Errors are not cached, the second request reaches the server
+        assert!(matches!(
+            get_user(&context, 220120010822189979).await,
+            Err(Error::Api { code: 3, .. })
+        ));
+        let user = get_user(&context, 220120010822189979).await.unwrap();
+        assert_eq!(server.requests(), 2);
+        let cached = get_user(&context, 220120010822189979).await.unwrap();
+        assert_eq!(server.requests(), 2);
+        assert_eq!(cached, user);
+        // A different request body is a different key
+        let _ = get_user(&context, 1).await.unwrap();
+        assert_eq!(server.requests(), 3);
+
+        // Replay queries bypass the cache
+        let server =
+            MockServer::start(vec![MockResponse::new(200, REPLAY_RESPONSE_1.to_vec())]).await;
+        let context = Context::new(server.url()).cache(TtlCache::new(Duration::from_secs(60)));
+        for _ in 0..2 {
+            get_replays(&context, 1, 10, QueryParameters::default())
+                .await
+                .unwrap();
+        }
+        assert_eq!(server.requests(), 2);
     }
 
-    #[test]
-    fn test_parse_response_2() {
-        // This test used to miss one replay before true messagepack parsing
-        const RESPONSE: &[u8] = b"\x92\x98\xad61ff0f60da094\0\xb32022/02/05 23:59:28\xa50.1.0\xa50.0.2\xa50.0.2\xa0\xa0\x94\0\0\n\x9a\x9d\xcf\x03\x0eS}\x9f\x8ds\xbf\t\x08\x0c\x0b\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x95\xb2210818223745601103\xafSamuraiPizzaCat\xb176561199149925226\xaf110000146e8c36a\x07\x02\xb32022-02-06 04:07:59\x01\0\0\0\x9d\xcf\x03\x0eS|v\xbc6N\t\x08\x11\x0c\x95\xb2210905181006143473\xa8Haratura\xb176561198148293594\xaf11000010b3513da\x07\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x01\xb32022-02-06 03:58:19\x01\0\0\0\x9d\xcf\x03\x0eS|lr}\xc1\t\x08\x11\x0c\x95\xb2210905181006143473\xa8Haratura\xb176561198148293594\xaf11000010b3513da\x07\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x01\xb32022-02-06 03:56:46\x01\0\0\0\x9d\xcf\x03\x0eS|du\xac>\t\x08\x11\x0c\x95\xb2210905181006143473\xa8Haratura\xb176561198148293594\xaf11000010b3513da\x07\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x01\xb32022-02-06 03:55:12\x01\x01\0\0\x9d\xcf\x03\x0eSy?\x93\x83\x86\t\x06\x04\0\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2211128031436376804\xa9BundleBox\xb176561198103224698\xaf11000010885617a\x05\x01\xb32022-02-06 03:29:31\x01\0\0\0\x9d\xcf\x03\x0eSy/\xfbL\xaa\t\x06\x04\0\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2211128031436376804\xa9BundleBox\xb176561198103224698\xaf11000010885617a\x05\x01\xb32022-02-06 03:27:10\x01\0\0\0\x9d\xcf\x03\x0eSy\"\xfc\x1d\x85\t\x06\x04\0\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2211128031436376804\xa9BundleBox\xb176561198103224698\xaf11000010885617a\x05\x02\xb32022-02-06 03:24:52\x01\0\0\0\x9d\xcf\x03\x0eSx\xf9\x8c\xd2\r\t\x06\x04\x12\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2210719021019879063\xa9Sebastard\xb176561198354593280\xaf11000011780f600\x05\x01\xb32022-02-06 03:17:56\x01\0\0\0\x9d\xcf\x03\x0eSx\xedf\x1f\xf4\t\x06\x04\x12\x95\xb2210825010040078270\xacKenoMcsteamo\xb176561198354688358\xaf110000117826966\x05\x95\xb2210719021019879063\xa9Sebastard\xb176561198354593280\xaf11000011780f600\x05\x01\xb32022-02-06 03:15:53\x01\0\0\0\x9d\xcf\x03\x0eS{q&\x8d\x92\t\x07\x05\x0c\x95\xb2220117205818084945\xa8Bugabalu\xb176561198136737187\xaf11000010a84bda3\x05\x95\xb2210611232517053199\xa5limon\xb176561198082398187\xaf1100001074797eb\x06\x02\xb32022-02-06 03:14:30\x01\0\0\0";
+    #[tokio::test]
+    async fn bounded_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct Slow {
+            running: AtomicUsize,
+            max: AtomicUsize,
+        }
+
+        impl Transport for Slow {
+            fn post<'a>(
+                &'a self,
+                _: &'static str,
+                _: &'a str,
+            ) -> BoxFuture<'a, Result<RawResponse>> {
+                Box::pin(async move {
+                    let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+                    self.max.fetch_max(running, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    self.running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(RawResponse::ok(hex::decode(STATISTICS_RESPONSE).unwrap()))
+                })
+            }
+        }
 
-        let mut matches = BTreeSet::new();
-        let mut errors = Vec::new();
-        parse_response_from_bytes(&mut matches, &mut errors, &RESPONSE);
+        let transport = Arc::new(Slow::default());
+        let context = Context::default().transport(transport.clone());
+        let results = get_statistics_batch(&context, [5, 1, 4, 1, 3, 2, 6], 3).await;
+        assert_eq!(
+            results.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            [5, 1, 4, 3, 2, 6]
+        );
+        assert!(results.iter().all(|(_, statistics)| statistics.is_ok()));
+        assert_eq!(transport.max.load(Ordering::SeqCst), 3);
+    }
 
-        assert!(errors.is_empty(), "Got errors: {:#?}", errors);
+    #[tokio::test]
+    async fn get_user_profile() {
+        use crate::mock_server::*;
 
-        expect_test::expect_file!["../test_data/replay_response_2.txt"].assert_debug_eq(&matches);
+        let server = MockServer::start(vec![MockResponse::new(
+            200,
+            hex::decode(STATISTICS_RESPONSE).unwrap(),
+        )])
+        .await;
+        let user = get_user(&Context::new(server.url()), 220120010822189979)
+            .await
+            .unwrap();
+        assert_eq!(user.id, 220120010822189979);
+        assert_eq!(user.name, "GG Player");
+        assert_eq!(user.comment, "Good luck!");
+        assert_eq!(user.floor, Floor::F9);
+        assert_eq!(user.floor_change(), model::user::FloorChange::Unchanged);
+        assert_eq!(user.playtime, Duration::from_secs(30989481));
+        assert_eq!(user.rank_matches, 2020);
+        assert_eq!((user.win_chain, user.max_win_chain), (10, 118));
+        assert_eq!(user.char_stats.len(), 19);
+        assert_eq!(user.celestial.phase, 7);
+        assert_eq!(user.celestial.raw.len(), 8);
+        // The captured player hid their R-code, the per character wins are all zero
+        assert!(user.is_hidden());
+        assert!(user.char_stats.values().all(|stats| stats.wins == 0));
+        let levels = user
+            .char_stats
+            .iter()
+            .filter(|(_, stats)| stats.level > 1)
+            .map(|(character, stats)| {
+                (
+                    character.to_code().unwrap(),
+                    stats.level,
+                    stats.max_win_chain,
+                )
+            })
+            .collect::<BTreeSet<_>>();
+        expect_test::expect![[r#"
+            {
+                (
+                    "COS",
+                    1546,
+                    118,
+                ),
+                (
+                    "GIO",
+                    893,
+                    53,
+                ),
+                (
+                    "RAM",
+                    439,
+                    56,
+                ),
+            }
+        "#]]
+        .assert_debug_eq(&levels);
     }
 
     #[test]
-    fn test_parse_response_3() {
-        // This test used to miss one replay before true messagepack parsing
-        const RESPONSE: &[u8] = b"\x92\x98\xad61ffa1560e387\0\xb32022/02/06 10:22:14\xa50.1.0\xa50.0.2\xa50.0.2\xa0\xa0\x94\0\x04\n\x9a\x9d\xcf\x03\x0e\n\xb0\x95(\xcd2\x07c\x06\x07\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\t\x95\xb2210611121603560347\xadLuna Goodgirl\xb176561197977446342\xaf1100001010627c6\t\x01\xb32022-01-25 18:53:19\x01\x01\x01\x01\x9d\xcf\x03\r\xfb5{F6\"\x07c\x06\x07\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\t\x95\xb2210611162113864298\xa8Lizardos\xb176561197994492361\xaf1100001020a41c9\t\x01\xb32022-01-08 16:39:30\x01\x03\x01\x01\x9d\xcf\x02\xed\xbb\xb9\x7f\xdd?!\x06c\x06\x05\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\t\x95\xb2210611095248078392\xa9MOMO MODY\xb176561198156904572\xaf11000010bb8787c\t\x02\xb32021-10-31 16:29:42\x01\x03\x02\0\x9d\xcf\x02\xed\xa2D\x07\x98m\x1a\x05\n\x06\x0b\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\t\x95\xb2210611083023337322\xadPunishedVenom\xb176561198043848438\xaf110000104fb5ef6\t\x02\xb32021-10-03 17:06:24\x01\0\x02\0\x9d\xcf\x02\xec\xef\x05\xe7\xe6\xf1\x88\x04\n\x08\x07\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\t\x95\xb2210611072758921052\xaageorgekupo\xb176561198054369781\xaf1100001059be9f5\t\x02\xb32021-08-06 09:12:22\x01\0\x02\0\x9d\xcf\x02\xec\xed6\xf1\xea8\xbe\x04\n\x08\x0b\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\x08\x95\xb2210618173410867109\xabDominimator\xb176561197994451661\xaf11000010209a2cd\t\x02\xb32021-08-04 10:28:20\x01\0\x02\0\x9d\xcf\x02\xecG\xc9\xde*\x8e\xd6\x03\x07\x08\x01\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\x06\x95\xb2210614203011010057\xa5Hydro\xb176561198077327061\xaf110000106fa36d5\x06\x02\xb32021-06-22 21:49:19\x01\0\x02\0\x9d\xcf\x03\x0eSo\xd3qzH\tc\r\x06\x95\xb2210611114424649707\xa9Pistachio\xb176561198074756096\xaf110000106d2fc00\t\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\t\x02\xb32022-02-05 17:15:39\x01\0\0\0\x9d\xcf\x03\x0eSo\xbc\x9cz\x82\tc\x02\x06\x95\xb2210611151221285918\xa7Rikkumi\xb176561198117246557\xaf1100001095b565d\t\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\t\x02\xb32022-02-05 17:11:56\x01\0\0\0\x9d\xcf\x03\x0eSo\xae.\x18\xcc\tc\x02\x06\x95\xb2210611151221285918\xa7Rikkumi\xb176561198117246557\xaf1100001095b565d\t\x95\xb2210611073056107537\xa3Mar\xb176561197993198569\xaf110000101f683e9\t\x02\xb32022-02-05 17:09:14\x01\0\0\0";
+    fn nonce() {
+        use messagepack::Nonce;
 
-        let result = rmp_serde::decode::from_slice::<messagepack::ReplayResponse>(&RESPONSE);
+        let time = DateTime::from_timestamp(0x61ff96a1, 0x1e7b5 * 1000).unwrap();
+        let nonce = Nonce::at(time);
+        assert_eq!(nonce.as_str(), "61ff96a11e7b5");
+        assert_eq!(nonce.timestamp(), Some(time));
+        assert!(Nonce::from("61ff96a1e7b59").timestamp().is_some());
+        assert_eq!(Nonce::from("not a nonce").timestamp(), None);
 
-        expect_test::expect_file!["../test_data/replay_response_3.txt"].assert_debug_eq(&result);
+        let generated = Nonce::generate();
+        assert_eq!(generated.as_str().len(), 13);
+        assert!(generated.timestamp().is_some());
     }
 
     #[test]
-    fn test_parse_response_4() {
-        // This test used to miss one replay before true messagepack parsing
-        const RESPONSE: &[u8] = b"\x92\x98\xad61ffa6c3dce48\x00\xb32022/02/06 10:45:23\xa50.1.0\xa50.0.2\xa50.0.2\xa0\xa0\x94\x00\x00\x14\xdc\x00\x14\x9d\xcf\x03\x0eTH\xb4\x9fm\xae\tc\x0c\x00\x95\xb2210612125643406306\xa6\xe3\x81\xab\xe3\x81\x97\xb176561198128581292\xaf11000010a084aac\t\x95\xb2210812201532300023\xa4Aya_\xb176561198082485936\xaf11000010748eeb0\t\x01\xb32022-02-06 10:30:35\x01\x00\x04\x00\x9d\xcf\x03\x0eTH\xb4\xe79|\t\x07\x0e\r\x95\xb2210615052252624822\xa7kenwood\xb176561197966537714\xaf1100001005fb3f2\x06\x95\xb2210611154646317449\xadSacral Choppa\xb176561199006810534\xaf11000013e6101a6\x06\x01\xb32022-02-06 10:30:34\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\xb6\\\xe7\t\x08\x12\x02\x95\xb2210612021027770109\xafSEAFOOD_TEACHER\xb176561198113434879\xaf110000109212cff\x07\x95\xb2211207080045848646\xa4Snao\xb176561199222646653\xaf11000014b3e677d\x07\x02\xb32022-02-06 10:30:33\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\xb5*e\tc\x07\x00\x95\xb2210613140711574755\xac\xed\x95\xa0\xeb\x9d\xbc\xed\x94\xbc\xeb\x87\xa8\xb176561198864345829\xaf110000135e32ae5\t\x95\xb2210611143729214686\xa3kim\xb176561198854003264\xaf110000135455a40\t\x01\xb32022-02-06 10:30:33\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\xb0:E\tc\x0c\t\x95\xb2210611072136083266\xafKiiwiFrankenCop\xb176561198895319862\xaf110000137bbcb36\t\x95\xb2210611182927774405\xa9Mr. Quick\xb176561198069518514\xaf1100001068310b2\t\x02\xb32022-02-06 10:30:32\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\xaf[Z\t\x06\x0f\x12\x95\xb2220121231856937297\xaaThoraxe237\xb176561198052581773\xaf11000010580a18d\x05\x95\xb2210613005107516525\xa8Keshabro\xb176561198027398330\xaf110000104005cba\x04\x01\xb32022-02-06 10:30:32\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\xb0\xbd\x1f\t\x08\x0c\n\x95\xb2210912022814996615\xa7highlow\xb176561199205533603\xaf11000014a3947a3\x07\x95\xb2210611085648495430\xa8nametake\xb176561199149370171\xaf110000146e04b3b\x07\x01\xb32022-02-06 10:30:31\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\xa8\x8c\x06\t\t\x00\x05\x95\xb2210611073057022504\xa8AlphaMJB\xb176561198305607584\xaf110000114957fa0\x08\x95\xb2220127151856058147\xaf\xe3\x82\xaf\xe3\x83\xa9\xe3\x83\x83\xe3\x82\xb7\xe3\x83\xa5\xb176561198165187796\xaf11000010c36dcd4\x08\x02\xb32022-02-06 10:30:29\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\x807d\t\n\r\x12\x95\xb2210611084139551457\xaaDivin#1214\xb176561198077941403\xaf11000010703969b\t\x95\xb2210611101724829815\xaeRez:Gilgystera\xb176561198132106791\xaf11000010a3e1627\t\x01\xb32022-02-06 10:30:29\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4d\r\xce\t\n\x07\x12\x95\xb2211222225908577640\xa7Cotezzo\xb176561198421841583\xaf11000011b8316af\x08\x95\xb2210611071849576512\xa7Taiga2k\xb176561198040834092\xaf110000104cd602c\x08\x01\xb32022-02-06 10:30:27\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb3\xbeV\xbd\t\n\x0c\r\x95\xb2210721083447239477\xac\xed\x96\x89\xeb\xb3\xb5\xed\x9a\x8c\xeb\xa1\x9c\xb176561198058727476\xaf110000105de6834\t\x95\xb2210828085855460099\xb8\xe4\xbf\xa1\xe5\xb7\x9e\xe7\x84\xa1\xe6\x95\xb5\xe3\x81\xae\xe6\xa1\x83\xe5\xa4\xaa\xe9\x83\x8e\xb176561198138785803\xaf11000010aa4000b\x08\x02\xb32022-02-06 10:30:26\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb47\x1f+\t\x08\x02\x00\x95\xb2210612121526544046\xa6\xe3\x81\xb5\xe3\x82\x8f\xb176561199174118419\xaf11000014859ec13\x07\x95\xb2210611094539865120\xa3lan\xb176561198317011665\xaf1100001154382d1\x07\x01\xb32022-02-06 10:30:25\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\x1b\x8ds\t\t\x0b\x00\x95\xb2210617234253473467\xaeballsack_penis\xb176561198055995469\xaf110000105b4b84d\x08\x95\xb2210611071427578001\xa6Xsaber\xb176561198101112765\xaf1100001086527bd\x08\x02\xb32022-02-06 10:30:22\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\x0e(\x04\tc\x12\x00\x95\xb2210811113031312233\xac\xe3\x81\xbe\xe3\x81\x9f\xe3\x82\x8f\xe3\x82\x8a\xb176561198196931129\xaf11000010e1b3a39\t\x95\xb2210811153641989054\xb2\xe3\x81\x99\xe3\x81\xb4\xe3\x81\x8b\xe3\x81\xa1\xe3\x82\x83\xe3\x82\x93\xb176561199123357584\xaf110000145535f90\t\x02\xb32022-02-06 10:30:22\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\x0e\\\xe6\tc\x00\x06\x95\xb2210611145942951029\xabPlaceholder\xb176561198123582712\xaf110000109bc04f8\t\x95\xb2210613043239093829\xadMouljaveel-PC\xb176561198105342214\xaf110000108a5b106\t\x02\xb32022-02-06 10:30:21\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb4\x04\xfb7\t\n\x0e\x0b\x95\xb2210729195702260121\xaemystery cruise\xb176561197980107402\xaf1100001012ec28a\t\x95\xb2211231003426173119\xaaWilling555\xb176561198158500699\xaf11000010bd0d35b\t\x01\xb32022-02-06 10:30:20\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb2\xd2\xdb\x17\t\n\x01\x12\x95\xb2210611080232191761\xaaKOIBITO\xef\xbc\x81\xb176561198159124250\xaf11000010bda571a\t\x95\xb2210708134321624142\xa5loser\xb176561198207840299\xaf11000010ec1b02b\t\x01\xb32022-02-06 10:30:20\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb3\xc3y\xcc\t\n\x12\x0c\x95\xb2220117164656998999\xa4syan\xb176561199204162898\xaf11000014a245d52\t\x95\xb2210618050710408587\xb8\xe5\x90\x89\xe7\x94\xb0\xe3\x83\x92\xe3\x83\xad\xe3\x83\x95\xe3\x83\x9f\xe3\x81\xae\xe5\xa5\xb3\xb176561198395837298\xaf110000119f64b72\t\x02\xb32022-02-06 10:30:18\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xb3\xbb^K\t\x07\r\x01\x95\xb2210612065256836370\xa9Aquascape\xb176561198108384313\xaf110000108d41c39\x06\x95\xb2211207004536721762\xacBaldilocksTM\xb176561199057152272\xaf110000141612910\x06\x01\xb32022-02-06 10:30:17\x01\x00\x00\x00\x9d\xcf\x03\x0eTH\xbb.B\xa7\t\n\x12\x01\x95\xb2210611155821768595\xadJ A I G E R E\xb176561198835237053\xaf1100001342700bd\t\x95\xb2210611133136888481\xaf\xe3\x81\x95\xe3\x82\x84\xe3\x81\x8b\xe3\x81\x95\xe3\x82\x93\xb176561198006011479\xaf110000102ba0657\t\x02\xb32022-02-06 10:30:16\x01\x00\x00\x00";
-
-        let mut de = rmp_serde::decode::Deserializer::from_read_ref(RESPONSE);
-        let result = serde_path_to_error::deserialize::<_, messagepack::ReplayResponse>(&mut de)
-            .map_err(|err| err.to_string());
+    fn fixed_nonce() {
+        let header = |context: &Context| {
+            request_header(
+                None,
+                context
+                    .nonce
+                    .clone()
+                    .unwrap_or_else(messagepack::Nonce::generate),
+            )
+        };
+        let context = Context::default();
+        assert_eq!(context.nonce, None);
+        assert!(header(&context).string2.timestamp().is_some());
 
-        expect_test::expect_file!["../test_data/replay_response_4.txt"].assert_debug_eq(&result);
+        let context = context.fixed_nonce("61a5ed4f461c2".into());
+        assert_eq!(header(&context).string2.as_str(), "61a5ed4f461c2");
+        assert_eq!(header(&context).string2, header(&context).string2);
     }
 
     #[test]
@@ -696,13 +5153,7 @@ mod tests {
         use messagepack::*;
 
         let query = ReplayRequest {
-            header: RequestHeader {
-                player_id: "211027113123008384".into(),
-                string2: "61a5ed4f461c2".into(),
-                int1: 2,
-                version: "0.1.0".into(),
-                platform: messagepack::Platform::PC,
-            },
+            header: request_header(None, Nonce::from("61a5ed4f461c2")),
             body: RequestBody {
                 int1: 1,
                 index: 0,
@@ -722,17 +5173,435 @@ mod tests {
             },
         };
 
-        expect_test::expect![[r#"9295B2323131303237313133313233303038333834AD3631613565643466343631633202A5302E312E30039401007F9AFFA3416C6C016390FFFF000001"#]].assert_eq(&query.to_hex())
+        expect_test::expect![[r#"9295b2323131303237313133313233303038333834ad3631613565643466343631633202a5302e312e30039401007f9aff00016390ffff000001"#]].assert_eq(&query.to_hex())
+    }
+
+    // Requests captured from the game client, see the decode tests below
+    const CAPTURED_REPLAY_REQUEST: &str = "9295b2323130363131303733303536313037353337ad3631666639366131653762353902a5302e312e30039401000a9aff02016390ffff000101";
+    const CAPTURED_VIP_REQUEST: &str = "9295b2323130363131303733303536313037353337ad3632306132363930623165653102a5302e312e3003940000ff00";
+    const CAPTURED_STATISTICS_REQUEST: &str = "9295b2323130363131303733303536313037353337ad3632306132363930623165653102a5302e312e300396b232323031323030313038323231383939373907ffffffff";
+
+    #[test]
+    fn encoding_matches_game_client() {
+        let request = messagepack::ReplayRequest::from_hex(CAPTURED_REPLAY_REQUEST).unwrap();
+        assert_eq!(request.to_hex(), CAPTURED_REPLAY_REQUEST);
+        let request =
+            messagepack::Request::<messagepack::VipRequest>::from_hex(CAPTURED_VIP_REQUEST)
+                .unwrap();
+        assert_eq!(request.to_hex(), CAPTURED_VIP_REQUEST);
+        let request = messagepack::Request::<messagepack::StatisticsRequest>::from_hex(
+            CAPTURED_STATISTICS_REQUEST,
+        )
+        .unwrap();
+        assert_eq!(request.to_hex(), CAPTURED_STATISTICS_REQUEST);
+    }
+
+    #[test]
+    fn replays_plan() {
+        let context = Context::default().fixed_nonce(Nonce::from("61a5ed4f461c2"));
+        let plan = get_replays_plan(&context, 3, 127, QueryParameters::default()).unwrap();
+        assert_eq!(
+            plan.url,
+            "https://ggst-game.guiltygear.com/api/catalog/get_replay"
+        );
+        assert_eq!(plan.requests, 3);
+        // The encoding of test_query
+        assert_eq!(
+            plan.first_request.unwrap(),
+            "9295b2323131303237313133313233303038333834ad3631613565643466343631633202a5302e312e30039401007f9aff00016390ffff000001"
+        );
+
+        let context = context.credentials(Credentials {
+            player_id: "210611073056107537".into(),
+            token: "61ff96a1e7b59".into(),
+        });
+        let follow = QueryParameters::default().player_search(PlayerSearch::Follow);
+        let plan = get_replays_plan(&context, 1, 10, follow).unwrap();
+        let mut captured = ReplayRequest::from_hex(CAPTURED_REPLAY_REQUEST).unwrap();
+        // The game client set a flag QueryParameters doesn't expose
+        captured.body.query.prioritize_best_bout = 0;
+        assert_eq!(plan.first_request, Some(captured.to_hex()));
+
+        let plan = get_replays_plan(&context, 0, 10, QueryParameters::default()).unwrap();
+        assert_eq!((plan.requests, plan.first_request), (0, None));
+        assert!(get_replays_plan(&context, 101, 10, QueryParameters::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn dry_run() {
+        use crate::mock_server::*;
+
+        let server = MockServer::start(vec![MockResponse::new(200, vec![])]).await;
+        let context = Context::new(server.url())
+            .fixed_nonce(Nonce::from("61a5ed4f461c2"))
+            .dry_run(true);
+        assert!(context.is_dry_run());
+        let replays = get_replays(&context, 2, 127, QueryParameters::default())
+            .await
+            .unwrap();
+        assert_eq!(replays.matches().count(), 0);
+        let plan = replays.plan().unwrap();
+        assert_eq!(
+            plan,
+            &get_replays_plan(&context, 2, 127, QueryParameters::default()).unwrap()
+        );
+        assert_eq!(plan.url, server.url() + "/api/catalog/get_replay");
+
+        let shard = get_replays_shard(
+            &context,
+            PageRange { start: 2, end: 5 },
+            127,
+            QueryParameters::default(),
+        )
+        .await
+        .unwrap();
+        let shard_plan = shard.plan().unwrap();
+        assert_eq!(shard_plan.requests, 3);
+        let first = ReplayRequest::from_hex(shard_plan.first_request.as_ref().unwrap()).unwrap();
+        assert_eq!(first.body.index, 2);
+        let merged = ReplaySet::merge(vec![replays, shard]);
+        assert_eq!(merged.plan().unwrap().requests, 5);
+
+        match get_user(&context, 2).await {
+            Err(Error::DryRun(plan)) => {
+                assert_eq!(plan.url, server.url() + endpoints::Statistics::PATH);
+                assert_eq!(plan.requests, 1);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(get_replays(&context, 1, 128, QueryParameters::default())
+            .await
+            .is_err());
+        assert_eq!(server.requests(), 0);
+
+        let context = context.transport(MockTransport::new());
+        assert_eq!(
+            get_replays_plan(&context, 1, 10, QueryParameters::default())
+                .unwrap()
+                .url,
+            "/api/catalog/get_replay"
+        );
+    }
+
+    // Serves replays with the ids 0 to available - 1 at their position, at most cap per page. Reading
+    // the body of a page is reported to take per_replay for each replay.
+    struct Positions {
+        available: usize,
+        cap: usize,
+        per_replay: Duration,
+        requests: std::sync::Mutex<Vec<(usize, usize)>>,
+    }
+
+    impl Positions {
+        fn new(available: usize, cap: usize, per_replay: Duration) -> Self {
+            Positions {
+                available,
+                cap,
+                per_replay,
+                requests: Default::default(),
+            }
+        }
+    }
+
+    impl Transport for Positions {
+        fn post<'a>(
+            &'a self,
+            _: &'static str,
+            data: &'a str,
+        ) -> BoxFuture<'a, Result<RawResponse>> {
+            let request = ReplayRequest::from_hex(data).unwrap();
+            let (index, per_page) = (request.body.index, request.body.replays_per_page);
+            self.requests.lock().unwrap().push((index, per_page));
+            let start = (index * per_page).min(self.available);
+            let end = (start + per_page.min(self.cap)).min(self.available);
+            let player = |id: u64| messagepack::Player {
+                id: id.to_string(),
+                name: RawString(b"player".to_vec()),
+                string1: "".into(),
+                string2: "".into(),
+                int1: 0,
+            };
+            let replays = (start as u64..end as u64)
+                .map(|id| Replay {
+                    id,
+                    int2: 9,
+                    floor: Floor::F10,
+                    player1_character: Character::Sol,
+                    player2_character: Character::Ky,
+                    player1: player(1),
+                    player2: player(2),
+                    winner: 1,
+                    date: DateTime::from_timestamp(1_644_000_000 - id as i64, 0).unwrap(),
+                    int7: 1,
+                    views: 0,
+                    int8: 0,
+                    likes: 0,
+                    extra: vec![],
+                })
+                .collect::<Vec<_>>();
+            let mut response = RawResponse::ok(
+                rmp_serde::encode::to_vec(&Response {
+                    header: test_response_header(),
+                    body: ResponseBody {
+                        int1: 0,
+                        int2: 0,
+                        int3: 0,
+                        replays,
+                    },
+                })
+                .unwrap(),
+            );
+            response.body_time = Some(self.per_replay * (end - start) as u32);
+            Box::pin(std::future::ready(Ok(response)))
+        }
+    }
+
+    #[tokio::test]
+    async fn adaptive_page_size() {
+        let paging = AdaptivePaging {
+            initial_per_page: 8,
+            max_per_page: 64,
+            target_latency: Duration::from_millis(40),
+        };
+        let sizes = |replays: &ReplaySet| {
+            replays
+                .metadata()
+                .iter()
+                .map(|metadata| (metadata.page, metadata.replays_per_page.unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        // Grows while a page takes at most 20ms, only where the larger pages line up
+        let transport = Arc::new(Positions::new(1000, 127, Duration::from_millis(1)));
+        let context = Context::default().transport(transport.clone());
+        let replays = get_replays_adaptive(&context, 200, QueryParameters::default(), paging)
+            .await
+            .unwrap();
+        assert_eq!(
+            sizes(&replays),
+            [
+                (0, 8),
+                (1, 8),
+                (1, 16),
+                (1, 32),
+                (2, 32),
+                (3, 32),
+                (4, 32),
+                (5, 32),
+                (6, 32)
+            ]
+        );
+        assert_eq!(*transport.requests.lock().unwrap(), sizes(&replays));
+        // Every position was requested exactly once
+        let mut ids = replays.matches().map(Match::id).collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..224).collect::<Vec<_>>());
+        assert!(replays
+            .overlaps()
+            .iter()
+            .all(|overlap| !overlap.possible_gap));
+
+        // Shrinks while a page takes more than 40ms
+        let context =
+            Context::default().transport(Positions::new(1000, 127, Duration::from_millis(3)));
+        let replays = get_replays_adaptive(
+            &context,
+            64,
+            QueryParameters::default(),
+            AdaptivePaging {
+                initial_per_page: 32,
+                ..paging
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(sizes(&replays), [(0, 32), (2, 16), (6, 8), (7, 8)]);
+
+        // Doesn't grow past what the server returns and stops once it runs out
+        let context = Context::default().transport(Positions::new(50, 10, Duration::ZERO));
+        let replays = get_replays_adaptive(&context, 1000, QueryParameters::default(), paging)
+            .await
+            .unwrap();
+        assert_eq!(
+            sizes(&replays),
+            [(0, 8), (1, 8), (1, 16), (2, 16), (3, 16), (4, 16)]
+        );
+        assert_eq!(replays.metadata().last().unwrap().replays, 0);
+
+        assert!(get_replays_adaptive(
+            &context,
+            10,
+            QueryParameters::default(),
+            AdaptivePaging {
+                initial_per_page: 0,
+                ..paging
+            }
+        )
+        .await
+        .is_err());
+        assert!(get_replays_adaptive(
+            &context,
+            10,
+            QueryParameters::default(),
+            AdaptivePaging {
+                max_per_page: 128,
+                ..paging
+            }
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn cancel_crawl() {
+        let context = Context::default().transport(Slow {
+            inner: MockTransport::new()
+                .page(0, RawResponse::ok(REPLAY_RESPONSE_1))
+                .page(1, RawResponse::ok(REPLAY_RESPONSE_2))
+                .page(2, RawResponse::ok(REPLAY_RESPONSE_3)),
+            delay: Duration::from_millis(100),
+            body_time: None,
+        });
+        // Cancelled while the second page is requested
+        let replays = get_replays_cancellable(
+            &context,
+            3,
+            10,
+            QueryParameters::default(),
+            tokio::time::sleep(Duration::from_millis(150)),
+        )
+        .await
+        .unwrap();
+        assert!(replays.cancelled());
+        assert_eq!(replays.metadata().len(), 1);
+        let first = get_replays(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert!(!first.cancelled());
+        assert_eq!(
+            replays.matches().map(Match::id).collect::<Vec<_>>(),
+            first.matches().map(Match::id).collect::<Vec<_>>()
+        );
+
+        // A request that never finishes is aborted
+        let context = context.transport(Slow {
+            inner: MockTransport::new(),
+            delay: Duration::from_secs(3600),
+            body_time: None,
+        });
+        let replays = tokio::time::timeout(
+            Duration::from_secs(5),
+            get_replays_cancellable(
+                &context,
+                1,
+                10,
+                QueryParameters::default(),
+                tokio::time::sleep(Duration::from_millis(10)),
+            ),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(replays.cancelled());
+        assert_eq!(replays.matches().count(), 0);
+    }
+
+    #[test]
+    fn estimated_sizes() {
+        let player = |name: &str| crate::Player {
+            id: 1,
+            character: Character::Sol,
+            name: name.into(),
+        };
+        let game = |names: (&str, &str)| {
+            Match::from_unix(
+                1,
+                1_640_000_000,
+                Floor::F10,
+                (player(names.0), player(names.1)),
+                Winner::Player1,
+            )
+            .unwrap()
+        };
+        let unnamed = game(("", ""));
+        assert_eq!(unnamed.estimated_size(), std::mem::size_of::<Match>());
+        assert_eq!(
+            game(("Ky", "Sol Badguy")).estimated_size(),
+            std::mem::size_of::<Match>() + 12
+        );
+
+        let error = ParseError::new(vec![0; 1000], Error::UnexpectedResponse("test"));
+        let base = error.estimated_size();
+        assert!(base >= 1000);
+        let error = ParseError::new(vec![0; 3000], Error::UnexpectedResponse("test"))
+            .with_path("body.replays[3]".into());
+        assert_eq!(error.estimated_size(), base + 2000 + 15);
+
+        let mut replays = ReplaySet::default();
+        let mut matches = MatchCollector::default();
+        matches.matches.insert(1, game(("Ky", "Sol Badguy")));
+        replays.errors.push(error);
+        replays.set_matches(matches);
+        assert_eq!(
+            replays.estimated_size(),
+            std::mem::size_of::<Match>() + 12 + base + 2015
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_budget() {
+        let pages = || {
+            MockTransport::new()
+                .page(0, RawResponse::ok(REPLAY_RESPONSE_1))
+                .page(1, RawResponse::ok(REPLAY_RESPONSE_2))
+                .page(2, RawResponse::ok(REPLAY_RESPONSE_3))
+        };
+        let context = Context::default().transport(pages());
+        let first = get_replays(&context, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        assert!(first.budget_exceeded().is_none());
+        let budget = first.estimated_size();
+
+        // The first page fits exactly, the second one exceeds the budget and is kept
+        let context = Context::default().transport(pages()).memory_budget(budget);
+        let replays = get_replays(&context, 3, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        let exceeded = replays.budget_exceeded().unwrap();
+        assert_eq!((exceeded.budget, exceeded.pages), (budget, 2));
+        assert_eq!(exceeded.used, replays.estimated_size());
+        assert!(exceeded.used > budget);
+        assert_eq!(replays.metadata().len(), 2);
+
+        let unbounded =
+            get_replays_unbounded(&context, QueryParameters::default(), FetchLimits::default())
+                .await
+                .unwrap();
+        assert_eq!(unbounded.stop, FetchStop::BudgetExceeded);
+        assert_eq!(unbounded.pages, 2);
+
+        let shard = get_replays_shard(
+            &context,
+            PageRange { start: 0, end: 3 },
+            10,
+            QueryParameters::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(shard.budget_exceeded(), Some(exceeded));
     }
 
     #[test]
     fn decode_request() {
-        let request = messagepack::ReplayRequest::from_hex("9295b2323130363131303733303536313037353337ad3631666639366131653762353902a5302e312e30039401000a9aff02016390ffff000101").unwrap();
+        let request = messagepack::ReplayRequest::from_hex(CAPTURED_REPLAY_REQUEST).unwrap();
         expect_test::expect![[r#"
             Request {
                 header: RequestHeader {
                     player_id: "210611073056107537",
-                    string2: "61ff96a1e7b59",
+                    string2: Nonce(
+                        "61ff96a1e7b59",
+                    ),
                     int1: 2,
                     version: "0.1.0",
                     platform: "PC",
@@ -761,13 +5630,17 @@ mod tests {
 
     #[test]
     fn decode_vip_ranking_request() {
-        let request = messagepack::Request::<messagepack::VipRequest>::from_hex("9295b2323130363131303733303536313037353337ad3632306132363930623165653102a5302e312e3003940000ff00").unwrap();
+        let request =
+            messagepack::Request::<messagepack::VipRequest>::from_hex(CAPTURED_VIP_REQUEST)
+                .unwrap();
 
         expect_test::expect![[r#"
             Request {
                 header: RequestHeader {
                     player_id: "210611073056107537",
-                    string2: "620a2690b1ee1",
+                    string2: Nonce(
+                        "620a2690b1ee1",
+                    ),
                     int1: 2,
                     version: "0.1.0",
                     platform: "PC",
@@ -783,20 +5656,28 @@ mod tests {
         .assert_debug_eq(&request);
     }
 
+    const VIP_RESPONSE: &str = "9298AD3632306132646263356236373400B3323032322F30322F31342031303A32333A3536A5302E312E30A5302E302E32A5302E302E32A0A09700CCD1CD180514DC0014970100CD05F3B2323130363131303731333036393337363036A7456D6572616C64B13736353631313939313535343434313331AF313130303030313437336366396133970211CD04D5B2323230313230303130383232313839393739A9474720506C61796572B13736353631313937393630343536353432AF313130303030313030303265393565970301CD04BDB2323130373231303131353237323231383439AE44616879756E2047616D696E6720B13736353631313938323536393130333836AF313130303030313131616537303332970409CD0485B2323130363131303730373338333431373538A84D656D6F6B617270B13736353631313938343236383533343931AF313130303030313162636639303733970507CD041CB2323130363132313334313130363738333537AC416F6D696E65204461696B69B13736353631313939303132333236393238AF313130303030313365623532653130970610CD0419B2323130363131323035363131323636313330AE43726F776E5468756E6465725350B13736353631313938323433343835383138AF31313030303031313065313938376197070FCD03C3B2323130363131303733303237323433343234A9536D6F696240747476B13736353631313938303435373832383935AF313130303030313035313865333666970808CD03BEB2323130393237313535373138303334343532AC4E415352207C204C61746966B13736353631313939323130363430323730AF31313030303031346138373333386597090ECD0382B2323130393235313133363139323030303530B2E38194E383BCE38284E383BCE381BEE38293B13736353631313938323830313734383433AF313130303030313133313136636662970A0FCD0371B2323130363131303730383133383339383536A3727569B13736353631313938303036393131323339AF313130303030313032633763313037970B01CD036EB2323130363131313132343131343331303039AA536E61696C7469676572B13736353631313938313432323032343538AF313130303030313061643832323561970C0CCD035FB2323130363131313834333132333731323339AB4261726679437261796F6EB13736353631313938303835363831383135AF313130303030313037373962323937970D10CD035CB2323130363131313332383439383634363337AE436172726F744F66576973646F6DB13736353631313938323033333034323738AF313130303030313065376337393536970E0FCD0358B2323130363135323031383438343333393237A74461726B726169B13736353631313938383034353533303831AF313130303030313332353263643739970F09CD034CB2323130363131313135353030343937373237AC565458207C20416E65656D61B13736353631313938323834363730333933AF31313030303031313335363035623997100BCD0345B2323130363133303031303439383432343830AB436F66666565706F776572B13736353631313937393939333739323236AF313130303030313032353464333161971102CD033CB2323130363139303733333531303334313133A86B75726F73617761B13736353631313938373936363037333739AF31313030303031333164393866393397120ECD0334B2323130363131313534323237363338363639A654656E736869B13736353631313938313036353936313135AF31313030303031303862386433313397130BCD032FB2323130363137303934353034333731383436B3ED9D91EC9DB820EC82ACEBACB4EB9DBCEC9DB4B13736353631313938303133303631363035AF313130303030313033323539396535971402CD0328B2323130363131303731323333333233313635A343424BB13736353631313938383336313031343739AF31313030303031333433343331363793CD0238CD058DCD0B1A00";
+
     #[test]
     fn test_vip_response() {
-        let response = messagepack::Response::<messagepack::VipResponse>::from_hex("9298AD3632306132646263356236373400B3323032322F30322F31342031303A32333A3536A5302E312E30A5302E302E32A5302E302E32A0A09700CCD1CD180514DC0014970100CD05F3B2323130363131303731333036393337363036A7456D6572616C64B13736353631313939313535343434313331AF313130303030313437336366396133970211CD04D5B2323230313230303130383232313839393739A9474720506C61796572B13736353631313937393630343536353432AF313130303030313030303265393565970301CD04BDB2323130373231303131353237323231383439AE44616879756E2047616D696E6720B13736353631313938323536393130333836AF313130303030313131616537303332970409CD0485B2323130363131303730373338333431373538A84D656D6F6B617270B13736353631313938343236383533343931AF313130303030313162636639303733970507CD041CB2323130363132313334313130363738333537AC416F6D696E65204461696B69B13736353631313939303132333236393238AF313130303030313365623532653130970610CD0419B2323130363131323035363131323636313330AE43726F776E5468756E6465725350B13736353631313938323433343835383138AF31313030303031313065313938376197070FCD03C3B2323130363131303733303237323433343234A9536D6F696240747476B13736353631313938303435373832383935AF313130303030313035313865333666970808CD03BEB2323130393237313535373138303334343532AC4E415352207C204C61746966B13736353631313939323130363430323730AF31313030303031346138373333386597090ECD0382B2323130393235313133363139323030303530B2E38194E383BCE38284E383BCE381BEE38293B13736353631313938323830313734383433AF313130303030313133313136636662970A0FCD0371B2323130363131303730383133383339383536A3727569B13736353631313938303036393131323339AF313130303030313032633763313037970B01CD036EB2323130363131313132343131343331303039AA536E61696C7469676572B13736353631313938313432323032343538AF313130303030313061643832323561970C0CCD035FB2323130363131313834333132333731323339AB4261726679437261796F6EB13736353631313938303835363831383135AF313130303030313037373962323937970D10CD035CB2323130363131313332383439383634363337AE436172726F744F66576973646F6DB13736353631313938323033333034323738AF313130303030313065376337393536970E0FCD0358B2323130363135323031383438343333393237A74461726B726169B13736353631313938383034353533303831AF313130303030313332353263643739970F09CD034CB2323130363131313135353030343937373237AC565458207C20416E65656D61B13736353631313938323834363730333933AF31313030303031313335363035623997100BCD0345B2323130363133303031303439383432343830AB436F66666565706F776572B13736353631313937393939333739323236AF313130303030313032353464333161971102CD033CB2323130363139303733333531303334313133A86B75726F73617761B13736353631313938373936363037333739AF31313030303031333164393866393397120ECD0334B2323130363131313534323237363338363639A654656E736869B13736353631313938313036353936313135AF31313030303031303862386433313397130BCD032FB2323130363137303934353034333731383436B3ED9D91EC9DB820EC82ACEBACB4EB9DBCEC9DB4B13736353631313938303133303631363035AF313130303030313033323539396535971402CD0328B2323130363131303731323333333233313635A343424BB13736353631313938383336313031343739AF31313030303031333433343331363793CD0238CD058DCD0B1A00").unwrap();
+        let response =
+            messagepack::Response::<messagepack::VipResponse>::from_hex(VIP_RESPONSE).unwrap();
         expect_test::expect_file!["../test_data/vip_response.txt"].assert_debug_eq(&response);
     }
 
     #[test]
     fn statistics_request() {
-        let response = messagepack::Request::<messagepack::StatisticsRequest>::from_hex("9295b2323130363131303733303536313037353337ad3632306132363930623165653102a5302e312e300396b232323031323030313038323231383939373907ffffffff").unwrap();
+        let response = messagepack::Request::<messagepack::StatisticsRequest>::from_hex(
+            CAPTURED_STATISTICS_REQUEST,
+        )
+        .unwrap();
         expect_test::expect![[r#"
             Request {
                 header: RequestHeader {
                     player_id: "210611073056107537",
-                    string2: "620a2690b1ee1",
+                    string2: Nonce(
+                        "620a2690b1ee1",
+                    ),
                     int1: 2,
                     version: "0.1.0",
                     platform: "PC",
@@ -814,9 +5695,91 @@ mod tests {
         .assert_debug_eq(&response);
     }
 
+    const STATISTICS_RESPONSE: &str = "9298AD3632306133393039363765346300B3323032322F30322F31342031313A31323A3039A5302E312E30A5302E302E32A5302E302E32A0A09200DA13BA7B22414E4A5F426164676531223A323130332C22414E4A5F4261646765315F56616C223A392C22414E4A5F426164676532223A3530343030302C22414E4A5F4261646765325F56616C223A302C22414E4A5F426164676533223A3530313030302C22414E4A5F4261646765335F56616C223A312C22414E4A5F457870223A302C22414E4A5F4C76223A312C22414E4A5F4E6578744C76457870223A3130302C22414E4A5F504D5F57696E73223A302C22414E4A5F57696E436861696E4D6178223A302C22414E4A5F57696E436861696E4E6F77223A302C2241584C5F426164676531223A323130332C2241584C5F4261646765315F56616C223A392C2241584C5F426164676532223A3530343030302C2241584C5F4261646765325F56616C223A302C2241584C5F426164676533223A3530313030302C2241584C5F4261646765335F56616C223A312C2241584C5F457870223A302C2241584C5F4C76223A312C2241584C5F4E6578744C76457870223A3130302C2241584C5F504D5F57696E73223A302C2241584C5F57696E436861696E4D6178223A302C2241584C5F57696E436861696E4E6F77223A302C224163636F756E744944223A37363536313139373936303435363534322C2241766174617241757261223A302C22417661746172417572615465726D223A302C22424B4E5F426164676531223A323130332C22424B4E5F4261646765315F56616C223A392C22424B4E5F426164676532223A3530343030302C22424B4E5F4261646765325F56616C223A302C22424B4E5F426164676533223A3530313030302C22424B4E5F4261646765335F56616C223A312C22424B4E5F457870223A302C22424B4E5F4C76223A312C22424B4E5F4E6578744C76457870223A3130302C22424B4E5F504D5F57696E73223A302C22424B4E5F57696E436861696E4D6178223A302C22424B4E5F57696E436861696E4E6F77223A302C224348505F426164676531223A323130332C224348505F4261646765315F56616C223A392C224348505F426164676532223A3530343030302C224348505F4261646765325F56616C223A302C224348505F426164676533223A3530313030302C224348505F4261646765335F56616C223A312C224348505F457870223A302C224348505F4C76223A312C224348505F4E6578744C76457870223A3130302C224348505F504D5F57696E73223A302C224348505F57696E436861696E4D6178223A302C224348505F57696E436861696E4E6F77223A302C22434F535F426164676531223A3530333030392C22434F535F4261646765315F56616C223A313233382C22434F535F426164676532223A3530323138392C22434F535F4261646765325F56616C223A313534362C22434F535F426164676533223A3530313030332C22434F535F4261646765335F56616C223A313534362C22434F535F457870223A37353838373135342C22434F535F4C76223A313534362C22434F535F4E6578744C76457870223A37353932323530302C22434F535F504D5F57696E73223A302C22434F535F57696E436861696E4D6178223A3131382C22434F535F57696E436861696E4E6F77223A31302C22436F6E646974696F6E426974223A2D313032352C224461746148696464656E223A312C2244656D6F7465645F4275727374223A302C2244656D6F7465645F5243223A302C2244656D6F7465645F52434D6F7665223A302C2244656D6F7465645F5243536B696C6C223A302C2244656D6F7465645F556C74696D617465223A302C2244656D6F7465645F575342223A302C224641555F426164676531223A323130332C224641555F4261646765315F56616C223A392C224641555F426164676532223A3530343030302C224641555F4261646765325F56616C223A302C224641555F426164676533223A3530313030302C224641555F4261646765335F56616C223A312C224641555F457870223A302C224641555F4C76223A312C224641555F4E6578744C76457870223A3130302C224641555F504D5F57696E73223A302C224641555F57696E436861696E4D6178223A302C224641555F57696E436861696E4E6F77223A302C2247494F5F426164676531223A3530333030392C2247494F5F4261646765315F56616C223A3333312C2247494F5F426164676532223A3530313030332C2247494F5F4261646765325F56616C223A3839332C2247494F5F426164676533223A3530323133392C2247494F5F4261646765335F56616C223A3839332C2247494F5F457870223A31383031373236302C2247494F5F4C76223A3839332C2247494F5F4E6578744C76457870223A31383034323530302C2247494F5F504D5F57696E73223A302C2247494F5F57696E436861696E4D6178223A35332C2247494F5F57696E436861696E4E6F77223A372C22474C445F426164676531223A323130332C22474C445F4261646765315F56616C223A392C22474C445F426164676532223A3530343030302C22474C445F4261646765325F56616C223A302C22474C445F426164676533223A3530313030302C22474C445F4261646765335F56616C223A312C22474C445F457870223A302C22474C445F4C76223A312C22474C445F4E6578744C76457870223A3130302C22474C445F504D5F57696E73223A302C22474C445F57696E436861696E4D6178223A302C22474C445F57696E436861696E4E6F77223A302C22494E4F5F426164676531223A323130332C22494E4F5F4261646765315F56616C223A392C22494E4F5F426164676532223A3530343030302C22494E4F5F4261646765325F56616C223A302C22494E4F5F426164676533223A3530313030302C22494E4F5F4261646765335F56616C223A312C22494E4F5F457870223A302C22494E4F5F4C76223A312C22494E4F5F4E6578744C76457870223A3130302C22494E4F5F504D5F57696E73223A302C22494E4F5F57696E436861696E4D6178223A302C22494E4F5F57696E436861696E4E6F77223A302C224A4B4F5F426164676531223A323130332C224A4B4F5F4261646765315F56616C223A392C224A4B4F5F426164676532223A3530343030302C224A4B4F5F4261646765325F56616C223A302C224A4B4F5F426164676533223A3530313030302C224A4B4F5F4261646765335F56616C223A312C224A4B4F5F457870223A302C224A4B4F5F4C76223A312C224A4B4F5F4E6578744C76457870223A3130302C224A4B4F5F504D5F57696E73223A302C224A4B4F5F57696E436861696E4D6178223A302C224A4B4F5F57696E436861696E4E6F77223A302C224B594B5F426164676531223A323130332C224B594B5F4261646765315F56616C223A392C224B594B5F426164676532223A3530343030302C224B594B5F4261646765325F56616C223A302C224B594B5F426164676533223A3530313030302C224B594B5F4261646765335F56616C223A312C224B594B5F457870223A302C224B594B5F4C76223A312C224B594B5F4E6578744C76457870223A3130302C224B594B5F504D5F57696E73223A302C224B594B5F57696E436861696E4D6178223A302C224B594B5F57696E436861696E4E6F77223A302C224C454F5F426164676531223A323130332C224C454F5F4261646765315F56616C223A392C224C454F5F426164676532223A3530343030302C224C454F5F4261646765325F56616C223A302C224C454F5F426164676533223A3530313030302C224C454F5F4261646765335F56616C223A312C224C454F5F457870223A302C224C454F5F4C76223A312C224C454F5F4E6578744C76457870223A3130302C224C454F5F504D5F57696E73223A302C224C454F5F57696E436861696E4D6178223A302C224C454F5F57696E436861696E4E6F77223A302C224C6F62627952616E6B223A392C224C6F6262795475746F7269616C223A312C224D41595F426164676531223A323130332C224D41595F4261646765315F56616C223A392C224D41595F426164676532223A3530343030302C224D41595F4261646765325F56616C223A302C224D41595F426164676533223A3530313030302C224D41595F4261646765335F56616C223A312C224D41595F457870223A302C224D41595F4C76223A312C224D41595F4E6578744C76457870223A3130302C224D41595F504D5F57696E73223A302C224D41595F57696E436861696E4D6178223A302C224D41595F57696E436861696E4E6F77223A302C224D4C4C5F426164676531223A323130332C224D4C4C5F4261646765315F56616C223A392C224D4C4C5F426164676532223A3530343030302C224D4C4C5F4261646765325F56616C223A302C224D4C4C5F426164676533223A3530313030302C224D4C4C5F4261646765335F56616C223A312C224D4C4C5F457870223A302C224D4C4C5F4C76223A312C224D4C4C5F4E6578744C76457870223A3130302C224D4C4C5F504D5F57696E73223A302C224D4C4C5F57696E436861696E4D6178223A302C224D4C4C5F57696E436861696E4E6F77223A302C224D61784C6F62627952616E6B223A392C224D6178566970537461747573223A322C224D79526F6F6D48696464656E223A302C224E41475F426164676531223A323130332C224E41475F4261646765315F56616C223A392C224E41475F426164676532223A3530343030302C224E41475F4261646765325F56616C223A302C224E41475F426164676533223A3530313030302C224E41475F4261646765335F56616C223A312C224E41475F457870223A302C224E41475F4C76223A312C224E41475F4E6578744C76457870223A3130302C224E41475F504D5F57696E73223A302C224E41475F57696E436861696E4D6178223A302C224E41475F57696E436861696E4E6F77223A302C224E616D6541757261223A302C224E616D65417572615465726D223A302C224E69636B4E616D65223A22474720506C61796572222C224E6F74426567696E6E6572223A302C224F6E6C696E6543686561745074223A35302C224F6E6C696E654944223A22313130303030313030303265393565222C22504F545F426164676531223A323130332C22504F545F4261646765315F56616C223A392C22504F545F426164676532223A3530343030302C22504F545F4261646765325F56616C223A302C22504F545F426164676533223A3530313030302C22504F545F4261646765335F56616C223A312C22504F545F457870223A302C22504F545F4C76223A312C22504F545F4E6578744C76457870223A3130302C22504F545F504D5F57696E73223A302C22504F545F57696E436861696E4D6178223A302C22504F545F57696E436861696E4E6F77223A302C22506C617956657273696F6E223A3130322C22506C6179657257696E436861696E4D6178223A3131382C22506C6179657257696E436861696E4E6F77223A31302C22507265764C6F62627952616E6B223A392C2250726576566970537461747573223A322C225075626C6963436F6D6D656E74223A22476F6F64206C75636B21222C2252414D5F426164676531223A323130332C2252414D5F4261646765315F56616C223A392C2252414D5F426164676532223A3530343030322C2252414D5F4261646765325F56616C223A3132382C2252414D5F426164676533223A3530313030332C2252414D5F4261646765335F56616C223A3433392C2252414D5F457870223A353331353339302C2252414D5F4C76223A3433392C2252414D5F4E6578744C76457870223A353332323530302C2252414D5F504D5F57696E73223A302C2252414D5F57696E436861696E4D6178223A35362C2252414D5F57696E436861696E4E6F77223A31322C2252616E6B436865636B4D61746368223A302C2252616E6B436865636B5074223A302C2252616E6B436865636B54657374223A372C22534F4C5F426164676531223A323130332C22534F4C5F4261646765315F56616C223A392C22534F4C5F426164676532223A3530343030302C22534F4C5F4261646765325F56616C223A302C22534F4C5F426164676533223A3530313030302C22534F4C5F4261646765335F56616C223A312C22534F4C5F457870223A302C22534F4C5F4C76223A312C22534F4C5F4E6578744C76457870223A3130302C22534F4C5F504D5F57696E73223A302C22534F4C5F57696E436861696E4D6178223A302C22534F4C5F57696E436861696E4E6F77223A302C2253656C65637442474D223A302C2253656C6563744368617261223A302C2253656C6563744368617261436F6C6F72223A302C2253656C6563745374616765223A302C22546F74616C506C617954696D65223A33303938393438312C22546F74616C52616E6B4D61746368223A323032302C225570646174655F446179223A31332C225570646174655F486F7572223A31342C225570646174655F4D696E223A31322C225570646174655F4D6F6E7468223A322C225570646174655F59656172223A323032322C22557365724944223A3232303132303031303832323138393937392C22566970436865636B4D61746368223A302C22566970436865636B5074223A302C22566970537461747573223A322C22576F726C64446F6C6C6172223A3430393430302C22576F726C64446F6C6C6172546F74616C223A3530323030302C225A41545F426164676531223A323130332C225A41545F4261646765315F56616C223A392C225A41545F426164676532223A3530343030302C225A41545F4261646765325F56616C223A302C225A41545F426164676533223A3530313030302C225A41545F4261646765335F56616C223A312C225A41545F457870223A302C225A41545F4C76223A312C225A41545F4E6578744C76457870223A3130302C225A41545F504D5F57696E73223A302C225A41545F57696E436861696E4D6178223A302C225A41545F57696E436861696E4E6F77223A307D";
+
     #[test]
     fn statistics_response() {
-        let response = Response::<StatisticsResponse>::from_hex("9298AD3632306133393039363765346300B3323032322F30322F31342031313A31323A3039A5302E312E30A5302E302E32A5302E302E32A0A09200DA13BA7B22414E4A5F426164676531223A323130332C22414E4A5F4261646765315F56616C223A392C22414E4A5F426164676532223A3530343030302C22414E4A5F4261646765325F56616C223A302C22414E4A5F426164676533223A3530313030302C22414E4A5F4261646765335F56616C223A312C22414E4A5F457870223A302C22414E4A5F4C76223A312C22414E4A5F4E6578744C76457870223A3130302C22414E4A5F504D5F57696E73223A302C22414E4A5F57696E436861696E4D6178223A302C22414E4A5F57696E436861696E4E6F77223A302C2241584C5F426164676531223A323130332C2241584C5F4261646765315F56616C223A392C2241584C5F426164676532223A3530343030302C2241584C5F4261646765325F56616C223A302C2241584C5F426164676533223A3530313030302C2241584C5F4261646765335F56616C223A312C2241584C5F457870223A302C2241584C5F4C76223A312C2241584C5F4E6578744C76457870223A3130302C2241584C5F504D5F57696E73223A302C2241584C5F57696E436861696E4D6178223A302C2241584C5F57696E436861696E4E6F77223A302C224163636F756E744944223A37363536313139373936303435363534322C2241766174617241757261223A302C22417661746172417572615465726D223A302C22424B4E5F426164676531223A323130332C22424B4E5F4261646765315F56616C223A392C22424B4E5F426164676532223A3530343030302C22424B4E5F4261646765325F56616C223A302C22424B4E5F426164676533223A3530313030302C22424B4E5F4261646765335F56616C223A312C22424B4E5F457870223A302C22424B4E5F4C76223A312C22424B4E5F4E6578744C76457870223A3130302C22424B4E5F504D5F57696E73223A302C22424B4E5F57696E436861696E4D6178223A302C22424B4E5F57696E436861696E4E6F77223A302C224348505F426164676531223A323130332C224348505F4261646765315F56616C223A392C224348505F426164676532223A3530343030302C224348505F4261646765325F56616C223A302C224348505F426164676533223A3530313030302C224348505F4261646765335F56616C223A312C224348505F457870223A302C224348505F4C76223A312C224348505F4E6578744C76457870223A3130302C224348505F504D5F57696E73223A302C224348505F57696E436861696E4D6178223A302C224348505F57696E436861696E4E6F77223A302C22434F535F426164676531223A3530333030392C22434F535F4261646765315F56616C223A313233382C22434F535F426164676532223A3530323138392C22434F535F4261646765325F56616C223A313534362C22434F535F426164676533223A3530313030332C22434F535F4261646765335F56616C223A313534362C22434F535F457870223A37353838373135342C22434F535F4C76223A313534362C22434F535F4E6578744C76457870223A37353932323530302C22434F535F504D5F57696E73223A302C22434F535F57696E436861696E4D6178223A3131382C22434F535F57696E436861696E4E6F77223A31302C22436F6E646974696F6E426974223A2D313032352C224461746148696464656E223A312C2244656D6F7465645F4275727374223A302C2244656D6F7465645F5243223A302C2244656D6F7465645F52434D6F7665223A302C2244656D6F7465645F5243536B696C6C223A302C2244656D6F7465645F556C74696D617465223A302C2244656D6F7465645F575342223A302C224641555F426164676531223A323130332C224641555F4261646765315F56616C223A392C224641555F426164676532223A3530343030302C224641555F4261646765325F56616C223A302C224641555F426164676533223A3530313030302C224641555F4261646765335F56616C223A312C224641555F457870223A302C224641555F4C76223A312C224641555F4E6578744C76457870223A3130302C224641555F504D5F57696E73223A302C224641555F57696E436861696E4D6178223A302C224641555F57696E436861696E4E6F77223A302C2247494F5F426164676531223A3530333030392C2247494F5F4261646765315F56616C223A3333312C2247494F5F426164676532223A3530313030332C2247494F5F4261646765325F56616C223A3839332C2247494F5F426164676533223A3530323133392C2247494F5F4261646765335F56616C223A3839332C2247494F5F457870223A31383031373236302C2247494F5F4C76223A3839332C2247494F5F4E6578744C76457870223A31383034323530302C2247494F5F504D5F57696E73223A302C2247494F5F57696E436861696E4D6178223A35332C2247494F5F57696E436861696E4E6F77223A372C22474C445F426164676531223A323130332C22474C445F4261646765315F56616C223A392C22474C445F426164676532223A3530343030302C22474C445F4261646765325F56616C223A302C22474C445F426164676533223A3530313030302C22474C445F4261646765335F56616C223A312C22474C445F457870223A302C22474C445F4C76223A312C22474C445F4E6578744C76457870223A3130302C22474C445F504D5F57696E73223A302C22474C445F57696E436861696E4D6178223A302C22474C445F57696E436861696E4E6F77223A302C22494E4F5F426164676531223A323130332C22494E4F5F4261646765315F56616C223A392C22494E4F5F426164676532223A3530343030302C22494E4F5F4261646765325F56616C223A302C22494E4F5F426164676533223A3530313030302C22494E4F5F4261646765335F56616C223A312C22494E4F5F457870223A302C22494E4F5F4C76223A312C22494E4F5F4E6578744C76457870223A3130302C22494E4F5F504D5F57696E73223A302C22494E4F5F57696E436861696E4D6178223A302C22494E4F5F57696E436861696E4E6F77223A302C224A4B4F5F426164676531223A323130332C224A4B4F5F4261646765315F56616C223A392C224A4B4F5F426164676532223A3530343030302C224A4B4F5F4261646765325F56616C223A302C224A4B4F5F426164676533223A3530313030302C224A4B4F5F4261646765335F56616C223A312C224A4B4F5F457870223A302C224A4B4F5F4C76223A312C224A4B4F5F4E6578744C76457870223A3130302C224A4B4F5F504D5F57696E73223A302C224A4B4F5F57696E436861696E4D6178223A302C224A4B4F5F57696E436861696E4E6F77223A302C224B594B5F426164676531223A323130332C224B594B5F4261646765315F56616C223A392C224B594B5F426164676532223A3530343030302C224B594B5F4261646765325F56616C223A302C224B594B5F426164676533223A3530313030302C224B594B5F4261646765335F56616C223A312C224B594B5F457870223A302C224B594B5F4C76223A312C224B594B5F4E6578744C76457870223A3130302C224B594B5F504D5F57696E73223A302C224B594B5F57696E436861696E4D6178223A302C224B594B5F57696E436861696E4E6F77223A302C224C454F5F426164676531223A323130332C224C454F5F4261646765315F56616C223A392C224C454F5F426164676532223A3530343030302C224C454F5F4261646765325F56616C223A302C224C454F5F426164676533223A3530313030302C224C454F5F4261646765335F56616C223A312C224C454F5F457870223A302C224C454F5F4C76223A312C224C454F5F4E6578744C76457870223A3130302C224C454F5F504D5F57696E73223A302C224C454F5F57696E436861696E4D6178223A302C224C454F5F57696E436861696E4E6F77223A302C224C6F62627952616E6B223A392C224C6F6262795475746F7269616C223A312C224D41595F426164676531223A323130332C224D41595F4261646765315F56616C223A392C224D41595F426164676532223A3530343030302C224D41595F4261646765325F56616C223A302C224D41595F426164676533223A3530313030302C224D41595F4261646765335F56616C223A312C224D41595F457870223A302C224D41595F4C76223A312C224D41595F4E6578744C76457870223A3130302C224D41595F504D5F57696E73223A302C224D41595F57696E436861696E4D6178223A302C224D41595F57696E436861696E4E6F77223A302C224D4C4C5F426164676531223A323130332C224D4C4C5F4261646765315F56616C223A392C224D4C4C5F426164676532223A3530343030302C224D4C4C5F4261646765325F56616C223A302C224D4C4C5F426164676533223A3530313030302C224D4C4C5F4261646765335F56616C223A312C224D4C4C5F457870223A302C224D4C4C5F4C76223A312C224D4C4C5F4E6578744C76457870223A3130302C224D4C4C5F504D5F57696E73223A302C224D4C4C5F57696E436861696E4D6178223A302C224D4C4C5F57696E436861696E4E6F77223A302C224D61784C6F62627952616E6B223A392C224D6178566970537461747573223A322C224D79526F6F6D48696464656E223A302C224E41475F426164676531223A323130332C224E41475F4261646765315F56616C223A392C224E41475F426164676532223A3530343030302C224E41475F4261646765325F56616C223A302C224E41475F426164676533223A3530313030302C224E41475F4261646765335F56616C223A312C224E41475F457870223A302C224E41475F4C76223A312C224E41475F4E6578744C76457870223A3130302C224E41475F504D5F57696E73223A302C224E41475F57696E436861696E4D6178223A302C224E41475F57696E436861696E4E6F77223A302C224E616D6541757261223A302C224E616D65417572615465726D223A302C224E69636B4E616D65223A22474720506C61796572222C224E6F74426567696E6E6572223A302C224F6E6C696E6543686561745074223A35302C224F6E6C696E654944223A22313130303030313030303265393565222C22504F545F426164676531223A323130332C22504F545F4261646765315F56616C223A392C22504F545F426164676532223A3530343030302C22504F545F4261646765325F56616C223A302C22504F545F426164676533223A3530313030302C22504F545F4261646765335F56616C223A312C22504F545F457870223A302C22504F545F4C76223A312C22504F545F4E6578744C76457870223A3130302C22504F545F504D5F57696E73223A302C22504F545F57696E436861696E4D6178223A302C22504F545F57696E436861696E4E6F77223A302C22506C617956657273696F6E223A3130322C22506C6179657257696E436861696E4D6178223A3131382C22506C6179657257696E436861696E4E6F77223A31302C22507265764C6F62627952616E6B223A392C2250726576566970537461747573223A322C225075626C6963436F6D6D656E74223A22476F6F64206C75636B21222C2252414D5F426164676531223A323130332C2252414D5F4261646765315F56616C223A392C2252414D5F426164676532223A3530343030322C2252414D5F4261646765325F56616C223A3132382C2252414D5F426164676533223A3530313030332C2252414D5F4261646765335F56616C223A3433392C2252414D5F457870223A353331353339302C2252414D5F4C76223A3433392C2252414D5F4E6578744C76457870223A353332323530302C2252414D5F504D5F57696E73223A302C2252414D5F57696E436861696E4D6178223A35362C2252414D5F57696E436861696E4E6F77223A31322C2252616E6B436865636B4D61746368223A302C2252616E6B436865636B5074223A302C2252616E6B436865636B54657374223A372C22534F4C5F426164676531223A323130332C22534F4C5F4261646765315F56616C223A392C22534F4C5F426164676532223A3530343030302C22534F4C5F4261646765325F56616C223A302C22534F4C5F426164676533223A3530313030302C22534F4C5F4261646765335F56616C223A312C22534F4C5F457870223A302C22534F4C5F4C76223A312C22534F4C5F4E6578744C76457870223A3130302C22534F4C5F504D5F57696E73223A302C22534F4C5F57696E436861696E4D6178223A302C22534F4C5F57696E436861696E4E6F77223A302C2253656C65637442474D223A302C2253656C6563744368617261223A302C2253656C6563744368617261436F6C6F72223A302C2253656C6563745374616765223A302C22546F74616C506C617954696D65223A33303938393438312C22546F74616C52616E6B4D61746368223A323032302C225570646174655F446179223A31332C225570646174655F486F7572223A31342C225570646174655F4D696E223A31322C225570646174655F4D6F6E7468223A322C225570646174655F59656172223A323032322C22557365724944223A3232303132303031303832323138393937392C22566970436865636B4D61746368223A302C22566970436865636B5074223A302C22566970537461747573223A322C22576F726C64446F6C6C6172223A3430393430302C22576F726C64446F6C6C6172546F74616C223A3530323030302C225A41545F426164676531223A323130332C225A41545F4261646765315F56616C223A392C225A41545F426164676532223A3530343030302C225A41545F4261646765325F56616C223A302C225A41545F426164676533223A3530313030302C225A41545F4261646765335F56616C223A312C225A41545F457870223A302C225A41545F4C76223A312C225A41545F4E6578744C76457870223A3130302C225A41545F504D5F57696E73223A302C225A41545F57696E436861696E4D6178223A302C225A41545F57696E436861696E4E6F77223A307D").unwrap();
-        expect_test::expect_file!["../test_data/vip_response.txt"].assert_debug_eq(&response);
+        let response = Response::<StatisticsResponse>::from_hex(STATISTICS_RESPONSE).unwrap();
+        expect_test::expect_file!["../test_data/statistics_response.txt"]
+            .assert_debug_eq(&response);
+    }
+
+    #[test]
+    fn interned_player_names() {
+        // 10k matches between 300 players, about what a long crawl of a single floor returns
+        let player = |n: u64| messagepack::Player {
+            id: format!("2106110712535{:05}", n),
+            name: RawString(format!("Floor 10 regular no. {}", n).into_bytes()),
+            string1: "".into(),
+            string2: "".into(),
+            int1: 0,
+        };
+        let replays = (0..10_000)
+            .map(|i| Replay {
+                id: i,
+                int2: 0,
+                floor: Floor::F10,
+                player1_character: Character::Sol,
+                player2_character: Character::Ky,
+                player1: player(i * 7 % 300),
+                player2: player((i * 13 + 1) % 300),
+                winner: 1,
+                date: DateTime::from_timestamp(1_644_000_000 + i as i64, 0).unwrap(),
+                int7: 0,
+                views: 0,
+                int8: 0,
+                likes: 0,
+                extra: vec![],
+            })
+            .collect();
+        let response = Response {
+            header: test_response_header(),
+            body: ResponseBody {
+                int1: 0,
+                int2: 0,
+                int3: 0,
+                replays,
+            },
+        };
+        let bytes = rmp_serde::encode::to_vec(&response).unwrap();
+        let replays = decode_replay_response(&bytes, DecodeMode::Lenient).unwrap();
+
+        let names = replays
+            .matches()
+            .flat_map(|m| [&m.players.0.name, &m.players.1.name])
+            .collect::<Vec<_>>();
+        let allocations = names
+            .iter()
+            .map(|name| Arc::as_ptr(name) as *const u8)
+            .collect::<HashSet<_>>();
+        let total_bytes: usize = names.iter().map(|name| name.len()).sum();
+        let interned_bytes: usize = names
+            .iter()
+            .map(|name| (Arc::as_ptr(name) as *const u8, name.len()))
+            .collect::<HashMap<_, _>>()
+            .values()
+            .sum();
+        assert_eq!(names.len(), 20_000);
+        assert_eq!(allocations.len(), 300);
+        assert!(
+            interned_bytes * 50 < total_bytes,
+            "{} of {} bytes",
+            interned_bytes,
+            total_bytes
+        );
+    }
+
+    #[test]
+    fn retain_new_replays() {
+        let mut cache = crate::cache::ReplayIdCache::new(1000);
+        let mut first = decode_replay_response(REPLAY_RESPONSE_1, DecodeMode::Lenient).unwrap();
+        let count = first.matches().count();
+        first.retain_new(&mut cache);
+        assert_eq!(first.matches().count(), count);
+        assert_eq!(cache.len(), count);
+
+        let mut again = decode_replay_response(REPLAY_RESPONSE_1, DecodeMode::Lenient).unwrap();
+        again.retain_new(&mut cache);
+        assert_eq!(again.matches().count(), 0);
     }
 }
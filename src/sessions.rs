@@ -0,0 +1,152 @@
+//! Grouping of matches into sessions, runs of consecutive matches between the same two players.
+
+use crate::Match;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Longest break between two matches of the same session used by group
+pub const DEFAULT_GAP: Duration = Duration::from_secs(10 * 60);
+
+/// Consecutive matches between two players
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Session {
+    /// Ids of the players, in the order of the first match of the session
+    pub players: (i64, i64),
+    /// Timestamp of the first match
+    pub start: DateTime<Utc>,
+    /// Timestamp of the last match
+    pub end: DateTime<Utc>,
+    /// Matches won by each player, in the same order as players
+    pub score: (u64, u64),
+    /// Matches in chronological order
+    pub matches: Vec<Match>,
+}
+
+impl Session {
+    fn new(m: &Match) -> Self {
+        let mut session = Session {
+            players: (m.players.0.id, m.players.1.id),
+            start: m.timestamp,
+            end: m.timestamp,
+            score: (0, 0),
+            matches: vec![],
+        };
+        session.push(m);
+        session
+    }
+
+    fn push(&mut self, m: &Match) {
+        match m.try_winner() {
+            Some(winner) if winner.id == self.players.0 => self.score.0 += 1,
+            Some(_) => self.score.1 += 1,
+            None => {}
+        }
+        self.end = m.timestamp;
+        self.matches.push(m.clone());
+    }
+}
+
+/// Group matches into sessions with a gap of DEFAULT_GAP, see group_with_gap
+pub fn group<'a, I>(matches: I) -> Vec<Session>
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    group_with_gap(matches, DEFAULT_GAP)
+}
+
+/// Group matches into sessions. A match continues the session of its two players if it was played
+/// at most gap after the previous match of the session, otherwise it starts a new one. Matches of
+/// other players in between don't end a session. The matches don't have to be sorted, sessions
+/// are returned in order of their start.
+pub fn group_with_gap<'a, I>(matches: I, gap: Duration) -> Vec<Session>
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let gap = chrono::Duration::from_std(gap).unwrap_or(chrono::Duration::MAX);
+    let mut matches = matches.into_iter().collect::<Vec<_>>();
+    matches.sort_by_key(|m| m.timestamp);
+
+    let mut sessions: Vec<Session> = vec![];
+    // Index of the latest session of each pair of players, keyed by the smaller id first
+    let mut open: HashMap<(i64, i64), usize> = HashMap::new();
+    for m in matches {
+        let (a, b) = (m.players.0.id, m.players.1.id);
+        let pair = (a.min(b), a.max(b));
+        match open.get(&pair) {
+            Some(&index) if m.timestamp - sessions[index].end <= gap => sessions[index].push(m),
+            _ => {
+                open.insert(pair, sessions.len());
+                sessions.push(Session::new(m));
+            }
+        }
+    }
+    sessions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Character, Player};
+
+    // A match at the given minute, the winner is the player with the first id
+    fn game(minute: i64, winner: i64, loser: i64) -> Match {
+        let player = |id| Player::test(id, Character::Sol);
+        Match::test(minute as u64, minute * 60, (player(winner), player(loser)))
+    }
+
+    #[test]
+    fn interleaved_opponents() {
+        let matches = [
+            game(0, 1, 2),
+            game(1, 3, 4),
+            game(4, 2, 1),
+            game(5, 4, 3),
+            game(8, 1, 2),
+            // 3 and 4 take a long break
+            game(30, 3, 4),
+        ];
+        let sessions = group(&matches);
+        assert_eq!(sessions.len(), 3);
+
+        assert_eq!(sessions[0].players, (1, 2));
+        assert_eq!(sessions[0].score, (2, 1));
+        assert_eq!(
+            sessions[0].matches.iter().map(|m| m.id).collect::<Vec<_>>(),
+            [0, 4, 8]
+        );
+        assert_eq!(sessions[0].start, matches[0].timestamp);
+        assert_eq!(sessions[0].end, matches[4].timestamp);
+
+        assert_eq!(sessions[1].players, (3, 4));
+        assert_eq!(sessions[1].score, (1, 1));
+        assert_eq!(sessions[2].players, (3, 4));
+        assert_eq!(sessions[2].matches.len(), 1);
+    }
+
+    #[test]
+    fn resuming_after_a_break() {
+        let matches = [
+            game(25, 1, 2),
+            game(0, 1, 2),
+            game(10, 1, 2),
+            game(11, 2, 1),
+        ];
+        let sessions = group(&matches);
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].score, (2, 1));
+        assert_eq!(sessions[1].start, matches[0].timestamp);
+
+        let sessions = group_with_gap(&matches, Duration::from_secs(15 * 60));
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].score, (3, 1));
+    }
+}
@@ -0,0 +1,155 @@
+//! Destinations for matches, see get_replays_into.
+
+use crate::{error::*, transport::BoxFuture, Match};
+
+/// Receives matches one at a time, e.g. to write them to a channel, a database or a file. The
+/// caller waits for accept to finish before continuing, so a slow sink slows down the requests
+/// instead of buffering matches. An error stops the caller and is returned from it.
+pub trait MatchSink: Send {
+    fn accept(&mut self, m: Match) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Sends every match over the channel, fails once the receiver was dropped
+impl MatchSink for tokio::sync::mpsc::Sender<Match> {
+    fn accept(&mut self, m: Match) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move { self.send(m).await.map_err(|_| Error::SinkClosed) })
+    }
+}
+
+/// Writes every match as a line of JSON
+#[cfg(feature = "serde")]
+pub struct JsonLinesSink<W> {
+    writer: W,
+}
+
+#[cfg(feature = "serde")]
+impl<W> JsonLinesSink<W>
+where
+    W: tokio::io::AsyncWrite + Unpin + Send,
+{
+    pub fn new(writer: W) -> Self {
+        JsonLinesSink { writer }
+    }
+
+    /// Flush and return the writer
+    pub async fn into_inner(mut self) -> Result<W> {
+        use tokio::io::AsyncWriteExt;
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<W> MatchSink for JsonLinesSink<W>
+where
+    W: tokio::io::AsyncWrite + Unpin + Send,
+{
+    fn accept(&mut self, m: Match) -> BoxFuture<'_, Result<()>> {
+        use tokio::io::AsyncWriteExt;
+        Box::pin(async move {
+            let mut line = serde_json::to_vec(&m).map_err(std::io::Error::from)?;
+            line.push(b'\n');
+            self.writer.write_all(&line).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MockTransport, RawResponse};
+    use crate::{get_replays, get_replays_into, Context, QueryParameters};
+    use std::sync::Arc;
+
+    fn fixture_transport() -> Arc<MockTransport> {
+        Arc::new(
+            MockTransport::new()
+                .page(
+                    0,
+                    RawResponse::ok(&include_bytes!("../test_data/replay_response_1.msgpack")[..]),
+                )
+                .page(
+                    1,
+                    RawResponse::ok(&include_bytes!("../test_data/replay_response_3.msgpack")[..]),
+                ),
+        )
+    }
+
+    #[tokio::test]
+    async fn channel_sink() {
+        let ctx = Context::default().transport(fixture_transport());
+        let (mut sender, mut receiver) = tokio::sync::mpsc::channel(1000);
+        let errors = get_replays_into(&ctx, 2, 127, QueryParameters::default(), &mut sender)
+            .await
+            .unwrap();
+        drop(sender);
+        assert!(errors.is_empty());
+
+        let mut received = vec![];
+        while let Some(m) = receiver.recv().await {
+            received.push(m);
+        }
+        let (collected, _) = get_replays(&ctx, 2, 127, QueryParameters::default())
+            .await
+            .unwrap()
+            .into_parts();
+        let mut sorted = received.clone();
+        sorted.sort();
+        assert_eq!(sorted, collected.collect::<Vec<_>>());
+
+        let (mut closed, receiver) = tokio::sync::mpsc::channel(1);
+        drop(receiver);
+        let result = get_replays_into(&ctx, 1, 127, QueryParameters::default(), &mut closed).await;
+        assert!(matches!(result, Err(Error::SinkClosed)));
+    }
+
+    // Records how many pages were requested when each match arrives
+    struct PageCounter(Arc<MockTransport>, Vec<usize>);
+
+    impl MatchSink for PageCounter {
+        fn accept(&mut self, _: Match) -> BoxFuture<'_, Result<()>> {
+            self.1.push(self.0.requests().len());
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn waits_for_sink_between_pages() {
+        let transport = fixture_transport();
+        let ctx = Context::default().transport(transport.clone());
+        let mut sink = PageCounter(transport, vec![]);
+        get_replays_into(&ctx, 2, 127, QueryParameters::default(), &mut sink)
+            .await
+            .unwrap();
+        let first_page = sink.1.iter().filter(|&&pages| pages == 1).count();
+        assert!(first_page > 0);
+        assert!(sink.1[first_page..].iter().all(|&pages| pages == 2));
+        assert!(sink.1.len() > first_page);
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn json_lines_sink() {
+        let ctx = Context::default().transport(fixture_transport());
+        let mut sink = JsonLinesSink::new(vec![]);
+        get_replays_into(&ctx, 2, 127, QueryParameters::default(), &mut sink)
+            .await
+            .unwrap();
+        let written = sink.into_inner().await.unwrap();
+        let lines = std::str::from_utf8(&written)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>();
+        let (collected, _) = get_replays(&ctx, 2, 127, QueryParameters::default())
+            .await
+            .unwrap()
+            .into_parts();
+        let mut decoded = lines
+            .iter()
+            .map(|line| serde_json::from_str::<Match>(line).unwrap())
+            .collect::<Vec<_>>();
+        decoded.sort();
+        assert_eq!(decoded, collected.collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,290 @@
+//! Compact, versioned archive format for matches.
+//!
+//! A snapshot is a sequence of messagepack values: a header with the format version, the number of
+//! matches and the character and floor code tables of the writing crate version, followed by one
+//! array per match. Characters and floors are stored as the codes used by the game, the tables map
+//! them to names so an archive stays readable even if the enums of this crate change. Reading
+//! fails if a code in the tables names something else than in this version of the crate, instead
+//! of silently remapping the matches.
+
+use crate::{error::*, Character, Floor, Match, MatchVenue, Player, Winner};
+use chrono::DateTime;
+use serde_crate::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Format version written by this crate, snapshots of this or an older version can be read.
+/// Version 2 added the venue code to every match as a byte, version 3 stores the code as sent by
+/// the server and nil for matches without a venue.
+pub const VERSION: u32 = 3;
+
+const CHARACTERS: [Character; 19] = [
+    Character::Sol,
+    Character::Ky,
+    Character::May,
+    Character::Axl,
+    Character::Chipp,
+    Character::Potemkin,
+    Character::Faust,
+    Character::Millia,
+    Character::Zato,
+    Character::Ramlethal,
+    Character::Leo,
+    Character::Nagoriyuki,
+    Character::Giovanna,
+    Character::Anji,
+    Character::Ino,
+    Character::Goldlewis,
+    Character::Jacko,
+    Character::HappyChaos,
+    Character::Baiken,
+];
+
+const FLOORS: [Floor; 11] = [
+    Floor::F1,
+    Floor::F2,
+    Floor::F3,
+    Floor::F4,
+    Floor::F5,
+    Floor::F6,
+    Floor::F7,
+    Floor::F8,
+    Floor::F9,
+    Floor::F10,
+    Floor::Celestial,
+];
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct Header {
+    version: u32,
+    matches: u64,
+    characters: Vec<(u8, String)>,
+    floors: Vec<(u8, String)>,
+}
+
+// Player id, character code and name
+type PlayerRecord = (i64, u8, String);
+
+fn character_table() -> Vec<(u8, String)> {
+    CHARACTERS
+        .iter()
+        .map(|c| (c.to_u8(), c.to_code().unwrap_or_default().to_string()))
+        .collect()
+}
+
+fn floor_table() -> Vec<(u8, String)> {
+    FLOORS
+        .iter()
+        .map(|f| (f.to_u8(), format!("{:?}", f)))
+        .collect()
+}
+
+// Codes of the snapshot must name the same character or floor as here. Codes only one side knows
+// are fine, the reader keeps unknown codes as they are.
+fn check_table(
+    field: &'static str,
+    written: &[(u8, String)],
+    known: &[(u8, String)],
+) -> Result<()> {
+    for (code, name) in written {
+        if let Some((_, known)) = known.iter().find(|(known, _)| known == code) {
+            if known != name {
+                return Err(Error::ParsingBytesError {
+                    field,
+                    value: format!("{} is {} in the snapshot but {} here", code, name, known),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+// Replay id, unix timestamp, floor code, winner byte and the players
+type MatchRecordV1 = (u64, i64, u8, u8, PlayerRecord, PlayerRecord);
+
+// A version 1 record followed by the venue code
+type MatchRecordV2 = (u64, i64, u8, u8, PlayerRecord, PlayerRecord, u8);
+
+type MatchRecord = (u64, i64, u8, u8, PlayerRecord, PlayerRecord, Option<i64>);
+
+/// Write the matches as a snapshot of the current format version
+pub fn write<'a, W, I>(matches: I, mut writer: W) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a Match>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let matches = matches.into_iter();
+    let header = Header {
+        version: VERSION,
+        matches: matches.len() as u64,
+        characters: character_table(),
+        floors: floor_table(),
+    };
+    rmp_serde::encode::write(&mut writer, &header)?;
+    for m in matches {
+        let player = |p: &Player| (p.id, p.character.to_u8(), p.name.to_string());
+        let record: MatchRecord = (
+            m.id,
+            m.timestamp.timestamp(),
+            m.floor.to_u8(),
+            m.winner.to_u8(),
+            player(&m.players.0),
+            player(&m.players.1),
+            m.venue.map(MatchVenue::code),
+        );
+        rmp_serde::encode::write(&mut writer, &record)?;
+    }
+    Ok(())
+}
+
+/// Read a snapshot written by this or an earlier version of the crate. Codes which are not known
+/// to this version become Character::Unknown and Floor::Unknown. Matches of version 1 snapshots
+/// didn't record the venue and are read without one. Version 2 snapshots stored codes which
+/// don't fit a byte as 255.
+pub fn read<R: Read>(mut reader: R) -> Result<Vec<Match>> {
+    let header: Header = rmp_serde::decode::from_read(&mut reader)?;
+    if header.version == 0 || header.version > VERSION {
+        return Err(Error::ParsingBytesError {
+            field: "snapshot version",
+            value: header.version.to_string(),
+        });
+    }
+    check_table(
+        "snapshot character table",
+        &header.characters,
+        &character_table(),
+    )?;
+    check_table("snapshot floor table", &header.floors, &floor_table())?;
+    (0..header.matches)
+        .map(|_| {
+            let (id, timestamp, floor, winner, p1, p2, venue): MatchRecord = match header.version {
+                1 => {
+                    let (id, timestamp, floor, winner, p1, p2): MatchRecordV1 =
+                        rmp_serde::decode::from_read(&mut reader)?;
+                    (id, timestamp, floor, winner, p1, p2, None)
+                }
+                2 => {
+                    let (id, timestamp, floor, winner, p1, p2, venue): MatchRecordV2 =
+                        rmp_serde::decode::from_read(&mut reader)?;
+                    (id, timestamp, floor, winner, p1, p2, Some(venue.into()))
+                }
+                _ => rmp_serde::decode::from_read(&mut reader)?,
+            };
+            let player = |(id, character, name): PlayerRecord| Player {
+                id,
+                character: Character::from_u8_lossy(character),
+                name: name.into(),
+            };
+            Ok(Match {
+                timestamp: DateTime::from_timestamp(timestamp, 0).ok_or_else(|| {
+                    Error::ParsingBytesError {
+                        field: "timestamp",
+                        value: timestamp.to_string(),
+                    }
+                })?,
+                floor: Floor::from_u8_lossy(floor),
+                players: (player(p1), player(p2)),
+                winner: Winner::from_u8(winner),
+                id,
+                venue: venue.map(MatchVenue),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_replay_response, DecodeMode};
+
+    const SNAPSHOT_V1: &[u8] = include_bytes!("../test_data/snapshot_v1.msgpack");
+
+    fn fixture_matches() -> Vec<Match> {
+        let replays = decode_replay_response(
+            include_bytes!("../test_data/replay_response_2.msgpack"),
+            DecodeMode::Lenient,
+        )
+        .unwrap();
+        replays.matches().cloned().collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut matches = fixture_matches();
+        matches[0].players.0.character = Character::Unknown(0x40);
+        matches[0].floor = Floor::Unknown(12);
+        matches[1].venue = None;
+        matches[2].venue = Some(MatchVenue(1 << 40));
+        let mut bytes = vec![];
+        write(&matches, &mut bytes).unwrap();
+        assert_eq!(read(&bytes[..]).unwrap(), matches);
+    }
+
+    // The checked-in snapshot was written by the first version of the format, it has to stay
+    // readable by every later version
+    #[test]
+    fn read_v1() {
+        let matches = read(SNAPSHOT_V1).unwrap();
+        let without_venue = fixture_matches()
+            .into_iter()
+            .map(|m| Match { venue: None, ..m })
+            .collect::<Vec<_>>();
+        assert_eq!(matches, without_venue);
+        let names = matches
+            .iter()
+            .map(|m| m.players.0.name.clone())
+            .collect::<Vec<_>>();
+        assert!(names.iter().all(|name| !name.is_empty()));
+    }
+
+    #[test]
+    fn remapped_codes() {
+        let mut characters = character_table();
+        characters[0].1 = "KYK".into();
+        characters[1].1 = "SOL".into();
+        let header = Header {
+            version: VERSION,
+            matches: 0,
+            characters,
+            floors: floor_table(),
+        };
+        let bytes = rmp_serde::encode::to_vec(&header).unwrap();
+        match read(&bytes[..]) {
+            Err(Error::ParsingBytesError { field, value }) => {
+                assert_eq!(field, "snapshot character table");
+                assert_eq!(value, "0 is KYK in the snapshot but SOL here");
+            }
+            other => panic!("expected a table mismatch, got {:?}", other),
+        }
+
+        // A code this version doesn't know is not a mismatch
+        let mut floors = floor_table();
+        floors.push((12, "F12".into()));
+        let header = Header {
+            floors,
+            characters: character_table(),
+            ..header
+        };
+        let bytes = rmp_serde::encode::to_vec(&header).unwrap();
+        assert_eq!(read(&bytes[..]).unwrap(), []);
+    }
+
+    #[test]
+    fn future_version() {
+        let header = Header {
+            version: VERSION + 1,
+            matches: 0,
+            characters: vec![],
+            floors: vec![],
+        };
+        let bytes = rmp_serde::encode::to_vec(&header).unwrap();
+        assert!(matches!(
+            read(&bytes[..]),
+            Err(Error::ParsingBytesError {
+                field: "snapshot version",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+//! Win streaks of a single player, comparable to the win chains reported by the statistics
+//! endpoint (see model::user::User::win_chain and model::character::Stats::win_chain).
+
+use crate::{Character, Match};
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde_crate::{Deserialize, Serialize};
+
+/// Consecutive wins, current is reset by a loss
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Streak {
+    pub current: u64,
+    pub longest: u64,
+}
+
+impl Streak {
+    fn record(&mut self, won: bool) {
+        if won {
+            self.current += 1;
+            self.longest = self.longest.max(self.current);
+        } else {
+            self.current = 0;
+        }
+    }
+}
+
+/// Streaks over all matches of a player and over the matches played with each character
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct StreakReport {
+    pub overall: Streak,
+    /// Serialized as a list of [character, streak] pairs
+    #[cfg_attr(feature = "serde", serde(with = "crate::pairs"))]
+    pub per_character: BTreeMap<Character, Streak>,
+}
+
+/// Compute the streaks of the player from matches in chronological order. The player may be on
+/// either side, matches without them are ignored. A character's streak only counts matches played
+/// with that character, like the per character win chain of the game.
+pub fn compute<'a, I>(player_id: i64, matches: I) -> StreakReport
+where
+    I: IntoIterator<Item = &'a Match>,
+{
+    let mut report = StreakReport::default();
+    for m in matches {
+        let (p1, p2) = m.players();
+        let character = if p1.id == player_id {
+            p1.character
+        } else if p2.id == player_id {
+            p2.character
+        } else {
+            continue;
+        };
+        let won = match m.try_winner() {
+            Some(winner) => winner.id == player_id,
+            None => continue,
+        };
+        report.overall.record(won);
+        report
+            .per_character
+            .entry(character)
+            .or_default()
+            .record(won);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Player, Winner};
+
+    const ME: i64 = 1;
+
+    // A match against player 2, sides alternate with every match
+    fn game(i: u64, character: Character, won: bool) -> Match {
+        let me = Player {
+            name: "me".into(),
+            ..Player::test(ME, character)
+        };
+        let opponent = Player {
+            name: "opponent".into(),
+            ..Player::test(2, Character::Ky)
+        };
+        let m = Match {
+            winner: if won {
+                Winner::Player1
+            } else {
+                Winner::Player2
+            },
+            ..Match::test(i, i as i64 * 60, (me, opponent))
+        };
+        if i % 2 == 1 {
+            m.swapped()
+        } else {
+            m
+        }
+    }
+
+    #[test]
+    fn alternating_sides() {
+        use Character::*;
+        let results = [
+            (Sol, true),
+            (Sol, true),
+            (May, true),
+            (Sol, false),
+            (May, true),
+            (Sol, true),
+            (Sol, true),
+            (Sol, true),
+            (May, false),
+        ];
+        let mut matches = results
+            .iter()
+            .enumerate()
+            .map(|(i, &(character, won))| game(i as u64, character, won))
+            .collect::<Vec<_>>();
+        // Matches of other players don't affect the streaks
+        let mut unrelated = game(100, Sol, true);
+        unrelated.players.0.id = 3;
+        unrelated.players.1.id = 4;
+        matches.insert(2, unrelated);
+
+        let report = compute(ME, &matches);
+        assert_eq!(
+            report.overall,
+            Streak {
+                current: 0,
+                longest: 4
+            }
+        );
+        assert_eq!(
+            report.per_character[&Sol],
+            Streak {
+                current: 3,
+                longest: 3
+            }
+        );
+        assert_eq!(
+            report.per_character[&May],
+            Streak {
+                current: 0,
+                longest: 2
+            }
+        );
+        assert_eq!(report.per_character.len(), 2);
+    }
+
+    #[test]
+    fn no_matches() {
+        let report = compute(ME, &[]);
+        assert_eq!(report, StreakReport::default());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_with_unknown_character() {
+        let matches = [
+            game(0, Character::Unknown(0x13), true),
+            game(1, Character::Sol, false),
+        ];
+        let report = compute(ME, &matches);
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<StreakReport>(&json).unwrap(), report);
+    }
+}
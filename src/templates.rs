@@ -0,0 +1,457 @@
+//! The requests this crate sends, for clients in other languages to reproduce.
+//!
+//! Every template holds the hex encoded messagepack that is posted as the data field of a form and
+//! a JSON description of its layout. Both are generated from the structs real requests are sent
+//! with, so they follow any change to them. Structs are encoded as arrays, the layout names each
+//! element after its field:
+//!
+//! ```json
+//! {"type": "array", "elements": [{"name": "header", "type": "array", "elements": [...]}, ...]}
+//! ```
+//!
+//! Leaves have a type of int, str, bin, nil, bool or float and their value. Elements without a
+//! name belong to a list.
+
+use crate::protocol::{encode_request, request_header};
+use crate::requests::{endpoints, messagepack, Credentials, Endpoint};
+use crate::QueryParameters;
+use serde_crate::ser::{self, Serialize};
+use serde_json::{json, Value};
+
+/// The values filled into the templates
+#[derive(Debug, Clone)]
+pub struct Placeholders {
+    /// The player the requests are sent as, None for the fixed player this crate sends as
+    pub credentials: Option<Credentials>,
+    /// Sent in the header unless credentials are set
+    pub nonce: messagepack::Nonce,
+    /// Index of the requested replay page
+    pub page: usize,
+    pub replays_per_page: usize,
+    pub query: messagepack::RequestQuery,
+    /// Player whose statistics are requested
+    pub player_id: i64,
+}
+
+impl Default for Placeholders {
+    fn default() -> Self {
+        Placeholders {
+            credentials: None,
+            nonce: messagepack::Nonce::from("61a5ed4f461c2"),
+            page: 0,
+            replays_per_page: 127,
+            query: messagepack::RequestQuery::from(&QueryParameters::default()),
+            player_id: 210611073056107537,
+        }
+    }
+}
+
+/// A request of one endpoint, see the module documentation
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestTemplate {
+    /// Path of the endpoint the request is posted to
+    pub path: &'static str,
+    pub hex: String,
+    pub layout: Value,
+}
+
+impl RequestTemplate {
+    fn new<E: Endpoint>(placeholders: &Placeholders, body: E::Request) -> Self {
+        let request = messagepack::Request {
+            header: request_header(
+                placeholders.credentials.as_ref(),
+                placeholders.nonce.clone(),
+            ),
+            body,
+        };
+        let names = request
+            .serialize(NameCollector)
+            .expect("requests serialize without errors");
+        let hex = encode_request(request.header, request.body);
+        let bytes = crate::hex::decode(&hex).expect("encoded requests are valid hex");
+        let value = rmpv::decode::read_value(&mut &bytes[..])
+            .expect("encoded requests are valid messagepack");
+        RequestTemplate {
+            path: E::PATH,
+            layout: describe(&value, &names),
+            hex,
+        }
+    }
+
+    /// The template as an object with the path, hex and layout
+    pub fn to_json(&self) -> Value {
+        json!({
+            "path": self.path,
+            "hex": self.hex,
+            "layout": self.layout,
+        })
+    }
+}
+
+/// The requests of every endpoint this crate supports: a page of replays, the vip ranking and the
+/// statistics get_statistics reads
+pub fn templates(placeholders: &Placeholders) -> Vec<RequestTemplate> {
+    vec![
+        RequestTemplate::new::<endpoints::Replays>(
+            placeholders,
+            messagepack::RequestBody::page(
+                placeholders.page,
+                placeholders.replays_per_page,
+                placeholders.query.clone(),
+            ),
+        ),
+        RequestTemplate::new::<endpoints::Vip>(placeholders, messagepack::VipRequest::first_page()),
+        RequestTemplate::new::<endpoints::Statistics>(
+            placeholders,
+            messagepack::StatisticsRequest::profile(placeholders.player_id),
+        ),
+    ]
+}
+
+// Combine the encoded value with the field names of the struct it was encoded from
+fn describe(value: &rmpv::Value, names: &Names) -> Value {
+    match value {
+        rmpv::Value::Array(elements) => {
+            let elements = elements
+                .iter()
+                .enumerate()
+                .map(|(i, element)| match names {
+                    Names::Fields(fields) if i < fields.len() => {
+                        let mut described = describe(element, &fields[i].1);
+                        described["name"] = fields[i].0.into();
+                        described
+                    }
+                    Names::Items(items) if i < items.len() => describe(element, &items[i]),
+                    _ => describe(element, &Names::Leaf),
+                })
+                .collect::<Vec<_>>();
+            json!({"type": "array", "elements": elements})
+        }
+        rmpv::Value::Nil => json!({"type": "nil"}),
+        rmpv::Value::Boolean(b) => json!({"type": "bool", "value": b}),
+        rmpv::Value::Integer(i) => {
+            let value = i
+                .as_i64()
+                .map_or_else(|| Value::from(i.as_u64()), Value::from);
+            json!({"type": "int", "value": value})
+        }
+        rmpv::Value::F32(f) => json!({"type": "float", "value": f}),
+        rmpv::Value::F64(f) => json!({"type": "float", "value": f}),
+        rmpv::Value::String(s) => json!({"type": "str", "value": s.as_str()}),
+        rmpv::Value::Binary(b) => json!({"type": "bin", "value": crate::hex::encode(b)}),
+        other => json!({"type": "other", "value": other.to_string()}),
+    }
+}
+
+// The names of the fields of a serialized value, nested like the value
+enum Names {
+    Leaf,
+    Fields(Vec<(&'static str, Names)>),
+    Items(Vec<Names>),
+}
+
+// A serializer that only records the structure and the field names of a value
+struct NameCollector;
+
+struct Collect {
+    fields: Vec<(&'static str, Names)>,
+    items: Vec<Names>,
+}
+
+impl Collect {
+    fn new() -> Self {
+        Collect {
+            fields: vec![],
+            items: vec![],
+        }
+    }
+
+    fn item<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), serde_json::Error> {
+        self.items.push(value.serialize(NameCollector)?);
+        Ok(())
+    }
+
+    fn field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), serde_json::Error> {
+        self.fields.push((name, value.serialize(NameCollector)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Names, serde_json::Error> {
+        Ok(if self.fields.is_empty() {
+            Names::Items(self.items)
+        } else {
+            Names::Fields(self.fields)
+        })
+    }
+}
+
+macro_rules! leaves {
+    ($($method:ident: $ty:ty),*) => {
+        $(fn $method(self, _: $ty) -> Result<Names, serde_json::Error> {
+            Ok(Names::Leaf)
+        })*
+    };
+}
+
+impl ser::Serializer for NameCollector {
+    type Ok = Names;
+    type Error = serde_json::Error;
+    type SerializeSeq = Collect;
+    type SerializeTuple = Collect;
+    type SerializeTupleStruct = Collect;
+    type SerializeTupleVariant = Collect;
+    type SerializeMap = Collect;
+    type SerializeStruct = Collect;
+    type SerializeStructVariant = Collect;
+
+    leaves!(
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+        serialize_bytes: &[u8],
+        serialize_unit_struct: &'static str
+    );
+
+    fn serialize_none(self) -> Result<Names, serde_json::Error> {
+        Ok(Names::Leaf)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Names, serde_json::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Names, serde_json::Error> {
+        Ok(Names::Leaf)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<Names, serde_json::Error> {
+        Ok(Names::Leaf)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<Names, serde_json::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Names, serde_json::Error> {
+        Ok(Names::Leaf)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Collect, serde_json::Error> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Collect, serde_json::Error> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Collect, serde_json::Error> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Collect, serde_json::Error> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Collect, serde_json::Error> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Collect, serde_json::Error> {
+        Ok(Collect::new())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Collect, serde_json::Error> {
+        Ok(Collect::new())
+    }
+}
+
+impl ser::SerializeSeq for Collect {
+    type Ok = Names;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Names, Self::Error> {
+        Collect::end(self)
+    }
+}
+
+impl ser::SerializeTuple for Collect {
+    type Ok = Names;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Names, Self::Error> {
+        Collect::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Collect {
+    type Ok = Names;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Names, Self::Error> {
+        Collect::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for Collect {
+    type Ok = Names;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Names, Self::Error> {
+        Collect::end(self)
+    }
+}
+
+// Maps aren't arrays in messagepack, their entries are left unnamed
+impl ser::SerializeMap for Collect {
+    type Ok = Names;
+    type Error = serde_json::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, _: &T) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, _: &T) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn end(self) -> Result<Names, Self::Error> {
+        Ok(Names::Leaf)
+    }
+}
+
+impl ser::SerializeStruct for Collect {
+    type Ok = Names;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.field(name, value)
+    }
+
+    fn end(self) -> Result<Names, Self::Error> {
+        Collect::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Collect {
+    type Ok = Names;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.field(name, value)
+    }
+
+    fn end(self) -> Result<Names, Self::Error> {
+        Collect::end(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn golden_templates() {
+        let templates = templates(&Placeholders::default())
+            .iter()
+            .map(RequestTemplate::to_json)
+            .collect::<Vec<_>>();
+        expect_test::expect_file!["../test_data/templates.json"]
+            .assert_eq(&(serde_json::to_string_pretty(&templates).unwrap() + "\n"));
+    }
+
+    #[test]
+    fn placeholders_resolved() {
+        let placeholders = Placeholders {
+            credentials: Some(Credentials {
+                player_id: "210611073056107537".into(),
+                token: "61ff96a1e7b59".into(),
+            }),
+            page: 3,
+            replays_per_page: 10,
+            player_id: 42,
+            ..Placeholders::default()
+        };
+        let templates = templates(&placeholders);
+        let replays = messagepack::ReplayRequest::from_hex(&templates[0].hex).unwrap();
+        assert_eq!(replays.header.player_id, "210611073056107537");
+        assert_eq!(replays.header.string2.as_str(), "61ff96a1e7b59");
+        assert_eq!((replays.body.index, replays.body.replays_per_page), (3, 10));
+        let statistics =
+            messagepack::Request::<messagepack::StatisticsRequest>::from_hex(&templates[2].hex)
+                .unwrap();
+        assert_eq!(statistics.body.id, "42");
+        assert_eq!(
+            templates[2].layout["elements"][1]["elements"][0],
+            json!({"name": "id", "type": "str", "value": "42"})
+        );
+    }
+}
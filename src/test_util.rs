@@ -0,0 +1,123 @@
+//! Realistic values for unit tests of crates built on top of this one, so they don't have to hit
+//! the API. Only available with the test-util feature.
+
+use crate::*;
+use std::collections::BTreeSet;
+
+const FIXTURES: [&[u8]; 4] = [
+    include_bytes!("../test_data/replay_response_1.msgpack"),
+    include_bytes!("../test_data/replay_response_2.msgpack"),
+    include_bytes!("../test_data/replay_response_3.msgpack"),
+    include_bytes!("../test_data/replay_response_4.msgpack"),
+];
+
+const FLOORS: [Floor; 11] = [
+    Floor::F1,
+    Floor::F2,
+    Floor::F3,
+    Floor::F4,
+    Floor::F5,
+    Floor::F6,
+    Floor::F7,
+    Floor::F8,
+    Floor::F9,
+    Floor::F10,
+    Floor::Celestial,
+];
+
+/// Two players with fixed ids, names and characters
+pub fn sample_players() -> (Player, Player) {
+    (
+        Player {
+            id: 210611071253598570,
+            character: Character::Sol,
+            name: "Sample Sol".into(),
+        },
+        Player {
+            id: 210611071253598571,
+            character: Character::Ky,
+            name: "Sample Ky".into(),
+        },
+    )
+}
+
+/// A match between the sample_players on the celestial floor won by player 1
+pub fn sample_match() -> Match {
+    Match {
+        timestamp: Utc.with_ymd_and_hms(2021, 12, 1, 12, 0, 0).unwrap(),
+        floor: Floor::Celestial,
+        players: sample_players(),
+        winner: Winner::Player1,
+        id: 220205230800597951,
+        venue: Some(MatchVenue(0)),
+    }
+}
+
+/// Generate n distinct matches. Characters, floors, players and winners vary, timestamps are
+/// strictly increasing starting at the timestamp of sample_match. The output only depends on n.
+pub fn matches(n: usize) -> Vec<Match> {
+    let start = *sample_match().timestamp();
+    (0..n)
+        .map(|i| {
+            let player = |id: usize, character: usize| Player {
+                id: 210611071253598000 + id as i64,
+                character: Character::from_u8_lossy((character % 19) as u8),
+                name: format!("Player {}", id).into(),
+            };
+            Match {
+                timestamp: start + chrono::Duration::seconds(97 * i as i64),
+                floor: FLOORS[i * 7 % FLOORS.len()],
+                // Even ids on one side and odd on the other so nobody plays against themselves
+                players: (
+                    player(i * 6 % 40, i * 5),
+                    player(i * 14 % 40 + 1, i * 11 + 3),
+                ),
+                winner: if i * (i + 1) % 3 == 0 {
+                    Winner::Player2
+                } else {
+                    Winner::Player1
+                },
+                id: 220205230800000000 + i as u64,
+                venue: Some(MatchVenue(0)),
+            }
+        })
+        .collect()
+}
+
+/// The matches contained in the replay responses shipped with this crate, deduplicated and sorted
+/// the same way as in a ReplaySet
+pub fn fixture_matches() -> Vec<Match> {
+    let mut matches = BTreeSet::new();
+    for fixture in FIXTURES {
+        let (replays, _) = decode_replay_response(fixture, DecodeOptions::default())
+            .expect("fixtures are valid replay responses")
+            .into_parts();
+        matches.extend(replays);
+    }
+    matches.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_matches() {
+        let generated = matches(100);
+        assert_eq!(generated, matches(100));
+        assert_eq!(generated.iter().collect::<BTreeSet<_>>().len(), 100);
+        assert!(generated
+            .windows(2)
+            .all(|w| w[0].timestamp < w[1].timestamp));
+        assert!(generated.iter().all(|m| m.players.0 != m.players.1));
+        assert_eq!(
+            generated
+                .iter()
+                .map(|m| m.floor)
+                .collect::<BTreeSet<_>>()
+                .len(),
+            FLOORS.len()
+        );
+        assert_eq!(fixture_matches().len(), 60);
+    }
+}
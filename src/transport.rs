@@ -0,0 +1,571 @@
+//! How requests reach the API, see Context::transport.
+
+use crate::{error::*, requests::endpoints, Endpoint};
+use bytes::Bytes;
+use reqwest::header::{self, HeaderMap};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A response as it was received, before it is checked for error pages, error statuses or
+/// throttling
+#[derive(Debug, Clone)]
+pub struct RawResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// How long reading the body took once the headers had arrived, measured by HttpTransport
+    /// and MiddlewareTransport. None if the transport had the body at once.
+    pub body_time: Option<Duration>,
+}
+
+impl RawResponse {
+    pub fn new(status: u16, body: impl Into<Bytes>) -> Self {
+        RawResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: body.into(),
+            body_time: None,
+        }
+    }
+
+    /// A successful response with the given body
+    pub fn ok(body: impl Into<Bytes>) -> Self {
+        RawResponse::new(200, body)
+    }
+}
+
+/// Delivers requests to the API. Context uses HttpTransport unless another transport is set,
+/// MockTransport answers with canned responses without touching the network.
+pub trait Transport: Send + Sync {
+    /// Post the hex encoded request as the data field of a form to the endpoint at path
+    fn post<'a>(&'a self, path: &'static str, data: &'a str) -> BoxFuture<'a, Result<RawResponse>>;
+}
+
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn post<'a>(&'a self, path: &'static str, data: &'a str) -> BoxFuture<'a, Result<RawResponse>> {
+        (**self).post(path, data)
+    }
+}
+
+/// The User-Agent header of HttpTransport and MiddlewareTransport. The game client sends "Steam".
+/// A user agent has to start with "Steam", optionally followed by a space and more, so that a
+/// server or proxy matching on the prefix still sees the game's string. This rule is a precaution
+/// and not verified: the live_user_agent test in this module fails if the server disagrees with
+/// it, but it has not been run against the server yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserAgent(header::HeaderValue);
+
+impl UserAgent {
+    /// The user agent of the game client, the default
+    pub const STEAM: &'static str = "Steam";
+
+    /// Fails with Error::InvalidArgument if value doesn't start with "Steam" or isn't a valid
+    /// header value
+    pub fn new(value: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidArgument {
+            parameter: "user_agent",
+            value: value.to_string(),
+            reason: format!("user agent {:?} {}", value, reason),
+        };
+        let suffix = value
+            .strip_prefix(UserAgent::STEAM)
+            .ok_or_else(|| invalid("must start with Steam"))?;
+        if !suffix.is_empty() && !suffix.starts_with(' ') {
+            return Err(invalid("must separate additions from Steam with a space"));
+        }
+        let value = header::HeaderValue::from_str(value)
+            .map_err(|_| invalid("is not a valid header value"))?;
+        Ok(UserAgent(value))
+    }
+
+    /// "Steam ggst-api-rs/<version> (+contact)", so the operators of the server can tell the
+    /// requests of a bot apart and reach whoever runs it, e.g. with a url or mail address
+    pub fn identify(contact: &str) -> Result<Self> {
+        if contact.contains(['(', ')']) {
+            return Err(Error::InvalidArgument {
+                parameter: "contact",
+                value: contact.to_string(),
+                reason: format!("contact {:?} must not contain parentheses", contact),
+            });
+        }
+        UserAgent::new(&format!(
+            "{} ggst-api-rs/{} (+{})",
+            UserAgent::STEAM,
+            env!("CARGO_PKG_VERSION"),
+            contact
+        ))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only constructed from a &str
+        self.0.to_str().unwrap_or_default()
+    }
+}
+
+impl Default for UserAgent {
+    fn default() -> Self {
+        UserAgent(header::HeaderValue::from_static(UserAgent::STEAM))
+    }
+}
+
+/// Sends requests over HTTP with the headers the game client uses
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    client: reqwest::Client,
+    base_url: String,
+    user_agent: UserAgent,
+}
+
+impl HttpTransport {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        HttpTransport::with_client(reqwest::Client::new(), base_url)
+    }
+
+    /// Use a preconfigured client, e.g. with a proxy or timeouts
+    pub fn with_client(client: reqwest::Client, base_url: impl Into<String>) -> Self {
+        HttpTransport {
+            client,
+            base_url: base_url.into(),
+            user_agent: UserAgent::default(),
+        }
+    }
+
+    /// Send user_agent instead of the one of the game client
+    pub fn user_agent(self, user_agent: UserAgent) -> Self {
+        HttpTransport { user_agent, ..self }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn post<'a>(&'a self, path: &'static str, data: &'a str) -> BoxFuture<'a, Result<RawResponse>> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .post(self.base_url.clone() + path)
+                .header(header::USER_AGENT, self.user_agent.0.clone())
+                .header(header::CACHE_CONTROL, "no-cache")
+                .form(&[("data", data)])
+                .send()
+                .await?;
+            raw_response(response).await
+        })
+    }
+}
+
+/// Sends requests through a client with middleware, e.g. for tracing, retries or authentication.
+/// Uses the same headers as HttpTransport.
+#[cfg(feature = "middleware")]
+#[derive(Debug, Clone)]
+pub struct MiddlewareTransport {
+    client: reqwest_middleware::ClientWithMiddleware,
+    base_url: String,
+    user_agent: UserAgent,
+}
+
+#[cfg(feature = "middleware")]
+impl MiddlewareTransport {
+    pub fn new(
+        client: reqwest_middleware::ClientWithMiddleware,
+        base_url: impl Into<String>,
+    ) -> Self {
+        MiddlewareTransport {
+            client,
+            base_url: base_url.into(),
+            user_agent: UserAgent::default(),
+        }
+    }
+
+    /// Send user_agent instead of the one of the game client
+    pub fn user_agent(self, user_agent: UserAgent) -> Self {
+        MiddlewareTransport { user_agent, ..self }
+    }
+}
+
+#[cfg(feature = "middleware")]
+impl Transport for MiddlewareTransport {
+    fn post<'a>(&'a self, path: &'static str, data: &'a str) -> BoxFuture<'a, Result<RawResponse>> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .post(self.base_url.clone() + path)
+                .header(header::USER_AGENT, self.user_agent.0.clone())
+                .header(header::CACHE_CONTROL, "no-cache")
+                .form(&[("data", data)])
+                .send()
+                .await
+                .map_err(|e| match e {
+                    reqwest_middleware::Error::Reqwest(e) => Error::ReqwestError(e),
+                    reqwest_middleware::Error::Middleware(e) => Error::Transport(e.into()),
+                })?;
+            raw_response(response).await
+        })
+    }
+}
+
+async fn raw_response(response: reqwest::Response) -> Result<RawResponse> {
+    let status = response.status().as_u16();
+    let headers = response.headers().clone();
+    let started = Instant::now();
+    let body = response.bytes().await?;
+    Ok(RawResponse {
+        status,
+        headers,
+        body,
+        body_time: Some(started.elapsed()),
+    })
+}
+
+/// Answers requests with canned responses, for tests and for working with captured responses
+/// offline. Replay queries are answered with the response registered for the requested page,
+/// other endpoints with the response registered for their path. Requests without a response get
+/// an empty 404.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    pages: BTreeMap<usize, RawResponse>,
+    responses: BTreeMap<&'static str, RawResponse>,
+    requests: Mutex<Vec<(&'static str, String)>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer the replay query for the page index with response
+    pub fn page(mut self, index: usize, response: RawResponse) -> Self {
+        self.pages.insert(index, response);
+        self
+    }
+
+    /// Answer every request to the endpoint at path with response
+    pub fn response(mut self, path: &'static str, response: RawResponse) -> Self {
+        self.responses.insert(path, response);
+        self
+    }
+
+    /// Path and hex encoded data of every request received so far
+    pub fn requests(&self) -> Vec<(&'static str, String)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn respond(&self, path: &'static str, data: &str) -> RawResponse {
+        let page = if path == endpoints::Replays::PATH {
+            crate::messagepack::Request::<crate::messagepack::RequestBody>::from_hex(data)
+                .ok()
+                .and_then(|request| self.pages.get(&request.body.index))
+        } else {
+            None
+        };
+        page.or_else(|| self.responses.get(path))
+            .cloned()
+            .unwrap_or_else(|| RawResponse::new(404, Bytes::new()))
+    }
+}
+
+impl Transport for MockTransport {
+    fn post<'a>(&'a self, path: &'static str, data: &'a str) -> BoxFuture<'a, Result<RawResponse>> {
+        self.requests.lock().unwrap().push((path, data.to_string()));
+        Box::pin(std::future::ready(Ok(self.respond(path, data))))
+    }
+}
+
+/// Passes requests through to another transport, usually HttpTransport, and writes every exchange
+/// to a directory so it can be served again by ReplayTransport. Each request is stored as
+/// `<hash>.request` with the path and hex data, `<hash>.status` and `<hash>.response` with the raw
+/// body, which can be used as a fixture as is. Response headers are not recorded. Requests carry a
+/// fresh nonce, use Context::fixed_nonce when recording and replaying so the hashes match.
+#[cfg(any(test, feature = "test-util"))]
+pub struct RecordingTransport<T> {
+    inner: T,
+    dir: std::path::PathBuf,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl<T: Transport> RecordingTransport<T> {
+    pub fn new(inner: T, dir: impl Into<std::path::PathBuf>) -> Self {
+        RecordingTransport {
+            inner,
+            dir: dir.into(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn post<'a>(&'a self, path: &'static str, data: &'a str) -> BoxFuture<'a, Result<RawResponse>> {
+        Box::pin(async move {
+            let response = self.inner.post(path, data).await?;
+            let file = self.dir.join(request_hash(path, data));
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(
+                file.with_extension("request"),
+                format!("{}\n{}\n", path, data),
+            )
+            .await?;
+            tokio::fs::write(file.with_extension("status"), response.status.to_string()).await?;
+            tokio::fs::write(file.with_extension("response"), &response.body).await?;
+            Ok(response)
+        })
+    }
+}
+
+/// Serves the exchanges written by RecordingTransport, looked up by the hash of path and request
+/// data. Requests that were not recorded get an empty 404.
+#[cfg(any(test, feature = "test-util"))]
+pub struct ReplayTransport {
+    dir: std::path::PathBuf,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl ReplayTransport {
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> Self {
+        ReplayTransport { dir: dir.into() }
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Transport for ReplayTransport {
+    fn post<'a>(&'a self, path: &'static str, data: &'a str) -> BoxFuture<'a, Result<RawResponse>> {
+        Box::pin(async move {
+            let file = self.dir.join(request_hash(path, data));
+            let body = match tokio::fs::read(file.with_extension("response")).await {
+                Ok(body) => body,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(RawResponse::new(404, Bytes::new()))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let status = tokio::fs::read_to_string(file.with_extension("status")).await?;
+            let status = status
+                .trim()
+                .parse()
+                .map_err(|_| Error::ParsingBytesError {
+                    field: "status",
+                    value: status.clone(),
+                })?;
+            Ok(RawResponse::new(status, body))
+        })
+    }
+}
+
+// FNV-1a, stable across platforms and compiler versions unlike the std hasher
+#[cfg(any(test, feature = "test-util"))]
+fn request_hash(path: &str, data: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in path.bytes().chain([0]).chain(data.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, QueryParameters};
+
+    #[tokio::test]
+    async fn missing_page() {
+        let transport = Arc::new(MockTransport::new());
+        let ctx = Context::default().transport(transport.clone());
+        let result = crate::get_replays(&ctx, 1, 10, QueryParameters::default()).await;
+        assert!(matches!(result, Err(Error::HttpStatus { status: 404, .. })));
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, endpoints::Replays::PATH);
+    }
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let dir = std::env::temp_dir().join(format!("ggst-api-recording-{}", std::process::id()));
+        let fixture = include_bytes!("../test_data/replay_response_2.msgpack");
+        let mock = MockTransport::new().page(0, RawResponse::ok(fixture.to_vec()));
+        let ctx = Context::default()
+            .fixed_nonce("61a5ed4f461c2".into())
+            .transport(RecordingTransport::new(mock, &dir));
+        let recorded = crate::get_replays(&ctx, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+
+        let ctx = Context::default()
+            .fixed_nonce("61a5ed4f461c2".into())
+            .transport(ReplayTransport::new(&dir));
+        let replayed = crate::get_replays(&ctx, 1, 10, QueryParameters::default())
+            .await
+            .unwrap();
+        let unrecorded = crate::get_replays(&ctx, 2, 10, QueryParameters::default()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Ids, players and winners, names are compared separately
+        assert_eq!(recorded.matches().count(), 10);
+        assert!(recorded.matches().eq(replayed.matches()));
+        assert!(recorded.matches().zip(replayed.matches()).all(|(a, b)| a
+            .players
+            .0
+            .eq_strict(&b.players.0)
+            && a.players.1.eq_strict(&b.players.1)));
+        assert_eq!(recorded.metadata(), replayed.metadata());
+        assert_eq!(
+            recorded
+                .errors()
+                .iter()
+                .map(|e| e.raw_bytes())
+                .collect::<Vec<_>>(),
+            replayed
+                .errors()
+                .iter()
+                .map(|e| e.raw_bytes())
+                .collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            unrecorded,
+            Err(Error::HttpStatus { status: 404, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn http_headers() {
+        use crate::mock_server::{MockResponse, MockServer};
+        let server = MockServer::start(vec![MockResponse::new(404, vec![])]).await;
+        let transport = HttpTransport::new(server.url());
+        let response = transport.post("/api/test", "9201").await.unwrap();
+        assert_eq!(response.status, 404);
+
+        let heads = server.request_heads();
+        assert!(heads[0].starts_with("post /api/test "), "{}", heads[0]);
+        assert!(heads[0].contains("user-agent: steam"), "{}", heads[0]);
+        assert!(heads[0].contains("cache-control: no-cache"), "{}", heads[0]);
+    }
+
+    #[test]
+    fn user_agent_validation() {
+        assert_eq!(UserAgent::default().as_str(), "Steam");
+        assert_eq!(
+            UserAgent::new("Steam mybot/1.0").unwrap().as_str(),
+            "Steam mybot/1.0"
+        );
+        let identified = UserAgent::identify("https://example.com").unwrap();
+        assert_eq!(
+            identified.as_str(),
+            format!(
+                "Steam ggst-api-rs/{} (+https://example.com)",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        for invalid in ["", "curl/8.0", "steam", "SteamBot", "Steam\nX-Injected: 1"] {
+            assert!(
+                matches!(
+                    UserAgent::new(invalid),
+                    Err(Error::InvalidArgument {
+                        parameter: "user_agent",
+                        ..
+                    })
+                ),
+                "{:?}",
+                invalid
+            );
+        }
+        assert!(UserAgent::identify("me (at) example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn custom_user_agent() {
+        use crate::mock_server::{MockResponse, MockServer};
+        let server = MockServer::start(vec![MockResponse::new(404, vec![])]).await;
+        let user_agent = UserAgent::identify("admin@example.com").unwrap();
+        let context = Context::new(server.url()).user_agent(user_agent);
+        assert!(crate::get_user(&context, 1).await.is_err());
+        let heads = server.request_heads();
+        assert!(
+            heads[0].contains("user-agent: steam ggst-api-rs/"),
+            "{}",
+            heads[0]
+        );
+        assert!(heads[0].contains("(+admin@example.com)"), "{}", heads[0]);
+    }
+
+    // Whether the server answers exactly the user agents UserAgent::new accepts, run with
+    // `cargo test -- --ignored`. Fails for a user agent the validation rejects but the server
+    // answers, or the other way around, so that the rule in UserAgent can be updated.
+    #[tokio::test]
+    #[ignore]
+    async fn live_user_agent() {
+        let user_agents = [
+            UserAgent::STEAM.to_string(),
+            UserAgent::identify("https://github.com/xynxynxyn/ggst-api-rs")
+                .unwrap()
+                .as_str()
+                .to_string(),
+            "SteamBot".to_string(),
+            "ggst-api-rs/0.2.0".to_string(),
+            "".to_string(),
+        ];
+        for user_agent in user_agents {
+            // Bypasses the validation of UserAgent::new
+            let transport = HttpTransport {
+                user_agent: UserAgent(header::HeaderValue::from_str(&user_agent).unwrap()),
+                ..HttpTransport::new(crate::DEFAULT_BASE_URL)
+            };
+            let context = Context::default()
+                .transport(transport)
+                .retry_policy(crate::requests::RetryPolicy::none());
+            let result = crate::get_replays(&context, 1, 10, QueryParameters::default()).await;
+            let answered = match &result {
+                Ok(replays) => replays.errors().is_empty() && !replays.metadata().is_empty(),
+                Err(_) => false,
+            };
+            println!(
+                "{:?}: {:?}",
+                user_agent,
+                result.map(|r| r.matches().count())
+            );
+            assert_eq!(
+                answered,
+                UserAgent::new(&user_agent).is_ok(),
+                "{:?}",
+                user_agent
+            );
+        }
+    }
+
+    #[cfg(feature = "middleware")]
+    fn tag_request<'a>(
+        mut request: reqwest::Request,
+        extensions: &'a mut task_local_extensions::Extensions,
+        next: reqwest_middleware::Next<'a>,
+    ) -> BoxFuture<'a, reqwest_middleware::Result<reqwest::Response>> {
+        request.headers_mut().insert(
+            "x-ggst-test",
+            header::HeaderValue::from_static("middleware"),
+        );
+        Box::pin(next.run(request, extensions))
+    }
+
+    #[cfg(feature = "middleware")]
+    #[tokio::test]
+    async fn middleware_headers() {
+        use crate::mock_server::{MockResponse, MockServer};
+        let fixture = include_bytes!("../test_data/replay_response_2.msgpack");
+        let server = MockServer::start(vec![MockResponse::new(200, fixture.to_vec())]).await;
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(tag_request)
+            .build();
+        let ctx = Context::default().transport(MiddlewareTransport::new(client, server.url()));
+        let (replays, _) = crate::get_replays(&ctx, 1, 10, QueryParameters::default())
+            .await
+            .unwrap()
+            .into_parts();
+        assert!(replays.count() > 0);
+
+        let heads = server.request_heads();
+        assert_eq!(heads.len(), 1);
+        assert!(heads[0].contains("x-ggst-test: middleware"), "{}", heads[0]);
+        assert!(heads[0].contains("user-agent: steam"), "{}", heads[0]);
+    }
+}
@@ -0,0 +1,524 @@
+//! Background task that polls the replay endpoint and reports new matches over a channel, and a
+//! live feed of the matches played in the last minutes.
+
+use crate::cache::ReplayIdCache;
+use crate::filter::MatchFilter;
+use crate::requests::{check_query, collect_page, messagepack, MatchCollector};
+use crate::{error::*, Context, Match, QueryParameters, ReplaySet, ResponseMetadata};
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+const REPLAYS_PER_PAGE: usize = 127;
+const MAX_PAGES: usize = 100;
+// Enough to recognize every replay of a full crawl
+const CACHE_CAPACITY: usize = MAX_PAGES * REPLAYS_PER_PAGE;
+/// Pages a LiveFeed reads per poll at most, older pages hardly contain fresh matches
+pub const LIVE_PAGES: usize = 3;
+
+/// Something the watcher observed
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// A replay that wasn't reported before
+    NewMatch(Match),
+    /// A request failed or a page or replay could not be parsed. The watcher keeps polling, except
+    /// for invalid query parameters.
+    Error(Error),
+    /// A poll finished, sent after the new matches it found
+    Heartbeat,
+}
+
+/// Poll the replays matching the query every interval and send each match once. The first poll
+/// only reads the newest page. Later polls read further pages as long as every replay on a page is
+/// new, so nothing is missed if many matches are played between two polls. New matches of a poll
+/// are sent in ascending order of their timestamp, followed by a Heartbeat.
+///
+/// Retries are done according to the retry policy of the context. The task ends once the receiver
+/// is dropped.
+pub fn spawn_watcher<A, B, C, D, E>(
+    context: impl Into<Arc<Context>>,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+    interval: Duration,
+) -> (JoinHandle<()>, mpsc::Receiver<WatchEvent>) {
+    spawn_watcher_until(
+        context,
+        request_parameters,
+        interval,
+        std::future::pending::<()>(),
+    )
+}
+
+/// Same as spawn_watcher, but the task also ends once cancel completes, e.g. the `cancelled()`
+/// future of a tokio_util CancellationToken. A poll in progress is dropped together with its
+/// requests and the matches it found are not sent.
+pub fn spawn_watcher_until<A, B, C, D, E>(
+    context: impl Into<Arc<Context>>,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+    interval: Duration,
+    cancel: impl std::future::Future + Send + 'static,
+) -> (JoinHandle<()>, mpsc::Receiver<WatchEvent>) {
+    let context = context.into();
+    let query = check_query(&context, MAX_PAGES, REPLAYS_PER_PAGE, &request_parameters);
+    let (sender, receiver) = mpsc::channel(REPLAYS_PER_PAGE);
+    let handle = tokio::spawn(async move {
+        let query = match query {
+            Ok(query) => query,
+            Err(e) => {
+                let _ = sender.send(WatchEvent::Error(e)).await;
+                return;
+            }
+        };
+        let mut poller = Poller::new(query, MAX_PAGES);
+        tokio::pin!(cancel);
+        loop {
+            let outcome = tokio::select! {
+                biased;
+                _ = &mut cancel => return,
+                outcome = poller.poll(&context) => outcome,
+            };
+            let mut events = outcome
+                .errors
+                .into_iter()
+                .map(WatchEvent::Error)
+                .collect::<Vec<_>>();
+            events.extend(outcome.new_matches.into_iter().map(WatchEvent::NewMatch));
+            events.push(WatchEvent::Heartbeat);
+            for event in events {
+                if sender.send(event).await.is_err() {
+                    return;
+                }
+            }
+
+            tokio::select! {
+                _ = context.clock_ref().sleep(interval) => {}
+                _ = sender.closed() => return,
+                _ = &mut cancel => return,
+            }
+        }
+    });
+    (handle, receiver)
+}
+
+// The polling shared by spawn_watcher and LiveFeed. The first poll only reads the newest page,
+// later polls read further pages up to max_pages as long as every replay on a page is new.
+struct Poller {
+    query: messagepack::RequestQuery,
+    cache: ReplayIdCache,
+    first_poll: bool,
+    max_pages: usize,
+}
+
+#[derive(Default)]
+struct PollOutcome {
+    // In ascending order of their timestamp
+    new_matches: Vec<Match>,
+    errors: Vec<Error>,
+    metadata: Vec<ResponseMetadata>,
+}
+
+impl Poller {
+    fn new(query: messagepack::RequestQuery, max_pages: usize) -> Self {
+        Poller {
+            query,
+            cache: ReplayIdCache::new(CACHE_CAPACITY),
+            first_poll: true,
+            max_pages,
+        }
+    }
+
+    async fn poll(&mut self, context: &Context) -> PollOutcome {
+        let mut outcome = PollOutcome::default();
+        for page in 0..self.max_pages {
+            let mut replays = ReplaySet::default();
+            let mut matches = MatchCollector::default();
+            let result = collect_page(
+                context,
+                page,
+                REPLAYS_PER_PAGE,
+                &self.query,
+                &mut replays,
+                &mut matches,
+            )
+            .await;
+            outcome.metadata.extend_from_slice(replays.metadata());
+            let (_, errors) = replays.into_parts();
+            outcome.errors.extend(errors.map(|e| e.into_inner()));
+            if let Err(e) = result {
+                outcome.errors.push(e);
+                break;
+            }
+
+            let found = matches.matches.len();
+            let known = outcome.new_matches.len();
+            outcome.new_matches.extend(
+                matches
+                    .matches
+                    .into_values()
+                    .filter(|m| self.cache.insert(m.id)),
+            );
+            let all_new = found > 0 && outcome.new_matches.len() - known == found;
+            if self.first_poll || !all_new {
+                break;
+            }
+        }
+        self.first_poll = false;
+        outcome.new_matches.sort_unstable();
+        outcome
+    }
+}
+
+/// How long the polls of a LiveFeed took, including retries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub polls: u32,
+    pub last: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl LatencyStats {
+    fn record(&mut self, latency: Duration) {
+        self.min = if self.polls == 0 {
+            latency
+        } else {
+            self.min.min(latency)
+        };
+        self.max = self.max.max(latency);
+        self.last = latency;
+        self.total += latency;
+        self.polls += 1;
+    }
+
+    /// Average duration of a poll, zero before the first one
+    pub fn mean(&self) -> Duration {
+        self.total.checked_div(self.polls).unwrap_or_default()
+    }
+}
+
+/// Result of LiveFeed::poll
+#[derive(Debug)]
+pub struct LivePoll {
+    /// Matches not returned by an earlier poll which were played within the freshness window, in
+    /// ascending order of their timestamp
+    pub matches: Vec<Match>,
+    /// Failed requests and replays or pages that could not be parsed
+    pub errors: Vec<Error>,
+    /// How long the poll took
+    pub latency: Duration,
+    /// How far the server clock is ahead of Context::clock, measured with the last response
+    pub clock_skew: chrono::Duration,
+}
+
+/// Matches played in the last minutes, see live_feed
+pub struct LiveFeed {
+    context: Arc<Context>,
+    poller: Poller,
+    freshness: chrono::Duration,
+    clock_skew: chrono::Duration,
+    latency: LatencyStats,
+}
+
+/// Follow the newest matches of a query, e.g. floor 10 and celestial for a ticker on a stream.
+/// Every LiveFeed::poll reads at most LIVE_PAGES pages the same way spawn_watcher does and returns
+/// the new matches played within freshness of the current server time. The server time is the
+/// time of Context::clock corrected by the clock skew of the last response. The caller decides how often to
+/// poll, LiveFeed::latency helps to pick an interval. Fails if the query is invalid.
+pub fn live_feed<A, B, C, D, E>(
+    context: impl Into<Arc<Context>>,
+    request_parameters: QueryParameters<A, B, C, D, E>,
+    freshness: Duration,
+) -> Result<LiveFeed> {
+    let context = context.into();
+    let query = check_query(&context, LIVE_PAGES, REPLAYS_PER_PAGE, &request_parameters)?;
+    Ok(LiveFeed {
+        context,
+        poller: Poller::new(query, LIVE_PAGES),
+        freshness: chrono::Duration::from_std(freshness).unwrap_or(chrono::Duration::MAX),
+        clock_skew: chrono::Duration::zero(),
+        latency: LatencyStats::default(),
+    })
+}
+
+impl LiveFeed {
+    /// Request the newest pages once. Errors are returned in the result, the feed can keep
+    /// polling after them.
+    pub async fn poll(&mut self) -> LivePoll {
+        let clock = self.context.clock_ref();
+        let started = clock.instant();
+        let outcome = self.poller.poll(&self.context).await;
+        let latency = clock.instant() - started;
+        self.latency.record(latency);
+        if let Some(metadata) = outcome.metadata.last() {
+            self.clock_skew = metadata.server_date - clock.now();
+        }
+        let server_now = clock.now() + self.clock_skew;
+        let filter = MatchFilter::new().since(
+            server_now
+                .checked_sub_signed(self.freshness)
+                .unwrap_or(chrono::DateTime::<Utc>::MIN_UTC),
+        );
+        LivePoll {
+            matches: outcome
+                .new_matches
+                .into_iter()
+                .filter(|m| filter.matches(m))
+                .collect(),
+            errors: outcome.errors,
+            latency,
+            clock_skew: self.clock_skew,
+        }
+    }
+
+    /// Durations of all polls so far
+    pub fn latency(&self) -> &LatencyStats {
+        &self.latency
+    }
+
+    /// Clock skew measured with the last response, zero before the first one
+    pub fn clock_skew(&self) -> chrono::Duration {
+        self.clock_skew
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{BoxFuture, RawResponse, Transport};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const RESPONSE_1: &[u8] = include_bytes!("../test_data/replay_response_1.msgpack");
+    const RESPONSE_3: &[u8] = include_bytes!("../test_data/replay_response_3.msgpack");
+
+    // Answers the n-th request with the n-th response, the last one is repeated
+    struct Sequence(Vec<&'static [u8]>, AtomicUsize);
+
+    impl Transport for Sequence {
+        fn post<'a>(&'a self, _: &'static str, _: &'a str) -> BoxFuture<'a, Result<RawResponse>> {
+            let n = self.1.fetch_add(1, Ordering::SeqCst);
+            let body = self.0[n.min(self.0.len() - 1)];
+            Box::pin(async move { Ok(RawResponse::ok(body)) })
+        }
+    }
+
+    async fn next_poll(receiver: &mut mpsc::Receiver<WatchEvent>) -> Vec<u64> {
+        let mut ids = vec![];
+        loop {
+            match receiver.recv().await.unwrap() {
+                WatchEvent::NewMatch(m) => ids.push(m.id),
+                WatchEvent::Heartbeat => return ids,
+                WatchEvent::Error(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    fn ids(response: &[u8]) -> Vec<u64> {
+        crate::decode_replay_response(response, crate::DecodeMode::Lenient)
+            .unwrap()
+            .matches()
+            .map(|m| m.id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reports_new_matches_once() {
+        // The first poll reads page 0, the second poll finds only new replays on page 0 and
+        // continues with page 1 which was already seen
+        let transport = Sequence(
+            vec![RESPONSE_3, RESPONSE_1, RESPONSE_3],
+            AtomicUsize::new(0),
+        );
+        let context = Context::default().transport(transport);
+        let (handle, mut receiver) = spawn_watcher(
+            context,
+            QueryParameters::default(),
+            Duration::from_millis(1),
+        );
+
+        assert_eq!(next_poll(&mut receiver).await, ids(RESPONSE_3));
+        assert_eq!(next_poll(&mut receiver).await, ids(RESPONSE_1));
+        assert!(next_poll(&mut receiver).await.is_empty());
+
+        drop(receiver);
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn stops_during_interval() {
+        let transport = Sequence(vec![RESPONSE_1], AtomicUsize::new(0));
+        let context = Context::default().transport(transport);
+        let (handle, mut receiver) = spawn_watcher(
+            context,
+            QueryParameters::default(),
+            Duration::from_secs(3600),
+        );
+        next_poll(&mut receiver).await;
+        drop(receiver);
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancel_hung_poll() {
+        // Never answers
+        struct Hang;
+
+        impl Transport for Hang {
+            fn post<'a>(
+                &'a self,
+                _: &'static str,
+                _: &'a str,
+            ) -> BoxFuture<'a, Result<RawResponse>> {
+                Box::pin(std::future::pending())
+            }
+        }
+
+        let (cancel, cancelled) = tokio::sync::oneshot::channel::<()>();
+        let (handle, mut receiver) = spawn_watcher_until(
+            Context::default().transport(Hang),
+            QueryParameters::default(),
+            Duration::from_millis(1),
+            cancelled,
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        cancel.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn live_feed_keeps_fresh_matches() {
+        const RESPONSE_4: &[u8] = include_bytes!("../test_data/replay_response_4.msgpack");
+
+        // The response is dated 10:45:23 with replays from 10:30:16 to 10:30:35. The skew to the
+        // server clock puts the start of the window at 10:30:22.5.
+        let transport = Sequence(vec![RESPONSE_4], AtomicUsize::new(0));
+        let context = Context::default().transport(transport);
+        let mut feed = live_feed(
+            context,
+            QueryParameters::default(),
+            Duration::from_millis(900_500),
+        )
+        .unwrap();
+        let poll = feed.poll().await;
+        assert!(poll.errors.is_empty());
+        assert!(poll.clock_skew < -chrono::Duration::days(365));
+        let fresh = crate::decode_replay_response(RESPONSE_4, crate::DecodeMode::Lenient)
+            .unwrap()
+            .matches()
+            .filter(|m| {
+                m.timestamp
+                    > "2022-02-06T10:30:22Z"
+                        .parse::<chrono::DateTime<Utc>>()
+                        .unwrap()
+            })
+            .map(|m| m.id)
+            .collect::<Vec<_>>();
+        assert!(!fresh.is_empty() && fresh.len() < ids(RESPONSE_4).len());
+        assert_eq!(poll.matches.iter().map(|m| m.id).collect::<Vec<_>>(), fresh);
+
+        // Nothing is returned twice
+        assert!(feed.poll().await.matches.is_empty());
+        let latency = feed.latency();
+        assert_eq!(latency.polls, 2);
+        assert!(latency.min <= latency.mean() && latency.mean() <= latency.max);
+        // Measured again with the second response
+        assert!((feed.clock_skew() - poll.clock_skew).abs() < chrono::Duration::seconds(1));
+    }
+
+    #[tokio::test]
+    async fn live_feed_window_on_mock_clock() {
+        use crate::clock::MockClock;
+
+        const RESPONSE_4: &[u8] = include_bytes!("../test_data/replay_response_4.msgpack");
+        let date = |s: &str| s.parse::<chrono::DateTime<Utc>>().unwrap();
+
+        // The response is dated 10:45:23, with a clock that doesn't move during the poll the window
+        // starts exactly 15 minutes earlier at 10:30:23
+        let clock = Arc::new(MockClock::new(date("2023-01-01T00:00:00Z")));
+        let transport = Sequence(vec![RESPONSE_4], AtomicUsize::new(0));
+        let context = Context::default().transport(transport).clock(clock.clone());
+        let mut feed = live_feed(
+            context,
+            QueryParameters::default(),
+            Duration::from_secs(900),
+        )
+        .unwrap();
+        let poll = feed.poll().await;
+        assert!(poll.errors.is_empty());
+        assert_eq!(poll.latency, Duration::ZERO);
+        assert_eq!(
+            poll.clock_skew,
+            date("2022-02-06T10:45:23Z") - date("2023-01-01T00:00:00Z")
+        );
+        let fresh = crate::decode_replay_response(RESPONSE_4, crate::DecodeMode::Lenient)
+            .unwrap()
+            .matches()
+            .filter(|m| m.timestamp >= date("2022-02-06T10:30:23Z"))
+            .map(|m| m.id)
+            .collect::<Vec<_>>();
+        assert!(!fresh.is_empty() && fresh.len() < ids(RESPONSE_4).len());
+        assert_eq!(poll.matches.iter().map(|m| m.id).collect::<Vec<_>>(), fresh);
+
+        // The same response a minute later means the local clock ran a minute ahead
+        clock.advance(Duration::from_secs(60));
+        assert!(feed.poll().await.matches.is_empty());
+        assert_eq!(
+            feed.clock_skew(),
+            poll.clock_skew - chrono::Duration::seconds(60)
+        );
+    }
+
+    #[tokio::test]
+    async fn live_feed_reads_few_pages() {
+        // Every page is full of new replays, the watcher would read all 100
+        let transport = Sequence(
+            vec![RESPONSE_3, RESPONSE_1, RESPONSE_3],
+            AtomicUsize::new(0),
+        );
+        let transport = Arc::new(transport);
+        let context = Context::default().transport(transport.clone());
+        let mut feed = live_feed(context, QueryParameters::default(), Duration::MAX).unwrap();
+        assert_eq!(feed.poll().await.matches.len(), ids(RESPONSE_3).len());
+        assert_eq!(transport.1.load(Ordering::SeqCst), 1);
+        feed.poll().await;
+        assert!(transport.1.load(Ordering::SeqCst) <= 1 + LIVE_PAGES);
+
+        assert!(live_feed(
+            Context::default(),
+            QueryParameters::default()
+                .min_floor(crate::Floor::Celestial)
+                .max_floor(crate::Floor::F1),
+            Duration::from_secs(60),
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn invalid_query() {
+        let context = Context::default();
+        let (handle, mut receiver) = spawn_watcher(
+            context,
+            QueryParameters::default()
+                .min_floor(crate::Floor::Celestial)
+                .max_floor(crate::Floor::F1),
+            Duration::from_secs(1),
+        );
+        assert!(matches!(
+            receiver.recv().await,
+            Some(WatchEvent::Error(Error::InvalidArgument { .. }))
+        ));
+        assert!(receiver.recv().await.is_none());
+        handle.await.unwrap();
+    }
+}